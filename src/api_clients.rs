@@ -13,28 +13,192 @@
 // limitations under the License.
 
 use std::collections::HashMap;
-use std::env;
 use std::future::Future;
+use std::hash::Hash;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{env, fmt};
 
 use anyhow::{anyhow, Context};
+use chrono::{DateTime, Utc};
 use octocrab::commits::PullRequestTarget;
+use octocrab::models::commits::GithubCommitStatus;
 use octocrab::models::pulls::ReviewState;
 use octocrab::models::repos::RepoCommit;
-use octocrab::Octocrab;
-use tokio::sync::Semaphore;
+use octocrab::params::State;
+use octocrab::{Octocrab, Page};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use tokio::sync::{OnceCell, Semaphore};
+use url::Url;
 
-use crate::github::{Commit, PullRequest, Review};
+use crate::error::PearError;
+use crate::gitea::GiteaClient;
+use crate::github::{
+    self, BranchProtection, Commit, CompareStatus, Comparison, FilePatch, OpenPullRequest, PullRequest,
+    PullRequestDescription, PullRequestHead, RateLimit, Release, Review, Signature,
+};
+use crate::graphql;
+use crate::http_client::build_github_octocrab;
 use crate::remote::Remote;
+use crate::retry::retry_transient;
+
+/// The version of the GitHub REST API whose responses octocrab parses.
+pub const DEFAULT_GITHUB_API_VERSION: &str = "2022-11-28";
+
+#[derive(Clone, Debug)]
+pub struct ClientConfig {
+    /// applies to connecting as well as reading and writing of every request
+    pub request_timeout: Duration,
+    /// overrides the proxy from the `HTTPS_PROXY`, `HTTP_PROXY` and `ALL_PROXY` env variables
+    pub proxy: Option<String>,
+    /// PEM bundle with additional CA certificates, e.g. for GitHub Enterprise behind a private CA
+    pub ca_cert: Option<PathBuf>,
+    /// fetch the head and reviews of a PR with one GraphQL query
+    pub use_graphql: bool,
+    /// API path per GitHub Enterprise host, for instances that do not serve the API at /api/v3
+    pub api_paths: HashMap<String, String>,
+    /// hosts running Gitea or Forgejo instead of GitHub
+    pub gitea_hosts: Vec<String>,
+    /// maximum number of idle connections kept open per host, unlimited if not set
+    pub pool_size: Option<usize>,
+    /// version of the REST API requested from GitHub hosts
+    pub github_api_version: String,
+    /// overrides `github_api_version` per host, for GitHub Enterprise instances that do not know it yet
+    pub github_api_versions: HashMap<String, String>,
+    /// tokens given on the command line instead of env variables
+    pub tokens: Tokens,
+    /// paginated requests fail when there are more pages than this
+    pub max_pages: usize,
+    /// request the signatures of listed commits, which Gitea only verifies on demand
+    pub verify_signatures: bool,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            request_timeout: Duration::from_secs(30),
+            proxy: None,
+            ca_cert: None,
+            use_graphql: false,
+            api_paths: HashMap::new(),
+            gitea_hosts: Vec::new(),
+            pool_size: None,
+            github_api_version: DEFAULT_GITHUB_API_VERSION.to_owned(),
+            github_api_versions: HashMap::new(),
+            tokens: Tokens::default(),
+            max_pages: 50,
+            verify_signatures: false,
+        }
+    }
+}
+
+/// Tokens which take precedence over the env variables, a token for the host wins over the default one.
+#[derive(Clone, Default)]
+pub struct Tokens {
+    pub default: Option<String>,
+    pub hosts: HashMap<String, String>,
+}
+
+impl Tokens {
+    fn source(&self, host: &str, env_name: String) -> TokenSource {
+        match self.hosts.get(host).or(self.default.as_ref()) {
+            Some(token) => TokenSource::Value(token.clone()),
+            None => TokenSource::Env(env_name),
+        }
+    }
+}
+
+// the tokens must never end up in logs or error messages
+impl fmt::Debug for Tokens {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tokens")
+            .field("default", &self.default.as_ref().map(|_| "<redacted>"))
+            .field("hosts", &self.hosts.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// Where a client takes its token from.
+pub enum TokenSource {
+    /// name of the env variable holding the token
+    Env(String),
+    Value(String),
+}
+
+impl TokenSource {
+    pub fn token(self) -> anyhow::Result<String> {
+        match self {
+            TokenSource::Env(env_name) => env::var(&env_name).with_context(|| format!("missing {env_name} env")),
+            TokenSource::Value(token) => Ok(token),
+        }
+    }
+}
+
+/// The software a host runs, which decides the API to talk to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Forge {
+    GitHub,
+    Gitea,
+}
+
+const MAX_PARALLEL_REQUESTS: u32 = 5;
+const RATE_LIMIT_WARN_THRESHOLD: u64 = 100;
 
 #[derive(Debug)]
 pub struct RealClient {
     semaphore: Semaphore,
     octocrab: Arc<Octocrab>,
+    compare_cache: CompareCache,
+    team_members_cache: TeamMembersCache,
+    codeowners_cache: CodeownersCache,
+    use_graphql: bool,
+    max_pages: usize,
+}
+
+type CompareKey = (String, String, String, String);
+
+/// Results of compare calls by owner, repo, base and head, so that images built from the same range only fetch it once.
+type CompareCache = ResponseCache<CompareKey, Comparison>;
+
+/// Members by org and team, so that every repo analyzed with --required-team does not fetch them again.
+pub(crate) type TeamMembersCache = ResponseCache<(String, String), Vec<String>>;
+
+/// CODEOWNERS files by owner, repo and branch.
+pub(crate) type CodeownersCache = ResponseCache<(String, String, String), Option<String>>;
+
+/// Responses of the API by request, so that the same request is only sent once per run.
+#[derive(Debug)]
+pub(crate) struct ResponseCache<K, V> {
+    results: Mutex<HashMap<K, Arc<OnceCell<V>>>>,
+}
+
+impl<K, V> Default for ResponseCache<K, V> {
+    fn default() -> Self {
+        Self {
+            results: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<K: Eq + Hash, V: Clone> ResponseCache<K, V> {
+    // Concurrent calls for the same key wait for the first one instead of fetching again. Errors are not cached.
+    pub(crate) async fn get_or_fetch<F, Fut>(&self, key: K, fetch: F) -> anyhow::Result<V>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = anyhow::Result<V>>,
+    {
+        let cell = self.results.lock().unwrap().entry(key).or_default().clone();
+        cell.get_or_try_init(fetch).await.cloned()
+    }
 }
 
-pub trait Client {
-    fn new(env_name: String, api_endpoint: String) -> anyhow::Result<Arc<Self>>;
+pub trait Client: Sync {
+    fn new(forge: Forge, token: TokenSource, api_endpoint: String, config: &ClientConfig) -> anyhow::Result<Arc<Self>>;
+
+    /// The forge the client talks to, e.g. to build links to its web UI.
+    fn forge(&self) -> Forge;
 
     fn associated_prs(
         &self,
@@ -43,64 +207,200 @@ pub trait Client {
         sha: String,
     ) -> impl Future<Output = anyhow::Result<Vec<PullRequest>>> + Send;
 
-    async fn compare(
+    /// Looks up the associated PRs of many commits at once. Commits missing from the result, e.g. because the client
+    /// cannot batch lookups, are looked up one by one with `associated_prs`.
+    fn associated_prs_batch(
+        &self,
+        owner: &str,
+        repo: &str,
+        shas: &[String],
+    ) -> impl Future<Output = anyhow::Result<HashMap<String, Vec<PullRequest>>>> + Send;
+
+    fn compare(
         &self,
         owner: &str,
         repo: &str,
         original: &str,
         base_commit: &str,
         head_commit: &str,
-    ) -> anyhow::Result<Vec<Commit>>;
+    ) -> impl Future<Output = anyhow::Result<Comparison>> + Send;
 
-    async fn pr_commits(&self, owner: &str, repo: &str, pr_number: u64) -> anyhow::Result<Vec<RepoCommit>>;
+    fn commit(&self, owner: &str, repo: &str, sha: &str) -> impl Future<Output = anyhow::Result<Commit>> + Send;
 
-    fn pr_head_hash(
+    /// Paths of the files the commit touched, including the previous path of renamed files.
+    fn commit_files(
         &self,
         owner: &str,
         repo: &str,
-        pr_number: u64,
+        sha: &str,
+    ) -> impl Future<Output = anyhow::Result<Vec<String>>> + Send;
+
+    /// The patches of the files the commit touched.
+    fn commit_patches(
+        &self,
+        owner: &str,
+        repo: &str,
+        sha: &str,
+    ) -> impl Future<Output = anyhow::Result<Vec<FilePatch>>> + Send;
+
+    /// Commits reachable from head that were committed within the range, in the same order as a compare.
+    fn commits_in_range(
+        &self,
+        owner: &str,
+        repo: &str,
+        head: &str,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> impl Future<Output = anyhow::Result<Vec<Commit>>> + Send;
+
+    fn resolve_ref(
+        &self,
+        owner: &str,
+        repo: &str,
+        reference: &str,
     ) -> impl Future<Output = anyhow::Result<String>> + Send;
 
+    fn merge_base(
+        &self,
+        owner: &str,
+        repo: &str,
+        base_commit: &str,
+        head_commit: &str,
+    ) -> impl Future<Output = anyhow::Result<String>> + Send;
+
+    fn pr_head(
+        &self,
+        owner: &str,
+        repo: &str,
+        pr_number: u64,
+    ) -> impl Future<Output = anyhow::Result<PullRequestHead>> + Send;
+
     fn pr_reviews(
         &self,
         owner: &str,
         repo: &str,
         pr_number: u64,
     ) -> impl Future<Output = anyhow::Result<Vec<Review>>> + Send;
+
+    fn pr_description(
+        &self,
+        owner: &str,
+        repo: &str,
+        pr_number: u64,
+    ) -> impl Future<Output = anyhow::Result<PullRequestDescription>> + Send;
+
+    fn pr_commits(
+        &self,
+        owner: &str,
+        repo: &str,
+        pr_number: u64,
+    ) -> impl Future<Output = anyhow::Result<Vec<String>>> + Send;
+
+    /// All open PRs of the repo, newest first.
+    fn list_open_prs(
+        &self,
+        owner: &str,
+        repo: &str,
+    ) -> impl Future<Output = anyhow::Result<Vec<OpenPullRequest>>> + Send;
+
+    /// Everything needed to judge a PR, clients that can fetch both at once override this.
+    fn pr_head_and_reviews(
+        &self,
+        owner: &str,
+        repo: &str,
+        pr_number: u64,
+    ) -> impl Future<Output = anyhow::Result<(PullRequestHead, Vec<Review>)>> + Send {
+        async move {
+            let head = self.pr_head(owner, repo, pr_number).await?;
+            let reviews = self.pr_reviews(owner, repo, pr_number).await?;
+            Ok((head, reviews))
+        }
+    }
+
+    fn team_members(&self, org: &str, team: &str) -> impl Future<Output = anyhow::Result<Vec<String>>> + Send;
+
+    fn default_branch(&self, owner: &str, repo: &str) -> impl Future<Output = anyhow::Result<String>> + Send;
+
+    /// The tags of the releases created before the release of `tag`, newest first.
+    fn previous_tags(
+        &self,
+        owner: &str,
+        repo: &str,
+        tag: &str,
+    ) -> impl Future<Output = anyhow::Result<Vec<String>>> + Send;
+
+    /// None when the branch is not protected.
+    fn branch_protection(
+        &self,
+        owner: &str,
+        repo: &str,
+        branch: &str,
+    ) -> impl Future<Output = anyhow::Result<Option<BranchProtection>>> + Send;
+
+    /// Content of the CODEOWNERS file of the branch, None when there is none.
+    fn codeowners(
+        &self,
+        owner: &str,
+        repo: &str,
+        branch: &str,
+    ) -> impl Future<Output = anyhow::Result<Option<String>>> + Send;
+
+    fn rate_limit(&self) -> impl Future<Output = anyhow::Result<RateLimit>> + Send;
+
+    /// Holds back all requests to this host for the given duration, requests already running are finished first.
+    /// Clients without a rate limit to wait for return right away.
+    fn pause(&self, _duration: Duration) -> impl Future<Output = anyhow::Result<()>> + Send {
+        async { Ok(()) }
+    }
 }
 
 impl Client for RealClient {
-    fn new(env_name: String, api_endpoint: String) -> anyhow::Result<Arc<RealClient>> {
-        octocrab::initialise(
-            Octocrab::builder()
-                .personal_token(env::var(&env_name).with_context(|| format!("missing {env_name} env"))?)
-                .base_uri(&api_endpoint)
-                .with_context(|| format!("failed to set base_uri to {api_endpoint}"))?
-                .build()
-                .context("failed to build octocrab client")?,
-        );
+    fn new(
+        _forge: Forge,
+        token: TokenSource,
+        api_endpoint: String,
+        config: &ClientConfig,
+    ) -> anyhow::Result<Arc<RealClient>> {
+        let token = token.token()?;
+        octocrab::initialise(build_github_octocrab(
+            &token,
+            &api_endpoint,
+            &config.github_api_version,
+            config,
+        )?);
         Ok(Arc::new(Self {
-            semaphore: Semaphore::new(5), // i.e. up to 5 API calls in parallel to the same GitHub instance
+            semaphore: Semaphore::new(MAX_PARALLEL_REQUESTS as usize), // per GitHub instance
             octocrab: octocrab::instance(),
+            compare_cache: CompareCache::default(),
+            team_members_cache: TeamMembersCache::default(),
+            codeowners_cache: CodeownersCache::default(),
+            use_graphql: config.use_graphql,
+            max_pages: config.max_pages,
         }))
     }
 
+    fn forge(&self) -> Forge {
+        Forge::GitHub
+    }
+
     async fn associated_prs(&self, owner: &str, repo: &str, sha: String) -> anyhow::Result<Vec<PullRequest>> {
         let _permit = self.semaphore.acquire().await?;
 
-        let mut associated_prs_page = self
-            .octocrab
-            .commits(owner, repo)
-            .associated_pull_requests(PullRequestTarget::Sha(sha))
-            .send()
+        let first_page = retry_transient(|| async {
+            self.octocrab
+                .commits(owner, repo)
+                .associated_pull_requests(PullRequestTarget::Sha(sha.clone()))
+                .per_page(100u8)
+                .send()
+                .await
+        })
+        .await
+        .map_err(PearError::from)
+        .context("failed to get associated prs")?;
+        let associated_prs = self
+            .all_pages(first_page)
             .await
             .context("failed to get associated prs")?;
-        assert!(
-            associated_prs_page.next.is_none(),
-            "found more than one page for associated_prs"
-        );
-
-        let associated_prs = associated_prs_page.take_items();
 
         let mut prs: Vec<PullRequest> = Vec::new();
         for associated_pr in associated_prs {
@@ -113,12 +413,46 @@ impl Client for RealClient {
             prs.push(PullRequest {
                 number: associated_pr.number,
                 url: associated_pr_url,
+                draft: associated_pr.draft.unwrap_or_default(),
             });
         }
 
         Ok(prs)
     }
 
+    async fn associated_prs_batch(
+        &self,
+        owner: &str,
+        repo: &str,
+        shas: &[String],
+    ) -> anyhow::Result<HashMap<String, Vec<PullRequest>>> {
+        let mut prs = HashMap::new();
+        if !self.use_graphql {
+            return Ok(prs);
+        }
+
+        for shas in shas.chunks(graphql::ASSOCIATED_PRS_BATCH_SIZE) {
+            let _permit = self.semaphore.acquire().await?;
+
+            let mut variables = serde_json::json!({"owner": owner, "repo": repo});
+            for (i, sha) in shas.iter().enumerate() {
+                variables[format!("sha{i}")] = sha.as_str().into();
+            }
+            let query = serde_json::json!({
+                "query": graphql::associated_prs_query(shas.len()),
+                "variables": variables,
+            });
+            let response = retry_transient(|| async { self.octocrab.graphql(&query).await })
+                .await
+                .map_err(PearError::from)
+                .context("failed to query associated prs")?;
+
+            prs.extend(graphql::associated_prs(response, shas).context("failed to query associated prs")?);
+        }
+
+        Ok(prs)
+    }
+
     async fn compare(
         &self,
         owner: &str,
@@ -126,15 +460,492 @@ impl Client for RealClient {
         original: &str,
         base_commit: &str,
         head_commit: &str,
+    ) -> anyhow::Result<Comparison> {
+        let key = (
+            owner.to_owned(),
+            repo.to_owned(),
+            base_commit.to_owned(),
+            head_commit.to_owned(),
+        );
+        self.compare_cache
+            .get_or_fetch(key, || {
+                self.fetch_compare(owner, repo, original, base_commit, head_commit)
+            })
+            .await
+    }
+
+    async fn merge_base(
+        &self,
+        owner: &str,
+        repo: &str,
+        base_commit: &str,
+        head_commit: &str,
+    ) -> anyhow::Result<String> {
+        let _permit = self.semaphore.acquire().await?;
+
+        // only the merge base is needed, so don't transfer more commits than necessary
+        let compare = retry_transient(|| async {
+            self.octocrab
+                .commits(owner, repo)
+                .compare(base_commit, head_commit)
+                .per_page(1u8)
+                .send()
+                .await
+        })
+        .await
+        .map_err(PearError::from)
+        .with_context(|| format!("failed to get merge base of {base_commit} and {head_commit}"))?;
+
+        Ok(compare.merge_base_commit.sha)
+    }
+
+    async fn commit(&self, owner: &str, repo: &str, sha: &str) -> anyhow::Result<Commit> {
+        let _permit = self.semaphore.acquire().await?;
+
+        let commit = retry_transient(|| async { self.octocrab.commits(owner, repo).get(sha).await })
+            .await
+            .map_err(PearError::from)
+            .with_context(|| format!("failed to get commit {sha}"))?;
+
+        Ok(commit_from_repo_commit(commit))
+    }
+
+    // GitHub lists at most 300 files per commit, which is plenty for the commits that are reviewed
+    async fn commit_files(&self, owner: &str, repo: &str, sha: &str) -> anyhow::Result<Vec<String>> {
+        let _permit = self.semaphore.acquire().await?;
+
+        let commit = retry_transient(|| async { self.octocrab.commits(owner, repo).get(sha).await })
+            .await
+            .map_err(PearError::from)
+            .with_context(|| format!("failed to get files of commit {sha}"))?;
+
+        Ok(commit
+            .files
+            .unwrap_or_default()
+            .into_iter()
+            .flat_map(|file| [Some(file.filename), file.previous_filename])
+            .flatten()
+            .collect())
+    }
+
+    async fn commit_patches(&self, owner: &str, repo: &str, sha: &str) -> anyhow::Result<Vec<FilePatch>> {
+        let _permit = self.semaphore.acquire().await?;
+
+        let commit = retry_transient(|| async { self.octocrab.commits(owner, repo).get(sha).await })
+            .await
+            .map_err(PearError::from)
+            .with_context(|| format!("failed to get patches of commit {sha}"))?;
+
+        Ok(commit
+            .files
+            .unwrap_or_default()
+            .into_iter()
+            .map(|file| FilePatch {
+                path: file.filename,
+                patch: file.patch,
+            })
+            .collect())
+    }
+
+    async fn commits_in_range(
+        &self,
+        owner: &str,
+        repo: &str,
+        head: &str,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
     ) -> anyhow::Result<Vec<Commit>> {
         let _permit = self.semaphore.acquire().await?;
 
-        let compare = self
-            .octocrab
-            .commits(owner, repo)
-            .compare(base_commit, head_commit)
-            .send()
+        let first_page = retry_transient(|| async {
+            self.octocrab
+                .repos(owner, repo)
+                .list_commits()
+                .sha(head)
+                .since(since)
+                .until(until)
+                .per_page(100)
+                .send()
+                .await
+        })
+        .await
+        .map_err(PearError::from)
+        .with_context(|| format!("failed to list commits of {head} between {since} and {until}"))?;
+        let commits = self
+            .all_pages(first_page)
+            .await
+            .with_context(|| format!("failed to list commits of {head} between {since} and {until}"))?;
+
+        // the list starts with the newest commit, while a compare starts with the oldest one
+        Ok(commits.into_iter().rev().map(commit_from_repo_commit).collect())
+    }
+
+    // The commits endpoint accepts any ref and peels annotated tags to the commit they point to.
+    async fn resolve_ref(&self, owner: &str, repo: &str, reference: &str) -> anyhow::Result<String> {
+        let commit = self
+            .commit(owner, repo, reference)
+            .await
+            .with_context(|| format!("failed to resolve ref {reference}"))?;
+        Ok(commit.sha)
+    }
+
+    // The head is taken from the PR itself and not from its commits, as those live in the fork for PRs from forks.
+    async fn pr_head(&self, owner: &str, repo: &str, pr_number: u64) -> anyhow::Result<PullRequestHead> {
+        let _permit = self.semaphore.acquire().await?;
+
+        let pr = retry_transient(|| async { self.octocrab.pulls(owner, repo).get(pr_number).await })
+            .await
+            .map_err(PearError::from)
+            .with_context(|| format!("failed to get pr {pr_number}"))?;
+
+        Ok(PullRequestHead {
+            repo: pr.head.repo.and_then(|head_repo| head_repo.full_name),
+            sha: pr.head.sha,
+        })
+    }
+
+    async fn pr_description(&self, owner: &str, repo: &str, pr_number: u64) -> anyhow::Result<PullRequestDescription> {
+        let _permit = self.semaphore.acquire().await?;
+
+        let pr = retry_transient(|| async { self.octocrab.pulls(owner, repo).get(pr_number).await })
+            .await
+            .map_err(PearError::from)
+            .with_context(|| format!("failed to get pr {pr_number}"))?;
+
+        Ok(PullRequestDescription {
+            title: pr.title.unwrap_or_default(),
+            body: pr.body.unwrap_or_default(),
+            merge_commit: pr.merge_commit_sha.filter(|_| pr.merged_at.is_some()),
+        })
+    }
+
+    async fn pr_reviews(&self, owner: &str, repo: &str, pr_number: u64) -> anyhow::Result<Vec<Review>> {
+        let _permit = self.semaphore.acquire().await?;
+
+        let first_page = retry_transient(|| async {
+            self.octocrab
+                .pulls(owner, repo)
+                .list_reviews(pr_number)
+                .per_page(100u8)
+                .send()
+                .await
+        })
+        .await
+        .map_err(PearError::from)
+        .context("failed to get reviews")?;
+        let pr_reviews = self.all_pages(first_page).await.context("failed to get reviews")?;
+
+        let mut reviews = Vec::new();
+        for pr_review in &pr_reviews {
+            reviews.push(Review {
+                id: pr_review.id.into_inner(),
+                state: match pr_review.state {
+                    Some(ReviewState::Approved) => github::ReviewState::Approved,
+                    Some(ReviewState::ChangesRequested) => github::ReviewState::ChangesRequested,
+                    Some(ReviewState::Commented) => github::ReviewState::Commented,
+                    Some(ReviewState::Dismissed) => github::ReviewState::Dismissed,
+                    _ => github::ReviewState::Pending,
+                },
+                commit_id: pr_review.commit_id.clone().ok_or(anyhow!("review has no commit_id"))?,
+                submitted_at: pr_review
+                    .submitted_at
+                    .ok_or_else(|| anyhow!("review has no submitted_at"))?
+                    .timestamp_micros(),
+                user: pr_review.user.clone().ok_or(anyhow!("review has no user"))?.login,
+            });
+        }
+
+        reviews.sort_by_key(|r| (r.submitted_at, r.id));
+        Ok(reviews)
+    }
+
+    // Returns the hashes of all commits of the PR, GitHub lists at most 250 of them.
+    async fn pr_commits(&self, owner: &str, repo: &str, pr_number: u64) -> anyhow::Result<Vec<String>> {
+        let _permit = self.semaphore.acquire().await?;
+
+        let first_page = retry_transient(|| async {
+            self.octocrab
+                .pulls(owner, repo)
+                .pr_commits(pr_number)
+                .per_page(100u8)
+                .send()
+                .await
+        })
+        .await
+        .map_err(PearError::from)
+        .context("failed to get PR commits")?;
+        let pr_commits = self.all_pages(first_page).await.context("failed to get PR commits")?;
+
+        Ok(pr_commits.into_iter().map(|commit| commit.sha).collect())
+    }
+
+    async fn list_open_prs(&self, owner: &str, repo: &str) -> anyhow::Result<Vec<OpenPullRequest>> {
+        let _permit = self.semaphore.acquire().await?;
+
+        let first_page = retry_transient(|| async {
+            self.octocrab
+                .pulls(owner, repo)
+                .list()
+                .state(State::Open)
+                .per_page(100u8)
+                .send()
+                .await
+        })
+        .await
+        .map_err(PearError::from)
+        .context("failed to list open prs")?;
+        let prs = self.all_pages(first_page).await.context("failed to list open prs")?;
+
+        let mut open_prs = Vec::new();
+        for pr in prs {
+            open_prs.push(OpenPullRequest {
+                number: pr.number,
+                url: pr
+                    .html_url
+                    .ok_or_else(|| anyhow!("pr without an html link!?"))?
+                    .to_string(),
+                title: pr.title.unwrap_or_default(),
+                author: pr.user.map(|user| user.login),
+                head_sha: pr.head.sha,
+            });
+        }
+
+        Ok(open_prs)
+    }
+
+    async fn pr_head_and_reviews(
+        &self,
+        owner: &str,
+        repo: &str,
+        pr_number: u64,
+    ) -> anyhow::Result<(PullRequestHead, Vec<Review>)> {
+        if !self.use_graphql {
+            let head = self.pr_head(owner, repo, pr_number).await?;
+            let reviews = self.pr_reviews(owner, repo, pr_number).await?;
+            return Ok((head, reviews));
+        }
+
+        let _permit = self.semaphore.acquire().await?;
+
+        let query = serde_json::json!({
+            "query": graphql::PR_HEAD_AND_REVIEWS_QUERY,
+            "variables": {"owner": owner, "repo": repo, "number": pr_number},
+        });
+        let response = retry_transient(|| async { self.octocrab.graphql(&query).await })
+            .await
+            .map_err(PearError::from)
+            .with_context(|| format!("failed to query pr {pr_number}"))?;
+
+        graphql::pr_head_and_reviews(response).with_context(|| format!("failed to query pr {pr_number}"))
+    }
+
+    async fn team_members(&self, org: &str, team: &str) -> anyhow::Result<Vec<String>> {
+        self.team_members_cache
+            .get_or_fetch((org.to_owned(), team.to_owned()), || self.fetch_team_members(org, team))
+            .await
+    }
+
+    async fn default_branch(&self, owner: &str, repo: &str) -> anyhow::Result<String> {
+        let _permit = self.semaphore.acquire().await?;
+
+        let repository = retry_transient(|| async { self.octocrab.repos(owner, repo).get().await })
+            .await
+            .map_err(PearError::from)
+            .with_context(|| format!("failed to get repo {owner}/{repo}"))?;
+
+        repository
+            .default_branch
+            .ok_or_else(|| anyhow!("repo {owner}/{repo} has no default branch"))
+    }
+
+    async fn previous_tags(&self, owner: &str, repo: &str, tag: &str) -> anyhow::Result<Vec<String>> {
+        let _permit = self.semaphore.acquire().await?;
+
+        let first_page = retry_transient(|| async {
+            self.octocrab
+                .repos(owner, repo)
+                .releases()
+                .list()
+                .per_page(100u8)
+                .send()
+                .await
+        })
+        .await
+        .map_err(PearError::from)
+        .context("failed to list releases")?;
+        let releases = self.all_pages(first_page).await.context("failed to list releases")?;
+
+        let releases = releases
+            .into_iter()
+            .filter(|release| !release.draft)
+            .filter_map(|release| {
+                Some(Release {
+                    created_at: release.created_at?,
+                    tag: release.tag_name,
+                })
+            })
+            .collect();
+        github::previous_tags(releases, tag)
+    }
+
+    async fn branch_protection(
+        &self,
+        owner: &str,
+        repo: &str,
+        branch: &str,
+    ) -> anyhow::Result<Option<BranchProtection>> {
+        let _permit = self.semaphore.acquire().await?;
+
+        let result: Result<GitHubBranchProtection, _> = retry_transient(|| async {
+            self.octocrab
+                .get(
+                    format!("/repos/{owner}/{repo}/branches/{branch}/protection"),
+                    None::<&()>,
+                )
+                .await
+        })
+        .await
+        .map_err(PearError::from);
+        let protection = match result {
+            Ok(protection) => protection,
+            Err(PearError::NotFound(_)) => return Ok(None),
+            Err(err) => return Err(err).with_context(|| format!("failed to get protection of branch {branch}")),
+        };
+
+        let required_approvals = protection
+            .required_pull_request_reviews
+            .map(|reviews| reviews.required_approving_review_count)
+            .filter(|count| *count > 0);
+        // GitHub does not restrict who may approve, the users in dismissal_restrictions may only dismiss reviews
+        Ok(Some(BranchProtection {
+            required_approvals,
+            ..BranchProtection::default()
+        }))
+    }
+
+    async fn codeowners(&self, owner: &str, repo: &str, branch: &str) -> anyhow::Result<Option<String>> {
+        self.codeowners_cache
+            .get_or_fetch((owner.to_owned(), repo.to_owned(), branch.to_owned()), || {
+                self.fetch_codeowners(owner, repo, branch)
+            })
+            .await
+    }
+
+    async fn rate_limit(&self) -> anyhow::Result<RateLimit> {
+        // querying the rate limit does not count against it, so no permit is needed
+        let rate_limit = retry_transient(|| async { self.octocrab.ratelimit().get().await })
+            .await
+            .map_err(PearError::from)
+            .context("failed to get rate limit")?;
+
+        Ok(RateLimit {
+            limit: rate_limit.resources.core.limit as u64,
+            remaining: rate_limit.resources.core.remaining as u64,
+            reset: rate_limit.resources.core.reset,
+        })
+    }
+
+    async fn pause(&self, duration: Duration) -> anyhow::Result<()> {
+        let _permits = self.semaphore.acquire_many(MAX_PARALLEL_REQUESTS).await?;
+        tokio::time::sleep(duration).await;
+        Ok(())
+    }
+}
+
+impl RealClient {
+    // Like Octocrab::all_pages, but gives up after max_pages pages instead of using up the rate limit.
+    async fn all_pages<T: DeserializeOwned>(&self, mut page: Page<T>) -> anyhow::Result<Vec<T>> {
+        let mut items = page.take_items();
+        let mut pages = 1;
+        while let Some(next) = page.next.take() {
+            if pages >= self.max_pages {
+                return Err(PearError::TooManyPages(self.max_pages).into());
+            }
+            let next = Some(next);
+            page = match retry_transient(|| self.octocrab.get_page::<T>(&next))
+                .await
+                .map_err(PearError::from)?
+            {
+                Some(page) => page,
+                None => break,
+            };
+            items.extend(page.take_items());
+            pages += 1;
+        }
+        Ok(items)
+    }
+
+    async fn fetch_team_members(&self, org: &str, team: &str) -> anyhow::Result<Vec<String>> {
+        let _permit = self.semaphore.acquire().await?;
+
+        let first_page =
+            retry_transient(|| async { self.octocrab.teams(org).members(team).per_page(100).send().await })
+                .await
+                .map_err(PearError::from)
+                .with_context(|| format!("failed to get members of team {org}/{team}"))?;
+        let team_members = self
+            .all_pages(first_page)
+            .await
+            .with_context(|| format!("failed to get members of team {org}/{team}"))?;
+
+        Ok(team_members.into_iter().map(|member| member.login).collect())
+    }
+
+    async fn fetch_codeowners(&self, owner: &str, repo: &str, branch: &str) -> anyhow::Result<Option<String>> {
+        let _permit = self.semaphore.acquire().await?;
+
+        for path in GITHUB_CODEOWNERS_PATHS {
+            let result = retry_transient(|| async {
+                self.octocrab
+                    .repos(owner, repo)
+                    .get_content()
+                    .path(path)
+                    .r#ref(branch)
+                    .send()
+                    .await
+            })
+            .await
+            .map_err(PearError::from);
+            match result {
+                Ok(mut content) => {
+                    return Ok(content
+                        .take_items()
+                        .into_iter()
+                        .next()
+                        .and_then(|item| item.decoded_content()));
+                },
+                Err(PearError::NotFound(_)) => {},
+                Err(err) => return Err(err).with_context(|| format!("failed to get {path}")),
+            }
+        }
+        Ok(None)
+    }
+
+    async fn fetch_compare(
+        &self,
+        owner: &str,
+        repo: &str,
+        original: &str,
+        base_commit: &str,
+        head_commit: &str,
+    ) -> anyhow::Result<Comparison> {
+        let _permit = self.semaphore.acquire().await?;
+
+        // The compare is paginated by its commits, the comparison itself is repeated on every page.
+        let mut compare_commits = Vec::new();
+        let mut status = None;
+        for page in 1u32.. {
+            let compare = retry_transient(|| async {
+                self.octocrab
+                    .commits(owner, repo)
+                    .compare(base_commit, head_commit)
+                    .per_page(100u8)
+                    .page(page)
+                    .send()
+                    .await
+            })
             .await
+            .map_err(PearError::from)
             .context(format!(
                 "failed to compare {}/compare/{}...{}",
                 original.trim_end_matches(".git"),
@@ -142,106 +953,379 @@ impl Client for RealClient {
                 &head_commit
             ))?;
 
-        let mut commits: Vec<Commit> = vec![];
-        for commit in compare.commits {
-            commits.push(Commit {
-                html_url: commit.html_url,
-                message: commit.commit.message,
-                sha: commit.sha,
-            });
+            status = Some(match compare.status {
+                GithubCommitStatus::Ahead => CompareStatus::Ahead,
+                GithubCommitStatus::Behind => CompareStatus::Behind,
+                GithubCommitStatus::Diverged => CompareStatus::Diverged,
+                GithubCommitStatus::Identical => CompareStatus::Identical,
+            });
+            let is_empty = compare.commits.is_empty();
+            compare_commits.extend(compare.commits);
+            if is_empty || compare_commits.len() >= usize::try_from(compare.total_commits).unwrap_or_default() {
+                break;
+            }
+        }
+
+        let mut commits: Vec<Commit> = vec![];
+        for commit in compare_commits {
+            let git_author = commit.commit.author;
+            commits.push(Commit {
+                html_url: commit.html_url,
+                message: commit.commit.message,
+                sha: commit.sha,
+                author: commit
+                    .author
+                    .map(|author| author.login)
+                    .or_else(|| git_author.as_ref().and_then(|author| author.name.clone())),
+                authored_at: git_author
+                    .and_then(|author| author.date)
+                    .and_then(|date| DateTime::parse_from_rfc3339(&date).ok())
+                    .map(|date| date.with_timezone(&Utc)),
+                signature: commit.commit.verification.map(|verification| Signature {
+                    verified: verification.verified,
+                    reason: verification.reason,
+                }),
+            });
+        }
+
+        Ok(Comparison { status, commits })
+    }
+}
+
+fn commit_from_repo_commit(commit: RepoCommit) -> Commit {
+    let git_author = commit.commit.author;
+    Commit {
+        html_url: commit.html_url,
+        message: commit.commit.message,
+        sha: commit.sha,
+        author: commit
+            .author
+            .map(|author| author.login)
+            .or_else(|| git_author.as_ref().map(|author| author.user.name.clone())),
+        authored_at: git_author.and_then(|author| author.date),
+        signature: commit.commit.verification.map(|verification| Signature {
+            verified: verification.verified,
+            reason: verification.reason,
+        }),
+    }
+}
+
+/// Dispatches to the client matching the forge of a host, so that GitHub and Gitea hosts can be mixed in one run.
+#[derive(Debug)]
+pub enum AnyClient {
+    GitHub(Arc<RealClient>),
+    Gitea(Arc<GiteaClient>),
+}
+
+impl Client for AnyClient {
+    fn new(forge: Forge, token: TokenSource, api_endpoint: String, config: &ClientConfig) -> anyhow::Result<Arc<Self>> {
+        Ok(Arc::new(match forge {
+            Forge::GitHub => AnyClient::GitHub(RealClient::new(forge, token, api_endpoint, config)?),
+            Forge::Gitea => AnyClient::Gitea(GiteaClient::new(forge, token, api_endpoint, config)?),
+        }))
+    }
+
+    fn forge(&self) -> Forge {
+        match self {
+            AnyClient::GitHub(client) => client.forge(),
+            AnyClient::Gitea(client) => client.forge(),
+        }
+    }
+
+    async fn associated_prs(&self, owner: &str, repo: &str, sha: String) -> anyhow::Result<Vec<PullRequest>> {
+        match self {
+            AnyClient::GitHub(client) => client.associated_prs(owner, repo, sha).await,
+            AnyClient::Gitea(client) => client.associated_prs(owner, repo, sha).await,
+        }
+    }
+
+    async fn associated_prs_batch(
+        &self,
+        owner: &str,
+        repo: &str,
+        shas: &[String],
+    ) -> anyhow::Result<HashMap<String, Vec<PullRequest>>> {
+        match self {
+            AnyClient::GitHub(client) => client.associated_prs_batch(owner, repo, shas).await,
+            AnyClient::Gitea(client) => client.associated_prs_batch(owner, repo, shas).await,
+        }
+    }
+
+    async fn compare(
+        &self,
+        owner: &str,
+        repo: &str,
+        original: &str,
+        base_commit: &str,
+        head_commit: &str,
+    ) -> anyhow::Result<Comparison> {
+        match self {
+            AnyClient::GitHub(client) => client.compare(owner, repo, original, base_commit, head_commit).await,
+            AnyClient::Gitea(client) => client.compare(owner, repo, original, base_commit, head_commit).await,
+        }
+    }
+
+    async fn commit(&self, owner: &str, repo: &str, sha: &str) -> anyhow::Result<Commit> {
+        match self {
+            AnyClient::GitHub(client) => client.commit(owner, repo, sha).await,
+            AnyClient::Gitea(client) => client.commit(owner, repo, sha).await,
+        }
+    }
+
+    async fn commit_files(&self, owner: &str, repo: &str, sha: &str) -> anyhow::Result<Vec<String>> {
+        match self {
+            AnyClient::GitHub(client) => client.commit_files(owner, repo, sha).await,
+            AnyClient::Gitea(client) => client.commit_files(owner, repo, sha).await,
+        }
+    }
+
+    async fn commit_patches(&self, owner: &str, repo: &str, sha: &str) -> anyhow::Result<Vec<FilePatch>> {
+        match self {
+            AnyClient::GitHub(client) => client.commit_patches(owner, repo, sha).await,
+            AnyClient::Gitea(client) => client.commit_patches(owner, repo, sha).await,
+        }
+    }
+
+    async fn commits_in_range(
+        &self,
+        owner: &str,
+        repo: &str,
+        head: &str,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<Commit>> {
+        match self {
+            AnyClient::GitHub(client) => client.commits_in_range(owner, repo, head, since, until).await,
+            AnyClient::Gitea(client) => client.commits_in_range(owner, repo, head, since, until).await,
+        }
+    }
+
+    async fn resolve_ref(&self, owner: &str, repo: &str, reference: &str) -> anyhow::Result<String> {
+        match self {
+            AnyClient::GitHub(client) => client.resolve_ref(owner, repo, reference).await,
+            AnyClient::Gitea(client) => client.resolve_ref(owner, repo, reference).await,
+        }
+    }
+
+    async fn merge_base(
+        &self,
+        owner: &str,
+        repo: &str,
+        base_commit: &str,
+        head_commit: &str,
+    ) -> anyhow::Result<String> {
+        match self {
+            AnyClient::GitHub(client) => client.merge_base(owner, repo, base_commit, head_commit).await,
+            AnyClient::Gitea(client) => client.merge_base(owner, repo, base_commit, head_commit).await,
+        }
+    }
+
+    async fn pr_head(&self, owner: &str, repo: &str, pr_number: u64) -> anyhow::Result<PullRequestHead> {
+        match self {
+            AnyClient::GitHub(client) => client.pr_head(owner, repo, pr_number).await,
+            AnyClient::Gitea(client) => client.pr_head(owner, repo, pr_number).await,
+        }
+    }
+
+    async fn pr_reviews(&self, owner: &str, repo: &str, pr_number: u64) -> anyhow::Result<Vec<Review>> {
+        match self {
+            AnyClient::GitHub(client) => client.pr_reviews(owner, repo, pr_number).await,
+            AnyClient::Gitea(client) => client.pr_reviews(owner, repo, pr_number).await,
+        }
+    }
+
+    async fn pr_description(&self, owner: &str, repo: &str, pr_number: u64) -> anyhow::Result<PullRequestDescription> {
+        match self {
+            AnyClient::GitHub(client) => client.pr_description(owner, repo, pr_number).await,
+            AnyClient::Gitea(client) => client.pr_description(owner, repo, pr_number).await,
+        }
+    }
+
+    async fn pr_commits(&self, owner: &str, repo: &str, pr_number: u64) -> anyhow::Result<Vec<String>> {
+        match self {
+            AnyClient::GitHub(client) => client.pr_commits(owner, repo, pr_number).await,
+            AnyClient::Gitea(client) => client.pr_commits(owner, repo, pr_number).await,
+        }
+    }
+
+    async fn list_open_prs(&self, owner: &str, repo: &str) -> anyhow::Result<Vec<OpenPullRequest>> {
+        match self {
+            AnyClient::GitHub(client) => client.list_open_prs(owner, repo).await,
+            AnyClient::Gitea(client) => client.list_open_prs(owner, repo).await,
+        }
+    }
+
+    async fn pr_head_and_reviews(
+        &self,
+        owner: &str,
+        repo: &str,
+        pr_number: u64,
+    ) -> anyhow::Result<(PullRequestHead, Vec<Review>)> {
+        match self {
+            AnyClient::GitHub(client) => client.pr_head_and_reviews(owner, repo, pr_number).await,
+            AnyClient::Gitea(client) => client.pr_head_and_reviews(owner, repo, pr_number).await,
+        }
+    }
+
+    async fn team_members(&self, org: &str, team: &str) -> anyhow::Result<Vec<String>> {
+        match self {
+            AnyClient::GitHub(client) => client.team_members(org, team).await,
+            AnyClient::Gitea(client) => client.team_members(org, team).await,
         }
+    }
 
-        Ok(commits)
+    async fn default_branch(&self, owner: &str, repo: &str) -> anyhow::Result<String> {
+        match self {
+            AnyClient::GitHub(client) => client.default_branch(owner, repo).await,
+            AnyClient::Gitea(client) => client.default_branch(owner, repo).await,
+        }
     }
 
-    async fn pr_head_hash(&self, owner: &str, repo: &str, pr_number: u64) -> Result<String, anyhow::Error> {
-        Ok(self
-            .pr_commits(owner, repo, pr_number)
-            .await
-            .context("failed to get pr commits")?
-            .last()
-            .ok_or_else(|| anyhow!("PR contains no commits?"))?
-            .sha
-            .clone())
+    async fn previous_tags(&self, owner: &str, repo: &str, tag: &str) -> anyhow::Result<Vec<String>> {
+        match self {
+            AnyClient::GitHub(client) => client.previous_tags(owner, repo, tag).await,
+            AnyClient::Gitea(client) => client.previous_tags(owner, repo, tag).await,
+        }
     }
 
-    async fn pr_commits(&self, owner: &str, repo: &str, pr_number: u64) -> anyhow::Result<Vec<RepoCommit>> {
-        let _permit = self.semaphore.acquire().await?;
+    async fn branch_protection(
+        &self,
+        owner: &str,
+        repo: &str,
+        branch: &str,
+    ) -> anyhow::Result<Option<BranchProtection>> {
+        match self {
+            AnyClient::GitHub(client) => client.branch_protection(owner, repo, branch).await,
+            AnyClient::Gitea(client) => client.branch_protection(owner, repo, branch).await,
+        }
+    }
 
-        let mut pr_commits_page = self
-            .octocrab
-            .pulls(owner, repo)
-            .pr_commits(pr_number)
-            .page(250u32)
-            .send()
-            .await
-            .context("failed to get pr commits")?;
-        assert!(
-            pr_commits_page.next.is_none(),
-            "found more than one page for associated_prs"
-        );
+    async fn codeowners(&self, owner: &str, repo: &str, branch: &str) -> anyhow::Result<Option<String>> {
+        match self {
+            AnyClient::GitHub(client) => client.codeowners(owner, repo, branch).await,
+            AnyClient::Gitea(client) => client.codeowners(owner, repo, branch).await,
+        }
+    }
 
-        let pr_commits = pr_commits_page.take_items();
-        assert!(
-            pr_commits.len() <= 250,
-            "found more than 250 commits which requires a different api endpoint per doc"
-        );
+    async fn rate_limit(&self) -> anyhow::Result<RateLimit> {
+        match self {
+            AnyClient::GitHub(client) => client.rate_limit().await,
+            AnyClient::Gitea(client) => client.rate_limit().await,
+        }
+    }
 
-        Ok(pr_commits)
+    async fn pause(&self, duration: Duration) -> anyhow::Result<()> {
+        match self {
+            AnyClient::GitHub(client) => client.pause(duration).await,
+            AnyClient::Gitea(client) => client.pause(duration).await,
+        }
     }
+}
 
-    async fn pr_reviews(&self, owner: &str, repo: &str, pr_number: u64) -> anyhow::Result<Vec<Review>> {
-        let _permit = self.semaphore.acquire().await?;
+// the locations in which GitHub looks for a CODEOWNERS file, in the order it looks
+const GITHUB_CODEOWNERS_PATHS: [&str; 3] = [".github/CODEOWNERS", "CODEOWNERS", "docs/CODEOWNERS"];
 
-        let mut pr_reviews_page = self
-            .octocrab
-            .pulls(owner, repo)
-            .list_reviews(pr_number)
-            .send()
-            .await
-            .context("failed to get reviews")?;
-        assert!(
-            pr_reviews_page.next.is_none(),
-            "found more than one page for associated_prs"
-        );
-        let pr_reviews = pr_reviews_page.take_items();
+#[derive(Debug, Deserialize)]
+struct GitHubBranchProtection {
+    required_pull_request_reviews: Option<GitHubRequiredReviews>,
+}
 
-        let mut reviews = Vec::new();
-        for pr_review in &pr_reviews {
-            reviews.push(Review {
-                approved: pr_review.state == Some(ReviewState::Approved),
-                commit_id: pr_review.commit_id.clone().ok_or(anyhow!("review has no commit_id"))?,
-                submitted_at: pr_review
-                    .submitted_at
-                    .ok_or_else(|| anyhow!("review has no submitted_at"))?
-                    .timestamp_micros(),
-                user: pr_review.user.clone().ok_or(anyhow!("review has no user"))?.login,
-            });
-        }
+#[derive(Debug, Deserialize)]
+struct GitHubRequiredReviews {
+    #[serde(default)]
+    required_approving_review_count: usize,
+}
 
-        reviews.sort_by_key(|r| r.submitted_at);
-        Ok(reviews)
+#[derive(Debug, PartialEq)]
+pub enum RateLimitStatus {
+    Ok,
+    NearlyExhausted { remaining: u64, reset_in: Duration },
+}
+
+pub async fn check_rate_limit<C: Client>(client: &C) -> anyhow::Result<RateLimitStatus> {
+    let rate_limit = client.rate_limit().await?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    Ok(rate_limit_status(&rate_limit, now))
+}
+
+fn rate_limit_status(rate_limit: &RateLimit, now: u64) -> RateLimitStatus {
+    if rate_limit.remaining >= RATE_LIMIT_WARN_THRESHOLD {
+        return RateLimitStatus::Ok;
+    }
+
+    RateLimitStatus::NearlyExhausted {
+        remaining: rate_limit.remaining,
+        reset_in: Duration::from_secs(rate_limit.reset.saturating_sub(now)),
     }
 }
 
+/// Serves canned responses, for tests only.
+#[cfg(any(test, feature = "test-util"))]
 #[derive(Debug)]
 pub struct MockClient {
     pub associated_prs: Mutex<HashMap<String, Vec<PullRequest>>>,
-    pub pr_commits: Mutex<HashMap<u64, Vec<RepoCommit>>>,
-    pub pr_head_hash: Mutex<HashMap<u64, String>>,
+    pub commits: Mutex<HashMap<String, Commit>>,
+    pub commit_files: Mutex<HashMap<String, Vec<String>>>,
+    pub commit_patches: Mutex<HashMap<String, Vec<FilePatch>>>,
+    pub compares: Mutex<HashMap<(String, String), Vec<Commit>>>,
+    /// status of the compares above, None when missing like on Gitea
+    pub compare_statuses: Mutex<HashMap<(String, String), CompareStatus>>,
+    /// all commits of a branch for `commits_in_range`, oldest first
+    pub branch_commits: Mutex<HashMap<String, Vec<Commit>>>,
+    pub merge_bases: Mutex<HashMap<(String, String), String>>,
+    pub refs: Mutex<HashMap<String, String>>,
+    pub pr_head: Mutex<HashMap<u64, PullRequestHead>>,
+    pub pr_descriptions: Mutex<HashMap<u64, PullRequestDescription>>,
+    pub pr_commits: Mutex<HashMap<u64, Vec<String>>>,
     pub pr_reviews: Mutex<HashMap<u64, Vec<Review>>>,
+    pub open_prs: Mutex<Vec<OpenPullRequest>>,
+    pub team_members: Mutex<HashMap<String, Vec<String>>>,
+    pub default_branch: Mutex<Option<String>>,
+    pub releases: Mutex<Vec<Release>>,
+    pub branch_protections: Mutex<HashMap<String, BranchProtection>>,
+    pub codeowners: Mutex<HashMap<String, String>>,
+    pub rate_limit: Mutex<Option<RateLimit>>,
+    /// from the hosts in `ClientConfig::gitea_hosts`, like for real clients
+    pub forge: Forge,
 }
 
+#[cfg(any(test, feature = "test-util"))]
 impl Client for MockClient {
-    fn new(_env_name: String, _api_endpoint: String) -> anyhow::Result<Arc<Self>> {
+    fn new(
+        forge: Forge,
+        _token: TokenSource,
+        _api_endpoint: String,
+        _config: &ClientConfig,
+    ) -> anyhow::Result<Arc<Self>> {
         Ok(Arc::new(Self {
             associated_prs: Mutex::new(HashMap::new()),
+            commits: Mutex::new(HashMap::new()),
+            commit_files: Mutex::new(HashMap::new()),
+            commit_patches: Mutex::new(HashMap::new()),
+            compares: Mutex::new(HashMap::new()),
+            compare_statuses: Mutex::new(HashMap::new()),
+            branch_commits: Mutex::new(HashMap::new()),
+            merge_bases: Mutex::new(HashMap::new()),
+            refs: Mutex::new(HashMap::new()),
+            pr_head: Mutex::new(HashMap::new()),
+            pr_descriptions: Mutex::new(HashMap::new()),
             pr_commits: Mutex::new(HashMap::new()),
-            pr_head_hash: Mutex::new(HashMap::new()),
             pr_reviews: Mutex::new(HashMap::new()),
+            open_prs: Mutex::new(Vec::new()),
+            team_members: Mutex::new(HashMap::new()),
+            default_branch: Mutex::new(None),
+            releases: Mutex::new(Vec::new()),
+            branch_protections: Mutex::new(HashMap::new()),
+            codeowners: Mutex::new(HashMap::new()),
+            rate_limit: Mutex::new(None),
+            forge,
         }))
     }
 
+    fn forge(&self) -> Forge {
+        self.forge
+    }
+
     async fn associated_prs(&self, _owner: &str, _repo: &str, sha: String) -> anyhow::Result<Vec<PullRequest>> {
         Ok(self
             .associated_prs
@@ -252,37 +1336,154 @@ impl Client for MockClient {
             .clone())
     }
 
+    async fn associated_prs_batch(
+        &self,
+        _owner: &str,
+        _repo: &str,
+        _shas: &[String],
+    ) -> anyhow::Result<HashMap<String, Vec<PullRequest>>> {
+        Ok(HashMap::new())
+    }
+
     async fn compare(
         &self,
         _owner: &str,
         _repo: &str,
         _original: &str,
-        _base_commit: &str,
-        _head_commit: &str,
+        base_commit: &str,
+        head_commit: &str,
+    ) -> anyhow::Result<Comparison> {
+        let key = (base_commit.to_owned(), head_commit.to_owned());
+        let commits = self
+            .compares
+            .lock()
+            .unwrap()
+            .get(&key)
+            .ok_or_else(|| anyhow!("MockClient compares contains no {base_commit}...{head_commit}"))?
+            .clone();
+        let status = self.compare_statuses.lock().unwrap().get(&key).copied();
+        Ok(Comparison { status, commits })
+    }
+
+    async fn merge_base(
+        &self,
+        _owner: &str,
+        _repo: &str,
+        base_commit: &str,
+        head_commit: &str,
+    ) -> anyhow::Result<String> {
+        Ok(self
+            .merge_bases
+            .lock()
+            .unwrap()
+            .get(&(base_commit.to_owned(), head_commit.to_owned()))
+            .ok_or_else(|| anyhow!("MockClient merge_bases contains no {base_commit}...{head_commit}"))?
+            .clone())
+    }
+
+    async fn commit(&self, _owner: &str, _repo: &str, sha: &str) -> anyhow::Result<Commit> {
+        Ok(self
+            .commits
+            .lock()
+            .unwrap()
+            .get(sha)
+            .ok_or_else(|| PearError::NotFound(format!("MockClient commits contains no {sha}")))?
+            .clone())
+    }
+
+    async fn commit_files(&self, _owner: &str, _repo: &str, sha: &str) -> anyhow::Result<Vec<String>> {
+        Ok(self
+            .commit_files
+            .lock()
+            .unwrap()
+            .get(sha)
+            .ok_or_else(|| anyhow!("MockClient commit_files contains no {sha}"))?
+            .clone())
+    }
+
+    async fn commit_patches(&self, _owner: &str, _repo: &str, sha: &str) -> anyhow::Result<Vec<FilePatch>> {
+        Ok(self
+            .commit_patches
+            .lock()
+            .unwrap()
+            .get(sha)
+            .ok_or_else(|| anyhow!("MockClient commit_patches contains no {sha}"))?
+            .clone())
+    }
+
+    // returns the commits of head which were authored within the range, the mock has no separate commit date
+    async fn commits_in_range(
+        &self,
+        _owner: &str,
+        _repo: &str,
+        head: &str,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
     ) -> anyhow::Result<Vec<Commit>> {
-        todo!()
+        Ok(self
+            .branch_commits
+            .lock()
+            .unwrap()
+            .get(head)
+            .ok_or_else(|| PearError::NotFound(format!("MockClient branch_commits contains no {head}")))?
+            .iter()
+            .filter(|commit| {
+                commit
+                    .authored_at
+                    .is_some_and(|authored_at| since <= authored_at && authored_at <= until)
+            })
+            .cloned()
+            .collect())
     }
 
-    async fn pr_head_hash(&self, _owner: &str, _repo: &str, pr_number: u64) -> anyhow::Result<String> {
+    async fn resolve_ref(&self, _owner: &str, _repo: &str, reference: &str) -> anyhow::Result<String> {
         Ok(self
-            .pr_head_hash
+            .refs
+            .lock()
+            .unwrap()
+            .get(reference)
+            .ok_or_else(|| PearError::NotFound(format!("MockClient refs contains no {reference}")))?
+            .clone())
+    }
+
+    async fn pr_head(&self, _owner: &str, _repo: &str, pr_number: u64) -> anyhow::Result<PullRequestHead> {
+        Ok(self
+            .pr_head
             .lock()
             .unwrap()
             .get(&pr_number)
-            .ok_or_else(|| anyhow!("MockClient pr_head_hash contains no {}", pr_number))?
-            .to_string())
+            .ok_or_else(|| anyhow!("MockClient pr_head contains no {}", pr_number))?
+            .clone())
     }
 
-    async fn pr_commits(&self, _owner: &str, _repo: &str, pr_number: u64) -> anyhow::Result<Vec<RepoCommit>> {
+    async fn pr_description(
+        &self,
+        _owner: &str,
+        _repo: &str,
+        pr_number: u64,
+    ) -> anyhow::Result<PullRequestDescription> {
         Ok(self
-            .pr_commits
+            .pr_descriptions
             .lock()
             .unwrap()
             .get(&pr_number)
-            .ok_or_else(|| anyhow!("MockClient pr_commits contains no {}", pr_number))?
+            .ok_or_else(|| anyhow!("MockClient pr_descriptions contains no {pr_number}"))?
             .clone())
     }
 
+    // PRs without listed commits consist of their head commit only
+    async fn pr_commits(&self, owner: &str, repo: &str, pr_number: u64) -> anyhow::Result<Vec<String>> {
+        let pr_commits = self.pr_commits.lock().unwrap().get(&pr_number).cloned();
+        match pr_commits {
+            Some(pr_commits) => Ok(pr_commits),
+            None => Ok(vec![self.pr_head(owner, repo, pr_number).await?.sha]),
+        }
+    }
+
+    async fn list_open_prs(&self, _owner: &str, _repo: &str) -> anyhow::Result<Vec<OpenPullRequest>> {
+        Ok(self.open_prs.lock().unwrap().clone())
+    }
+
     async fn pr_reviews(&self, _owner: &str, _repo: &str, pr_number: u64) -> anyhow::Result<Vec<Review>> {
         Ok(self
             .pr_reviews
@@ -292,22 +1493,71 @@ impl Client for MockClient {
             .ok_or_else(|| anyhow!("MockClient pr_reviews contains no {}", pr_number))?
             .clone())
     }
+
+    async fn team_members(&self, org: &str, team: &str) -> anyhow::Result<Vec<String>> {
+        Ok(self
+            .team_members
+            .lock()
+            .unwrap()
+            .get(&format!("{org}/{team}"))
+            .ok_or_else(|| anyhow!("MockClient team_members contains no {org}/{team}"))?
+            .clone())
+    }
+
+    async fn default_branch(&self, _owner: &str, _repo: &str) -> anyhow::Result<String> {
+        self.default_branch
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| anyhow!("MockClient default_branch contains nothing"))
+    }
+
+    async fn previous_tags(&self, _owner: &str, _repo: &str, tag: &str) -> anyhow::Result<Vec<String>> {
+        github::previous_tags(self.releases.lock().unwrap().clone(), tag)
+    }
+
+    async fn branch_protection(
+        &self,
+        _owner: &str,
+        _repo: &str,
+        branch: &str,
+    ) -> anyhow::Result<Option<BranchProtection>> {
+        Ok(self.branch_protections.lock().unwrap().get(branch).cloned())
+    }
+
+    async fn codeowners(&self, _owner: &str, _repo: &str, branch: &str) -> anyhow::Result<Option<String>> {
+        Ok(self.codeowners.lock().unwrap().get(branch).cloned())
+    }
+
+    async fn rate_limit(&self) -> anyhow::Result<RateLimit> {
+        self.rate_limit
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| anyhow!("MockClient rate_limit contains nothing"))
+    }
 }
 
+/// Keeps one client per host, so that all repos on a host share its connection pool, TLS setup and semaphore.
 pub struct ClientSet<C: Client> {
     clients: HashMap<String, Arc<C>>,
+    config: ClientConfig,
 }
 
 impl<C: Client> ClientSet<C> {
-    pub fn new() -> Self {
+    pub fn new(config: ClientConfig) -> Self {
         Self {
             clients: HashMap::new(),
+            config,
         }
     }
 
+    pub fn clients(&self) -> impl Iterator<Item = (&String, &Arc<C>)> {
+        self.clients.iter()
+    }
+
     pub fn fill(&mut self, remote: &mut Remote<C>) -> Result<(), anyhow::Error> {
-        let host = remote.host.to_string();
-        let client = self.get_client(&host)?;
+        let client = self.get_client(&remote.authority())?;
         remote.client = Some(client);
         Ok(())
     }
@@ -317,41 +1567,630 @@ impl<C: Client> ClientSet<C> {
             return Ok(client.clone());
         }
 
-        let (env_name, api_endpoint) = get_env_name_api_endpoint_for_host(host);
-        let client = C::new(env_name, api_endpoint)?;
+        let forge = if self.config.gitea_hosts.iter().any(|gitea_host| gitea_host == host) {
+            Forge::Gitea
+        } else {
+            Forge::GitHub
+        };
+        let api_path = self
+            .config
+            .api_paths
+            .get(host)
+            .cloned()
+            .or_else(|| env::var(host_env_name(forge, host, "API_PATH")).ok());
+        let (env_name, api_endpoint) = match forge {
+            Forge::GitHub => get_env_name_api_endpoint_for_host(host, api_path.as_deref()),
+            Forge::Gitea => gitea_env_name_api_endpoint_for_host(host, api_path.as_deref()),
+        };
+        let token = self.config.tokens.source(host, env_name);
+        let client = match self.config.github_api_versions.get(host) {
+            Some(api_version) => {
+                let config = ClientConfig {
+                    github_api_version: api_version.clone(),
+                    ..self.config.clone()
+                };
+                C::new(forge, token, api_endpoint, &config)?
+            },
+            None => C::new(forge, token, api_endpoint, &self.config)?,
+        };
         self.clients.insert(host.to_owned(), client.clone());
 
         Ok(client)
     }
 }
 
-fn get_env_name_api_endpoint_for_host(host: &str) -> (String, String) {
+// An explicitly configured API path takes precedence over the API endpoint set by GitHub Actions.
+fn get_env_name_api_endpoint_for_host(host: &str, api_path: Option<&str>) -> (String, String) {
     let mut env_name = "GITHUB_TOKEN".to_string();
     let mut api_endpoint = "https://api.github.com".to_string();
 
     if host != "github.com" {
+        env_name = host_env_name(Forge::GitHub, host, "TOKEN");
+        if let Some(api_path) = api_path {
+            let api_path = api_path.trim_matches('/');
+            if api_path.is_empty() {
+                return (env_name, format!("https://{host}"));
+            }
+            return (env_name, format!("https://{host}/{api_path}"));
+        }
         api_endpoint = format!("https://{host}/api/v3");
-        env_name = format!(
-            "GITHUB_{}_TOKEN",
-            host.replace('.', "_").to_uppercase().trim_start_matches("GITHUB_")
-        );
     };
 
+    if let Some(actions_api_endpoint) = api_endpoint_from_actions(
+        host,
+        env::var("GITHUB_SERVER_URL").ok().as_deref(),
+        env::var("GITHUB_API_URL").ok().as_deref(),
+    ) {
+        api_endpoint = actions_api_endpoint;
+    }
+
     (env_name, api_endpoint)
 }
 
+// Gitea has no well-known API host like api.github.com, so the API is always below the host itself.
+fn gitea_env_name_api_endpoint_for_host(host: &str, api_path: Option<&str>) -> (String, String) {
+    let env_name = host_env_name(Forge::Gitea, host, "TOKEN");
+    let api_path = api_path.unwrap_or("/api/v1").trim_matches('/');
+    if api_path.is_empty() {
+        return (env_name, format!("https://{host}"));
+    }
+    (env_name, format!("https://{host}/{api_path}"))
+}
+
+// e.g. GITHUB_EXAMPLE_COM_TOKEN for github.example.com, GITEA_EXAMPLE_COM_TOKEN for gitea.example.com and
+// GITHUB_EXAMPLE_COM_8443_TOKEN for github.example.com:8443
+fn host_env_name(forge: Forge, host: &str, suffix: &str) -> String {
+    let prefix = match forge {
+        Forge::GitHub => "GITHUB_",
+        Forge::Gitea => "GITEA_",
+    };
+    format!(
+        "{prefix}{}_{suffix}",
+        host.replace(['.', ':'], "_").to_uppercase().trim_start_matches(prefix)
+    )
+}
+
+// GitHub Actions sets GITHUB_SERVER_URL and GITHUB_API_URL for the instance the workflow runs on,
+// which is more reliable than guessing the API path of Enterprise instances.
+fn api_endpoint_from_actions(host: &str, server_url: Option<&str>, api_url: Option<&str>) -> Option<String> {
+    let server_url = Url::parse(server_url?).ok()?;
+    let server_host = match server_url.port() {
+        Some(port) => format!("{}:{port}", server_url.host_str()?),
+        None => server_url.host_str()?.to_owned(),
+    };
+    if server_host != host {
+        return None;
+    }
+
+    Some(api_url?.trim_end_matches('/').to_string())
+}
+
 #[cfg(test)]
 mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    use tokio::task::JoinSet;
+
+    use super::*;
     use crate::api_clients;
+    use crate::github::ReviewState;
+    use crate::http_client::build_octocrab;
+
+    #[tokio::test]
+    async fn compare_cache() {
+        let cache = CompareCache::default();
+        let calls = AtomicUsize::new(0);
+        let key = |base: &str| {
+            (
+                "sapcc".to_owned(),
+                "keppel".to_owned(),
+                base.to_owned(),
+                "v2".to_owned(),
+            )
+        };
+        let fetch = || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Ok(Comparison {
+                status: Some(CompareStatus::Ahead),
+                commits: vec![Commit::test("1111111")],
+            })
+        };
+
+        let (first, second) = tokio::join!(
+            cache.get_or_fetch(key("v1"), fetch),
+            cache.get_or_fetch(key("v1"), fetch)
+        );
+        assert_eq!(first.unwrap().commits[0].sha, "1111111");
+        assert_eq!(second.unwrap().commits[0].sha, "1111111");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        cache.get_or_fetch(key("v0"), fetch).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn compare_cache_error() {
+        let cache = CompareCache::default();
+        let key = (
+            "sapcc".to_owned(),
+            "keppel".to_owned(),
+            "v1".to_owned(),
+            "v2".to_owned(),
+        );
+
+        let result = cache
+            .get_or_fetch(key.clone(), || async { Err(anyhow!("connection reset")) })
+            .await;
+        assert!(result.is_err());
+
+        // failed requests are fetched again
+        let result = cache.get_or_fetch(key, || async { Ok(Comparison::default()) }).await;
+        assert!(result.unwrap().commits.is_empty());
+    }
+
+    #[tokio::test]
+    async fn team_members_cache() {
+        let cache = Arc::new(TeamMembersCache::default());
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        // like the repos of a helm chart requiring the same team, which are analyzed in parallel
+        let mut join_set = JoinSet::new();
+        for team in ["keppel-admins", "keppel-admins", "limes-admins", "keppel-admins"] {
+            let cache = cache.clone();
+            let calls = calls.clone();
+            join_set.spawn(async move {
+                let key = ("sapcc".to_owned(), team.to_owned());
+                cache
+                    .get_or_fetch(key, || async {
+                        calls.fetch_add(1, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(5)).await;
+                        Ok(vec![format!("{team}-member")])
+                    })
+                    .await
+            });
+        }
+        while let Some(members) = join_set.join_next().await {
+            assert_eq!(members.unwrap().unwrap().len(), 1);
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn get_client_reuses_client_per_host() {
+        let mut client_set = ClientSet::<MockClient>::new(ClientConfig::default());
+        let client = client_set.get_client("github.com").unwrap();
+        assert!(Arc::ptr_eq(&client, &client_set.get_client("github.com").unwrap()));
+        assert!(!Arc::ptr_eq(
+            &client,
+            &client_set.get_client("github.example.com").unwrap()
+        ));
+        assert_eq!(client_set.clients().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn check_rate_limit_nearly_exhausted() {
+        let client = MockClient::new(
+            Forge::GitHub,
+            TokenSource::Env(String::new()),
+            String::new(),
+            &ClientConfig::default(),
+        )
+        .unwrap();
+        let reset = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() + 600;
+
+        *client.rate_limit.lock().unwrap() = Some(RateLimit {
+            limit: 5000,
+            remaining: 4000,
+            reset,
+        });
+        assert_eq!(check_rate_limit(client.as_ref()).await.unwrap(), RateLimitStatus::Ok);
+
+        *client.rate_limit.lock().unwrap() = Some(RateLimit {
+            limit: 5000,
+            remaining: 42,
+            reset,
+        });
+        match check_rate_limit(client.as_ref()).await.unwrap() {
+            RateLimitStatus::NearlyExhausted { remaining, reset_in } => {
+                assert_eq!(remaining, 42);
+                assert!((590..=600).contains(&reset_in.as_secs()));
+            },
+            RateLimitStatus::Ok => panic!("expected the rate limit to be nearly exhausted"),
+        }
+    }
+
+    #[test]
+    fn rate_limit_status_reset_passed() {
+        let rate_limit = RateLimit {
+            limit: 5000,
+            remaining: 0,
+            reset: 1000,
+        };
+        assert_eq!(rate_limit_status(&rate_limit, 2000), RateLimitStatus::NearlyExhausted {
+            remaining: 0,
+            reset_in: Duration::ZERO,
+        });
+    }
 
     #[test]
     fn get_env_name_api_endpoint_for_host() {
-        let (env_name, api_endpoint) = api_clients::get_env_name_api_endpoint_for_host("github.com");
+        let (env_name, api_endpoint) = api_clients::get_env_name_api_endpoint_for_host("github.com", None);
         assert_eq!(env_name, "GITHUB_TOKEN");
         assert_eq!(api_endpoint, "https://api.github.com");
 
-        let (env_name, api_endpoint) = api_clients::get_env_name_api_endpoint_for_host("github.example.com");
+        let (env_name, api_endpoint) = api_clients::get_env_name_api_endpoint_for_host("github.example.com", None);
         assert_eq!(env_name, "GITHUB_EXAMPLE_COM_TOKEN");
         assert_eq!(api_endpoint, "https://github.example.com/api/v3");
+
+        let mut remote = Remote::<MockClient>::parse("https://github.example.com:8443/sapcc/keppel.git").unwrap();
+        let (env_name, api_endpoint) = api_clients::get_env_name_api_endpoint_for_host(&remote.authority(), None);
+        assert_eq!(env_name, "GITHUB_EXAMPLE_COM_8443_TOKEN");
+        assert_eq!(api_endpoint, "https://github.example.com:8443/api/v3");
+
+        // clients are picked by host and explicit port
+        let mut client_set = ClientSet::<MockClient>::new(ClientConfig::default());
+        client_set.fill(&mut remote).unwrap();
+        assert_eq!(
+            client_set.clients().map(|(host, _)| host.as_str()).collect::<Vec<_>>(),
+            vec!["github.example.com:8443"]
+        );
+
+        // http and ssh remotes of github.com share the client of https ones
+        for url in [
+            "https://github.com/sapcc/keppel.git",
+            "http://github.com/sapcc/keppel.git",
+            "ssh://git@github.com/sapcc/keppel.git",
+            "ssh://git@github.com:22/sapcc/keppel.git",
+        ] {
+            let mut remote = Remote::<MockClient>::parse(url).unwrap();
+            let (env_name, api_endpoint) = api_clients::get_env_name_api_endpoint_for_host(&remote.authority(), None);
+            assert_eq!(env_name, "GITHUB_TOKEN", "{url}");
+            assert_eq!(api_endpoint, "https://api.github.com", "{url}");
+            client_set.fill(&mut remote).unwrap();
+        }
+        let mut hosts: Vec<&str> = client_set.clients().map(|(host, _)| host.as_str()).collect();
+        hosts.sort_unstable();
+        assert_eq!(hosts, vec!["github.com", "github.example.com:8443"]);
+    }
+
+    #[test]
+    fn get_env_name_api_endpoint_for_host_api_path() {
+        let (env_name, api_endpoint) =
+            api_clients::get_env_name_api_endpoint_for_host("github.example.com", Some("/github/api/"));
+        assert_eq!(env_name, "GITHUB_EXAMPLE_COM_TOKEN");
+        assert_eq!(api_endpoint, "https://github.example.com/github/api");
+
+        // API served at the root
+        let (_, api_endpoint) = api_clients::get_env_name_api_endpoint_for_host("github.example.com", Some("/"));
+        assert_eq!(api_endpoint, "https://github.example.com");
+
+        // github.com has a fixed API endpoint
+        let (_, api_endpoint) = api_clients::get_env_name_api_endpoint_for_host("github.com", Some("/api/v3"));
+        assert_eq!(api_endpoint, "https://api.github.com");
+
+        assert_eq!(
+            api_clients::host_env_name(Forge::GitHub, "github.example.com", "API_PATH"),
+            "GITHUB_EXAMPLE_COM_API_PATH"
+        );
+    }
+
+    #[test]
+    fn gitea_env_name_api_endpoint_for_host() {
+        let (env_name, api_endpoint) = api_clients::gitea_env_name_api_endpoint_for_host("gitea.example.com", None);
+        assert_eq!(env_name, "GITEA_EXAMPLE_COM_TOKEN");
+        assert_eq!(api_endpoint, "https://gitea.example.com/api/v1");
+
+        let (env_name, api_endpoint) =
+            api_clients::gitea_env_name_api_endpoint_for_host("git.example.com", Some("/gitea/api/v1"));
+        assert_eq!(env_name, "GITEA_GIT_EXAMPLE_COM_TOKEN");
+        assert_eq!(api_endpoint, "https://git.example.com/gitea/api/v1");
+    }
+
+    #[test]
+    fn tokens_precedence() {
+        let token = |tokens: &Tokens, host: &str| match tokens.source(host, "GITHUB_TOKEN".to_owned()) {
+            TokenSource::Env(env_name) => format!("env {env_name}"),
+            TokenSource::Value(token) => token,
+        };
+
+        let mut tokens = Tokens::default();
+        assert_eq!(token(&tokens, "github.com"), "env GITHUB_TOKEN");
+
+        tokens.default = Some("default-token".to_owned());
+        assert_eq!(token(&tokens, "github.com"), "default-token");
+
+        tokens
+            .hosts
+            .insert("github.example.com".to_owned(), "host-token".to_owned());
+        assert_eq!(token(&tokens, "github.example.com"), "host-token");
+        assert_eq!(token(&tokens, "github.com"), "default-token");
+
+        // the tokens must not leak through debug output
+        let debug = format!("{tokens:?}");
+        assert!(
+            !debug.contains("default-token") && !debug.contains("host-token"),
+            "{debug}"
+        );
+        assert!(debug.contains("github.example.com"), "{debug}");
+    }
+
+    #[test]
+    fn api_endpoint_from_actions() {
+        assert_eq!(
+            api_clients::api_endpoint_from_actions(
+                "github.example.com",
+                Some("https://github.example.com"),
+                Some("https://github.example.com/api/v4/"),
+            ),
+            Some("https://github.example.com/api/v4".to_owned())
+        );
+        // the variables describe a different instance
+        assert_eq!(
+            api_clients::api_endpoint_from_actions(
+                "github.example.com",
+                Some("https://github.com"),
+                Some("https://api.github.com"),
+            ),
+            None
+        );
+        assert_eq!(
+            api_clients::api_endpoint_from_actions(
+                "github.example.com:8443",
+                Some("https://github.example.com:8443"),
+                Some("https://github.example.com:8443/api/v3"),
+            ),
+            Some("https://github.example.com:8443/api/v3".to_owned())
+        );
+        // not running in GitHub Actions
+        assert_eq!(
+            api_clients::api_endpoint_from_actions("github.example.com", None, None),
+            None
+        );
+        assert_eq!(
+            api_clients::api_endpoint_from_actions("github.example.com", Some("https://github.example.com"), None),
+            None
+        );
+    }
+
+    type Recording = (&'static str, u32, &'static str);
+
+    // Serves recorded GitHub responses by path and page. When the next page is recorded too, the Link header points
+    // to it like GitHub does. Other query parameters are ignored, requests without a recording get a 404.
+    fn serve_recordings(recordings: &'static [Recording]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let mut reader = BufReader::new(stream);
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut header = String::new();
+                let mut content_length = 0;
+                while reader.read_line(&mut header).unwrap() > 2 {
+                    if let Some(length) = header.to_lowercase().strip_prefix("content-length:") {
+                        content_length = length.trim().parse().unwrap();
+                    }
+                    header.clear();
+                }
+                // GraphQL queries are POSTed, their body is not needed to pick the recording
+                reader.read_exact(&mut vec![0; content_length]).unwrap();
+
+                let uri = request_line.split_whitespace().nth(1).unwrap_or_default();
+                let (path, query) = uri.split_once('?').unwrap_or((uri, ""));
+                let page = query
+                    .split('&')
+                    .find_map(|param| param.strip_prefix("page="))
+                    .map_or(1, |page| page.parse().unwrap());
+                let recording = |page: u32| {
+                    recordings
+                        .iter()
+                        .find(|recording| recording.0 == path && recording.1 == page)
+                };
+
+                let (status, body, link) = match recording(page) {
+                    Some((.., body)) => (
+                        "200 OK",
+                        (*body).to_owned(),
+                        recording(page + 1)
+                            .map(|_| format!("Link: <http://{addr}{path}?page={}>; rel=\"next\"\r\n", page + 1)),
+                    ),
+                    None => (
+                        "404 Not Found",
+                        format!(r#"{{"message":"no recording for {uri}"}}"#),
+                        None,
+                    ),
+                };
+                let _ = write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n{}Connection: \
+                     close\r\n\r\n{body}",
+                    body.len(),
+                    link.unwrap_or_default(),
+                );
+            }
+        });
+        format!("http://{addr}")
+    }
+
+    fn recorded_client(recordings: &'static [Recording]) -> RealClient {
+        RealClient {
+            semaphore: Semaphore::new(MAX_PARALLEL_REQUESTS as usize),
+            octocrab: Arc::new(
+                build_octocrab("token", &serve_recordings(recordings), &ClientConfig::default()).unwrap(),
+            ),
+            compare_cache: CompareCache::default(),
+            team_members_cache: TeamMembersCache::default(),
+            codeowners_cache: CodeownersCache::default(),
+            use_graphql: false,
+            max_pages: ClientConfig::default().max_pages,
+        }
+    }
+
+    #[tokio::test]
+    async fn real_client_compare() {
+        let client = recorded_client(&[
+            (
+                "/repos/sapcc/keppel/compare/v1.0.0...v1.1.0",
+                1,
+                include_str!("testdata/github/compare_page1.json"),
+            ),
+            (
+                "/repos/sapcc/keppel/compare/v1.0.0...v1.1.0",
+                2,
+                include_str!("testdata/github/compare_page2.json"),
+            ),
+        ]);
+
+        let comparison = client
+            .compare(
+                "sapcc",
+                "keppel",
+                "https://github.com/sapcc/keppel.git",
+                "v1.0.0",
+                "v1.1.0",
+            )
+            .await
+            .unwrap();
+        assert_eq!(comparison.status, Some(CompareStatus::Ahead));
+        let commits = comparison.commits;
+        let shas: Vec<&str> = commits.iter().map(|commit| &commit.sha[..7]).collect();
+        assert_eq!(shas, vec!["1111111", "2222222", "3333333"]);
+        assert_eq!(commits[0].message, "Fix manifest validation (#401)");
+        assert_eq!(commits[0].author.as_deref(), Some("user1"));
+        assert_eq!(
+            commits[0].authored_at,
+            DateTime::parse_from_rfc3339("2024-07-01T10:00:00Z")
+                .ok()
+                .map(|date| date.with_timezone(&Utc))
+        );
+        // commits of authors without a GitHub account fall back to the git author
+        assert_eq!(commits[2].author.as_deref(), Some("Someone Else"));
+        assert_eq!(
+            commits[2].html_url,
+            "https://github.com/sapcc/keppel/commit/3333333333333333333333333333333333333333"
+        );
+    }
+
+    #[tokio::test]
+    async fn real_client_associated_prs() {
+        let client = recorded_client(&[
+            (
+                "/repos/sapcc/keppel/commits/1111111111111111111111111111111111111111/pulls",
+                1,
+                include_str!("testdata/github/associated_prs_page1.json"),
+            ),
+            (
+                "/repos/sapcc/keppel/commits/1111111111111111111111111111111111111111/pulls",
+                2,
+                include_str!("testdata/github/associated_prs_page2.json"),
+            ),
+        ]);
+
+        let prs = client.associated_prs("sapcc", "keppel", "1".repeat(40)).await.unwrap();
+        let prs: Vec<(u64, &str)> = prs.iter().map(|pr| (pr.number, pr.url.as_str())).collect();
+        assert_eq!(prs, vec![
+            (401, "https://github.com/sapcc/keppel/pull/401"),
+            (405, "https://github.com/sapcc/keppel/pull/405"),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn real_client_associated_prs_batch() {
+        let recordings = &[
+            (
+                "/repos/sapcc/keppel/commits/1111111111111111111111111111111111111111/pulls",
+                1,
+                include_str!("testdata/github/associated_prs_page1.json"),
+            ),
+            (
+                "/repos/sapcc/keppel/commits/1111111111111111111111111111111111111111/pulls",
+                2,
+                include_str!("testdata/github/associated_prs_page2.json"),
+            ),
+            (
+                "/graphql",
+                1,
+                include_str!("testdata/github/associated_prs_graphql.json"),
+            ),
+        ];
+        let shas = vec!["1".repeat(40), "2".repeat(40), "3".repeat(40)];
+
+        // without --use-graphql every commit is looked up with the REST API
+        let client = recorded_client(recordings);
+        assert!(client
+            .associated_prs_batch("sapcc", "keppel", &shas)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let client = RealClient {
+            use_graphql: true,
+            ..recorded_client(recordings)
+        };
+        let mut batch = client.associated_prs_batch("sapcc", "keppel", &shas).await.unwrap();
+        assert!(batch.remove(&shas[1]).unwrap().is_empty());
+        // the unknown commit is left to the REST API, which reports it properly
+        assert_eq!(batch.keys().collect::<Vec<_>>(), vec![&shas[0]]);
+
+        let rest = client.associated_prs("sapcc", "keppel", shas[0].clone()).await.unwrap();
+        let pr = |pr: &PullRequest| (pr.number, pr.url.clone());
+        assert_eq!(
+            batch[&shas[0]].iter().map(pr).collect::<Vec<_>>(),
+            rest.iter().map(pr).collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn real_client_pr_reviews() {
+        let client = recorded_client(&[
+            (
+                "/repos/sapcc/keppel/pulls/401/reviews",
+                1,
+                include_str!("testdata/github/pr_reviews_page1.json"),
+            ),
+            (
+                "/repos/sapcc/keppel/pulls/401/reviews",
+                2,
+                include_str!("testdata/github/pr_reviews_page2.json"),
+            ),
+        ]);
+
+        let reviews = client.pr_reviews("sapcc", "keppel", 401).await.unwrap();
+        let reviews: Vec<(&str, ReviewState, &str)> = reviews
+            .iter()
+            .map(|review| (review.user.as_str(), review.state, &review.commit_id[..7]))
+            .collect();
+        assert_eq!(reviews, vec![
+            ("user2", ReviewState::ChangesRequested, "ccccccc"),
+            ("user3", ReviewState::Commented, "ccccccc"),
+            ("user2", ReviewState::Approved, "aaaaaaa"),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn real_client_max_pages() {
+        let mut client = recorded_client(&[
+            (
+                "/repos/sapcc/keppel/pulls/401/reviews",
+                1,
+                include_str!("testdata/github/pr_reviews_page1.json"),
+            ),
+            (
+                "/repos/sapcc/keppel/pulls/401/reviews",
+                2,
+                include_str!("testdata/github/pr_reviews_page2.json"),
+            ),
+        ]);
+        client.max_pages = 1;
+
+        let err = client.pr_reviews("sapcc", "keppel", 401).await.unwrap_err();
+        assert!(matches!(PearError::find(&err), Some(PearError::TooManyPages(1))));
+        assert_eq!(
+            format!("{err:#}"),
+            "failed to get reviews: more than 1 pages of results, narrow down the analyzed range or raise --max-pages"
+        );
     }
 }
@@ -0,0 +1,140 @@
+// Copyright 2024 SAP SE
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeSet;
+
+use anyhow::Context;
+
+use crate::api_clients::Client;
+use crate::github::Team;
+use crate::remote::Remote;
+
+/// Users that branch protection or CODEOWNERS of the branch name as reviewers, with teams expanded to their members.
+pub async fn eligible_approvers<C: Client>(remote: &Remote<C>, branch: &str) -> anyhow::Result<BTreeSet<String>> {
+    let mut users = BTreeSet::new();
+    let mut teams = Vec::new();
+
+    if let Some(protection) = remote
+        .branch_protection(branch)
+        .await
+        .with_context(|| format!("while getting protection of branch {branch}"))?
+    {
+        users.extend(protection.users);
+        teams.extend(protection.teams);
+    }
+
+    if let Some(codeowners) = remote.codeowners(branch).await.context("while getting CODEOWNERS")? {
+        let (codeowner_users, codeowner_teams) = parse_codeowners(&codeowners, &remote.owner);
+        users.extend(codeowner_users);
+        teams.extend(codeowner_teams);
+    }
+
+    let mut seen_teams = Vec::new();
+    for team in teams {
+        if seen_teams.contains(&team) {
+            continue;
+        }
+        users.extend(
+            remote
+                .team_members(&team)
+                .await
+                .with_context(|| format!("while getting members of team {team}"))?,
+        );
+        seen_teams.push(team);
+    }
+
+    Ok(users)
+}
+
+// Owners are given as @user or @org/team. Owners given by email are skipped, as they can't be mapped to a login.
+fn parse_codeowners(content: &str, org: &str) -> (Vec<String>, Vec<Team>) {
+    let mut users = Vec::new();
+    let mut teams = Vec::new();
+
+    for line in content.lines() {
+        let line = line.split_once('#').map_or(line, |(line, _)| line);
+        // the first field is the pattern
+        for owner in line.split_whitespace().skip(1) {
+            let Some(owner) = owner.strip_prefix('@') else {
+                continue;
+            };
+            match owner.parse::<Team>() {
+                Ok(team) => teams.push(team),
+                Err(_) if !owner.contains('/') => users.push(owner.to_owned()),
+                Err(_) => {},
+            }
+        }
+    }
+
+    // teams of other organizations can't be reviewers
+    teams.retain(|team| team.org.eq_ignore_ascii_case(org));
+    (users, teams)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_clients::{ClientConfig, ClientSet, MockClient};
+    use crate::github::BranchProtection;
+
+    #[test]
+    fn parse_codeowners() {
+        let content = "# global owners\n\
+                       *       @user1 @sapcc/team1\n\
+                       \n\
+                       /docs/  docs@example.com @user2 # the docs team\n\
+                       /vendor/ @other-org/team2 @sapcc/nested/team\n";
+        let (users, teams) = super::parse_codeowners(content, "sapcc");
+        assert_eq!(users, vec!["user1", "user2"]);
+        assert_eq!(teams, vec![Team {
+            org: "sapcc".to_owned(),
+            slug: "team1".to_owned(),
+        }]);
+    }
+
+    #[tokio::test]
+    async fn eligible_approvers() {
+        let mut api_clients = ClientSet::new(ClientConfig::default());
+        let mut remote = Remote::<MockClient>::parse("https://github.com/sapcc/keppel.git").unwrap();
+        api_clients.fill(&mut remote).unwrap();
+        let client = remote.client.as_ref().unwrap();
+
+        client
+            .branch_protections
+            .lock()
+            .unwrap()
+            .insert("main".to_owned(), BranchProtection {
+                users: vec!["user1".to_owned()],
+                teams: vec!["sapcc/team1".parse().unwrap()],
+                required_approvals: Some(2),
+            });
+        client
+            .codeowners
+            .lock()
+            .unwrap()
+            .insert("main".to_owned(), "* @user2 @sapcc/team1 @sapcc/team2\n".to_owned());
+        client.team_members.lock().unwrap().extend([
+            ("sapcc/team1".to_owned(), vec!["user1".to_owned(), "user3".to_owned()]),
+            ("sapcc/team2".to_owned(), vec!["user4".to_owned()]),
+        ]);
+
+        let approvers = super::eligible_approvers(&remote, "main").await.unwrap();
+        assert_eq!(approvers.into_iter().collect::<Vec<_>>(), vec![
+            "user1", "user2", "user3", "user4"
+        ]);
+
+        // an unprotected branch without CODEOWNERS has no eligible approvers
+        assert!(super::eligible_approvers(&remote, "feature").await.unwrap().is_empty());
+    }
+}
@@ -12,43 +12,351 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::mem;
 use std::sync::Arc;
 
-use anyhow::Context;
+use anyhow::{bail, Context};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use glob::Pattern;
+use serde::{Deserialize, Serialize};
 use tokio::task::JoinSet;
 
 use crate::api_clients::Client;
-use crate::github::{Commit, Review};
+use crate::diff;
+use crate::error::PearError;
+use crate::github::{Commit, CompareStatus, OpenPullRequest, PullRequest, Review, ReviewState, Signature, Team};
 use crate::remote::Remote;
 
+/// Commits of one repo that are analyzed at the same time, which keeps memory flat for huge compare ranges.
+const MAX_COMMITS_IN_FLIGHT: usize = 20;
+
+/// Backports of backports are followed up to this many PRs back.
+const MAX_BACKPORT_DEPTH: usize = 3;
+
+#[derive(Clone, Debug, Default)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct AnalyzeOptions {
+    /// only approvals from members of this team count, others are listed as non-qualifying
+    pub required_team: Option<Team>,
+    /// only commits authored at or after this time are analyzed
+    pub since: Option<DateTime<Utc>>,
+    /// falls back to the PR number in the commit message when GitHub knows no PR for a commit
+    pub parse_pr_from_message: bool,
+    /// compares head with the merge base of base and head instead of base itself
+    pub use_merge_base: bool,
+    /// takes the number of required approvals from the protection of the default branch of each repo
+    pub use_branch_protection: bool,
+    /// analyzes the commits of head within this range instead of comparing base and head
+    pub date_range: Option<DateRange>,
+    /// only commits that touched a path matching one of these globs are analyzed, costs one API call per commit
+    pub paths: Vec<Pattern>,
+    /// also counts the approvals of the original PR when the PR of a change is a backport of it
+    pub follow_backports: bool,
+    /// fails on the first repo that cannot be analyzed instead of reporting it and analyzing the others
+    pub strict: bool,
+    /// analyzes the open PRs of each repo instead of commits, base and head are only validated
+    pub open_prs: bool,
+    /// derives the base from the head instead of taking the given one
+    pub auto_base: Option<AutoBase>,
+    /// leaves out draft PRs, a commit that is only part of drafts is treated like one without a PR
+    pub ignore_drafts: bool,
+    /// marks commits that are reverted within the analyzed range, and their reverts, as self-cancelling
+    pub pair_reverts: bool,
+    /// how the changes of the analyzed commits are merged into rows
+    pub group_by: GroupBy,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum GroupBy {
+    /// one row per PR showing its first commit, later commits only add their approvals to it. Commits without a PR
+    /// share one row the same way.
+    #[default]
+    Commit,
+    /// one row per PR listing all of its commits
+    Pr,
+}
+
+/// How --auto-base finds the base of a repo.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum AutoBase {
+    /// the tag of the newest release before the release of the head tag that the head tag contains
+    PreviousTag,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct DateRange {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+}
+
 #[derive(Debug)]
 pub struct RepoChangeset<C: Client> {
     pub name: String,
     pub remote: Remote<C>,
+    /// empty when there is nothing to compare against
     pub base_commit: String,
     pub head_commit: String,
+    /// the tag or branch that was resolved to the base commit, unless it was given as a commit hash
+    pub base_ref: Option<String>,
+    /// the tag or branch that was resolved to the head commit, unless it was given as a commit hash
+    pub head_ref: Option<String>,
+    /// approvals required by the protection of the default branch or by requiredApprovals of images.yaml, whichever
+    /// is stricter, raises --min-approvals when set
+    pub min_approvals: Option<usize>,
+    /// users who all have to approve every change, from requiredApprovers of images.yaml
+    pub required_approvers: Vec<String>,
+    /// how head relates to base according to the compare, None when nothing was compared
+    pub compare_status: Option<CompareStatus>,
+    /// why the repo could not be analyzed, there are no changes then
+    pub error: Option<String>,
     pub changes: Vec<Changeset>,
 }
 
 impl<C: Client + Sync + Send + 'static> RepoChangeset<C> {
-    pub async fn analyze_commits(mut self) -> anyhow::Result<Self> {
-        let compare_commits = self.remote.compare(&self.base_commit, &self.head_commit).await?;
+    /// The repo without any results, which is reported instead when analyzing it fails.
+    #[must_use]
+    pub fn without_changes(&self) -> Self {
+        Self {
+            name: self.name.clone(),
+            remote: self.remote.clone(),
+            base_commit: self.base_commit.clone(),
+            head_commit: self.head_commit.clone(),
+            base_ref: self.base_ref.clone(),
+            head_ref: self.head_ref.clone(),
+            min_approvals: None,
+            required_approvers: Vec::new(),
+            compare_status: None,
+            error: None,
+            changes: Vec::new(),
+        }
+    }
 
-        let mut join_set = JoinSet::new();
-        let remote = Arc::new(self.remote);
-        for commit in compare_commits {
-            join_set.spawn(Self::analyze_commit(remote.clone(), commit));
+    // When base has moved on since head branched off, comparing with base would include the commits of base
+    // that are not in head. The merge base yields only the commits of head.
+    async fn resolve_merge_base(&mut self) -> anyhow::Result<()> {
+        if self.base_commit.is_empty() {
+            return Ok(());
+        }
+        self.base_commit = self
+            .remote
+            .merge_base(&self.base_commit, &self.head_commit)
+            .await
+            .with_context(|| format!("while resolving the merge base of {}", self.name))?;
+        Ok(())
+    }
+
+    // HEAD of a remote is its default branch. The API is not guaranteed to resolve HEAD like a local git does, so the
+    // default branch is looked up instead.
+    pub async fn resolve_head(&mut self) -> anyhow::Result<()> {
+        if self.head_commit != "HEAD" {
+            return Ok(());
+        }
+        self.head_commit = self
+            .remote
+            .default_branch()
+            .await
+            .with_context(|| format!("while resolving HEAD of {}", self.name))?;
+
+        Ok(())
+    }
+
+    /// Replaces the base with the tag of the release before the one of head, which has to be a tag. Releases of
+    /// other branches, e.g. a patch release of an old version, are skipped, as head does not contain them.
+    pub async fn resolve_previous_tag(&mut self) -> anyhow::Result<()> {
+        if is_commit_hash(&self.head_commit) {
+            bail!(
+                "--auto-base previous-tag needs a tag as head, got commit {} for {}",
+                self.head_commit,
+                self.name
+            );
+        }
+        let context = || format!("while finding the tag before {} of {}", self.head_commit, self.name);
+        let tags = self
+            .remote
+            .previous_tags(&self.head_commit)
+            .await
+            .with_context(context)?;
+        if tags.is_empty() {
+            bail!(
+                "{} is the first release of {}, there is no tag to compare with",
+                self.head_commit,
+                self.name
+            );
+        }
+
+        for tag in tags {
+            let comparison = self
+                .remote
+                .compare(&tag, &self.head_commit)
+                .await
+                .with_context(context)?;
+            // without a status the history is unknown, so the newest release has to do
+            if matches!(
+                comparison.status,
+                None | Some(CompareStatus::Ahead | CompareStatus::Identical)
+            ) {
+                self.base_commit = tag;
+                return Ok(());
+            }
+        }
+        bail!(
+            "no release before {} of {} is contained in it, there is no tag to compare with",
+            self.head_commit,
+            self.name
+        )
+    }
+
+    /// Checks that base and head exist in the remote, so that a typo fails before any analysis happens.
+    pub async fn validate_refs(&self) -> anyhow::Result<()> {
+        let repo = format!("{}/{}", self.remote.owner, self.remote.repository);
+        if !self.base_commit.is_empty() {
+            self.remote
+                .commit(&self.base_commit)
+                .await
+                .map_err(|err| ref_error(err, "base", &self.base_commit, &repo))?;
+        }
+        self.remote
+            .commit(&self.head_commit)
+            .await
+            .map_err(|err| ref_error(err, "head", &self.head_commit, &repo))?;
+
+        Ok(())
+    }
+
+    // Changes end up in the default branch, so its protection decides how many approvals they need.
+    async fn fetch_required_approvals(&mut self) -> anyhow::Result<()> {
+        let branch = self
+            .remote
+            .default_branch()
+            .await
+            .with_context(|| format!("while getting default branch of {}", self.name))?;
+        let protection = self
+            .remote
+            .branch_protection(&branch)
+            .await
+            .with_context(|| format!("while getting protection of branch {branch} of {}", self.name))?;
+        // images.yaml may demand more approvals than the branch protection
+        self.min_approvals = self
+            .min_approvals
+            .max(protection.and_then(|protection| protection.required_approvals));
+
+        Ok(())
+    }
+
+    // images.yaml files sometimes reference a tag instead of a commit, which is replaced by the commit it points to
+    // so that the output always shows a commit hash where one is expected. Branches given with --base-branch are
+    // prefixed with refs/heads/, so that they are resolved even when their name looks like a commit hash.
+    pub async fn resolve_refs(&mut self) -> anyhow::Result<()> {
+        if !self.base_commit.is_empty() && !is_commit_hash(&self.base_commit) {
+            let sha = self
+                .remote
+                .resolve_ref(&self.base_commit)
+                .await
+                .with_context(|| format!("while resolving base ref {:?} of {}", self.base_commit, self.name))?;
+            let reference = std::mem::replace(&mut self.base_commit, sha);
+            self.base_ref = Some(if let Some(branch) = reference.strip_prefix(BRANCH_REF_PREFIX) {
+                branch.to_owned()
+            } else {
+                reference
+            });
+        }
+        if !is_commit_hash(&self.head_commit) {
+            let sha = self
+                .remote
+                .resolve_ref(&self.head_commit)
+                .await
+                .with_context(|| format!("while resolving head ref {:?} of {}", self.head_commit, self.name))?;
+            self.head_ref = Some(std::mem::replace(&mut self.head_commit, sha));
+        }
+
+        Ok(())
+    }
+
+    pub async fn analyze_commits(mut self, options: AnalyzeOptions) -> anyhow::Result<Self> {
+        if options.use_merge_base {
+            self.resolve_merge_base().await?;
+        }
+        if options.use_branch_protection {
+            self.fetch_required_approvals().await?;
+        }
+
+        // fetch the team membership once per repo instead of once per PR
+        let team_members = match &options.required_team {
+            Some(team) => Some(Arc::new(
+                self.remote
+                    .team_members(team)
+                    .await
+                    .with_context(|| format!("while fetching members of team {team}"))?,
+            )),
+            None => None,
+        };
+        if options.open_prs {
+            return self.analyze_open_prs(team_members, &options).await;
         }
 
-        let mut changesets: Vec<Changeset> = vec![];
-        while let Some(res) = join_set.join_next().await {
-            let changes = res?.context("while collecting change")?;
-            for change in &changes {
-                changesets.push(change.clone());
+        // without a base, e.g. when not diffing helm charts, only the head commit itself is analyzed
+        let compare_commits = if let Some(range) = options.date_range {
+            self.remote
+                .commits_in_range(&self.head_commit, range.from, range.to)
+                .await?
+        } else if self.base_commit.is_empty() {
+            vec![self.remote.commit(&self.head_commit).await?]
+        } else {
+            let comparison = self.remote.compare(&self.base_commit, &self.head_commit).await?;
+            self.compare_status = comparison.status;
+            if comparison.status == Some(CompareStatus::Behind) {
+                eprintln!(
+                    "warning: head {} of {} is behind base {}, base and head might be swapped",
+                    self.head_commit, self.name, self.base_commit
+                );
             }
+            comparison.commits
+        };
+        let compare_commits = commits_since(compare_commits, options.since);
+
+        let remote = Arc::new(self.remote);
+        let compare_commits = filter_by_paths(&remote, compare_commits, &options.paths).await?;
+        let shas: Vec<String> = compare_commits.iter().map(|commit| commit.sha.clone()).collect();
+        let mut associated_prs = remote.associated_prs_batch(&shas).await?;
+        let changes = compare_commits.into_iter().map(|commit| {
+            let associated_prs = associated_prs.remove(&commit.sha);
+            Self::analyze_commit(
+                remote.clone(),
+                commit,
+                team_members.clone(),
+                options.parse_pr_from_message,
+                options.ignore_drafts,
+                associated_prs,
+            )
+        });
+
+        let mut changesets = Vec::new();
+        for changes in run_in_window(changes, MAX_COMMITS_IN_FLIGHT).await? {
+            changesets.push(changes.context("while collecting change")?);
+        }
+        let mut changesets: Vec<Changeset> = changesets.into_iter().flatten().collect();
+        if options.follow_backports {
+            changesets = Self::follow_backports(&remote, changesets, team_members.as_ref()).await?;
+        }
+        Self::check_approval_times(&remote, &mut changesets).await?;
+        if options.pair_reverts {
+            Self::pair_reverts(&remote, &mut changesets).await?;
+        }
+
+        self.remote = Arc::into_inner(remote).unwrap();
+        match options.group_by {
+            GroupBy::Commit => self.merge_changes(changesets),
+            GroupBy::Pr => self.changes.extend(group_by_pr(changesets)),
         }
+        Ok(self)
+    }
 
-        for change in &changesets {
+    // Every change is added to the first one with the same PR link, which only takes over its approvals.
+    fn merge_changes(&mut self, changesets: Vec<Changeset>) {
+        for change in changesets {
             if let Some(self_change) = self
                 .changes
                 .iter_mut()
@@ -57,26 +365,286 @@ impl<C: Client + Sync + Send + 'static> RepoChangeset<C> {
                 for approval in &change.approvals {
                     self_change.approvals.push(approval.clone());
                 }
+                for approval in &change.non_qualifying_approvals {
+                    self_change.non_qualifying_approvals.push(approval.clone());
+                }
+                for user in &change.changes_requested_by {
+                    self_change.changes_requested_by.push(user.clone());
+                }
                 continue;
             }
 
-            self.changes.push(change.clone());
+            self.changes.push(change);
+        }
+    }
+
+    async fn analyze_open_prs(
+        mut self,
+        team_members: Option<Arc<Vec<String>>>,
+        options: &AnalyzeOptions,
+    ) -> anyhow::Result<Self> {
+        let prs = self
+            .remote
+            .list_open_prs()
+            .await
+            .with_context(|| format!("while listing open PRs of {}", self.name))?;
+
+        let remote = Arc::new(self.remote);
+        let changes = prs
+            .into_iter()
+            .map(|pr| Self::analyze_open_pr(remote.clone(), pr, team_members.clone()));
+        for changeset in run_in_window(changes, MAX_COMMITS_IN_FLIGHT).await? {
+            self.changes.push(changeset.context("while collecting open PR")?);
+        }
+        if options.follow_backports {
+            self.changes = Self::follow_backports(&remote, mem::take(&mut self.changes), team_members.as_ref()).await?;
         }
+        Self::check_approval_times(&remote, &mut self.changes).await?;
 
         self.remote = Arc::into_inner(remote).unwrap();
         Ok(self)
     }
 
-    async fn analyze_commit(remote: Arc<Remote<C>>, commit: Commit) -> anyhow::Result<Vec<Changeset>> {
+    // An open PR is shown like a commit with its title as headline, linking to the head of the PR.
+    async fn analyze_open_pr(
+        remote: Arc<Remote<C>>,
+        pr: OpenPullRequest,
+        team_members: Option<Arc<Vec<String>>>,
+    ) -> anyhow::Result<Changeset> {
+        let mut changeset = Changeset {
+            commits: vec![CommitMetadata {
+                headline: Some(pr.title),
+                link: remote.commit_url(&pr.head_sha),
+                sha: pr.head_sha.clone(),
+                author: pr.author,
+                authored_at: None,
+                co_authors: Vec::new(),
+                signature: None,
+                reverts: None,
+                self_cancelling: false,
+            }],
+            pr_link: Some(pr.url),
+            approvals: Vec::new(),
+            non_qualifying_approvals: Vec::new(),
+            partial_approvals: Vec::new(),
+            approved_commits: BTreeMap::new(),
+            changes_requested_by: Vec::new(),
+            backport_approvals: BTreeMap::new(),
+            approved_at: BTreeMap::new(),
+            approved_before_head: Vec::new(),
+            pr_without_commits: false,
+        };
+        changeset
+            .collect_reviews(&remote, pr.number, team_members.as_deref().map(Vec::as_slice))
+            .await?;
+
+        Ok(changeset)
+    }
+
+    async fn follow_backports(
+        remote: &Arc<Remote<C>>,
+        changes: Vec<Changeset>,
+        team_members: Option<&Arc<Vec<String>>>,
+    ) -> anyhow::Result<Vec<Changeset>> {
+        let changes = changes
+            .into_iter()
+            .map(|changeset| Self::add_backport_approvals(remote.clone(), changeset, team_members.cloned()));
+        let mut changesets = Vec::new();
+        for changeset in run_in_window(changes, MAX_COMMITS_IN_FLIGHT).await? {
+            changesets.push(changeset.context("while following backports")?);
+        }
+        Ok(changesets)
+    }
+
+    // Approvals count for the head commit of their PR, which might still claim to be authored after the approval,
+    // e.g. when it was pushed with a skewed clock or a forged date.
+    async fn check_approval_times(remote: &Arc<Remote<C>>, changes: &mut [Changeset]) -> anyhow::Result<()> {
+        let mut shas: Vec<String> = changes
+            .iter()
+            .flat_map(|changeset| {
+                changeset
+                    .approvals
+                    .iter()
+                    .filter_map(|user| changeset.approved_commits.get(user).cloned())
+            })
+            .collect();
+        shas.sort();
+        shas.dedup();
+
+        let lookups = shas.into_iter().map(|sha| {
+            let remote = remote.clone();
+            async move {
+                let commit = remote.commit(&sha).await?;
+                anyhow::Ok((sha, commit.authored_at))
+            }
+        });
+        let mut authored_at = HashMap::new();
+        for lookup in run_in_window(lookups, MAX_COMMITS_IN_FLIGHT).await? {
+            let (sha, commit_authored_at) = lookup.context("while fetching the approved head commits")?;
+            if let Some(commit_authored_at) = commit_authored_at {
+                authored_at.insert(sha, commit_authored_at);
+            }
+        }
+
+        for changeset in changes {
+            changeset.flag_approvals_before_head(&authored_at);
+        }
+        Ok(())
+    }
+
+    // A commit only counts as reverted when the diff of the revert is the inverse of its own. The message alone would
+    // let any commit hide another one.
+    async fn pair_reverts(remote: &Arc<Remote<C>>, changes: &mut [Changeset]) -> anyhow::Result<()> {
+        let lookups = revert_candidates(changes).into_iter().map(|(revert, target)| {
+            let remote = remote.clone();
+            async move {
+                let revert_patches = remote.commit_patches(&revert).await?;
+                let target_patches = remote.commit_patches(&target).await?;
+                let inverts = diff::is_inverse(&target_patches, &revert_patches);
+                anyhow::Ok(inverts.then_some((revert, target)))
+            }
+        });
+        let mut reverts = Vec::new();
+        for lookup in run_in_window(lookups, MAX_COMMITS_IN_FLIGHT).await? {
+            reverts.extend(lookup.context("while comparing reverts with the commits they revert")?);
+        }
+
+        cancel_reverts(changes, &reverts);
+        Ok(())
+    }
+
+    // Takes over the approvals of the PR that the PR of the change is a backport of, and of the PR that one is a
+    // backport of and so on. Approvals by authors of the backported commits still don't count.
+    async fn add_backport_approvals(
+        remote: Arc<Remote<C>>,
+        mut changeset: Changeset,
+        team_members: Option<Arc<Vec<String>>>,
+    ) -> anyhow::Result<Changeset> {
+        let Some(mut pr_number) = changeset.pr_link.as_deref().and_then(pr_number_from_link) else {
+            return Ok(changeset);
+        };
+        let mut seen = vec![pr_number];
+        let mut description = remote.pr_description(pr_number).await?;
+        for _ in 0..MAX_BACKPORT_DEPTH {
+            let Some(original) = backport_of(&description.title).or_else(|| backport_of(&description.body)) else {
+                break;
+            };
+            // PRs claiming to be backports of each other would otherwise be followed until the depth is reached
+            if seen.contains(&original) {
+                break;
+            }
+            seen.push(original);
+
+            // the description is free text, only the commits prove that the approved changes were backported
+            let original_description = remote.pr_description(original).await?;
+            let Some(merge_commit) = &original_description.merge_commit else {
+                break;
+            };
+            if !Self::is_backport(&remote, pr_number, original, merge_commit)
+                .await
+                .with_context(|| format!("while comparing the commits of PR {pr_number} with those of PR {original}"))?
+            {
+                break;
+            }
+
+            let mut original_changeset = Changeset::default();
+            original_changeset
+                .collect_reviews(&remote, original, team_members.as_deref().map(Vec::as_slice))
+                .await
+                .with_context(|| {
+                    format!("while collecting reviews of PR {original}, which PR {pr_number} backports")
+                })?;
+            let original_link = remote.pr_url(original);
+            for user in original_changeset.approvals {
+                if !changeset.approvals.contains(&user) {
+                    changeset.backport_approvals.insert(user.clone(), original_link.clone());
+                    changeset.approvals.push(user);
+                }
+            }
+            pr_number = original;
+            description = original_description;
+        }
+        changeset.exclude_author_approvals();
+        changeset.sort_users();
+
+        Ok(changeset)
+    }
+
+    // Every commit of the backport needs to be a cherry-pick of a commit of the original PR or of the commit it was
+    // merged as, either according to the trailer of git cherry-pick -x or because it changes the same lines.
+    async fn is_backport(remote: &Remote<C>, backport: u64, original: u64, merge_commit: &str) -> anyhow::Result<bool> {
+        let mut original_commits = remote.pr_commits(original).await?;
+        original_commits.push(merge_commit.to_owned());
+        let mut original_patches = Vec::new();
+
+        for sha in remote.pr_commits(backport).await? {
+            let commit = remote.commit(&sha).await?;
+            if cherry_picked_from(&commit.message)
+                .is_some_and(|source| original_commits.iter().any(|original| original.starts_with(&source)))
+            {
+                continue;
+            }
+
+            if original_patches.is_empty() {
+                for original in &original_commits {
+                    original_patches.push(remote.commit_patches(original).await?);
+                }
+            }
+            let patches = remote.commit_patches(&sha).await?;
+            if !original_patches
+                .iter()
+                .any(|original| diff::is_same_change(original, &patches))
+            {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    async fn analyze_commit(
+        remote: Arc<Remote<C>>,
+        commit: Commit,
+        team_members: Option<Arc<Vec<String>>>,
+        parse_pr_from_message: bool,
+        ignore_drafts: bool,
+        associated_prs: Option<Vec<PullRequest>>,
+    ) -> anyhow::Result<Vec<Changeset>> {
         let change_commit = CommitMetadata::new(&commit);
         let mut changes = vec![];
 
-        let associated_prs = remote.associated_prs(commit.sha.clone()).await?;
+        // already known when they were looked up together with the other commits of the compare
+        let mut associated_prs = match associated_prs {
+            Some(associated_prs) => associated_prs,
+            None => remote.associated_prs(commit.sha.clone()).await?,
+        };
+        let associated_count = associated_prs.len();
+        if ignore_drafts {
+            associated_prs.retain(|associated_pr| !associated_pr.draft);
+        }
+        // the message of a commit that only belongs to drafts would name one of them again
+        let drafts_dropped = associated_prs.len() < associated_count;
+        if associated_prs.is_empty() && parse_pr_from_message && !drafts_dropped {
+            if let Some(number) = pr_number_from_message(&commit.message) {
+                associated_prs.push(PullRequest {
+                    number,
+                    url: remote.pr_url(number),
+                    draft: false,
+                });
+            }
+        }
         if associated_prs.is_empty() {
             changes.push(Changeset {
                 commits: vec![change_commit],
                 pr_link: None,
                 approvals: Vec::new(),
+                non_qualifying_approvals: Vec::new(),
+                partial_approvals: Vec::new(),
+                approved_commits: BTreeMap::new(),
+                changes_requested_by: Vec::new(),
+                backport_approvals: BTreeMap::new(),
+                approved_at: BTreeMap::new(),
+                approved_before_head: Vec::new(),
+                pr_without_commits: false,
             });
             return Ok(changes);
         }
@@ -86,11 +654,23 @@ impl<C: Client + Sync + Send + 'static> RepoChangeset<C> {
                 commits: vec![change_commit.clone()],
                 pr_link: Some(associated_pr.url.clone()),
                 approvals: Vec::new(),
+                non_qualifying_approvals: Vec::new(),
+                partial_approvals: Vec::new(),
+                approved_commits: BTreeMap::new(),
+                changes_requested_by: Vec::new(),
+                backport_approvals: BTreeMap::new(),
+                approved_at: BTreeMap::new(),
+                approved_before_head: Vec::new(),
+                pr_without_commits: false,
             };
 
-            let pr_reviews = remote.pr_reviews(associated_pr.number).await?;
-            let head_sha = remote.pr_head_hash(associated_pr.number).await?;
-            changeset.collect_approved_reviews(&pr_reviews, &head_sha);
+            changeset
+                .collect_reviews(
+                    &remote,
+                    associated_pr.number,
+                    team_members.as_deref().map(Vec::as_slice),
+                )
+                .await?;
 
             changes.push(changeset);
         }
@@ -99,19 +679,377 @@ impl<C: Client + Sync + Send + 'static> RepoChangeset<C> {
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+// Keeps the commits that touched a path matching one of the globs, an empty list of globs keeps every commit.
+async fn filter_by_paths<C: Client + Send + 'static>(
+    remote: &Arc<Remote<C>>,
+    commits: Vec<Commit>,
+    paths: &[Pattern],
+) -> anyhow::Result<Vec<Commit>> {
+    if paths.is_empty() {
+        return Ok(commits);
+    }
+
+    let files = commits
+        .iter()
+        .map(|commit| commit_files(remote.clone(), commit.sha.clone()))
+        .collect::<Vec<_>>();
+    let files = run_in_window(files, MAX_COMMITS_IN_FLIGHT).await?;
+
+    let mut filtered = Vec::new();
+    for (commit, files) in commits.into_iter().zip(files) {
+        let files = files.with_context(|| format!("while getting files of commit {}", commit.sha))?;
+        if files
+            .iter()
+            .any(|file| paths.iter().any(|pattern| pattern.matches(file)))
+        {
+            filtered.push(commit);
+        }
+    }
+    Ok(filtered)
+}
+
+async fn commit_files<C: Client>(remote: Arc<Remote<C>>, sha: String) -> anyhow::Result<Vec<String>> {
+    remote.commit_files(&sha).await
+}
+
+// Runs the tasks with at most `window` of them spawned at the same time. The results keep the order of the input.
+async fn run_in_window<T, F>(tasks: impl IntoIterator<Item = F>, window: usize) -> anyhow::Result<Vec<T>>
+where
+    T: Send + 'static,
+    F: Future<Output = T> + Send + 'static,
+{
+    let mut tasks = tasks.into_iter().enumerate();
+    let mut join_set = JoinSet::new();
+    let mut results = Vec::new();
+    loop {
+        while join_set.len() < window {
+            let Some((idx, task)) = tasks.next() else {
+                break;
+            };
+            join_set.spawn(async move { (idx, task.await) });
+        }
+
+        let Some(res) = join_set.join_next().await else {
+            break;
+        };
+        results.push(res?);
+    }
+    results.sort_by_key(|(idx, _)| *idx);
+
+    Ok(results.into_iter().map(|(_, result)| result).collect())
+}
+
+// A tag consisting only of hex digits is mistaken for a commit hash, which the compare resolves all the same.
+// A ref which is not found is most likely a typo, other errors like a bad token are passed on as they are.
+fn ref_error(err: anyhow::Error, kind: &str, reference: &str, repo: &str) -> anyhow::Error {
+    if matches!(PearError::find(&err), Some(PearError::NotFound(_))) {
+        err.context(format!("{kind} ref {reference:?} does not exist in {repo}"))
+    } else {
+        err.context(format!("while validating {kind} ref {reference:?} of {repo}"))
+    }
+}
+
+/// Prefix of fully qualified branch refs, which the API resolves as branch even if the name looks like a commit hash.
+pub const BRANCH_REF_PREFIX: &str = "refs/heads/";
+
+pub fn is_commit_hash(reference: &str) -> bool {
+    (7..=40).contains(&reference.len()) && reference.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+// Finds the PR number in the headline of squash merges like "Fix things (#187)" and merge commits like
+// "Merge pull request #187 from owner/branch".
+fn pr_number_from_message(message: &str) -> Option<u64> {
+    let headline = message.lines().next()?.trim();
+    if let Some(rest) = headline.strip_prefix("Merge pull request #") {
+        return rest.split_whitespace().next()?.parse().ok();
+    }
+    let (_, number) = headline.strip_suffix(')')?.rsplit_once("(#")?;
+    number.parse().ok()
+}
+
+// Finds "Backport of #123", as written by the usual backport tools, in the title or body of a PR. Only the first
+// reference counts.
+fn backport_of(text: &str) -> Option<u64> {
+    const MARKER: &str = "backport of #";
+    // ASCII lowercase keeps the byte offsets of the original text
+    let start = text.to_ascii_lowercase().find(MARKER)? + MARKER.len();
+    let digits = text[start..].split(|c: char| !c.is_ascii_digit()).next()?;
+    digits.parse().ok()
+}
+
+// Parses the "This reverts commit <sha>." line that git revert puts into the message, the sha is lowercased.
+fn reverted_commit(message: &str) -> Option<String> {
+    const MARKER: &str = "this reverts commit ";
+    message.lines().find_map(|line| {
+        // ASCII lowercase keeps the byte offsets of the original line
+        let line = line.trim().to_ascii_lowercase();
+        let start = line.find(MARKER)? + MARKER.len();
+        let sha = line[start..].split(|c: char| !c.is_ascii_hexdigit()).next()?;
+        is_commit_hash(sha).then(|| sha.to_owned())
+    })
+}
+
+fn commit_sha(commit: &CommitMetadata) -> String {
+    commit.sha.to_ascii_lowercase()
+}
+
+// Pairs every revert with the commit it reverts as (revert, reverted). Reverts of commits outside of the changes are
+// left out, as they change the result of the range like any other commit.
+fn revert_candidates(changes: &[Changeset]) -> Vec<(String, String)> {
+    let shas: Vec<String> = changes
+        .iter()
+        .flat_map(|change| &change.commits)
+        .map(commit_sha)
+        .collect();
+
+    changes
+        .iter()
+        .flat_map(|change| &change.commits)
+        .filter_map(|commit| {
+            let sha = commit_sha(commit);
+            let reverted = commit.reverts.as_deref()?;
+            let target = shas
+                .iter()
+                .find(|other| **other != sha && other.starts_with(reverted))?;
+            Some((sha, target.clone()))
+        })
+        .collect()
+}
+
+/// Marks a commit and the revert that undoes it as self-cancelling, given the reverts as (revert, reverted) pairs.
+/// A revert that is reverted itself undoes nothing, so in a chain of reverts only the parity decides whether the
+/// original commit is part of the result. The changes are expected in the order of the compare, oldest first.
+pub fn cancel_reverts(changes: &mut [Changeset], reverts: &[(String, String)]) {
+    let shas: Vec<String> = changes
+        .iter()
+        .flat_map(|change| &change.commits)
+        .map(commit_sha)
+        .collect();
+
+    // a revert is newer than the commit it reverts, so walking from the newest commit decides every revert first
+    let mut cancelled: Vec<&str> = Vec::new();
+    let mut undone: Vec<&str> = Vec::new();
+    for sha in shas.iter().rev() {
+        let undone_by = reverts
+            .iter()
+            .filter(|(revert, target)| target == sha && !undone.contains(&revert.as_str()))
+            .map(|(revert, _)| revert.as_str())
+            .min_by_key(|revert| shas.iter().position(|other| other == revert));
+        if let Some(revert) = undone_by {
+            undone.push(sha);
+            cancelled.push(sha);
+            cancelled.push(revert);
+        }
+    }
+
+    let cancelled: Vec<String> = cancelled.into_iter().map(ToOwned::to_owned).collect();
+    for commit in changes.iter_mut().flat_map(|change| &mut change.commits) {
+        if cancelled.contains(&commit_sha(commit)) {
+            commit.self_cancelling = true;
+        }
+    }
+}
+
+// Parses the "(cherry picked from commit <sha>)" line that git cherry-pick -x appends, the sha is lowercased.
+fn cherry_picked_from(message: &str) -> Option<String> {
+    const MARKER: &str = "(cherry picked from commit ";
+    message.lines().find_map(|line| {
+        let sha = line
+            .trim()
+            .to_ascii_lowercase()
+            .strip_prefix(MARKER)?
+            .strip_suffix(')')?
+            .to_owned();
+        is_commit_hash(&sha).then_some(sha)
+    })
+}
+
+fn pr_number_from_link(link: &str) -> Option<u64> {
+    link.rsplit('/').next()?.parse().ok()
+}
+
+// Commits without an authored date are kept, as they can't be proven to be older.
+fn commits_since(commits: Vec<Commit>, since: Option<DateTime<Utc>>) -> Vec<Commit> {
+    match since {
+        Some(since) => commits
+            .into_iter()
+            .filter(|commit| commit.authored_at.is_none_or(|authored_at| authored_at >= since))
+            .collect(),
+        None => commits,
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
 pub struct Changeset {
     pub commits: Vec<CommitMetadata>,
     pub pr_link: Option<String>,
     pub approvals: Vec<String>,
+    /// approvals by users outside of the required team
+    pub non_qualifying_approvals: Vec<String>,
+    /// users whose latest review approved an earlier commit that is still part of the PR
+    #[serde(default)]
+    pub partial_approvals: Vec<String>,
+    /// the commit each approver approved, keyed by user
+    pub approved_commits: BTreeMap<String, String>,
+    /// users whose latest review on the head commit requested changes, this blocks the change
+    pub changes_requested_by: Vec<String>,
+    /// approvals taken over from the PR that the PR of this change is a backport of, the link to that PR keyed by user
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub backport_approvals: BTreeMap<String, String>,
+    /// when each approver in `approved_commits` submitted their approval, keyed by user
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub approved_at: BTreeMap<String, DateTime<Utc>>,
+    /// approvers whose approval was submitted before the commit they approved was authored
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub approved_before_head: Vec<String>,
+    /// the PR has no commits left, e.g. after its branch was reset to the base
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pr_without_commits: bool,
+}
+
+// Merges the changesets of each PR into one, changesets without a PR are kept as they are.
+pub fn group_by_pr(changes: Vec<Changeset>) -> Vec<Changeset> {
+    let mut grouped: Vec<Changeset> = Vec::new();
+    for change in changes {
+        if change.pr_link.is_some() {
+            if let Some(existing) = grouped.iter_mut().find(|existing| existing.pr_link == change.pr_link) {
+                existing.merge(change);
+                continue;
+            }
+        }
+        grouped.push(change);
+    }
+    grouped
 }
 
 impl Changeset {
-    // pr_reviews must be sorted by key submitted_at!
-    pub fn collect_approved_reviews(&mut self, pr_reviews: &[Review], head_sha: &String) {
+    fn merge(&mut self, other: Changeset) {
+        for commit in other.commits {
+            if !self.commits.contains(&commit) {
+                self.commits.push(commit);
+            }
+        }
+        for (users, other_users) in [
+            (&mut self.approvals, other.approvals),
+            (&mut self.non_qualifying_approvals, other.non_qualifying_approvals),
+            (&mut self.partial_approvals, other.partial_approvals),
+            (&mut self.changes_requested_by, other.changes_requested_by),
+        ] {
+            for user in other_users {
+                if !users.contains(&user) {
+                    users.push(user);
+                }
+            }
+        }
+        for (user, commit_id) in other.approved_commits {
+            self.approved_commits.entry(user).or_insert(commit_id);
+        }
+        for (user, pr_link) in other.backport_approvals {
+            self.backport_approvals.entry(user).or_insert(pr_link);
+        }
+        for (user, approved_at) in other.approved_at {
+            self.approved_at.entry(user).or_insert(approved_at);
+        }
+        for user in other.approved_before_head {
+            if !self.approved_before_head.contains(&user) {
+                self.approved_before_head.push(user);
+            }
+        }
+        self.pr_without_commits |= other.pr_without_commits;
+        // an approver of one commit might have authored another one
+        self.exclude_author_approvals();
+        self.sort_users();
+    }
+
+    // Reviews are processed newest first and changesets merged in compare order, sorting keeps the output stable.
+    fn sort_users(&mut self) {
+        for users in [
+            &mut self.approvals,
+            &mut self.non_qualifying_approvals,
+            &mut self.partial_approvals,
+            &mut self.changes_requested_by,
+        ] {
+            users.sort();
+            users.dedup();
+        }
+    }
+
+    // Approvals by an author or co-author of any of the commits are not independent and only count as
+    // non-qualifying.
+    fn exclude_author_approvals(&mut self) {
+        let authors: Vec<&String> = self
+            .commits
+            .iter()
+            .flat_map(|commit| commit.author.iter().chain(&commit.co_authors))
+            .collect();
+        let (mut excluded, approvals): (Vec<String>, Vec<String>) = mem::take(&mut self.approvals)
+            .into_iter()
+            .partition(|approval| authors.contains(&approval));
+        let (excluded_partial, partial_approvals): (Vec<String>, Vec<String>) = mem::take(&mut self.partial_approvals)
+            .into_iter()
+            .partition(|approval| authors.contains(&approval));
+
+        self.approvals = approvals;
+        self.partial_approvals = partial_approvals;
+        self.backport_approvals.retain(|user, _| !excluded.contains(user));
+        excluded.extend(excluded_partial);
+        for user in excluded {
+            if !self.non_qualifying_approvals.contains(&user) {
+                self.non_qualifying_approvals.push(user);
+            }
+        }
+    }
+
+    // Only the partial approvals of commits that are still part of the PR are kept.
+    pub fn drop_stale_approvals(&mut self, pr_commits: &[String]) {
+        let approved_commits = &mut self.approved_commits;
+        let approved_at = &mut self.approved_at;
+        self.partial_approvals.retain(|user| {
+            let is_present = approved_commits
+                .get(user)
+                .is_some_and(|commit_id| pr_commits.contains(commit_id));
+            if !is_present {
+                approved_commits.remove(user);
+                approved_at.remove(user);
+            }
+            is_present
+        });
+    }
+
+    async fn collect_reviews<C: Client>(
+        &mut self,
+        remote: &Remote<C>,
+        pr_number: u64,
+        team_members: Option<&[String]>,
+    ) -> anyhow::Result<()> {
+        // reviews are on the base repo, but refer to the head commit which may live in a fork. The head of the
+        // PR is also what reviewers saw when it was squash-merged or rebased, not the commit on the base branch.
+        let (head, pr_reviews) = remote.pr_head_and_reviews(pr_number).await?;
+        self.collect_approved_reviews(&pr_reviews, &head.sha, team_members);
+        // tells partial approvals from stale ones. A PR can end up without commits, e.g. when its branch was reset
+        // to the base, then every partial approval is stale.
+        let pr_commits = remote.pr_commits(pr_number).await?;
+        self.pr_without_commits = pr_commits.is_empty();
+        self.drop_stale_approvals(&pr_commits);
+        self.exclude_author_approvals();
+
+        Ok(())
+    }
+
+    // When team_members is given, approvals of anyone else are collected as non-qualifying.
+    pub fn collect_approved_reviews(
+        &mut self,
+        pr_reviews: &[Review],
+        head_sha: &String,
+        team_members: Option<&[String]>,
+    ) {
         let mut last_review_by: Vec<String> = vec![];
 
-        // reverse the order of reviews to start with the oldest
+        // don't rely on the caller to pass the reviews in order
+        let mut pr_reviews = pr_reviews.to_vec();
+        pr_reviews.sort_by_key(|r| (r.submitted_at, r.id));
+
+        // reverse the order of reviews to start with the newest
         for pr_review in pr_reviews.iter().rev() {
             // Only consider the last review of any user.
             // For example a user might have requested changes early on in the PR and later approved it
@@ -121,29 +1059,93 @@ impl Changeset {
             }
             last_review_by.push(pr_review.user.clone());
 
-            // Only account for reviews done on the last commit of the PR.
-            // We could count the PR as partly reviewed but that is to complicated to present at the moment.
+            // Approvals of an earlier commit only cover part of the PR, they are collected separately and
+            // drop_stale_approvals() removes those whose commit was force-pushed away.
             if pr_review.commit_id != *head_sha {
+                let qualifies = team_members.is_none_or(|members| members.contains(&pr_review.user));
+                if pr_review.state == ReviewState::Approved && qualifies {
+                    self.partial_approvals.push(pr_review.user.clone());
+                    self.record_approval(pr_review);
+                }
                 continue;
             }
 
-            // in case it isn't approve, ignore it
-            if !pr_review.approved {
-                continue;
+            match pr_review.state {
+                ReviewState::Approved => {},
+                ReviewState::ChangesRequested => {
+                    if !self.changes_requested_by.contains(&pr_review.user) {
+                        self.changes_requested_by.push(pr_review.user.clone());
+                    }
+                    continue;
+                },
+                // comments and dismissed reviews neither approve nor block
+                _ => continue,
             }
 
+            let approvals = match team_members {
+                Some(members) if !members.contains(&pr_review.user) => &mut self.non_qualifying_approvals,
+                _ => &mut self.approvals,
+            };
+
             // don't duplicate user names
-            if !self.approvals.contains(&pr_review.user) {
-                self.approvals.push(pr_review.user.clone());
+            if !approvals.contains(&pr_review.user) {
+                approvals.push(pr_review.user.clone());
             }
+            self.record_approval(pr_review);
+        }
+        self.sort_users();
+    }
+
+    /// Whether every commit of the change is cancelled out by a revert within the analyzed range.
+    pub fn is_self_cancelling(&self) -> bool {
+        !self.commits.is_empty() && self.commits.iter().all(|commit| commit.self_cancelling)
+    }
+
+    /// Flags the approvals submitted before the commit they approved was authored, given the authored date by sha.
+    pub fn flag_approvals_before_head(&mut self, authored_at: &HashMap<String, DateTime<Utc>>) {
+        self.approved_before_head = self
+            .approvals
+            .iter()
+            .filter(|user| {
+                let commit_authored_at = self.approved_commits.get(*user).and_then(|sha| authored_at.get(sha));
+                matches!(
+                    (self.approved_at.get(*user), commit_authored_at),
+                    (Some(approved_at), Some(commit_authored_at)) if approved_at < commit_authored_at
+                )
+            })
+            .cloned()
+            .collect();
+    }
+
+    fn record_approval(&mut self, review: &Review) {
+        self.approved_commits
+            .insert(review.user.clone(), review.commit_id.clone());
+        if let Some(submitted_at) = review.submitted_date() {
+            self.approved_at.insert(review.user.clone(), submitted_at);
         }
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
 pub struct CommitMetadata {
-    pub headline: String,
+    /// the first line of the commit message, none for commits without a message
+    pub headline: Option<String>,
     pub link: String,
+    #[serde(default)]
+    pub sha: String,
+    pub author: Option<String>,
+    pub authored_at: Option<DateTime<Utc>>,
+    /// authors from Co-authored-by trailers, as GitHub login when known
+    #[serde(default)]
+    pub co_authors: Vec<String>,
+    #[serde(default)]
+    pub signature: Option<Signature>,
+    /// the commit this one reverts according to its message, as given there
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reverts: Option<String>,
+    /// reverted within the analyzed range or a revert of such a commit, see `cancel_reverts`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub self_cancelling: bool,
 }
 
 impl CommitMetadata {
@@ -152,52 +1154,110 @@ impl CommitMetadata {
             .message
             .split('\n')
             .next()
-            .unwrap_or("<empty commit message>")
-            .to_string();
+            .filter(|headline| !headline.trim().is_empty())
+            .map(ToString::to_string);
         Self {
             headline,
             link: commit.html_url.clone(),
+            sha: commit.sha.clone(),
+            author: commit.author.clone(),
+            authored_at: commit.authored_at,
+            co_authors: co_authors(&commit.message),
+            signature: commit.signature.clone(),
+            reverts: reverted_commit(&commit.message),
+            self_cancelling: false,
+        }
+    }
+
+    /// The headline, or the short sha for commits without a message.
+    pub fn title(&self) -> &str {
+        match &self.headline {
+            Some(headline) => headline,
+            None if !self.sha.is_empty() => self.sha.get(..7).unwrap_or(&self.sha),
+            None => "<empty commit message>",
+        }
+    }
+}
+
+// Parses "Co-authored-by: Name <email>" trailers. The GitHub login is taken from noreply addresses like
+// 12345+login@users.noreply.github.com, otherwise the name is used. Trailers without an email are ignored.
+fn co_authors(message: &str) -> Vec<String> {
+    let mut co_authors = Vec::new();
+    for line in message.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        if !key.trim().eq_ignore_ascii_case("co-authored-by") {
+            continue;
+        }
+        let Some((name, email)) = value.trim().strip_suffix('>').and_then(|value| value.split_once('<')) else {
+            continue;
+        };
+
+        let login = email
+            .strip_suffix("@users.noreply.github.com")
+            .map(|local| local.split_once('+').map_or(local, |(_, login)| login));
+        let co_author = login.unwrap_or(name).trim();
+        if !co_author.is_empty() && !co_authors.iter().any(|existing| existing == co_author) {
+            co_authors.push(co_author.to_owned());
         }
     }
+    co_authors
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use chrono::TimeZone;
+
     use super::*;
-    use crate::api_clients::{ClientSet, MockClient};
-    use crate::github::{PullRequest, Review};
+    use crate::api_clients::{ClientConfig, ClientSet, MockClient};
+    use crate::github::{
+        BranchProtection, FilePatch, PullRequest, PullRequestDescription, PullRequestHead, Release, Review,
+        ReviewState, Team,
+    };
+    use crate::policy::{self, FailPolicy};
 
     fn gen_change_review() -> (Changeset, Vec<Review>) {
         (
             Changeset {
                 commits: vec![
                     CommitMetadata {
-                        headline: "Commit 1".to_owned(),
+                        headline: Some("Commit 1".to_owned()),
                         link: "https://github.com/example/project/commit/00000000000000000000000000000001".to_owned(),
+                        sha: "00000000000000000000000000000001".to_owned(),
+                        ..CommitMetadata::default()
                     },
                     CommitMetadata {
-                        headline: "Commit 2".to_owned(),
+                        headline: Some("Commit 2".to_owned()),
                         link: "https://github.com/example/project/commit/00000000000000000000000000000002".to_owned(),
+                        sha: "00000000000000000000000000000002".to_owned(),
+                        ..CommitMetadata::default()
                     },
                 ],
                 pr_link: Some("https://github.com/example/project/pulls/1".to_owned()),
-                approvals: Vec::new(),
+                ..Changeset::default()
             },
             vec![
                 Review {
-                    approved: true,
+                    id: 1,
+                    state: ReviewState::Approved,
                     commit_id: "00000000000000000000000000000001".to_owned(),
                     submitted_at: 1,
                     user: "user1".to_owned(),
                 },
                 Review {
-                    approved: true,
+                    id: 2,
+                    state: ReviewState::Approved,
                     commit_id: "00000000000000000000000000000002".to_owned(),
                     submitted_at: 2,
                     user: "user2".to_owned(),
                 },
                 Review {
-                    approved: false,
+                    id: 3,
+                    state: ReviewState::Commented,
                     commit_id: "00000000000000000000000000000003".to_owned(),
                     submitted_at: 3,
                     user: "user3".to_owned(),
@@ -209,65 +1269,1575 @@ mod tests {
     #[test]
     fn collect_approved_reviews() {
         let (mut changeset, pr_reviews) = gen_change_review();
-        changeset.collect_approved_reviews(&pr_reviews, &"00000000000000000000000000000002".to_owned());
+        changeset.collect_approved_reviews(&pr_reviews, &"00000000000000000000000000000002".to_owned(), None);
         assert_eq!(changeset.approvals, vec!["user2"]);
     }
 
+    #[test]
+    fn collect_approved_reviews_approved_commits() {
+        let (mut changeset, pr_reviews) = gen_change_review();
+        changeset.collect_approved_reviews(&pr_reviews, &"00000000000000000000000000000002".to_owned(), None);
+        // user1 approved an earlier commit
+        assert_eq!(
+            changeset.approved_commits,
+            BTreeMap::from([
+                ("user1".to_owned(), "00000000000000000000000000000001".to_owned()),
+                ("user2".to_owned(), "00000000000000000000000000000002".to_owned()),
+            ])
+        );
+    }
+
+    #[test]
+    fn collect_approved_reviews_partial() {
+        let (mut changeset, pr_reviews) = gen_change_review();
+        // both approvals are on commits before the head
+        changeset.collect_approved_reviews(&pr_reviews, &"00000000000000000000000000000003".to_owned(), None);
+        assert_eq!(changeset.approvals, Vec::<String>::new());
+        assert_eq!(changeset.partial_approvals, vec!["user1", "user2"]);
+
+        // the commit approved by user1 was force-pushed away
+        changeset.drop_stale_approvals(&[
+            "00000000000000000000000000000002".to_owned(),
+            "00000000000000000000000000000003".to_owned(),
+        ]);
+        assert_eq!(changeset.partial_approvals, vec!["user2"]);
+        assert_eq!(
+            changeset.approved_commits,
+            BTreeMap::from([("user2".to_owned(), "00000000000000000000000000000002".to_owned())])
+        );
+    }
+
     #[test]
     fn collect_approved_reviews_extra_commit() {
         let (mut changeset, pr_reviews) = gen_change_review();
-        changeset.collect_approved_reviews(&pr_reviews, &"00000000000000000000000000000003".to_owned());
+        changeset.collect_approved_reviews(&pr_reviews, &"00000000000000000000000000000003".to_owned(), None);
         assert_eq!(changeset.approvals, Vec::<String>::new());
     }
 
-    fn get_mock_remote() -> Remote<MockClient> {
-        let mut api_clients = ClientSet::new();
-        let mut remote = Remote::<MockClient>::parse("https://github.com/example/project.git").unwrap();
-        api_clients.fill(&mut remote).unwrap();
+    #[test]
+    fn collect_approved_reviews_unsorted() {
+        let (mut changeset, mut pr_reviews) = gen_change_review();
+        // user2 requested changes after approving, but the reviews are passed newest first
+        pr_reviews.push(Review {
+            id: 4,
+            state: ReviewState::ChangesRequested,
+            commit_id: "00000000000000000000000000000002".to_owned(),
+            submitted_at: 4,
+            user: "user2".to_owned(),
+        });
+        pr_reviews.push(Review {
+            id: 5,
+            state: ReviewState::Approved,
+            commit_id: "00000000000000000000000000000002".to_owned(),
+            submitted_at: 5,
+            user: "user1".to_owned(),
+        });
+        pr_reviews.reverse();
 
-        remote
+        changeset.collect_approved_reviews(&pr_reviews, &"00000000000000000000000000000002".to_owned(), None);
+        assert_eq!(changeset.approvals, vec!["user1"]);
+        assert_eq!(changeset.changes_requested_by, vec!["user2"]);
     }
 
-    #[tokio::test]
-    async fn analyze_commit_approved() {
-        let remote = get_mock_remote();
-        let remote_client = remote.client.as_ref().unwrap();
+    #[test]
+    fn collect_approved_reviews_sorted() {
+        let (mut changeset, _) = gen_change_review();
+        let review = |id: u32, user: &str| Review {
+            id: id.into(),
+            state: ReviewState::Approved,
+            commit_id: "00000000000000000000000000000002".to_owned(),
+            submitted_at: id.into(),
+            user: user.to_owned(),
+        };
+        changeset.collect_approved_reviews(
+            &[review(1, "user3"), review(2, "user1"), review(3, "user2")],
+            &"00000000000000000000000000000002".to_owned(),
+            None,
+        );
+        assert_eq!(changeset.approvals, vec!["user1", "user2", "user3"]);
 
-        remote_client
-            .associated_prs
-            .lock()
-            .unwrap()
-            .insert("00000000000000000000000000000002".to_string(), vec![PullRequest {
-                number: 1,
-                url: "https://github.com/example/project/pulls/1".to_owned(),
-            }]);
+        // merging keeps them sorted and unique
+        let mut other = changeset.clone();
+        other.approvals = vec!["user4".to_owned(), "user2".to_owned(), "user0".to_owned()];
+        changeset.merge(other);
+        assert_eq!(changeset.approvals, vec!["user0", "user1", "user2", "user3", "user4"]);
+    }
+
+    #[test]
+    fn collect_approved_reviews_same_timestamp() {
+        let (mut changeset, _) = gen_change_review();
+        let approval = Review {
+            id: 11,
+            state: ReviewState::Approved,
+            commit_id: "00000000000000000000000000000002".to_owned(),
+            submitted_at: 42,
+            user: "user1".to_owned(),
+        };
+        let changes_requested = Review {
+            id: 12,
+            state: ReviewState::ChangesRequested,
+            ..approval.clone()
+        };
+
+        // the review with the higher id wins regardless of the order they are passed in
+        for pr_reviews in [vec![approval.clone(), changes_requested.clone()], vec![
+            changes_requested.clone(),
+            approval.clone(),
+        ]] {
+            changeset.approvals.clear();
+            changeset.changes_requested_by.clear();
+            changeset.collect_approved_reviews(&pr_reviews, &"00000000000000000000000000000002".to_owned(), None);
+            assert_eq!(changeset.approvals, Vec::<String>::new());
+            assert_eq!(changeset.changes_requested_by, vec!["user1"]);
+        }
+    }
+
+    #[test]
+    fn collect_approved_reviews_required_team() {
+        let (mut changeset, mut pr_reviews) = gen_change_review();
+        pr_reviews.push(Review {
+            id: 6,
+            state: ReviewState::Approved,
+            commit_id: "00000000000000000000000000000002".to_owned(),
+            submitted_at: 4,
+            user: "user4".to_owned(),
+        });
+        changeset.collect_approved_reviews(
+            &pr_reviews,
+            &"00000000000000000000000000000002".to_owned(),
+            Some(&["user4".to_owned()]),
+        );
+        assert_eq!(changeset.approvals, vec!["user4"]);
+        assert_eq!(changeset.non_qualifying_approvals, vec!["user2"]);
+    }
+
+    #[test]
+    fn collect_approved_reviews_changes_requested() {
+        let (mut changeset, mut pr_reviews) = gen_change_review();
+        // user2 first approved and then requested changes on the same commit
+        pr_reviews.push(Review {
+            id: 7,
+            state: ReviewState::ChangesRequested,
+            commit_id: "00000000000000000000000000000002".to_owned(),
+            submitted_at: 4,
+            user: "user2".to_owned(),
+        });
+        // user1 requested changes on an older commit, which is ignored like an approval there would be
+        pr_reviews.push(Review {
+            id: 8,
+            state: ReviewState::ChangesRequested,
+            commit_id: "00000000000000000000000000000001".to_owned(),
+            submitted_at: 5,
+            user: "user1".to_owned(),
+        });
+        changeset.collect_approved_reviews(&pr_reviews, &"00000000000000000000000000000002".to_owned(), None);
+        assert_eq!(changeset.approvals, Vec::<String>::new());
+        assert_eq!(changeset.changes_requested_by, vec!["user2"]);
+    }
+
+    #[test]
+    fn collect_approved_reviews_changes_requested_then_approved() {
+        let (mut changeset, mut pr_reviews) = gen_change_review();
+        pr_reviews.insert(0, Review {
+            id: 9,
+            state: ReviewState::ChangesRequested,
+            commit_id: "00000000000000000000000000000002".to_owned(),
+            submitted_at: 0,
+            user: "user2".to_owned(),
+        });
+        changeset.collect_approved_reviews(&pr_reviews, &"00000000000000000000000000000002".to_owned(), None);
+        assert_eq!(changeset.approvals, vec!["user2"]);
+        assert_eq!(changeset.changes_requested_by, Vec::<String>::new());
+    }
+
+    #[test]
+    fn commits_since() {
+        let commits = vec![
+            Commit {
+                authored_at: DateTime::from_timestamp(100, 0),
+                ..Commit::test("00000000000000000000000000000001")
+            },
+            Commit {
+                authored_at: DateTime::from_timestamp(200, 0),
+                ..Commit::test("00000000000000000000000000000002")
+            },
+            Commit::test("00000000000000000000000000000003"),
+            Commit {
+                authored_at: DateTime::from_timestamp(300, 0),
+                ..Commit::test("00000000000000000000000000000004")
+            },
+        ];
+
+        let filtered = super::commits_since(commits.clone(), DateTime::from_timestamp(200, 0));
+        let shas: Vec<&str> = filtered.iter().map(|commit| commit.sha.as_str()).collect();
+        assert_eq!(shas, vec![
+            "00000000000000000000000000000002",
+            "00000000000000000000000000000003",
+            "00000000000000000000000000000004",
+        ]);
+
+        assert_eq!(super::commits_since(commits, None).len(), 4);
+    }
+
+    #[tokio::test]
+    async fn run_in_window() {
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+
+        let tasks = (0..1000u64).map(|idx| {
+            let in_flight = in_flight.clone();
+            let max_in_flight = max_in_flight.clone();
+            async move {
+                let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                max_in_flight.fetch_max(current, Ordering::SeqCst);
+                // finish out of order
+                tokio::time::sleep(Duration::from_micros((idx * 7919) % 500)).await;
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                idx * 2
+            }
+        });
+        let results = super::run_in_window(tasks, 20).await.unwrap();
+
+        assert_eq!(results, (0..1000).map(|idx| idx * 2).collect::<Vec<_>>());
+        assert!(max_in_flight.load(Ordering::SeqCst) <= 20);
+    }
+
+    #[test]
+    fn co_authors() {
+        assert_eq!(super::co_authors("Fix the frobnicator"), Vec::<String>::new());
+        assert_eq!(
+            super::co_authors("Fix the frobnicator\n\nCo-authored-by: Jane Doe <jane@example.com>"),
+            vec!["Jane Doe"]
+        );
+        assert_eq!(
+            super::co_authors(
+                "Fix the frobnicator
+
+Co-authored-by: Jane Doe <12345+janedoe@users.noreply.github.com>
+co-authored-by: John Doe <johndoe@users.noreply.github.com>
+Co-Authored-By: Jane Doe <12345+janedoe@users.noreply.github.com>"
+            ),
+            vec!["janedoe", "johndoe"]
+        );
+        // malformed trailers are skipped
+        assert_eq!(
+            super::co_authors(
+                "Fix the frobnicator
+
+Co-authored-by:
+Co-authored-by: Jane Doe
+Co-authored-by: <jane@example.com>
+Co-authored-by: John Doe <johndoe@example.com"
+            ),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn exclude_author_approvals() {
+        let (mut changeset, _) = gen_change_review();
+        changeset.commits[0].author = Some("user1".to_owned());
+        changeset.commits[1].co_authors = vec!["user2".to_owned()];
+        changeset.approvals = vec!["user1".to_owned(), "user2".to_owned(), "user3".to_owned()];
+
+        changeset.exclude_author_approvals();
+        assert_eq!(changeset.approvals, vec!["user3"]);
+        assert_eq!(changeset.non_qualifying_approvals, vec!["user1", "user2"]);
+    }
+
+    #[test]
+    fn pr_number_from_message() {
+        assert_eq!(super::pr_number_from_message("Fix the frobnicator (#187)"), Some(187));
+        assert_eq!(
+            super::pr_number_from_message("Fix the frobnicator (#187)\n\n* fix\n* fix again"),
+            Some(187)
+        );
+        assert_eq!(
+            super::pr_number_from_message("Merge pull request #42 from sapcc/fix-frobnicator\n\nFix the frobnicator"),
+            Some(42)
+        );
+        assert_eq!(super::pr_number_from_message("Fix the frobnicator"), None);
+        assert_eq!(super::pr_number_from_message("Fix #187 in the frobnicator"), None);
+        assert_eq!(super::pr_number_from_message("Fix the frobnicator (#abc)"), None);
+    }
+
+    #[test]
+    fn group_by_pr() {
+        let gen_changeset = |sha: &str, pr_link: Option<&str>| Changeset {
+            commits: vec![CommitMetadata {
+                headline: Some(format!("Commit {sha}")),
+                link: format!("https://github.com/example/project/commit/{sha}"),
+                sha: sha.to_owned(),
+                ..CommitMetadata::default()
+            }],
+            pr_link: pr_link.map(ToOwned::to_owned),
+            approvals: if pr_link.is_some() {
+                vec!["user1".to_owned()]
+            } else {
+                Vec::new()
+            },
+            ..Changeset::default()
+        };
+
+        let grouped = super::group_by_pr(vec![
+            gen_changeset(
+                "00000000000000000000000000000001",
+                Some("https://github.com/example/project/pull/1"),
+            ),
+            gen_changeset("00000000000000000000000000000002", None),
+            gen_changeset(
+                "00000000000000000000000000000003",
+                Some("https://github.com/example/project/pull/1"),
+            ),
+            gen_changeset("00000000000000000000000000000004", None),
+        ]);
+
+        assert_eq!(grouped.len(), 3);
+        assert_eq!(
+            grouped[0].pr_link.as_deref(),
+            Some("https://github.com/example/project/pull/1")
+        );
+        let headlines: Vec<&str> = grouped[0].commits.iter().map(CommitMetadata::title).collect();
+        assert_eq!(headlines, vec![
+            "Commit 00000000000000000000000000000001",
+            "Commit 00000000000000000000000000000003",
+        ]);
+        assert_eq!(grouped[0].approvals, vec!["user1"]);
+        // direct pushes are never grouped together
+        assert_eq!(grouped[1].commits[0].title(), "Commit 00000000000000000000000000000002");
+        assert_eq!(grouped[2].commits[0].title(), "Commit 00000000000000000000000000000004");
+    }
+
+    fn get_mock_remote() -> Remote<MockClient> {
+        let mut api_clients = ClientSet::new(ClientConfig::default());
+        let mut remote = Remote::<MockClient>::parse("https://github.com/example/project.git").unwrap();
+        api_clients.fill(&mut remote).unwrap();
+
+        remote
+    }
+
+    fn mock_repo_changeset(base: &str, head: &str) -> RepoChangeset<MockClient> {
+        let remote = get_mock_remote();
+        let mut commits = remote.client.as_ref().unwrap().commits.lock().unwrap();
+        commits.insert("v1".to_owned(), Commit::test("v1"));
+        commits.insert("v2".to_owned(), Commit::test("v2"));
+        drop(commits);
+
+        RepoChangeset {
+            name: "project".to_owned(),
+            remote,
+            base_commit: base.to_owned(),
+            head_commit: head.to_owned(),
+            base_ref: None,
+            head_ref: None,
+            min_approvals: None,
+            required_approvers: Vec::new(),
+            compare_status: None,
+            error: None,
+            changes: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn validate_refs() {
+        mock_repo_changeset("v1", "v2").validate_refs().await.unwrap();
+        mock_repo_changeset("", "v2").validate_refs().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn resolve_previous_tag() {
+        let mut repo_changeset = mock_repo_changeset("", "v2.1.0");
+        let release = |tag: &str, day: u32| Release {
+            tag: tag.to_owned(),
+            created_at: Utc.with_ymd_and_hms(2024, 7, day, 12, 0, 0).unwrap(),
+        };
+        // listed newest first like the API does, with a patch release of an old version created in between
+        let client = repo_changeset.remote.client.as_ref().unwrap();
+        *client.releases.lock().unwrap() = vec![
+            release("v2.1.0", 20),
+            release("v1.9.1", 15),
+            release("v2.0.0", 10),
+            release("v1.9.0", 1),
+        ];
+        for (tag, status) in [("v1.9.1", CompareStatus::Diverged), ("v2.0.0", CompareStatus::Ahead)] {
+            let key = (tag.to_owned(), "v2.1.0".to_owned());
+            client.compares.lock().unwrap().insert(key.clone(), Vec::new());
+            client.compare_statuses.lock().unwrap().insert(key, status);
+        }
+
+        // v1.9.1 was released from a maintenance branch, which v2.1.0 does not contain
+        repo_changeset.resolve_previous_tag().await.unwrap();
+        assert_eq!(repo_changeset.base_commit, "v2.0.0");
+
+        let mut first_release = mock_repo_changeset("", "v1.9.0");
+        *first_release.remote.client.as_ref().unwrap().releases.lock().unwrap() = vec![release("v1.9.0", 1)];
+        assert_eq!(
+            format!("{:#}", first_release.resolve_previous_tag().await.unwrap_err()),
+            "v1.9.0 is the first release of project, there is no tag to compare with"
+        );
+        assert_eq!(
+            format!(
+                "{:#}",
+                mock_repo_changeset("", "1111111")
+                    .resolve_previous_tag()
+                    .await
+                    .unwrap_err()
+            ),
+            "--auto-base previous-tag needs a tag as head, got commit 1111111 for project"
+        );
+    }
+
+    #[tokio::test]
+    async fn resolve_merge_base() {
+        let mut repo_changeset = mock_repo_changeset("main", "v2");
+        repo_changeset
+            .remote
+            .client
+            .as_ref()
+            .unwrap()
+            .merge_bases
+            .lock()
+            .unwrap()
+            .insert(("main".to_owned(), "v2".to_owned()), "v1".to_owned());
+
+        repo_changeset.resolve_merge_base().await.unwrap();
+        assert_eq!(repo_changeset.base_commit, "v1");
+        assert_eq!(repo_changeset.head_commit, "v2");
+
+        // without a base there is nothing to resolve
+        let mut repo_changeset = mock_repo_changeset("", "v2");
+        repo_changeset.resolve_merge_base().await.unwrap();
+        assert_eq!(repo_changeset.base_commit, "");
+    }
+
+    #[tokio::test]
+    async fn resolve_refs() {
+        let mut repo_changeset = mock_repo_changeset("v1.0.0", "2222222");
+        repo_changeset
+            .remote
+            .client
+            .as_ref()
+            .unwrap()
+            .refs
+            .lock()
+            .unwrap()
+            .insert(
+                "v1.0.0".to_owned(),
+                "1111111111111111111111111111111111111111".to_owned(),
+            );
+
+        repo_changeset.resolve_refs().await.unwrap();
+        assert_eq!(repo_changeset.base_commit, "1111111111111111111111111111111111111111");
+        assert_eq!(repo_changeset.base_ref.as_deref(), Some("v1.0.0"));
+        // commit hashes are used as they are
+        assert_eq!(repo_changeset.head_commit, "2222222");
+        assert_eq!(repo_changeset.head_ref, None);
+
+        let err = mock_repo_changeset("", "v2.0.0").resolve_refs().await.unwrap_err();
+        assert_eq!(err.to_string(), "while resolving head ref \"v2.0.0\" of project");
+    }
+
+    #[tokio::test]
+    async fn resolve_refs_branch_and_sha() {
+        // a branch that looks like a commit hash is resolved when given as branch
+        let mut repo_changeset = mock_repo_changeset("refs/heads/cafe123", "2222222");
+        repo_changeset
+            .remote
+            .client
+            .as_ref()
+            .unwrap()
+            .refs
+            .lock()
+            .unwrap()
+            .insert(
+                "refs/heads/cafe123".to_owned(),
+                "1111111111111111111111111111111111111111".to_owned(),
+            );
+        repo_changeset.resolve_refs().await.unwrap();
+        assert_eq!(repo_changeset.base_commit, "1111111111111111111111111111111111111111");
+        assert_eq!(repo_changeset.base_ref.as_deref(), Some("cafe123"));
+
+        // and used as it is when given as sha
+        let mut repo_changeset = mock_repo_changeset("cafe123", "2222222");
+        repo_changeset.resolve_refs().await.unwrap();
+        assert_eq!(repo_changeset.base_commit, "cafe123");
+        assert_eq!(repo_changeset.base_ref, None);
+    }
+
+    #[tokio::test]
+    async fn resolve_head() {
+        let mut repo_changeset = mock_repo_changeset("1111111", "HEAD");
+        let remote_client = repo_changeset.remote.client.as_ref().unwrap();
+        *remote_client.default_branch.lock().unwrap() = Some("main".to_owned());
+        remote_client
+            .refs
+            .lock()
+            .unwrap()
+            .insert("main".to_owned(), "2222222222222222222222222222222222222222".to_owned());
+
+        repo_changeset.resolve_head().await.unwrap();
+        assert_eq!(repo_changeset.head_commit, "main");
+        repo_changeset.resolve_refs().await.unwrap();
+        assert_eq!(repo_changeset.head_commit, "2222222222222222222222222222222222222222");
+        assert_eq!(repo_changeset.head_ref.as_deref(), Some("main"));
+
+        // other refs are left alone
+        let mut repo_changeset = mock_repo_changeset("v1", "v2");
+        repo_changeset.resolve_head().await.unwrap();
+        assert_eq!(repo_changeset.head_commit, "v2");
+    }
+
+    #[tokio::test]
+    async fn validate_refs_invalid_base() {
+        let err = mock_repo_changeset("v0", "v2").validate_refs().await.unwrap_err();
+        assert_eq!(err.to_string(), "base ref \"v0\" does not exist in example/project");
+    }
+
+    #[tokio::test]
+    async fn validate_refs_invalid_head() {
+        let err = mock_repo_changeset("v1", "v3").validate_refs().await.unwrap_err();
+        assert_eq!(err.to_string(), "head ref \"v3\" does not exist in example/project");
+    }
+
+    // two commits of PR 1 with a direct push in between
+    fn mock_pr_commits() -> RepoChangeset<MockClient> {
+        let repo_changeset = mock_repo_changeset("v1", "v2");
+        let remote_client = repo_changeset.remote.client.as_ref().unwrap();
+
+        remote_client
+            .compares
+            .lock()
+            .unwrap()
+            .insert(("v1".to_owned(), "v2".to_owned()), vec![
+                Commit {
+                    message: "Add feature".to_owned(),
+                    ..Commit::test("1111111")
+                },
+                Commit {
+                    message: "Push a fix directly".to_owned(),
+                    ..Commit::test("2222222")
+                },
+                Commit {
+                    message: "Fix feature".to_owned(),
+                    ..Commit::test("3333333")
+                },
+            ]);
+
+        let pr = PullRequest {
+            number: 1,
+            url: "https://github.com/example/project/pull/1".to_owned(),
+            draft: false,
+        };
+        remote_client.associated_prs.lock().unwrap().extend([
+            ("1111111".to_owned(), vec![pr.clone()]),
+            ("2222222".to_owned(), Vec::new()),
+            ("3333333".to_owned(), vec![pr]),
+        ]);
+
+        remote_client.pr_head.lock().unwrap().insert(1, PullRequestHead {
+            repo: Some("example/project".to_owned()),
+            sha: "3333333".to_owned(),
+        });
+        remote_client.pr_reviews.lock().unwrap().insert(1, vec![Review {
+            id: 10,
+            state: ReviewState::Approved,
+            commit_id: "3333333".to_owned(),
+            submitted_at: 42,
+            user: "user1".to_owned(),
+        }]);
+        remote_client
+            .commits
+            .lock()
+            .unwrap()
+            .insert("3333333".to_owned(), Commit::test("3333333"));
+
+        repo_changeset
+    }
+
+    #[tokio::test]
+    async fn analyze_commits() {
+        let headlines = |changeset: &Changeset| -> Vec<String> {
+            changeset
+                .commits
+                .iter()
+                .map(|commit| commit.title().to_owned())
+                .collect()
+        };
+
+        // by default, later commits of a PR only add their approvals to the row of the first one
+        let repo_changeset = mock_pr_commits()
+            .analyze_commits(AnalyzeOptions::default())
+            .await
+            .unwrap();
+        let changes = repo_changeset.changes;
+        assert_eq!(changes.len(), 2);
+        assert_eq!(headlines(&changes[0]), vec!["Add feature"]);
+        assert_eq!(
+            changes[0].pr_link.as_deref(),
+            Some("https://github.com/example/project/pull/1")
+        );
+        assert_eq!(changes[0].approvals, vec!["user1", "user1"]);
+        assert_eq!(headlines(&changes[1]), vec!["Push a fix directly"]);
+        assert_eq!(changes[1].pr_link, None);
+        assert!(changes[1].approvals.is_empty());
+
+        // commits of the same PR are merged into the position of the first one
+        let options = AnalyzeOptions {
+            group_by: GroupBy::Pr,
+            ..AnalyzeOptions::default()
+        };
+        let grouped = mock_pr_commits().analyze_commits(options).await.unwrap().changes;
+        assert_eq!(grouped.len(), 2);
+        assert_eq!(headlines(&grouped[0]), vec!["Add feature", "Fix feature"]);
+        assert_eq!(
+            grouped[0].pr_link.as_deref(),
+            Some("https://github.com/example/project/pull/1")
+        );
+        assert_eq!(grouped[0].approvals, vec!["user1"]);
+        assert_eq!(
+            grouped[0].approved_commits,
+            BTreeMap::from([("user1".to_owned(), "3333333".to_owned())])
+        );
+        assert_eq!(headlines(&grouped[1]), vec!["Push a fix directly"]);
+        assert_eq!(grouped[1].pr_link, None);
+    }
+
+    #[tokio::test]
+    async fn analyze_commits_date_range() {
+        let repo_changeset = mock_repo_changeset("", "main");
+        let remote_client = repo_changeset.remote.client.as_ref().unwrap();
+        remote_client
+            .branch_commits
+            .lock()
+            .unwrap()
+            .insert("main".to_owned(), vec![
+                Commit {
+                    authored_at: Some(Utc.with_ymd_and_hms(2024, 7, 1, 12, 0, 0).unwrap()),
+                    ..Commit::test("1111111")
+                },
+                Commit {
+                    authored_at: Some(Utc.with_ymd_and_hms(2024, 7, 15, 12, 0, 0).unwrap()),
+                    ..Commit::test("2222222")
+                },
+                Commit {
+                    authored_at: Some(Utc.with_ymd_and_hms(2024, 7, 20, 12, 0, 0).unwrap()),
+                    ..Commit::test("3333333")
+                },
+                Commit {
+                    authored_at: Some(Utc.with_ymd_and_hms(2024, 7, 31, 12, 0, 0).unwrap()),
+                    ..Commit::test("4444444")
+                },
+            ]);
+        remote_client
+            .associated_prs
+            .lock()
+            .unwrap()
+            .extend([("2222222".to_owned(), Vec::new()), ("3333333".to_owned(), Vec::new())]);
+
+        let options = AnalyzeOptions {
+            date_range: Some(DateRange {
+                from: Utc.with_ymd_and_hms(2024, 7, 10, 0, 0, 0).unwrap(),
+                to: Utc.with_ymd_and_hms(2024, 7, 30, 0, 0, 0).unwrap(),
+            }),
+            // keeps the direct pushes apart
+            group_by: GroupBy::Pr,
+            ..AnalyzeOptions::default()
+        };
+        let repo_changeset = repo_changeset.analyze_commits(options).await.unwrap();
+        let links: Vec<&str> = repo_changeset
+            .changes
+            .iter()
+            .map(|change| change.commits[0].link.as_str())
+            .collect();
+        assert_eq!(links, vec![
+            "https://github.com/example/project/commit/2222222",
+            "https://github.com/example/project/commit/3333333",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn analyze_commits_paths() {
+        let repo_changeset = mock_repo_changeset("v1", "v2");
+        let remote_client = repo_changeset.remote.client.as_ref().unwrap();
+        remote_client
+            .compares
+            .lock()
+            .unwrap()
+            .insert(("v1".to_owned(), "v2".to_owned()), vec![
+                Commit::test("1111111"),
+                Commit::test("2222222"),
+                Commit::test("3333333"),
+            ]);
+        remote_client.commit_files.lock().unwrap().extend([
+            ("1111111".to_owned(), vec!["README.md".to_owned()]),
+            ("2222222".to_owned(), vec![
+                "docs/auth.md".to_owned(),
+                "src/auth/token.go".to_owned(),
+            ]),
+            ("3333333".to_owned(), vec!["src/api/handler.go".to_owned()]),
+        ]);
+        remote_client
+            .associated_prs
+            .lock()
+            .unwrap()
+            .insert("2222222".to_owned(), Vec::new());
+
+        let options = AnalyzeOptions {
+            paths: vec![Pattern::new("src/auth/**").unwrap()],
+            ..AnalyzeOptions::default()
+        };
+        let repo_changeset = repo_changeset.analyze_commits(options).await.unwrap();
+        let links: Vec<&str> = repo_changeset
+            .changes
+            .iter()
+            .map(|change| change.commits[0].link.as_str())
+            .collect();
+        assert_eq!(links, vec!["https://github.com/example/project/commit/2222222"]);
+    }
+
+    #[tokio::test]
+    async fn analyze_commits_branch_protection() {
+        let repo_changeset = mock_repo_changeset("v1", "v2");
+        let remote_client = repo_changeset.remote.client.as_ref().unwrap();
+        remote_client
+            .compares
+            .lock()
+            .unwrap()
+            .insert(("v1".to_owned(), "v2".to_owned()), vec![Commit::test("1111111")]);
+        remote_client
+            .associated_prs
+            .lock()
+            .unwrap()
+            .insert("1111111".to_owned(), vec![PullRequest {
+                number: 1,
+                url: "https://github.com/example/project/pull/1".to_owned(),
+                draft: false,
+            }]);
+        remote_client.pr_head.lock().unwrap().insert(1, PullRequestHead {
+            repo: Some("example/project".to_owned()),
+            sha: "1111111".to_owned(),
+        });
+        remote_client.pr_reviews.lock().unwrap().insert(1, vec![Review {
+            id: 10,
+            state: ReviewState::Approved,
+            commit_id: "1111111".to_owned(),
+            submitted_at: 42,
+            user: "user1".to_owned(),
+        }]);
+        remote_client
+            .commits
+            .lock()
+            .unwrap()
+            .insert("1111111".to_owned(), Commit::test("1111111"));
+        *remote_client.default_branch.lock().unwrap() = Some("main".to_owned());
+        remote_client
+            .branch_protections
+            .lock()
+            .unwrap()
+            .insert("main".to_owned(), BranchProtection {
+                required_approvals: Some(2),
+                ..BranchProtection::default()
+            });
+
+        let options = AnalyzeOptions {
+            use_branch_protection: true,
+            ..AnalyzeOptions::default()
+        };
+        let repos = vec![repo_changeset.analyze_commits(options).await.unwrap()];
+        assert_eq!(repos[0].min_approvals, Some(2));
+
+        // the one approval satisfies --min-approvals, but not the branch protection
+        let violations = policy::violations(&repos, &[FailPolicy::BelowThreshold], 1);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(
+            violations[0].change,
+            "project: https://github.com/example/project/pull/1"
+        );
+    }
+
+    #[tokio::test]
+    async fn analyze_commits_signatures() {
+        let repo_changeset = mock_repo_changeset("v1", "v2");
+        let remote_client = repo_changeset.remote.client.as_ref().unwrap();
+        remote_client
+            .compares
+            .lock()
+            .unwrap()
+            .insert(("v1".to_owned(), "v2".to_owned()), vec![
+                Commit {
+                    signature: Some(Signature {
+                        verified: true,
+                        reason: "valid".to_owned(),
+                    }),
+                    ..Commit::test("1111111")
+                },
+                Commit {
+                    signature: Some(Signature {
+                        verified: false,
+                        reason: "unsigned".to_owned(),
+                    }),
+                    ..Commit::test("2222222")
+                },
+                Commit::test("3333333"),
+            ]);
+        for sha in ["1111111", "2222222", "3333333"] {
+            remote_client
+                .associated_prs
+                .lock()
+                .unwrap()
+                .insert(sha.to_owned(), Vec::new());
+        }
+
+        // keeps the direct pushes apart
+        let options = AnalyzeOptions {
+            group_by: GroupBy::Pr,
+            ..AnalyzeOptions::default()
+        };
+        let repos = vec![repo_changeset.analyze_commits(options).await.unwrap()];
+        let signatures: Vec<Option<&str>> = repos[0]
+            .changes
+            .iter()
+            .map(|change| {
+                change.commits[0]
+                    .signature
+                    .as_ref()
+                    .map(|signature| signature.reason.as_str())
+            })
+            .collect();
+        assert_eq!(signatures, vec![Some("valid"), Some("unsigned"), None]);
+
+        // commits without a known signature count as unsigned
+        let violations = policy::violations(&repos, &[FailPolicy::Unsigned], 1);
+        assert_eq!(violations.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn analyze_commits_behind() {
+        // v1 was passed as head and v2 as base, so head is an ancestor of base
+        let repo_changeset = mock_repo_changeset("v2", "v1");
+        let remote_client = repo_changeset.remote.client.as_ref().unwrap();
+        let key = ("v2".to_owned(), "v1".to_owned());
+        remote_client.compares.lock().unwrap().insert(key.clone(), Vec::new());
+        remote_client
+            .compare_statuses
+            .lock()
+            .unwrap()
+            .insert(key, CompareStatus::Behind);
+
+        let repo = repo_changeset.analyze_commits(AnalyzeOptions::default()).await.unwrap();
+        assert_eq!(repo.compare_status, Some(CompareStatus::Behind));
+        assert!(repo.changes.is_empty());
+    }
+
+    // PR 2 backports PR 1 to a release branch with commit 2222222
+    fn mock_backport(merge_commit: Option<&str>, backport_message: &str) -> RepoChangeset<MockClient> {
+        let repo_changeset = mock_repo_changeset("v1", "v2");
+        let remote_client = repo_changeset.remote.client.as_ref().unwrap();
+        let backport_commit = Commit {
+            message: backport_message.to_owned(),
+            ..Commit::test("2222222")
+        };
+        remote_client
+            .compares
+            .lock()
+            .unwrap()
+            .insert(("v1".to_owned(), "v2".to_owned()), vec![backport_commit.clone()]);
+        remote_client
+            .commits
+            .lock()
+            .unwrap()
+            .insert("2222222".to_owned(), backport_commit);
+        remote_client
+            .associated_prs
+            .lock()
+            .unwrap()
+            .insert("2222222".to_owned(), vec![PullRequest {
+                number: 2,
+                url: "https://github.com/example/project/pull/2".to_owned(),
+                draft: false,
+            }]);
+        let patch = |line: &str| {
+            vec![FilePatch {
+                path: "main.go".to_owned(),
+                patch: Some(format!("@@ -1 +1 @@\n-bug\n+{line}\n")),
+            }]
+        };
+        remote_client.commit_patches.lock().unwrap().extend([
+            ("1111111".to_owned(), patch("fix")),
+            ("1111112".to_owned(), patch("fix")),
+            ("2222222".to_owned(), patch("backdoor")),
+        ]);
+
+        for (number, sha, title, body, approvers, merge_commit) in [
+            (1, "1111111", "Fix feature", "", vec!["user1", "user2"], merge_commit),
+            (
+                2,
+                "2222222",
+                "[stable] Fix feature",
+                "Backport of #1\n\nCherry-picked",
+                vec!["user2"],
+                None,
+            ),
+        ] {
+            remote_client.pr_head.lock().unwrap().insert(number, PullRequestHead {
+                repo: Some("example/project".to_owned()),
+                sha: sha.to_owned(),
+            });
+            remote_client
+                .pr_commits
+                .lock()
+                .unwrap()
+                .insert(number, vec![sha.to_owned()]);
+            remote_client
+                .pr_descriptions
+                .lock()
+                .unwrap()
+                .insert(number, PullRequestDescription {
+                    title: title.to_owned(),
+                    body: body.to_owned(),
+                    merge_commit: merge_commit.map(ToOwned::to_owned),
+                });
+            let reviews = approvers
+                .into_iter()
+                .enumerate()
+                .map(|(id, user)| Review {
+                    id: id as u64,
+                    state: ReviewState::Approved,
+                    commit_id: sha.to_owned(),
+                    submitted_at: 42,
+                    user: user.to_owned(),
+                })
+                .collect();
+            remote_client.pr_reviews.lock().unwrap().insert(number, reviews);
+        }
+        repo_changeset
+    }
+
+    #[tokio::test]
+    async fn analyze_commits_follow_backports() {
+        let options = AnalyzeOptions {
+            follow_backports: true,
+            ..AnalyzeOptions::default()
+        };
+        let cherry_picked = "Fix feature\n\n(cherry picked from commit 1111111)";
+
+        let repo = mock_backport(Some("1111112"), cherry_picked)
+            .analyze_commits(options.clone())
+            .await
+            .unwrap();
+        assert_eq!(repo.changes[0].approvals, vec!["user1", "user2"]);
+        assert_eq!(
+            repo.changes[0].backport_approvals,
+            BTreeMap::from([(
+                "user1".to_owned(),
+                "https://github.com/example/project/pull/1".to_owned()
+            )])
+        );
+
+        // the approvals of a PR that was closed without merging don't count
+        let repo = mock_backport(None, cherry_picked)
+            .analyze_commits(options.clone())
+            .await
+            .unwrap();
+        assert_eq!(repo.changes[0].approvals, vec!["user2"]);
+
+        // neither a cherry-pick nor the same change as a commit of PR 1
+        let repo = mock_backport(Some("1111112"), "Fix feature")
+            .analyze_commits(options)
+            .await
+            .unwrap();
+        assert_eq!(repo.changes[0].approvals, vec!["user2"]);
+        assert!(repo.changes[0].backport_approvals.is_empty());
+    }
+
+    #[tokio::test]
+    async fn analyze_commits_follow_backports_same_change() {
+        let repo_changeset = mock_backport(Some("1111112"), "Fix feature");
+        let remote_client = repo_changeset.remote.client.as_ref().unwrap();
+        // the backport was cherry-picked without -x, but changes the same lines as the squashed PR 1
+        remote_client
+            .commit_patches
+            .lock()
+            .unwrap()
+            .insert("2222222".to_owned(), vec![FilePatch {
+                path: "main.go".to_owned(),
+                patch: Some("@@ -7 +7 @@\n-bug\n+fix\n".to_owned()),
+            }]);
+        // the author of the backport can't approve it through the original PR
+        remote_client
+            .compares
+            .lock()
+            .unwrap()
+            .get_mut(&("v1".to_owned(), "v2".to_owned()))
+            .unwrap()[0]
+            .author = Some("user1".to_owned());
+        let options = AnalyzeOptions {
+            follow_backports: true,
+            ..AnalyzeOptions::default()
+        };
+
+        let repo = repo_changeset.analyze_commits(options).await.unwrap();
+        assert_eq!(repo.changes[0].approvals, vec!["user2"]);
+        assert_eq!(repo.changes[0].non_qualifying_approvals, vec!["user1"]);
+        assert!(repo.changes[0].backport_approvals.is_empty());
+    }
+
+    #[test]
+    fn cherry_picked_from() {
+        assert_eq!(
+            super::cherry_picked_from(
+                "Fix things\n\n(cherry picked from commit 1A2B3C4D5E6F7A8B9C0D1A2B3C4D5E6F7A8B9C0D)"
+            ),
+            Some("1a2b3c4d5e6f7a8b9c0d1a2b3c4d5e6f7a8b9c0d".to_owned())
+        );
+        assert_eq!(
+            super::cherry_picked_from("Fix things (cherry picked from commit abc)"),
+            None
+        );
+        assert_eq!(super::cherry_picked_from("Fix things"), None);
+    }
+
+    #[test]
+    fn backport_of() {
+        assert_eq!(super::backport_of("Backport of #187 to stable/2024.1"), Some(187));
+        assert_eq!(super::backport_of("This is an automated BACKPORT OF #42."), Some(42));
+        assert_eq!(super::backport_of("Backport of #abc"), None);
+        assert_eq!(
+            super::backport_of("Backport of https://github.com/sapcc/keppel/pull/187"),
+            None
+        );
+        assert_eq!(super::backport_of("Fix the frobnicator (#187)"), None);
+    }
+
+    #[test]
+    fn reverted_commit() {
+        assert_eq!(
+            super::reverted_commit(
+                "Revert \"Fix things\"\n\nThis reverts commit 1A2B3C4D5E6F7A8B9C0D1A2B3C4D5E6F7A8B9C0D."
+            ),
+            Some("1a2b3c4d5e6f7a8b9c0d1a2b3c4d5e6f7a8b9c0d".to_owned())
+        );
+        assert_eq!(
+            super::reverted_commit(
+                "Revert merge\n\n  This reverts commit abcdef1, reversing\nchanges made to 1234567."
+            ),
+            Some("abcdef1".to_owned())
+        );
+        assert_eq!(super::reverted_commit("This reverts commit abc."), None);
+        assert_eq!(super::reverted_commit("Revert the frobnicator"), None);
+    }
+
+    fn revert_change(sha: &str, message: &str) -> Changeset {
+        Changeset {
+            commits: vec![CommitMetadata::new(&Commit {
+                message: message.to_owned(),
+                ..Commit::test(sha)
+            })],
+            ..Changeset::default()
+        }
+    }
+
+    #[test]
+    fn revert_candidates() {
+        let changes = vec![
+            revert_change("1111111111111111111111111111111111111111", "Add the frobnicator"),
+            revert_change("2222222222222222222222222222222222222222", "Fix things"),
+            revert_change(
+                "3333333333333333333333333333333333333333",
+                "Revert \"Add the frobnicator\"\n\nThis reverts commit 1111111.",
+            ),
+            // the reverted commit is not part of the range
+            revert_change(
+                "4444444444444444444444444444444444444444",
+                "Revert \"Old things\"\n\nThis reverts commit 5555555555555555555555555555555555555555.",
+            ),
+        ];
+
+        assert_eq!(super::revert_candidates(&changes), vec![(
+            "3333333333333333333333333333333333333333".to_owned(),
+            "1111111111111111111111111111111111111111".to_owned()
+        )]);
+        assert_eq!(
+            changes[3].commits[0].reverts.as_deref(),
+            Some("5555555555555555555555555555555555555555")
+        );
+    }
+
+    #[test]
+    fn cancel_reverts() {
+        let pair = |revert: &str, target: &str| (revert.repeat(40), target.repeat(40));
+        let mut changes: Vec<Changeset> = ["1", "2", "3", "4", "5", "6", "7"]
+            .iter()
+            .map(|sha| revert_change(&sha.repeat(40), "Change things"))
+            .collect();
+        super::cancel_reverts(&mut changes, &[
+            // 2 reverts 1
+            pair("2", "1"),
+            // 4 reverts 3 and 5 reverts 4, so 3 is back
+            pair("4", "3"),
+            pair("5", "4"),
+            // 7 reverts 6 across another change
+            pair("7", "6"),
+        ]);
+
+        let self_cancelling: Vec<bool> = changes.iter().map(Changeset::is_self_cancelling).collect();
+        assert_eq!(self_cancelling, vec![true, true, false, true, true, true, true]);
+    }
+
+    #[tokio::test]
+    async fn analyze_commits_pair_reverts() {
+        let repo_changeset = mock_repo_changeset("v1", "v2");
+        let remote_client = repo_changeset.remote.client.as_ref().unwrap();
+        remote_client
+            .compares
+            .lock()
+            .unwrap()
+            .insert(("v1".to_owned(), "v2".to_owned()), vec![
+                Commit {
+                    message: "Add the frobnicator".to_owned(),
+                    ..Commit::test("1111111")
+                },
+                Commit {
+                    message: "Revert \"Add the frobnicator\"\n\nThis reverts commit 1111111.".to_owned(),
+                    ..Commit::test("2222222")
+                },
+                Commit {
+                    message: "Fix things".to_owned(),
+                    ..Commit::test("3333333")
+                },
+                // claims to be a revert, but changes something else
+                Commit {
+                    message: "Revert \"Fix things\"\n\nThis reverts commit 3333333.".to_owned(),
+                    ..Commit::test("4444444")
+                },
+            ]);
+        let patch = |path: &str, patch: &str| FilePatch {
+            path: path.to_owned(),
+            patch: Some(patch.to_owned()),
+        };
+        remote_client.commit_patches.lock().unwrap().extend([
+            ("1111111".to_owned(), vec![patch(
+                "main.go",
+                "@@ -1 +1 @@\n-old\n+frobnicator\n",
+            )]),
+            ("2222222".to_owned(), vec![patch(
+                "main.go",
+                "@@ -1 +1 @@\n-frobnicator\n+old\n",
+            )]),
+            ("3333333".to_owned(), vec![patch(
+                "main.go",
+                "@@ -1 +1 @@\n-bug\n+fix\n",
+            )]),
+            ("4444444".to_owned(), vec![patch(
+                "main.go",
+                "@@ -1 +1 @@\n-fix\n+backdoor\n",
+            )]),
+        ]);
+        remote_client
+            .associated_prs
+            .lock()
+            .unwrap()
+            .extend(["1111111", "2222222", "3333333", "4444444"].map(|sha| (sha.to_owned(), Vec::new())));
+
+        let options = AnalyzeOptions {
+            pair_reverts: true,
+            // keeps the direct pushes apart
+            group_by: GroupBy::Pr,
+            ..AnalyzeOptions::default()
+        };
+        let repo = repo_changeset.analyze_commits(options).await.unwrap();
+        let self_cancelling: Vec<bool> = repo.changes.iter().map(Changeset::is_self_cancelling).collect();
+        assert_eq!(self_cancelling, vec![true, true, false, false]);
+    }
+
+    #[tokio::test]
+    async fn analyze_open_prs() {
+        let repo_changeset = mock_repo_changeset("", "v2");
+        let remote_client = repo_changeset.remote.client.as_ref().unwrap();
+        let pr = |number: u64, author: &str, head_sha: &str| OpenPullRequest {
+            number,
+            url: format!("https://github.com/example/project/pull/{number}"),
+            title: format!("Open PR {number}"),
+            author: Some(author.to_owned()),
+            head_sha: head_sha.to_owned(),
+        };
+        *remote_client.open_prs.lock().unwrap() = vec![pr(1, "author1", "1111111"), pr(2, "author2", "2222222")];
+        for (number, sha) in [(1, "1111111"), (2, "2222222")] {
+            remote_client.pr_head.lock().unwrap().insert(number, PullRequestHead {
+                repo: Some("example/project".to_owned()),
+                sha: sha.to_owned(),
+            });
+        }
+        let review = |id: u64, user: &str, commit_id: &str| Review {
+            id,
+            state: ReviewState::Approved,
+            commit_id: commit_id.to_owned(),
+            submitted_at: i64::try_from(id).unwrap(),
+            user: user.to_owned(),
+        };
+        remote_client.pr_reviews.lock().unwrap().extend([
+            // the approval of the author does not count
+            (1, vec![
+                review(10, "user1", "1111111"),
+                review(11, "author1", "1111111"),
+            ]),
+            (2, vec![review(20, "user2", "0000002")]),
+        ]);
+        remote_client
+            .pr_commits
+            .lock()
+            .unwrap()
+            .insert(2, vec!["0000002".to_owned(), "2222222".to_owned()]);
+        remote_client
+            .commits
+            .lock()
+            .unwrap()
+            .insert("1111111".to_owned(), Commit::test("1111111"));
+
+        let options = AnalyzeOptions {
+            open_prs: true,
+            ..AnalyzeOptions::default()
+        };
+        let repo = repo_changeset.analyze_commits(options).await.unwrap();
+        assert_eq!(repo.changes.len(), 2);
+        assert_eq!(repo.changes[0].commits[0].title(), "Open PR 1");
+        assert_eq!(
+            repo.changes[0].commits[0].link,
+            "https://github.com/example/project/commit/1111111"
+        );
+        assert_eq!(
+            repo.changes[0].pr_link.as_deref(),
+            Some("https://github.com/example/project/pull/1")
+        );
+        assert_eq!(repo.changes[0].approvals, vec!["user1"]);
+        // approved before the last push
+        assert!(repo.changes[1].approvals.is_empty());
+        assert_eq!(repo.changes[1].partial_approvals, vec!["user2"]);
+    }
+
+    #[tokio::test]
+    async fn analyze_open_prs_approved_before_head() {
+        let repo_changeset = mock_repo_changeset("", "v2");
+        let remote_client = repo_changeset.remote.client.as_ref().unwrap();
+        *remote_client.open_prs.lock().unwrap() = vec![OpenPullRequest {
+            number: 1,
+            url: "https://github.com/example/project/pull/1".to_owned(),
+            title: "Open PR 1".to_owned(),
+            author: Some("author1".to_owned()),
+            head_sha: "1111111".to_owned(),
+        }];
+        remote_client.pr_head.lock().unwrap().insert(1, PullRequestHead {
+            repo: Some("example/project".to_owned()),
+            sha: "1111111".to_owned(),
+        });
+        let review = |id: u64, user: &str, submitted_at: i64| Review {
+            id,
+            state: ReviewState::Approved,
+            commit_id: "1111111".to_owned(),
+            submitted_at,
+            user: user.to_owned(),
+        };
+        remote_client
+            .pr_reviews
+            .lock()
+            .unwrap()
+            .insert(1, vec![review(10, "user1", 1_000_000), review(11, "user2", 3_000_000)]);
+        remote_client
+            .commits
+            .lock()
+            .unwrap()
+            .insert("1111111".to_owned(), Commit {
+                message: "Open PR 1".to_owned(),
+                author: Some("author1".to_owned()),
+                authored_at: DateTime::from_timestamp(2, 0),
+                ..Commit::test("1111111")
+            });
+
+        let options = AnalyzeOptions {
+            open_prs: true,
+            ..AnalyzeOptions::default()
+        };
+        let repo = repo_changeset.analyze_commits(options).await.unwrap();
+        assert_eq!(repo.changes[0].approvals, vec!["user1", "user2"]);
+        assert_eq!(repo.changes[0].approved_before_head, vec!["user1"]);
+    }
+
+    #[tokio::test]
+    async fn analyze_commit_pr_without_commits() {
+        let remote = get_mock_remote();
+        let remote_client = remote.client.as_ref().unwrap();
+        remote_client
+            .associated_prs
+            .lock()
+            .unwrap()
+            .insert("00000000000000000000000000000002".to_string(), vec![PullRequest {
+                number: 1,
+                url: "https://github.com/example/project/pulls/1".to_owned(),
+                draft: false,
+            }]);
+        // user1 approved a commit that is no longer part of the PR, which has no commits left at all
+        remote_client.pr_reviews.lock().unwrap().insert(1, vec![Review {
+            id: 10,
+            state: ReviewState::Approved,
+            commit_id: "00000000000000000000000000000002".to_owned(),
+            submitted_at: 42,
+            user: "user1".to_owned(),
+        }]);
+        remote_client.pr_head.lock().unwrap().insert(1, PullRequestHead {
+            repo: Some("example/project".to_owned()),
+            sha: "00000000000000000000000000000001".to_owned(),
+        });
+        remote_client.pr_commits.lock().unwrap().insert(1, Vec::new());
+
+        let changeset = RepoChangeset::analyze_commit(
+            remote.clone().into(),
+            Commit::test("00000000000000000000000000000002"),
+            None,
+            false,
+            false,
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(changeset.len(), 1);
+        assert_eq!(
+            changeset[0].pr_link.as_deref(),
+            Some("https://github.com/example/project/pulls/1")
+        );
+        assert_eq!(changeset[0].approvals, Vec::<String>::new());
+        assert_eq!(changeset[0].partial_approvals, Vec::<String>::new());
+        assert!(changeset[0].pr_without_commits);
+
+        // also noticed without any approval of an earlier commit
+        remote_client.pr_reviews.lock().unwrap().insert(1, Vec::new());
+        let changeset = RepoChangeset::analyze_commit(
+            remote.into(),
+            Commit::test("00000000000000000000000000000002"),
+            None,
+            false,
+            false,
+            None,
+        )
+        .await
+        .unwrap();
+        assert!(changeset[0].pr_without_commits);
+    }
+
+    #[tokio::test]
+    async fn analyze_commit_duplicate_prs() {
+        let remote = get_mock_remote();
+        let remote_client = remote.client.as_ref().unwrap();
+        let pr = |url: &str| PullRequest {
+            number: 1,
+            url: url.to_owned(),
+            draft: false,
+        };
+        // GitHub lists the PR once per base the commit was merged into
+        remote_client
+            .associated_prs
+            .lock()
+            .unwrap()
+            .insert("00000000000000000000000000000001".to_string(), vec![
+                pr("https://github.com/example/project/pull/1"),
+                pr("https://github.com/example/project/pulls/1"),
+            ]);
+        remote_client.pr_reviews.lock().unwrap().insert(1, vec![Review {
+            id: 10,
+            state: ReviewState::Approved,
+            commit_id: "00000000000000000000000000000001".to_owned(),
+            submitted_at: 42,
+            user: "user1".to_owned(),
+        }]);
+        remote_client.pr_head.lock().unwrap().insert(1, PullRequestHead {
+            repo: Some("example/project".to_owned()),
+            sha: "00000000000000000000000000000001".to_owned(),
+        });
+
+        let changeset = RepoChangeset::analyze_commit(
+            remote.into(),
+            Commit::test("00000000000000000000000000000001"),
+            None,
+            false,
+            false,
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(changeset.len(), 1);
+        assert_eq!(
+            changeset[0].pr_link.as_deref(),
+            Some("https://github.com/example/project/pull/1")
+        );
+        assert_eq!(changeset[0].approvals, vec!["user1"]);
+    }
+
+    #[tokio::test]
+    async fn analyze_commit_ignore_drafts() {
+        let remote = get_mock_remote();
+        let remote_client = remote.client.as_ref().unwrap();
+        let pr = |number: u64, draft: bool| PullRequest {
+            number,
+            url: format!("https://github.com/example/project/pull/{number}"),
+            draft,
+        };
+        remote_client
+            .associated_prs
+            .lock()
+            .unwrap()
+            .insert("00000000000000000000000000000001".to_string(), vec![
+                pr(1, true),
+                pr(2, false),
+            ]);
+        for (number, user) in [(1, "user1"), (2, "user2")] {
+            remote_client.pr_reviews.lock().unwrap().insert(number, vec![Review {
+                id: number,
+                state: ReviewState::Approved,
+                commit_id: "00000000000000000000000000000001".to_owned(),
+                submitted_at: 42,
+                user: user.to_owned(),
+            }]);
+            remote_client.pr_head.lock().unwrap().insert(number, PullRequestHead {
+                repo: Some("example/project".to_owned()),
+                sha: "00000000000000000000000000000001".to_owned(),
+            });
+        }
+        let remote = Arc::new(remote);
+        let commit = Commit::test("00000000000000000000000000000001");
+
+        let changeset = RepoChangeset::analyze_commit(remote.clone(), commit.clone(), None, false, false, None)
+            .await
+            .unwrap();
+        assert_eq!(changeset.len(), 2);
+
+        let changeset = RepoChangeset::analyze_commit(remote, commit, None, false, true, None)
+            .await
+            .unwrap();
+        assert_eq!(changeset.len(), 1);
+        assert_eq!(
+            changeset[0].pr_link.as_deref(),
+            Some("https://github.com/example/project/pull/2")
+        );
+        assert_eq!(changeset[0].approvals, vec!["user2"]);
+    }
+
+    #[tokio::test]
+    async fn analyze_commit_ignore_drafts_parse_pr_from_message() {
+        let remote = get_mock_remote();
+        let remote_client = remote.client.as_ref().unwrap();
+        remote_client
+            .associated_prs
+            .lock()
+            .unwrap()
+            .insert("00000000000000000000000000000001".to_string(), vec![PullRequest {
+                number: 1,
+                url: "https://github.com/example/project/pull/1".to_owned(),
+                draft: true,
+            }]);
+        let remote = Arc::new(remote);
+        let commit = Commit {
+            message: "Fix the frobnicator (#1)".to_owned(),
+            ..Commit::test("00000000000000000000000000000001")
+        };
+
+        let changeset = RepoChangeset::analyze_commit(remote, commit, None, true, true, None)
+            .await
+            .unwrap();
+        assert_eq!(changeset.len(), 1);
+        assert_eq!(changeset[0].pr_link, None);
+    }
+
+    #[tokio::test]
+    async fn analyze_commit_squash_merged() {
+        let remote = get_mock_remote();
+        let remote_client = remote.client.as_ref().unwrap();
+        // the squashed commit on the base branch is not part of the PR, whose head was approved
+        remote_client
+            .associated_prs
+            .lock()
+            .unwrap()
+            .insert("00000000000000000000000000000003".to_string(), vec![PullRequest {
+                number: 1,
+                url: "https://github.com/example/project/pulls/1".to_owned(),
+                draft: false,
+            }]);
+        remote_client.pr_reviews.lock().unwrap().insert(1, vec![Review {
+            id: 10,
+            state: ReviewState::Approved,
+            commit_id: "00000000000000000000000000000002".to_owned(),
+            submitted_at: 42,
+            user: "user1".to_owned(),
+        }]);
+        remote_client.pr_head.lock().unwrap().insert(1, PullRequestHead {
+            repo: Some("example/project".to_owned()),
+            sha: "00000000000000000000000000000002".to_owned(),
+        });
+
+        let changeset = RepoChangeset::analyze_commit(
+            remote.into(),
+            Commit::test("00000000000000000000000000000003"),
+            None,
+            false,
+            false,
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(changeset.len(), 1);
+        assert_eq!(changeset[0].approvals, vec!["user1".to_owned()]);
+        assert_eq!(changeset[0].partial_approvals, Vec::<String>::new());
+    }
+
+    #[tokio::test]
+    async fn analyze_commit_approved() {
+        let remote = get_mock_remote();
+        let remote_client = remote.client.as_ref().unwrap();
+
+        remote_client
+            .associated_prs
+            .lock()
+            .unwrap()
+            .insert("00000000000000000000000000000002".to_string(), vec![PullRequest {
+                number: 1,
+                url: "https://github.com/example/project/pulls/1".to_owned(),
+                draft: false,
+            }]);
 
         remote_client.pr_reviews.lock().unwrap().insert(1, vec![
             Review {
-                approved: false,
+                id: 10,
+                state: ReviewState::Commented,
                 commit_id: "00000000000000000000000000000001".to_owned(),
                 submitted_at: 42,
                 user: "user1".to_owned(),
             },
             Review {
-                approved: true,
+                id: 11,
+                state: ReviewState::Approved,
                 commit_id: "00000000000000000000000000000002".to_owned(),
                 submitted_at: 42,
                 user: "user1".to_owned(),
             },
         ]);
 
-        remote_client
-            .pr_head_hash
-            .lock()
-            .unwrap()
-            .insert(1, "00000000000000000000000000000002".to_owned());
-
-        let changeset = RepoChangeset::analyze_commit(remote.into(), Commit {
-            html_url: "https://github.com/example/project/commit/00000000000000000000000000000002".to_owned(),
-            message: "Testing test".to_owned(),
+        remote_client.pr_head.lock().unwrap().insert(1, PullRequestHead {
+            repo: Some("example/project".to_owned()),
             sha: "00000000000000000000000000000002".to_owned(),
-        })
+        });
+
+        let changeset = RepoChangeset::analyze_commit(
+            remote.into(),
+            Commit {
+                message: "Testing test".to_owned(),
+                ..Commit::test("00000000000000000000000000000002")
+            },
+            None,
+            false,
+            false,
+            None,
+        )
         .await
         .unwrap();
 
@@ -275,10 +2845,17 @@ mod tests {
         assert_eq!(changeset[0], Changeset {
             approvals: vec!["user1".to_owned()],
             commits: vec![CommitMetadata {
-                headline: "Testing test".to_owned(),
+                headline: Some("Testing test".to_owned()),
                 link: "https://github.com/example/project/commit/00000000000000000000000000000002".to_owned(),
+                sha: "00000000000000000000000000000002".to_owned(),
+                ..CommitMetadata::default()
             }],
             pr_link: Some("https://github.com/example/project/pulls/1".to_owned()),
+            non_qualifying_approvals: vec![],
+            approved_commits: BTreeMap::from([("user1".to_owned(), "00000000000000000000000000000002".to_owned())]),
+            changes_requested_by: vec![],
+            approved_at: BTreeMap::from([("user1".to_owned(), DateTime::from_timestamp_micros(42).unwrap())]),
+            ..Changeset::default()
         });
     }
 
@@ -294,26 +2871,33 @@ mod tests {
             .insert("00000000000000000000000000000002".to_string(), vec![PullRequest {
                 number: 1,
                 url: "https://github.com/example/project/pulls/2".to_owned(),
+                draft: false,
             }]);
 
         remote_client.pr_reviews.lock().unwrap().insert(1, vec![Review {
-            approved: false,
+            id: 12,
+            state: ReviewState::Commented,
             commit_id: "00000000000000000000000000000001".to_owned(),
             submitted_at: 42,
             user: "user1".to_owned(),
         }]);
 
-        remote_client
-            .pr_head_hash
-            .lock()
-            .unwrap()
-            .insert(1, "00000000000000000000000000000003".to_owned());
+        remote_client.pr_head.lock().unwrap().insert(1, PullRequestHead {
+            repo: Some("example/project".to_owned()),
+            sha: "00000000000000000000000000000003".to_owned(),
+        });
 
-        let changeset = RepoChangeset::analyze_commit(remote.into(), Commit {
-            html_url: "https://github.com/example/project/commit/00000000000000000000000000000002".to_owned(),
-            message: "Testing test".to_owned(),
-            sha: "00000000000000000000000000000002".to_owned(),
-        })
+        let changeset = RepoChangeset::analyze_commit(
+            remote.into(),
+            Commit {
+                message: "Testing test".to_owned(),
+                ..Commit::test("00000000000000000000000000000002")
+            },
+            None,
+            false,
+            false,
+            None,
+        )
         .await
         .unwrap();
 
@@ -321,10 +2905,172 @@ mod tests {
         assert_eq!(changeset[0], Changeset {
             approvals: vec![],
             commits: vec![CommitMetadata {
-                headline: "Testing test".to_owned(),
+                headline: Some("Testing test".to_owned()),
                 link: "https://github.com/example/project/commit/00000000000000000000000000000002".to_owned(),
+                sha: "00000000000000000000000000000002".to_owned(),
+                ..CommitMetadata::default()
             }],
             pr_link: Some("https://github.com/example/project/pulls/2".to_owned()),
+            non_qualifying_approvals: vec![],
+            changes_requested_by: vec![],
+            ..Changeset::default()
+        });
+    }
+
+    #[tokio::test]
+    async fn analyze_commit_required_team() {
+        let remote = get_mock_remote();
+        let remote_client = remote.client.as_ref().unwrap();
+
+        remote_client
+            .associated_prs
+            .lock()
+            .unwrap()
+            .insert("00000000000000000000000000000002".to_string(), vec![PullRequest {
+                number: 1,
+                url: "https://github.com/example/project/pulls/1".to_owned(),
+                draft: false,
+            }]);
+
+        remote_client.pr_reviews.lock().unwrap().insert(1, vec![
+            Review {
+                id: 13,
+                state: ReviewState::Approved,
+                commit_id: "00000000000000000000000000000002".to_owned(),
+                submitted_at: 42,
+                user: "user1".to_owned(),
+            },
+            Review {
+                id: 14,
+                state: ReviewState::Approved,
+                commit_id: "00000000000000000000000000000002".to_owned(),
+                submitted_at: 43,
+                user: "user2".to_owned(),
+            },
+        ]);
+
+        remote_client.pr_head.lock().unwrap().insert(1, PullRequestHead {
+            repo: Some("example/project".to_owned()),
+            sha: "00000000000000000000000000000002".to_owned(),
+        });
+
+        remote_client
+            .team_members
+            .lock()
+            .unwrap()
+            .insert("example/security-reviewers".to_owned(), vec!["user2".to_owned()]);
+
+        let team: Team = "example/security-reviewers".parse().unwrap();
+        let team_members = remote.team_members(&team).await.unwrap();
+
+        let changeset = RepoChangeset::analyze_commit(
+            remote.into(),
+            Commit {
+                message: "Testing test".to_owned(),
+                ..Commit::test("00000000000000000000000000000002")
+            },
+            Some(Arc::new(team_members)),
+            false,
+            false,
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(changeset.len(), 1);
+        assert_eq!(changeset[0].approvals, vec!["user2".to_owned()]);
+        assert_eq!(changeset[0].non_qualifying_approvals, vec!["user1".to_owned()]);
+    }
+
+    #[tokio::test]
+    async fn analyze_commit_pr_from_message() {
+        let remote = get_mock_remote();
+        let remote_client = remote.client.as_ref().unwrap();
+
+        remote_client
+            .associated_prs
+            .lock()
+            .unwrap()
+            .insert("00000000000000000000000000000002".to_string(), vec![]);
+        remote_client.pr_reviews.lock().unwrap().insert(7, vec![Review {
+            id: 10,
+            state: ReviewState::Approved,
+            commit_id: "00000000000000000000000000000003".to_owned(),
+            submitted_at: 42,
+            user: "user1".to_owned(),
+        }]);
+        remote_client.pr_head.lock().unwrap().insert(7, PullRequestHead {
+            repo: Some("example/project".to_owned()),
+            sha: "00000000000000000000000000000003".to_owned(),
+        });
+
+        let remote = Arc::new(remote);
+        let commit = Commit {
+            message: "Testing test (#7)".to_owned(),
+            ..Commit::test("00000000000000000000000000000002")
+        };
+
+        let changeset = RepoChangeset::analyze_commit(remote.clone(), commit.clone(), None, false, false, None)
+            .await
+            .unwrap();
+        assert_eq!(changeset[0].pr_link, None);
+
+        let changeset = RepoChangeset::analyze_commit(remote, commit, None, true, false, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            changeset[0].pr_link,
+            Some("https://github.com/example/project/pull/7".to_owned())
+        );
+        assert_eq!(changeset[0].approvals, vec!["user1"]);
+    }
+
+    #[tokio::test]
+    async fn analyze_commit_fork() {
+        let remote = get_mock_remote();
+        let remote_client = remote.client.as_ref().unwrap();
+
+        remote_client
+            .associated_prs
+            .lock()
+            .unwrap()
+            .insert("00000000000000000000000000000002".to_string(), vec![PullRequest {
+                number: 1,
+                url: "https://github.com/example/project/pulls/1".to_owned(),
+                draft: false,
+            }]);
+        // the PR was pushed to again in the fork after it was merged, the approval is on the head of the PR
+        remote_client.pr_reviews.lock().unwrap().insert(1, vec![Review {
+            id: 10,
+            state: ReviewState::Approved,
+            commit_id: "00000000000000000000000000000003".to_owned(),
+            submitted_at: 42,
+            user: "user1".to_owned(),
+        }]);
+        remote_client.pr_head.lock().unwrap().insert(1, PullRequestHead {
+            repo: Some("contributor/project".to_owned()),
+            sha: "00000000000000000000000000000003".to_owned(),
         });
+
+        let changeset = RepoChangeset::analyze_commit(
+            remote.into(),
+            Commit {
+                message: "Testing test".to_owned(),
+                ..Commit::test("00000000000000000000000000000002")
+            },
+            None,
+            false,
+            false,
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(changeset.len(), 1);
+        assert_eq!(changeset[0].approvals, vec!["user1"]);
+        assert_eq!(
+            changeset[0].approved_commits,
+            BTreeMap::from([("user1".to_owned(), "00000000000000000000000000000003".to_owned())])
+        );
     }
 }
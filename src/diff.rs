@@ -0,0 +1,435 @@
+// Copyright 2024 SAP SE
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::path::PathBuf;
+
+use anyhow::{bail, Context};
+
+use crate::github::FilePatch;
+
+const DEV_NULL: &str = "/dev/null";
+
+/// A file of a unified diff with its whole content before and after the change.
+#[derive(Debug, Default, PartialEq)]
+pub struct FileDiff {
+    /// path after the change, or before the change when the file was deleted
+    pub path: PathBuf,
+    /// None when the file was added
+    pub old: Option<String>,
+    /// None when the file was deleted
+    pub new: Option<String>,
+}
+
+/// Parses the output of `git diff --unified=1000000`. Every file needs to be a single hunk that contains the whole
+/// file, so that its old and new content can be restored. Files without a hunk, e.g. renamed ones, are left out.
+pub fn parse(diff: &str) -> Result<Vec<FileDiff>, anyhow::Error> {
+    let mut files = Vec::new();
+    let mut file: Option<FileDiff> = None;
+    // the old and new lines that the current hunk still needs according to its header
+    let mut hunk: Option<(usize, usize)> = None;
+    let mut had_hunk = false;
+
+    for (idx, line) in diff.lines().enumerate() {
+        let line_number = idx + 1;
+        if line.starts_with("diff ") {
+            if let Some(current) = file.take() {
+                check_hunk_complete(&current, hunk)?;
+                files.extend(Some(current).filter(has_content));
+            }
+            file = Some(FileDiff::default());
+            hunk = None;
+            had_hunk = false;
+            continue;
+        }
+        let Some(current) = file.as_mut() else {
+            // e.g. the commit message when the diff comes from git show
+            continue;
+        };
+
+        if let Some((old_count, new_count)) = hunk.as_mut() {
+            let (old, new) = match line.as_bytes().first() {
+                Some(b' ') => (Some(&line[1..]), Some(&line[1..])),
+                Some(b'-') => (Some(&line[1..]), None),
+                Some(b'+') => (None, Some(&line[1..])),
+                // "\ No newline at end of file" does not matter for YAML
+                Some(b'\\') => (None, None),
+                // some tools strip the space of empty context lines
+                None => (Some(""), Some("")),
+                Some(_) => bail!("unexpected line {line_number} in hunk of {}", current.path.display()),
+            };
+            for (count, line) in [(&mut *old_count, old), (&mut *new_count, new)] {
+                if line.is_some() {
+                    *count = count.checked_sub(1).with_context(|| {
+                        format!(
+                            "hunk of {} has more lines than its header announces on line {line_number}",
+                            current.path.display()
+                        )
+                    })?;
+                }
+            }
+            for (content, line) in [(&mut current.old, old), (&mut current.new, new)] {
+                if let (Some(content), Some(line)) = (content, line) {
+                    content.push_str(line);
+                    content.push('\n');
+                }
+            }
+            if *old_count == 0 && *new_count == 0 {
+                hunk = None;
+            }
+        } else if let Some(header) = line.strip_prefix("@@ ") {
+            if had_hunk {
+                bail!(
+                    "{} has more than one hunk on line {line_number}, create the diff with --unified=1000000 so that \
+                     it contains whole files",
+                    current.path.display()
+                );
+            }
+            let counts =
+                parse_hunk_header(header).with_context(|| format!("invalid hunk header on line {line_number}"))?;
+            hunk = Some(counts).filter(|&counts| counts != (0, 0));
+            had_hunk = true;
+        } else if had_hunk && line.starts_with(['+', '-', ' ']) {
+            bail!(
+                "hunk of {} has more lines than its header announces on line {line_number}",
+                current.path.display()
+            );
+        } else if let Some(path) = line.strip_prefix("--- ") {
+            if path != DEV_NULL {
+                current.path = strip_prefix(path, "a/");
+                current.old = Some(String::new());
+            }
+        } else if let Some(path) = line.strip_prefix("+++ ") {
+            if path != DEV_NULL {
+                current.path = strip_prefix(path, "b/");
+                current.new = Some(String::new());
+            }
+        }
+    }
+    if let Some(current) = file {
+        check_hunk_complete(&current, hunk)?;
+        files.extend(Some(current).filter(has_content));
+    }
+
+    Ok(files)
+}
+
+/// Splits a unified diff like `git show` prints it into the patches of its files, starting at their first hunk.
+pub fn file_patches(diff: &str) -> Vec<FilePatch> {
+    let mut patches: Vec<FilePatch> = Vec::new();
+    let mut in_hunk = false;
+
+    for line in diff.lines() {
+        if line.starts_with("diff ") {
+            // binary files have no ---/+++ lines, so their path is only in the header
+            let path = line.rsplit_once(" b/").map(|(_, path)| path).unwrap_or_default();
+            patches.push(FilePatch {
+                path: path.to_owned(),
+                patch: None,
+            });
+            in_hunk = false;
+            continue;
+        }
+        let Some(current) = patches.last_mut() else {
+            continue;
+        };
+
+        if line.starts_with("@@") {
+            in_hunk = true;
+        }
+        if in_hunk {
+            let patch = current.patch.get_or_insert_with(String::new);
+            patch.push_str(line);
+            patch.push('\n');
+        } else if let Some(path) = line.strip_prefix("--- ").filter(|path| *path != DEV_NULL) {
+            current.path = strip_prefix(path, "a/").to_string_lossy().into_owned();
+        } else if let Some(path) = line.strip_prefix("+++ ").filter(|path| *path != DEV_NULL) {
+            current.path = strip_prefix(path, "b/").to_string_lossy().into_owned();
+        }
+    }
+    patches
+}
+
+/// Whether `revert` removes exactly the lines that `target` added and adds back exactly the lines it removed, file
+/// by file. Files without a patch can't be compared, so they never match.
+pub fn is_inverse(target: &[FilePatch], revert: &[FilePatch]) -> bool {
+    compare_patches(target, revert, ('+', '-'))
+}
+
+/// Whether both commits remove and add the same lines in the same files, like a cherry-pick and its original do
+/// regardless of the line numbers of their hunks. Files without a patch never match.
+pub fn is_same_change(original: &[FilePatch], copy: &[FilePatch]) -> bool {
+    compare_patches(original, copy, ('-', '+'))
+}
+
+// The prefixes of the removed and added lines of `other` that need to match those of `patches`.
+fn compare_patches(patches: &[FilePatch], other: &[FilePatch], (removed, added): (char, char)) -> bool {
+    patches.len() == other.len()
+        && patches.iter().all(|file| {
+            other.iter().any(|other_file| {
+                other_file.path == file.path
+                    && matches!(
+                        (&file.patch, &other_file.patch),
+                        (Some(patch), Some(other_patch))
+                            if changed_lines(patch, '-', '+') == changed_lines(other_patch, removed, added)
+                    )
+            })
+        })
+}
+
+// The removed and the added lines of the hunks of a patch, in this order.
+fn changed_lines(patch: &str, removed: char, added: char) -> (Vec<&str>, Vec<&str>) {
+    let lines = |prefix| {
+        patch
+            .lines()
+            .filter_map(move |line| line.strip_prefix(prefix))
+            .collect()
+    };
+    (lines(removed), lines(added))
+}
+
+fn has_content(file: &FileDiff) -> bool {
+    file.old.is_some() || file.new.is_some()
+}
+
+fn strip_prefix(path: &str, prefix: &str) -> PathBuf {
+    // git appends a tab to paths containing spaces
+    let path = path.trim_end_matches('\t');
+    PathBuf::from(path.strip_prefix(prefix).unwrap_or(path))
+}
+
+// A hunk with the whole file starts at the first line, or at line 0 when one side is empty. Returns the number of
+// old and new lines that the header announces.
+fn parse_hunk_header(header: &str) -> Result<(usize, usize), anyhow::Error> {
+    let mut ranges = header.split_whitespace();
+    let mut counts = [0; 2];
+    for (prefix, count) in ['-', '+'].into_iter().zip(&mut counts) {
+        let range = ranges
+            .next()
+            .and_then(|range| range.strip_prefix(prefix))
+            .context("missing range")?;
+        let (start, length) = range.split_once(',').unwrap_or((range, "1"));
+        if start != "0" && start != "1" {
+            bail!(
+                "hunk starts at line {start}, create the diff with --unified=1000000 so that it contains whole files"
+            );
+        }
+        *count = length
+            .parse()
+            .with_context(|| format!("invalid line count {length:?}"))?;
+    }
+    Ok((counts[0], counts[1]))
+}
+
+fn check_hunk_complete(file: &FileDiff, hunk: Option<(usize, usize)>) -> Result<(), anyhow::Error> {
+    match hunk {
+        Some((old, new)) => bail!(
+            "hunk of {} is missing {old} old and {new} new lines that its header announces",
+            file.path.display()
+        ),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_diff() {
+        let diff = "\
+diff --git a/openstack/keppel/images.yaml b/openstack/keppel/images.yaml
+index 1111111..2222222 100644
+--- a/openstack/keppel/images.yaml
++++ b/openstack/keppel/images.yaml
+@@ -1,3 +1,3 @@
+ containerImages:
+-  keppel: old
++  keppel: new
+   limes: same
+diff --git a/openstack/limes/images.yaml b/openstack/limes/images.yaml
+new file mode 100644
+index 0000000..3333333
+--- /dev/null
++++ b/openstack/limes/images.yaml
+@@ -0,0 +1,1 @@
++containerImages: {}
+\\ No newline at end of file
+diff --git a/old/images.yaml b/new/images.yaml
+similarity index 100%
+rename from old/images.yaml
+rename to new/images.yaml
+diff --git a/openstack/nova/images.yaml b/openstack/nova/images.yaml
+deleted file mode 100644
+index 4444444..0000000
+--- a/openstack/nova/images.yaml
++++ /dev/null
+@@ -1 +0,0 @@
+-containerImages: {}
+";
+
+        assert_eq!(parse(diff).unwrap(), vec![
+            FileDiff {
+                path: PathBuf::from("openstack/keppel/images.yaml"),
+                old: Some("containerImages:\n  keppel: old\n  limes: same\n".to_owned()),
+                new: Some("containerImages:\n  keppel: new\n  limes: same\n".to_owned()),
+            },
+            FileDiff {
+                path: PathBuf::from("openstack/limes/images.yaml"),
+                old: None,
+                new: Some("containerImages: {}\n".to_owned()),
+            },
+            FileDiff {
+                path: PathBuf::from("openstack/nova/images.yaml"),
+                old: Some("containerImages: {}\n".to_owned()),
+                new: None,
+            },
+        ]);
+    }
+
+    #[test]
+    fn parse_diff_without_full_context() {
+        let diff = "\
+diff --git a/images.yaml b/images.yaml
+--- a/images.yaml
++++ b/images.yaml
+@@ -10,3 +10,3 @@ containerImages:
+";
+        assert_eq!(
+            format!("{:#}", parse(diff).unwrap_err()),
+            "invalid hunk header on line 4: hunk starts at line 10, create the diff with --unified=1000000 so that it \
+             contains whole files"
+        );
+
+        let diff = "\
+diff --git a/images.yaml b/images.yaml
+--- a/images.yaml
++++ b/images.yaml
+@@ -1,1 +1,1 @@
+-a
++b
+@@ -5,1 +5,1 @@
+";
+        assert_eq!(
+            parse(diff).unwrap_err().to_string(),
+            "images.yaml has more than one hunk on line 7, create the diff with --unified=1000000 so that it contains \
+             whole files"
+        );
+    }
+
+    #[test]
+    fn parse_diff_with_wrong_line_counts() {
+        let diff = "\
+diff --git a/images.yaml b/images.yaml
+--- a/images.yaml
++++ b/images.yaml
+@@ -1,3 +1,3 @@
+ containerImages:
+-  keppel: old
++  keppel: new
+diff --git a/other.yaml b/other.yaml
+";
+        assert_eq!(
+            parse(diff).unwrap_err().to_string(),
+            "hunk of images.yaml is missing 1 old and 1 new lines that its header announces"
+        );
+
+        let diff = "\
+diff --git a/images.yaml b/images.yaml
+--- a/images.yaml
++++ b/images.yaml
+@@ -1,2 +1,2 @@
+ containerImages:
+-  keppel: old
++  keppel: new
+   limes: same
+";
+        assert_eq!(
+            parse(diff).unwrap_err().to_string(),
+            "hunk of images.yaml has more lines than its header announces on line 8"
+        );
+    }
+
+    #[test]
+    fn file_patches() {
+        let diff = "\
+commit 1111111
+diff --git a/main.go b/main.go
+index 1111111..2222222 100644
+--- a/main.go
++++ b/main.go
+@@ -1,2 +1,2 @@
+ package main
+--- old comment
++++ new comment
+diff --git a/logo.png b/logo.png
+deleted file mode 100644
+Binary files a/logo.png and /dev/null differ
+diff --git a/README.md b/README.md
+new file mode 100644
+--- /dev/null
++++ b/README.md
+@@ -0,0 +1 @@
++# Readme
+";
+        assert_eq!(super::file_patches(diff), vec![
+            FilePatch {
+                path: "main.go".to_owned(),
+                patch: Some("@@ -1,2 +1,2 @@\n package main\n--- old comment\n+++ new comment\n".to_owned()),
+            },
+            FilePatch {
+                path: "logo.png".to_owned(),
+                patch: None,
+            },
+            FilePatch {
+                path: "README.md".to_owned(),
+                patch: Some("@@ -0,0 +1 @@\n+# Readme\n".to_owned()),
+            },
+        ]);
+    }
+
+    #[test]
+    fn is_inverse() {
+        let patch = |path: &str, patch: Option<&str>| FilePatch {
+            path: path.to_owned(),
+            patch: patch.map(str::to_owned),
+        };
+        let target = [patch("main.go", Some("@@ -1,2 +1,2 @@\n package main\n-old\n+new\n"))];
+        let revert = [patch("main.go", Some("@@ -1,2 +1,2 @@\n package main\n-new\n+old\n"))];
+        assert!(super::is_inverse(&target, &revert));
+        // the same change again does not undo it
+        assert!(!super::is_inverse(&target, &target));
+        assert!(!super::is_inverse(&target, &[
+            revert[0].clone(),
+            patch("other.go", Some("@@ -0,0 +1 @@\n+other\n"))
+        ]));
+        assert!(!super::is_inverse(&[patch("logo.png", None)], &[patch(
+            "logo.png", None
+        )]));
+    }
+
+    #[test]
+    fn is_same_change() {
+        let patch = |patch: &str| FilePatch {
+            path: "main.go".to_owned(),
+            patch: Some(patch.to_owned()),
+        };
+        let original = [patch("@@ -10,2 +10,2 @@\n context\n-old\n+new\n")];
+        // a cherry-pick onto an older branch has other line numbers and context
+        let copy = [patch("@@ -3,2 +3,2 @@\n other context\n-old\n+new\n")];
+        assert!(super::is_same_change(&original, &copy));
+        assert!(!super::is_same_change(&original, &[patch(
+            "@@ -3 +3 @@\n-old\n+newer\n"
+        )]));
+    }
+}
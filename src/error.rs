@@ -0,0 +1,138 @@
+// Copyright 2024 SAP SE
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use http::StatusCode;
+use thiserror::Error;
+
+use crate::http_client::RetryAfter;
+
+/// Errors of the API clients by category, so that callers can react to them without matching on messages. They are
+/// passed on wrapped in anyhow errors with context, [`PearError::find`] gets them back.
+#[derive(Debug, Error)]
+pub enum PearError {
+    /// the token is missing, invalid or lacks the permissions for the request
+    #[error("authentication failed: {0}")]
+    Auth(String),
+    #[error("rate limit exceeded: {0}")]
+    RateLimited(String),
+    #[error("not found: {0}")]
+    NotFound(String),
+    /// the response does not have the expected format
+    #[error("failed to parse response: {0}")]
+    Parse(String),
+    /// a paginated response has more pages than allowed by --max-pages
+    #[error("more than {0} pages of results, narrow down the analyzed range or raise --max-pages")]
+    TooManyPages(usize),
+    #[error("no client attached to remote")]
+    NoClient,
+    #[error(transparent)]
+    Api(octocrab::Error),
+}
+
+impl PearError {
+    /// The categorized error somewhere in the chain of causes of the given error.
+    pub fn find(err: &anyhow::Error) -> Option<&PearError> {
+        err.chain().find_map(|cause| cause.downcast_ref::<PearError>())
+    }
+}
+
+impl From<octocrab::Error> for PearError {
+    fn from(err: octocrab::Error) -> Self {
+        match &err {
+            octocrab::Error::GitHub { source, .. } => {
+                let message = source.message.clone();
+                match source.status_code {
+                    StatusCode::TOO_MANY_REQUESTS => PearError::RateLimited(message),
+                    // GitHub answers with 403 for missing permissions as well as for an exhausted rate limit
+                    StatusCode::FORBIDDEN if message.to_lowercase().contains("rate limit") => {
+                        PearError::RateLimited(message)
+                    },
+                    StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => PearError::Auth(message),
+                    StatusCode::NOT_FOUND => PearError::NotFound(message),
+                    _ => PearError::Api(err),
+                }
+            },
+            octocrab::Error::Service { source, .. } if source.is::<RetryAfter>() => {
+                PearError::RateLimited(source.to_string())
+            },
+            octocrab::Error::Serde { source, .. } => PearError::Parse(source.to_string()),
+            octocrab::Error::Json { source, .. } => PearError::Parse(source.to_string()),
+            _ => PearError::Api(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use anyhow::Context;
+
+    use super::*;
+    use crate::api_clients::ClientConfig;
+    use crate::http_client::build_octocrab;
+
+    // Answers every request with the given status and a GitHub style error message.
+    fn serve_status(status: &'static str, message: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut buf = [0; 4096];
+                let _ = stream.read(&mut buf);
+                let body = format!(r#"{{"message":"{message}"}}"#);
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: \
+                     close\r\n\r\n{body}",
+                    body.len()
+                );
+            }
+        });
+        format!("http://{addr}")
+    }
+
+    async fn request(status: &'static str, message: &'static str) -> PearError {
+        let octocrab = build_octocrab("token", &serve_status(status, message), &ClientConfig::default()).unwrap();
+        let err = octocrab
+            .get::<serde_json::Value, _, ()>("/repos/sapcc/keppel", None)
+            .await
+            .unwrap_err();
+        PearError::from(err)
+    }
+
+    #[tokio::test]
+    async fn from_octocrab_not_found() {
+        let err = request("404 Not Found", "Not Found").await;
+        assert!(matches!(&err, PearError::NotFound(message) if message == "Not Found"));
+
+        // the category survives being wrapped with context
+        let err = Err::<(), _>(err).context("failed to get repo").unwrap_err();
+        assert!(matches!(PearError::find(&err), Some(PearError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn from_octocrab_status_codes() {
+        let err = request("401 Unauthorized", "Bad credentials").await;
+        assert!(matches!(err, PearError::Auth(_)));
+        let err = request("403 Forbidden", "API rate limit exceeded for user ID 1.").await;
+        assert!(matches!(err, PearError::RateLimited(_)));
+        let err = request("403 Forbidden", "Resource not accessible by integration").await;
+        assert!(matches!(err, PearError::Auth(_)));
+        let err = request("422 Unprocessable Entity", "Validation Failed").await;
+        assert!(matches!(err, PearError::Api(_)));
+    }
+}
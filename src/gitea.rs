@@ -0,0 +1,783 @@
+// Copyright 2024 SAP SE
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context};
+use chrono::{DateTime, Utc};
+use octocrab::models::repos::Content;
+use octocrab::Octocrab;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use tokio::sync::Semaphore;
+
+use crate::api_clients::{Client, ClientConfig, CodeownersCache, Forge, TeamMembersCache, TokenSource};
+use crate::diff;
+use crate::error::PearError;
+use crate::github::{
+    self, BranchProtection, Commit, Comparison, FilePatch, OpenPullRequest, PullRequest, PullRequestDescription,
+    PullRequestHead, RateLimit, Release, Review, ReviewState, Signature, Team,
+};
+use crate::http_client::build_octocrab;
+use crate::retry::retry_transient;
+
+const MAX_PARALLEL_REQUESTS: usize = 5;
+// the default maximum page size of Gitea
+const PAGE_SIZE: usize = 50;
+// the locations in which Gitea looks for a CODEOWNERS file, in the order it looks
+const CODEOWNERS_PATHS: [&str; 3] = ["CODEOWNERS", "docs/CODEOWNERS", ".gitea/CODEOWNERS"];
+
+/// Talks to the API of Gitea and Forgejo, which mostly mirrors the one of GitHub.
+#[derive(Debug)]
+pub struct GiteaClient {
+    semaphore: Semaphore,
+    // only used as an HTTP client, so that proxies, timeouts and retries work the same as for GitHub
+    octocrab: Octocrab,
+    team_members_cache: TeamMembersCache,
+    codeowners_cache: CodeownersCache,
+    max_pages: usize,
+    verify_signatures: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct GiteaCommit {
+    sha: String,
+    html_url: String,
+    commit: GiteaRepoCommit,
+    author: Option<GiteaUser>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GiteaRepoCommit {
+    message: String,
+    author: Option<GiteaCommitUser>,
+    verification: Option<GiteaVerification>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GiteaVerification {
+    verified: bool,
+    reason: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GiteaCommitUser {
+    name: String,
+    date: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GiteaUser {
+    login: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GiteaCommitFiles {
+    #[serde(default)]
+    files: Vec<GiteaCommitFile>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GiteaCommitFile {
+    filename: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GiteaCompare {
+    commits: Vec<GiteaCommit>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GiteaPullRequest {
+    number: u64,
+    html_url: String,
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    body: String,
+    user: Option<GiteaUser>,
+    head: GiteaBranch,
+    /// only known since Gitea 1.22
+    #[serde(default)]
+    draft: bool,
+    #[serde(default)]
+    merged: bool,
+    merge_commit_sha: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GiteaBranch {
+    sha: String,
+    repo: Option<GiteaRepository>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GiteaRepository {
+    full_name: String,
+    #[serde(default)]
+    default_branch: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GiteaRelease {
+    tag_name: String,
+    draft: bool,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GiteaReview {
+    id: u64,
+    state: String,
+    /// only reviews of users that may approve count towards the approvals required by branch protection
+    official: bool,
+    dismissed: bool,
+    commit_id: String,
+    submitted_at: DateTime<Utc>,
+    user: Option<GiteaUser>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GiteaBranchProtection {
+    #[serde(default)]
+    required_approvals: usize,
+    enable_approvals_whitelist: bool,
+    #[serde(default)]
+    approvals_whitelist_username: Vec<String>,
+    #[serde(default)]
+    approvals_whitelist_teams: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GiteaTeamSearch {
+    data: Vec<GiteaTeam>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GiteaTeam {
+    id: u64,
+    name: String,
+}
+
+impl From<GiteaCommit> for Commit {
+    fn from(commit: GiteaCommit) -> Self {
+        let git_author = commit.commit.author;
+        Commit {
+            html_url: commit.html_url,
+            message: commit.commit.message,
+            sha: commit.sha,
+            author: commit
+                .author
+                .map(|author| author.login)
+                .or_else(|| git_author.as_ref().map(|author| author.name.clone())),
+            authored_at: git_author.and_then(|author| author.date),
+            signature: commit.commit.verification.map(|verification| Signature {
+                verified: verification.verified,
+                reason: verification.reason,
+            }),
+        }
+    }
+}
+
+impl Client for GiteaClient {
+    fn new(
+        _forge: Forge,
+        token: TokenSource,
+        api_endpoint: String,
+        config: &ClientConfig,
+    ) -> anyhow::Result<Arc<Self>> {
+        let token = token.token()?;
+        Ok(Arc::new(Self {
+            semaphore: Semaphore::new(MAX_PARALLEL_REQUESTS),
+            octocrab: build_octocrab(&token, &api_endpoint, config)?,
+            team_members_cache: TeamMembersCache::default(),
+            codeowners_cache: CodeownersCache::default(),
+            max_pages: config.max_pages,
+            verify_signatures: config.verify_signatures,
+        }))
+    }
+
+    fn forge(&self) -> Forge {
+        Forge::Gitea
+    }
+
+    // Gitea knows at most one PR per commit, namely the one that merged it.
+    async fn associated_prs(&self, owner: &str, repo: &str, sha: String) -> anyhow::Result<Vec<PullRequest>> {
+        let _permit = self.semaphore.acquire().await?;
+
+        let result: Result<GiteaPullRequest, _> = retry_transient(|| async {
+            self.octocrab
+                .get(format!("/repos/{owner}/{repo}/commits/{sha}/pull"), None::<&()>)
+                .await
+        })
+        .await
+        .map_err(PearError::from);
+        match result {
+            Ok(pr) => Ok(vec![PullRequest {
+                number: pr.number,
+                url: pr.html_url,
+                draft: pr.draft,
+            }]),
+            Err(PearError::NotFound(_)) => Ok(Vec::new()),
+            Err(err) => Err(err).context("failed to get associated prs"),
+        }
+    }
+
+    // Gitea has no batch lookup, every commit is looked up with associated_prs
+    async fn associated_prs_batch(
+        &self,
+        _owner: &str,
+        _repo: &str,
+        _shas: &[String],
+    ) -> anyhow::Result<HashMap<String, Vec<PullRequest>>> {
+        Ok(HashMap::new())
+    }
+
+    async fn compare(
+        &self,
+        owner: &str,
+        repo: &str,
+        _original: &str,
+        base_commit: &str,
+        head_commit: &str,
+    ) -> anyhow::Result<Comparison> {
+        let _permit = self.semaphore.acquire().await?;
+
+        let compare: GiteaCompare = retry_transient(|| async {
+            self.octocrab
+                .get(
+                    format!("/repos/{owner}/{repo}/compare/{base_commit}...{head_commit}"),
+                    None::<&()>,
+                )
+                .await
+        })
+        .await
+        .map_err(PearError::from)
+        .with_context(|| format!("failed to compare {base_commit}...{head_commit}"))?;
+
+        // Gitea does not report whether head is ahead of or behind base
+        Ok(Comparison {
+            status: None,
+            commits: compare.commits.into_iter().map(Commit::from).collect(),
+        })
+    }
+
+    async fn commit(&self, owner: &str, repo: &str, sha: &str) -> anyhow::Result<Commit> {
+        let _permit = self.semaphore.acquire().await?;
+
+        let commit: GiteaCommit = retry_transient(|| async {
+            self.octocrab
+                .get(format!("/repos/{owner}/{repo}/git/commits/{sha}"), None::<&()>)
+                .await
+        })
+        .await
+        .map_err(PearError::from)
+        .with_context(|| format!("failed to get commit {sha}"))?;
+
+        Ok(commit.into())
+    }
+
+    async fn commit_files(&self, owner: &str, repo: &str, sha: &str) -> anyhow::Result<Vec<String>> {
+        let _permit = self.semaphore.acquire().await?;
+
+        let commit: GiteaCommitFiles = retry_transient(|| async {
+            self.octocrab
+                .get(
+                    format!("/repos/{owner}/{repo}/git/commits/{sha}"),
+                    Some(&[("files", "true")]),
+                )
+                .await
+        })
+        .await
+        .map_err(PearError::from)
+        .with_context(|| format!("failed to get files of commit {sha}"))?;
+
+        Ok(commit.files.into_iter().map(|file| file.filename).collect())
+    }
+
+    // The commit API of Gitea lists no patches, only the raw diff has them.
+    async fn commit_patches(&self, owner: &str, repo: &str, sha: &str) -> anyhow::Result<Vec<FilePatch>> {
+        let _permit = self.semaphore.acquire().await?;
+
+        let diff = retry_transient(|| async {
+            let response = self
+                .octocrab
+                ._get(format!("/repos/{owner}/{repo}/git/commits/{sha}.diff"))
+                .await?;
+            let response = octocrab::map_github_error(response).await?;
+            self.octocrab.body_to_string(response).await
+        })
+        .await
+        .map_err(PearError::from)
+        .with_context(|| format!("failed to get patches of commit {sha}"))?;
+
+        Ok(diff::file_patches(&diff))
+    }
+
+    async fn commits_in_range(
+        &self,
+        owner: &str,
+        repo: &str,
+        head: &str,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<Commit>> {
+        let commits: Vec<GiteaCommit> = self
+            .get_all_pages(&format!("/repos/{owner}/{repo}/commits"), &[
+                ("sha", head.to_owned()),
+                ("since", since.to_rfc3339()),
+                ("until", until.to_rfc3339()),
+                // skip the expensive parts of the response which are not needed
+                ("stat", "false".to_owned()),
+                ("verification", self.verify_signatures.to_string()),
+                ("files", "false".to_owned()),
+            ])
+            .await
+            .with_context(|| format!("failed to list commits of {head} between {since} and {until}"))?;
+
+        // the list starts with the newest commit, while a compare starts with the oldest one
+        Ok(commits.into_iter().rev().map(Commit::from).collect())
+    }
+
+    // The commit endpoint of Gitea accepts branches and tags as well.
+    async fn resolve_ref(&self, owner: &str, repo: &str, reference: &str) -> anyhow::Result<String> {
+        let commit = self
+            .commit(owner, repo, reference)
+            .await
+            .with_context(|| format!("failed to resolve ref {reference}"))?;
+        Ok(commit.sha)
+    }
+
+    async fn merge_base(
+        &self,
+        _owner: &str,
+        _repo: &str,
+        _base_commit: &str,
+        _head_commit: &str,
+    ) -> anyhow::Result<String> {
+        bail!("Gitea has no API to find the merge base of two commits")
+    }
+
+    async fn pr_head(&self, owner: &str, repo: &str, pr_number: u64) -> anyhow::Result<PullRequestHead> {
+        let _permit = self.semaphore.acquire().await?;
+
+        let pr: GiteaPullRequest = retry_transient(|| async {
+            self.octocrab
+                .get(format!("/repos/{owner}/{repo}/pulls/{pr_number}"), None::<&()>)
+                .await
+        })
+        .await
+        .map_err(PearError::from)
+        .with_context(|| format!("failed to get pr {pr_number}"))?;
+
+        Ok(PullRequestHead {
+            repo: pr.head.repo.map(|repo| repo.full_name),
+            sha: pr.head.sha,
+        })
+    }
+
+    async fn pr_description(&self, owner: &str, repo: &str, pr_number: u64) -> anyhow::Result<PullRequestDescription> {
+        let _permit = self.semaphore.acquire().await?;
+
+        let pr: GiteaPullRequest = retry_transient(|| async {
+            self.octocrab
+                .get(format!("/repos/{owner}/{repo}/pulls/{pr_number}"), None::<&()>)
+                .await
+        })
+        .await
+        .map_err(PearError::from)
+        .with_context(|| format!("failed to get pr {pr_number}"))?;
+
+        Ok(PullRequestDescription {
+            title: pr.title,
+            body: pr.body,
+            merge_commit: pr.merge_commit_sha.filter(|_| pr.merged),
+        })
+    }
+
+    async fn pr_reviews(&self, owner: &str, repo: &str, pr_number: u64) -> anyhow::Result<Vec<Review>> {
+        let gitea_reviews: Vec<GiteaReview> = self
+            .get_all_pages(&format!("/repos/{owner}/{repo}/pulls/{pr_number}/reviews"), &[])
+            .await
+            .context("failed to get reviews")?;
+
+        let mut reviews = Vec::new();
+        for gitea_review in gitea_reviews {
+            reviews.push(review_from_gitea(gitea_review)?);
+        }
+        reviews.sort_by_key(|r| (r.submitted_at, r.id));
+        Ok(reviews)
+    }
+
+    async fn pr_commits(&self, owner: &str, repo: &str, pr_number: u64) -> anyhow::Result<Vec<String>> {
+        let pr_commits: Vec<GiteaCommit> = self
+            .get_all_pages(&format!("/repos/{owner}/{repo}/pulls/{pr_number}/commits"), &[])
+            .await
+            .context("failed to get PR commits")?;
+
+        Ok(pr_commits.into_iter().map(|commit| commit.sha).collect())
+    }
+
+    async fn list_open_prs(&self, owner: &str, repo: &str) -> anyhow::Result<Vec<OpenPullRequest>> {
+        let prs: Vec<GiteaPullRequest> = self
+            .get_all_pages(&format!("/repos/{owner}/{repo}/pulls"), &[("state", "open".to_owned())])
+            .await
+            .context("failed to list open prs")?;
+
+        Ok(prs
+            .into_iter()
+            .map(|pr| OpenPullRequest {
+                number: pr.number,
+                url: pr.html_url,
+                title: pr.title,
+                author: pr.user.map(|user| user.login),
+                head_sha: pr.head.sha,
+            })
+            .collect())
+    }
+
+    async fn team_members(&self, org: &str, team: &str) -> anyhow::Result<Vec<String>> {
+        self.team_members_cache
+            .get_or_fetch((org.to_owned(), team.to_owned()), || self.fetch_team_members(org, team))
+            .await
+    }
+
+    async fn default_branch(&self, owner: &str, repo: &str) -> anyhow::Result<String> {
+        let _permit = self.semaphore.acquire().await?;
+
+        let repository: GiteaRepository =
+            retry_transient(|| async { self.octocrab.get(format!("/repos/{owner}/{repo}"), None::<&()>).await })
+                .await
+                .map_err(PearError::from)
+                .with_context(|| format!("failed to get repo {owner}/{repo}"))?;
+
+        Ok(repository.default_branch)
+    }
+
+    async fn previous_tags(&self, owner: &str, repo: &str, tag: &str) -> anyhow::Result<Vec<String>> {
+        let releases: Vec<GiteaRelease> = self
+            .get_all_pages(&format!("/repos/{owner}/{repo}/releases"), &[])
+            .await
+            .context("failed to list releases")?;
+
+        let releases = releases
+            .into_iter()
+            .filter(|release| !release.draft)
+            .map(|release| Release {
+                tag: release.tag_name,
+                created_at: release.created_at,
+            })
+            .collect();
+        github::previous_tags(releases, tag)
+    }
+
+    // Branch protection rules of Gitea are looked up by their name, which is the branch name unless the rule uses a
+    // glob. Only users on the approvals whitelist can approve when it is enabled.
+    async fn branch_protection(
+        &self,
+        owner: &str,
+        repo: &str,
+        branch: &str,
+    ) -> anyhow::Result<Option<BranchProtection>> {
+        let _permit = self.semaphore.acquire().await?;
+
+        let result: Result<GiteaBranchProtection, _> = retry_transient(|| async {
+            self.octocrab
+                .get(
+                    format!("/repos/{owner}/{repo}/branch_protections/{branch}"),
+                    None::<&()>,
+                )
+                .await
+        })
+        .await
+        .map_err(PearError::from);
+        let protection = match result {
+            Ok(protection) => protection,
+            Err(PearError::NotFound(_)) => return Ok(None),
+            Err(err) => return Err(err).with_context(|| format!("failed to get protection of branch {branch}")),
+        };
+
+        let required_approvals = Some(protection.required_approvals).filter(|count| *count > 0);
+        if !protection.enable_approvals_whitelist {
+            return Ok(Some(BranchProtection {
+                required_approvals,
+                ..BranchProtection::default()
+            }));
+        }
+        Ok(Some(BranchProtection {
+            required_approvals,
+            users: protection.approvals_whitelist_username,
+            teams: protection
+                .approvals_whitelist_teams
+                .into_iter()
+                .map(|team| Team {
+                    org: owner.to_owned(),
+                    slug: team,
+                })
+                .collect(),
+        }))
+    }
+
+    async fn codeowners(&self, owner: &str, repo: &str, branch: &str) -> anyhow::Result<Option<String>> {
+        self.codeowners_cache
+            .get_or_fetch((owner.to_owned(), repo.to_owned(), branch.to_owned()), || {
+                self.fetch_codeowners(owner, repo, branch)
+            })
+            .await
+    }
+
+    // Gitea does not limit the rate of API requests by default and has no endpoint to query a limit.
+    async fn rate_limit(&self) -> anyhow::Result<RateLimit> {
+        Ok(RateLimit {
+            limit: u64::MAX,
+            remaining: u64::MAX,
+            reset: 0,
+        })
+    }
+}
+
+impl GiteaClient {
+    // Teams of Gitea have no slug, so the team is looked up by its name.
+    async fn fetch_team_members(&self, org: &str, team: &str) -> anyhow::Result<Vec<String>> {
+        let search: GiteaTeamSearch = {
+            let _permit = self.semaphore.acquire().await?;
+            retry_transient(|| async {
+                self.octocrab
+                    .get(format!("/orgs/{org}/teams/search"), Some(&[("q", team)]))
+                    .await
+            })
+            .await
+            .map_err(PearError::from)
+            .with_context(|| format!("failed to find team {org}/{team}"))?
+        };
+        let team_id = search
+            .data
+            .iter()
+            .find(|gitea_team| gitea_team.name.eq_ignore_ascii_case(team))
+            .ok_or_else(|| anyhow!("team {org}/{team} does not exist"))?
+            .id;
+
+        let members: Vec<GiteaUser> = self
+            .get_all_pages(&format!("/teams/{team_id}/members"), &[])
+            .await
+            .with_context(|| format!("failed to get members of team {org}/{team}"))?;
+
+        Ok(members.into_iter().map(|member| member.login).collect())
+    }
+
+    // The contents API of Gitea answers in the same format as the one of GitHub.
+    async fn fetch_codeowners(&self, owner: &str, repo: &str, branch: &str) -> anyhow::Result<Option<String>> {
+        let _permit = self.semaphore.acquire().await?;
+
+        for path in CODEOWNERS_PATHS {
+            let result: Result<Content, _> = retry_transient(|| async {
+                self.octocrab
+                    .get(
+                        format!("/repos/{owner}/{repo}/contents/{path}"),
+                        Some(&[("ref", branch)]),
+                    )
+                    .await
+            })
+            .await
+            .map_err(PearError::from);
+            match result {
+                Ok(content) => return Ok(content.decoded_content()),
+                Err(PearError::NotFound(_)) => {},
+                Err(err) => return Err(err).with_context(|| format!("failed to get {path}")),
+            }
+        }
+        Ok(None)
+    }
+
+    async fn get_all_pages<T: DeserializeOwned>(
+        &self,
+        route: &str,
+        query: &[(&str, String)],
+    ) -> anyhow::Result<Vec<T>> {
+        let _permit = self.semaphore.acquire().await?;
+
+        let mut items = Vec::new();
+        for page in 1.. {
+            if page > self.max_pages {
+                return Err(PearError::TooManyPages(self.max_pages).into());
+            }
+            let mut params = query.to_vec();
+            params.extend([("page", page.to_string()), ("limit", PAGE_SIZE.to_string())]);
+            let page_items: Vec<T> = retry_transient(|| async { self.octocrab.get(route, Some(&params)).await })
+                .await
+                .map_err(PearError::from)?;
+
+            let is_last_page = page_items.len() < PAGE_SIZE;
+            items.extend(page_items);
+            if is_last_page {
+                break;
+            }
+        }
+        Ok(items)
+    }
+}
+
+// Gitea keeps dismissed reviews with their original state and has review requests in the same list. Approvals of users
+// who are not allowed to approve are kept as comments, so that they don't count towards the approvals.
+fn review_from_gitea(review: GiteaReview) -> anyhow::Result<Review> {
+    let state = if review.dismissed {
+        ReviewState::Dismissed
+    } else {
+        match review.state.as_str() {
+            "APPROVED" if review.official => ReviewState::Approved,
+            "APPROVED" | "COMMENT" => ReviewState::Commented,
+            "REQUEST_CHANGES" => ReviewState::ChangesRequested,
+            _ => ReviewState::Pending,
+        }
+    };
+
+    Ok(Review {
+        id: review.id,
+        state,
+        commit_id: review.commit_id,
+        submitted_at: review.submitted_at.timestamp_micros(),
+        user: review.user.ok_or_else(|| anyhow!("review has no user"))?.login,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // recorded from Gitea 1.22, trimmed to the fields we read
+    const REVIEWS: &str = r#"[
+  {
+    "id": 101,
+    "user": { "login": "reviewer1" },
+    "state": "REQUEST_CHANGES",
+    "official": true,
+    "stale": true,
+    "dismissed": false,
+    "commit_id": "0a1b2c3d4e5f60718293a4b5c6d7e8f901234567",
+    "submitted_at": "2024-08-01T09:00:00Z"
+  },
+  {
+    "id": 102,
+    "user": { "login": "reviewer2" },
+    "state": "APPROVED",
+    "official": true,
+    "stale": false,
+    "dismissed": false,
+    "commit_id": "5c1d3b4ad4e0f3fa8b3a6c3f4d2e1a0b9c8d7e6f",
+    "submitted_at": "2024-08-02T14:30:00Z"
+  },
+  {
+    "id": 103,
+    "user": { "login": "outsider" },
+    "state": "APPROVED",
+    "official": false,
+    "stale": false,
+    "dismissed": false,
+    "commit_id": "5c1d3b4ad4e0f3fa8b3a6c3f4d2e1a0b9c8d7e6f",
+    "submitted_at": "2024-08-02T15:00:00Z"
+  },
+  {
+    "id": 104,
+    "user": { "login": "reviewer3" },
+    "state": "APPROVED",
+    "official": true,
+    "stale": false,
+    "dismissed": true,
+    "commit_id": "5c1d3b4ad4e0f3fa8b3a6c3f4d2e1a0b9c8d7e6f",
+    "submitted_at": "2024-08-02T16:00:00Z"
+  },
+  {
+    "id": 105,
+    "user": { "login": "reviewer4" },
+    "state": "REQUEST_REVIEW",
+    "official": false,
+    "stale": false,
+    "dismissed": false,
+    "commit_id": "5c1d3b4ad4e0f3fa8b3a6c3f4d2e1a0b9c8d7e6f",
+    "submitted_at": "2024-08-02T17:00:00Z"
+  }
+]"#;
+
+    #[test]
+    fn review_from_gitea() {
+        let gitea_reviews: Vec<GiteaReview> = serde_json::from_str(REVIEWS).unwrap();
+        let reviews: Vec<Review> = gitea_reviews
+            .into_iter()
+            .map(|review| super::review_from_gitea(review).unwrap())
+            .collect();
+
+        let states: Vec<(&str, ReviewState)> = reviews
+            .iter()
+            .map(|review| (review.user.as_str(), review.state))
+            .collect();
+        assert_eq!(states, vec![
+            ("reviewer1", ReviewState::ChangesRequested),
+            ("reviewer2", ReviewState::Approved),
+            // approvals of users who may not approve don't count
+            ("outsider", ReviewState::Commented),
+            ("reviewer3", ReviewState::Dismissed),
+            ("reviewer4", ReviewState::Pending),
+        ]);
+        assert_eq!(reviews[1].id, 102);
+        assert_eq!(reviews[1].commit_id, "5c1d3b4ad4e0f3fa8b3a6c3f4d2e1a0b9c8d7e6f");
+        assert_eq!(
+            reviews[1].submitted_at,
+            DateTime::parse_from_rfc3339("2024-08-02T14:30:00Z")
+                .unwrap()
+                .timestamp_micros()
+        );
+    }
+
+    #[test]
+    fn review_from_gitea_without_user() {
+        let review: GiteaReview = serde_json::from_str(
+            r#"{
+  "id": 106,
+  "user": null,
+  "state": "APPROVED",
+  "official": true,
+  "dismissed": false,
+  "commit_id": "5c1d3b4ad4e0f3fa8b3a6c3f4d2e1a0b9c8d7e6f",
+  "submitted_at": "2024-08-02T18:00:00Z"
+}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            super::review_from_gitea(review).unwrap_err().to_string(),
+            "review has no user"
+        );
+    }
+
+    #[test]
+    fn commit_from_gitea() {
+        let commit: GiteaCommit = serde_json::from_str(
+            r#"{
+  "sha": "5c1d3b4ad4e0f3fa8b3a6c3f4d2e1a0b9c8d7e6f",
+  "html_url": "https://gitea.example.com/sapcc/keppel/commit/5c1d3b4ad4e0f3fa8b3a6c3f4d2e1a0b9c8d7e6f",
+  "commit": {
+    "message": "Fix the frobnicator\n",
+    "author": { "name": "Jane Doe", "email": "jane@example.com", "date": "2024-08-01T09:00:00Z" }
+  },
+  "author": null
+}"#,
+        )
+        .unwrap();
+        let commit = Commit::from(commit);
+        // the git author is used when the email is not linked to an account
+        assert_eq!(commit.author.as_deref(), Some("Jane Doe"));
+        assert_eq!(commit.message, "Fix the frobnicator\n");
+        assert_eq!(commit.authored_at, DateTime::from_timestamp(1_722_502_800, 0));
+    }
+}
@@ -12,23 +12,244 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-#[derive(Clone, Debug)]
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::bail;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Default)]
 pub struct Commit {
     pub html_url: String,
     pub message: String,
     pub sha: String,
+    /// GitHub login of the author, or the git author name if the email is not linked to an account
+    pub author: Option<String>,
+    pub authored_at: Option<DateTime<Utc>>,
+    /// None when the forge does not tell whether the commit is signed
+    pub signature: Option<Signature>,
+}
+
+#[cfg(test)]
+impl Commit {
+    /// A commit of example/project on github.com.
+    pub(crate) fn test(sha: &str) -> Self {
+        Self {
+            html_url: format!("https://github.com/example/project/commit/{sha}"),
+            message: "Fix everything".to_owned(),
+            sha: sha.to_owned(),
+            ..Self::default()
+        }
+    }
+}
+
+/// The hunks a commit changed in one file.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FilePatch {
+    pub path: String,
+    /// None for binary files and for diffs too large for the forge to render
+    pub patch: Option<String>,
+}
+
+/// Whether the forge could verify the GPG, SSH or S/MIME signature of a commit.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Signature {
+    pub verified: bool,
+    /// e.g. `valid`, `unsigned` or `unknown_key` on GitHub
+    pub reason: String,
+}
+
+/// The commits between base and head, as returned by the compare API.
+#[derive(Clone, Debug, Default)]
+pub struct Comparison {
+    /// None when the forge does not report how head relates to base
+    pub status: Option<CompareStatus>,
+    pub commits: Vec<Commit>,
+}
+
+/// How head relates to base, e.g. `Behind` when head is an ancestor of base.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompareStatus {
+    Ahead,
+    Behind,
+    Diverged,
+    Identical,
+}
+
+impl fmt::Display for CompareStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CompareStatus::Ahead => "ahead",
+            CompareStatus::Behind => "behind",
+            CompareStatus::Diverged => "diverged",
+            CompareStatus::Identical => "identical",
+        })
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct PullRequest {
     pub number: u64,
     pub url: String,
+    /// marked as a draft, so that it is not ready for review yet
+    pub draft: bool,
 }
 
-#[derive(Clone, Debug)]
+/// Drops the PRs that are listed more than once, e.g. for a commit that was merged into several bases, keeping the
+/// first URL of each.
+pub fn dedup_prs(mut prs: Vec<PullRequest>) -> Vec<PullRequest> {
+    let mut numbers = HashSet::new();
+    prs.retain(|pr| numbers.insert(pr.number));
+    prs
+}
+
+/// A PR which is neither merged nor closed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OpenPullRequest {
+    pub number: u64,
+    pub url: String,
+    pub title: String,
+    pub author: Option<String>,
+    pub head_sha: String,
+}
+
+/// The free text of a PR, e.g. to find the PR it is a backport of.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PullRequestDescription {
+    pub title: String,
+    pub body: String,
+    /// the commit the PR was merged as, None while it is not merged
+    pub merge_commit: Option<String>,
+}
+
+/// A published release, drafts are not listed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Release {
+    pub tag: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The tags of the releases created before the release of `tag`, newest first. Empty when it is the first release.
+pub fn previous_tags(mut releases: Vec<Release>, tag: &str) -> anyhow::Result<Vec<String>> {
+    releases.sort_by_key(|release| release.created_at);
+    let Some(idx) = releases.iter().position(|release| release.tag == tag) else {
+        bail!("there is no release for tag {tag}");
+    };
+    releases.truncate(idx);
+    Ok(releases.into_iter().rev().map(|release| release.tag).collect())
+}
+
+/// The branch a PR wants to merge, which lives in another repository for PRs from forks.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PullRequestHead {
+    /// owner/name of the repository, None when the fork has been deleted
+    pub repo: Option<String>,
+    pub sha: String,
+}
+
+/// Users and teams which branch protection trusts with reviews of a branch. Only Gitea restricts who may approve,
+/// on GitHub the lists are empty.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BranchProtection {
+    pub users: Vec<String>,
+    pub teams: Vec<Team>,
+    /// number of approvals a PR needs before it can be merged, None when no approvals are required
+    pub required_approvals: Option<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RateLimit {
+    pub limit: u64,
+    pub remaining: u64,
+    /// unix timestamp in seconds when the limit resets
+    pub reset: u64,
+}
+
+/// Serializes to the review timeline entries of machine readable output.
+#[derive(Clone, Debug, Serialize)]
 pub struct Review {
-    pub approved: bool,
+    /// ids are increasing, so they order reviews with the same `submitted_at`
+    pub id: u64,
+    pub state: ReviewState,
     pub commit_id: String,
+    /// unix timestamp in microseconds
     pub submitted_at: i64,
     pub user: String,
 }
+
+impl Review {
+    /// `submitted_at` as a date, None when it is out of range.
+    pub fn submitted_date(&self) -> Option<DateTime<Utc>> {
+        DateTime::from_timestamp_micros(self.submitted_at)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewState {
+    Approved,
+    ChangesRequested,
+    Commented,
+    Dismissed,
+    Pending,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Team {
+    pub org: String,
+    pub slug: String,
+}
+
+impl FromStr for Team {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('/') {
+            Some((org, slug)) if !org.is_empty() && !slug.is_empty() && !slug.contains('/') => Ok(Self {
+                org: org.to_string(),
+                slug: slug.to_string(),
+            }),
+            _ => bail!("teams are expected to be in the format of org/team"),
+        }
+    }
+}
+
+impl fmt::Display for Team {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.org, self.slug)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialize_review() {
+        let states = [
+            (ReviewState::Approved, "approved"),
+            (ReviewState::ChangesRequested, "changes_requested"),
+            (ReviewState::Commented, "commented"),
+            (ReviewState::Dismissed, "dismissed"),
+            (ReviewState::Pending, "pending"),
+        ];
+        for (state, expected) in states {
+            let review = Review {
+                id: 1,
+                state,
+                commit_id: "00000000000000000000000000000001".to_owned(),
+                submitted_at: 1_722_500_000_000_000,
+                user: "user1".to_owned(),
+            };
+            assert_eq!(
+                serde_json::to_string(&review).unwrap(),
+                format!(
+                    r#"{{"id":1,"state":"{expected}","commit_id":"00000000000000000000000000000001","submitted_at":1722500000000000,"user":"user1"}}"#
+                )
+            );
+        }
+    }
+}
@@ -0,0 +1,343 @@
+// Copyright 2024 SAP SE
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use anyhow::{anyhow, bail, Context};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use crate::github::{self, PullRequestHead, Review, ReviewState};
+
+/// Fetches everything needed to judge a PR in one request instead of one for the head and one for the reviews.
+pub const PR_HEAD_AND_REVIEWS_QUERY: &str = "
+query($owner: String!, $repo: String!, $number: Int!) {
+  repository(owner: $owner, name: $repo) {
+    pullRequest(number: $number) {
+      headRefOid
+      headRepository { nameWithOwner }
+      reviews(first: 100) {
+        pageInfo { hasNextPage }
+        nodes {
+          databaseId
+          state
+          submittedAt
+          commit { oid }
+          author { login }
+        }
+      }
+    }
+  }
+}";
+
+#[derive(Debug, Deserialize)]
+pub struct Response {
+    data: Option<Data>,
+    errors: Option<Vec<Error>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Error {
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Data {
+    repository: Option<Repository>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Repository {
+    pull_request: Option<PullRequest>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PullRequest {
+    head_ref_oid: String,
+    head_repository: Option<HeadRepository>,
+    reviews: Reviews,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HeadRepository {
+    name_with_owner: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Reviews {
+    page_info: PageInfo,
+    nodes: Vec<ReviewNode>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PageInfo {
+    has_next_page: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReviewNode {
+    database_id: Option<u64>,
+    state: String,
+    submitted_at: Option<DateTime<Utc>>,
+    commit: Option<CommitNode>,
+    author: Option<Author>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CommitNode {
+    oid: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Author {
+    login: String,
+}
+
+// Converts the response of PR_HEAD_AND_REVIEWS_QUERY the same way the REST API results are converted.
+pub fn pr_head_and_reviews(response: Response) -> anyhow::Result<(PullRequestHead, Vec<Review>)> {
+    check_errors(response.errors)?;
+
+    let pr = response
+        .data
+        .and_then(|data| data.repository)
+        .and_then(|repository| repository.pull_request)
+        .ok_or_else(|| anyhow!("GraphQL response contains no pull request"))?;
+    if pr.reviews.page_info.has_next_page {
+        bail!("found more than one page of reviews");
+    }
+
+    let mut reviews = Vec::new();
+    for node in pr.reviews.nodes {
+        reviews.push(Review {
+            id: node.database_id.context("review has no databaseId")?,
+            state: match node.state.as_str() {
+                "APPROVED" => ReviewState::Approved,
+                "CHANGES_REQUESTED" => ReviewState::ChangesRequested,
+                "COMMENTED" => ReviewState::Commented,
+                "DISMISSED" => ReviewState::Dismissed,
+                _ => ReviewState::Pending,
+            },
+            commit_id: node.commit.context("review has no commit")?.oid,
+            submitted_at: node
+                .submitted_at
+                .context("review has no submittedAt")?
+                .timestamp_micros(),
+            user: node.author.context("review has no author")?.login,
+        });
+    }
+    reviews.sort_by_key(|r| (r.submitted_at, r.id));
+
+    let head = PullRequestHead {
+        repo: pr.head_repository.map(|repository| repository.name_with_owner),
+        sha: pr.head_ref_oid,
+    };
+    Ok((head, reviews))
+}
+
+/// Commits whose associated PRs are looked up with one query, the cost of a query grows with its fields.
+pub const ASSOCIATED_PRS_BATCH_SIZE: usize = 50;
+
+/// Looks up the associated PRs of `count` commits, passed as `$sha0` to `$sha{count-1}`, with one aliased field each.
+pub fn associated_prs_query(count: usize) -> String {
+    let mut variables = String::new();
+    let mut fields = String::new();
+    for i in 0..count {
+        let _ = write!(variables, ", $sha{i}: GitObjectID!");
+        let _ = write!(
+            fields,
+            "
+    commit{i}: object(oid: $sha{i}) {{
+      ... on Commit {{
+        associatedPullRequests(first: 100) {{
+          pageInfo {{ hasNextPage }}
+          nodes {{ number url isDraft }}
+        }}
+      }}
+    }}"
+        );
+    }
+    format!(
+        "
+query($owner: String!, $repo: String!{variables}) {{
+  repository(owner: $owner, name: $repo) {{{fields}
+  }}
+}}"
+    )
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AssociatedPrsResponse {
+    data: Option<AssociatedPrsData>,
+    errors: Option<Vec<Error>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AssociatedPrsData {
+    /// keyed by the alias of each commit, null for commits which do not exist
+    repository: Option<HashMap<String, Option<CommitObject>>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CommitObject {
+    associated_pull_requests: Option<AssociatedPullRequests>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AssociatedPullRequests {
+    page_info: PageInfo,
+    nodes: Vec<PullRequestNode>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PullRequestNode {
+    number: u64,
+    url: String,
+    #[serde(default)]
+    is_draft: bool,
+}
+
+// Converts the response of associated_prs_query for the given shas. Commits which are unknown or have more than one
+// page of PRs are left out, so that the caller can look them up with the REST API which reports those properly.
+pub fn associated_prs(
+    response: AssociatedPrsResponse,
+    shas: &[String],
+) -> anyhow::Result<HashMap<String, Vec<github::PullRequest>>> {
+    check_errors(response.errors)?;
+
+    let mut commits = response
+        .data
+        .and_then(|data| data.repository)
+        .ok_or_else(|| anyhow!("GraphQL response contains no repository"))?;
+
+    let mut prs = HashMap::new();
+    for (i, sha) in shas.iter().enumerate() {
+        let Some(associated_prs) = commits
+            .remove(&format!("commit{i}"))
+            .flatten()
+            .and_then(|commit| commit.associated_pull_requests)
+        else {
+            continue;
+        };
+        if associated_prs.page_info.has_next_page {
+            continue;
+        }
+
+        let associated_prs = associated_prs
+            .nodes
+            .into_iter()
+            .map(|node| github::PullRequest {
+                number: node.number,
+                url: node.url,
+                draft: node.is_draft,
+            })
+            .collect();
+        prs.insert(sha.clone(), associated_prs);
+    }
+
+    Ok(prs)
+}
+
+fn check_errors(errors: Option<Vec<Error>>) -> anyhow::Result<()> {
+    if let Some(errors) = errors.filter(|errors| !errors.is_empty()) {
+        let messages: Vec<String> = errors.into_iter().map(|error| error.message).collect();
+        bail!("GraphQL query failed: {}", messages.join(", "));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // recorded from a PR of a fork, reviews are returned oldest first
+    const RESPONSE: &str = r#"{
+  "data": {
+    "repository": {
+      "pullRequest": {
+        "headRefOid": "5c1d3b4ad4e0f3fa8b3a6c3f4d2e1a0b9c8d7e6f",
+        "headRepository": { "nameWithOwner": "contributor/keppel" },
+        "reviews": {
+          "pageInfo": { "hasNextPage": false },
+          "nodes": [
+            {
+              "databaseId": 2001,
+              "state": "CHANGES_REQUESTED",
+              "submittedAt": "2024-08-01T09:00:00Z",
+              "commit": { "oid": "0a1b2c3d4e5f60718293a4b5c6d7e8f901234567" },
+              "author": { "login": "reviewer1" }
+            },
+            {
+              "databaseId": 2002,
+              "state": "APPROVED",
+              "submittedAt": "2024-08-02T14:30:00Z",
+              "commit": { "oid": "5c1d3b4ad4e0f3fa8b3a6c3f4d2e1a0b9c8d7e6f" },
+              "author": { "login": "reviewer2" }
+            }
+          ]
+        }
+      }
+    }
+  }
+}"#;
+
+    #[test]
+    fn pr_head_and_reviews() {
+        let response: Response = serde_json::from_str(RESPONSE).unwrap();
+        let (head, reviews) = super::pr_head_and_reviews(response).unwrap();
+
+        assert_eq!(head, PullRequestHead {
+            repo: Some("contributor/keppel".to_owned()),
+            sha: "5c1d3b4ad4e0f3fa8b3a6c3f4d2e1a0b9c8d7e6f".to_owned(),
+        });
+        assert_eq!(reviews.len(), 2);
+        assert_eq!(reviews[0].id, 2001);
+        assert_eq!(reviews[0].state, ReviewState::ChangesRequested);
+        assert_eq!(reviews[0].user, "reviewer1");
+        assert_eq!(reviews[1].state, ReviewState::Approved);
+        assert_eq!(reviews[1].commit_id, "5c1d3b4ad4e0f3fa8b3a6c3f4d2e1a0b9c8d7e6f");
+        assert_eq!(
+            reviews[1].submitted_at,
+            DateTime::parse_from_rfc3339("2024-08-02T14:30:00Z")
+                .unwrap()
+                .timestamp_micros()
+        );
+    }
+
+    #[test]
+    fn pr_head_and_reviews_errors() {
+        let response: Response = serde_json::from_str(
+            r#"{
+  "data": { "repository": null },
+  "errors": [
+    { "type": "NOT_FOUND", "message": "Could not resolve to a Repository with the name 'sapcc/missing'." }
+  ]
+}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            super::pr_head_and_reviews(response).unwrap_err().to_string(),
+            "GraphQL query failed: Could not resolve to a Repository with the name 'sapcc/missing'."
+        );
+    }
+}
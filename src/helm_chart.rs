@@ -0,0 +1,1189 @@
+// Copyright 2024 SAP SE
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use anyhow::{anyhow, Context};
+use git2::{Delta, ErrorCode, Repository};
+use glob::Pattern;
+
+use crate::api_clients::{AnyClient, Client};
+use crate::changes::RepoChangeset;
+use crate::diff::{self, FileDiff};
+use crate::helm_config::{ImageRefs, ValuesImage};
+use crate::remote::Remote;
+use crate::repo;
+
+/// File in the root of the workspace with globs of image names or source repos to leave out, one per line. When base
+/// and head are diffed, it is read from the head commit.
+pub const PEAR_IGNORE_FILE: &str = ".pear-ignore";
+
+/// The images whose sources changed between the base and head commit of the workspace, one changeset per source.
+pub fn find_values_yaml(
+    workspace: String,
+    base: &str,
+    head: &str,
+    path_filter: &PathFilter,
+    use_merge_base: bool,
+) -> Result<Vec<RepoChangeset<AnyClient>>, anyhow::Error> {
+    let repo = Repository::open(workspace).context("failed to open repository")?;
+    let path_filter = path_filter.with_pear_ignore_at(&repo, head)?;
+    let diff_tree = repo::diff_refs(&repo, base, head, use_merge_base)?;
+
+    let mut changes = Vec::<RepoChangeset<AnyClient>>::new();
+
+    for diff_delta in diff_tree.deltas() {
+        // removed images don't need a review
+        if diff_delta.status() == Delta::Deleted {
+            continue;
+        }
+
+        let new_file = diff_delta.new_file();
+        let path = new_file.path().ok_or_else(|| anyhow!("failed to get file path"))?;
+        if !path_filter.includes(path) {
+            continue;
+        }
+
+        let new_image_refs = ImageRefs::parse(&repo, &new_file).context("while parsing new file")?;
+        let old_image_refs = if diff_delta.status() == Delta::Added {
+            ImageRefs::default()
+        } else {
+            ImageRefs::parse(&repo, &diff_delta.old_file()).context("while parsing old file")?
+        };
+        push_image_changesets(&mut changes, &new_image_refs, &old_image_refs, &path_filter)?;
+    }
+
+    Ok(dedup_changesets(changes))
+}
+
+/// Like `find_values_yaml`, but takes the changed images.yaml files from a unified diff with whole files, e.g. of
+/// `git diff --unified=1000000 base head`, so that the workspace does not need to be a git repository.
+pub fn find_values_yaml_in_diff<C: Client>(
+    diff: &str,
+    workspace: &Path,
+    path_filter: &PathFilter,
+) -> Result<Vec<RepoChangeset<C>>, anyhow::Error> {
+    let files = diff::parse(diff).context("cannot parse diff")?;
+    let path_filter = &path_filter.with_pear_ignore_in_diff(&files, workspace)?;
+    let mut changes = Vec::new();
+    for file in files {
+        // removed images don't need a review
+        let Some(new) = &file.new else {
+            continue;
+        };
+        if !path_filter.includes(&file.path) {
+            continue;
+        }
+
+        let new_image_refs = ImageRefs::parse_str(new, &file.path).context("while parsing new file")?;
+        let old_image_refs = match &file.old {
+            Some(old) => ImageRefs::parse_str(old, &file.path).context("while parsing old file")?,
+            None => ImageRefs::default(),
+        };
+        push_image_changesets(&mut changes, &new_image_refs, &old_image_refs, path_filter)?;
+    }
+
+    Ok(dedup_changesets(changes))
+}
+
+/// An image whose tag changed in a values.yaml. Without sources its changes cannot be analyzed, it is only listed.
+#[derive(Debug, PartialEq)]
+pub struct TagBump {
+    pub path: PathBuf,
+    pub key_path: String,
+    pub repository: String,
+    /// None when the image was added
+    pub old_tag: Option<String>,
+    pub new_tag: String,
+}
+
+/// The images at the given key paths of the values.yaml files whose tag changed between the base and head commit.
+pub fn find_values_yaml_tag_bumps(
+    workspace: &str,
+    base: &str,
+    head: &str,
+    key_paths: &[String],
+    path_filter: &PathFilter,
+    use_merge_base: bool,
+) -> Result<Vec<TagBump>, anyhow::Error> {
+    if key_paths.is_empty() {
+        return Ok(Vec::new());
+    }
+    let repo = Repository::open(workspace).context("failed to open repository")?;
+    let path_filter = path_filter.with_pear_ignore_at(&repo, head)?;
+    let diff_tree = repo::diff_refs(&repo, base, head, use_merge_base)?;
+    let blob = |id| {
+        repo.find_blob(id)
+            .map(|blob| blob.content().to_vec())
+            .with_context(|| format!("cannot find Git blob {id}"))
+    };
+
+    let mut tag_bumps = Vec::new();
+    for diff_delta in diff_tree.deltas() {
+        if diff_delta.status() == Delta::Deleted {
+            continue;
+        }
+        let path = diff_delta
+            .new_file()
+            .path()
+            .ok_or_else(|| anyhow!("failed to get file path"))?;
+        if !path_filter.includes_values_yaml(path) {
+            continue;
+        }
+
+        let old = if diff_delta.status() == Delta::Added {
+            None
+        } else {
+            Some(blob(diff_delta.old_file().id())?)
+        };
+        let new = blob(diff_delta.new_file().id())?;
+        push_tag_bumps(&mut tag_bumps, path, old.as_deref(), &new, key_paths, &path_filter)?;
+    }
+    Ok(tag_bumps)
+}
+
+/// Like `find_values_yaml_tag_bumps`, but takes the changed values.yaml files from a unified diff with whole files.
+pub fn find_values_yaml_tag_bumps_in_diff(
+    diff: &str,
+    key_paths: &[String],
+    workspace: &Path,
+    path_filter: &PathFilter,
+) -> Result<Vec<TagBump>, anyhow::Error> {
+    let mut tag_bumps = Vec::new();
+    if key_paths.is_empty() {
+        return Ok(tag_bumps);
+    }
+    let files = diff::parse(diff).context("cannot parse diff")?;
+    let path_filter = &path_filter.with_pear_ignore_in_diff(&files, workspace)?;
+    for file in files {
+        let Some(new) = &file.new else {
+            continue;
+        };
+        if !path_filter.includes_values_yaml(&file.path) {
+            continue;
+        }
+        let old = file.old.as_deref().map(str::as_bytes);
+        push_tag_bumps(&mut tag_bumps, &file.path, old, new.as_bytes(), key_paths, path_filter)?;
+    }
+    Ok(tag_bumps)
+}
+
+fn push_tag_bumps(
+    tag_bumps: &mut Vec<TagBump>,
+    path: &Path,
+    old: Option<&[u8]>,
+    new: &[u8],
+    key_paths: &[String],
+    path_filter: &PathFilter,
+) -> Result<(), anyhow::Error> {
+    let display_path = path.display().to_string();
+    for key_path in key_paths {
+        let Some(new_image) =
+            ValuesImage::from_slice(new, key_path, &display_path).context("while parsing new file")?
+        else {
+            continue;
+        };
+        if !path_filter.includes_image(&new_image.repository, &new_image.repository) {
+            continue;
+        }
+        let old_image = match old {
+            Some(old) => ValuesImage::from_slice(old, key_path, &display_path).context("while parsing old file")?,
+            None => None,
+        };
+        if old_image.as_ref() == Some(&new_image) {
+            continue;
+        }
+
+        tag_bumps.push(TagBump {
+            path: path.to_path_buf(),
+            key_path: key_path.clone(),
+            repository: new_image.repository,
+            // a changed repository is listed with the tag it had before as well
+            old_tag: old_image.map(|old_image| old_image.tag),
+            new_tag: new_image.tag,
+        });
+    }
+    Ok(())
+}
+
+// One changeset per source of every image in the new file, compared with the sources the image had in the old file.
+fn push_image_changesets<C: Client>(
+    changes: &mut Vec<RepoChangeset<C>>,
+    new_image_refs: &ImageRefs,
+    old_image_refs: &ImageRefs,
+    path_filter: &PathFilter,
+) -> Result<(), anyhow::Error> {
+    for (name, image) in &new_image_refs.container_images {
+        let old_image_sources = old_image_refs
+            .container_images
+            .get(name)
+            .map(|old_image| old_image.sources.as_slice())
+            .unwrap_or_default();
+
+        for source in image
+            .sources
+            .iter()
+            .filter(|source| path_filter.includes_image(name, &source.repo))
+        {
+            let remote = Remote::<C>::parse(&source.repo)?;
+            // the URL of a source may be spelled differently in the old file without changing the repo
+            let old_sources: Vec<_> = old_image_sources
+                .iter()
+                .filter(|old_source| {
+                    Remote::<C>::parse(&old_source.repo).is_ok_and(|old_remote| old_remote.is_same_repo(&remote))
+                })
+                .collect();
+
+            // sources that are new in this diff have nothing to compare against, only their commit is analyzed
+            if old_sources.is_empty() {
+                changes.push(RepoChangeset {
+                    name: name.clone(),
+                    remote: remote.clone(),
+                    base_commit: String::new(),
+                    head_commit: source.commit.clone(),
+                    base_ref: None,
+                    head_ref: None,
+                    min_approvals: image.required_approvals,
+                    required_approvers: image.required_approvers.clone(),
+                    compare_status: None,
+                    error: None,
+                    changes: Vec::new(),
+                });
+            }
+            for container_image_source in old_sources {
+                changes.push(RepoChangeset {
+                    name: name.clone(),
+                    remote: remote.clone(),
+                    base_commit: container_image_source.commit.clone(),
+                    head_commit: source.commit.clone(),
+                    base_ref: None,
+                    head_ref: None,
+                    min_approvals: image.required_approvals,
+                    required_approvers: image.required_approvers.clone(),
+                    compare_status: None,
+                    error: None,
+                    changes: Vec::new(),
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Selects the images.yaml files to analyze by their path relative to the workspace, and the images in them.
+#[derive(Clone, Default)]
+pub struct PathFilter {
+    pub repo_subdir: Option<PathBuf>,
+    pub exclude_paths: Vec<Pattern>,
+    /// matched against the image name and the repo of each of its sources
+    pub exclude_images: Vec<Pattern>,
+}
+
+impl PathFilter {
+    fn includes(&self, path: &Path) -> bool {
+        path.ends_with("images.yaml") && self.includes_dir(path)
+    }
+
+    fn includes_values_yaml(&self, path: &Path) -> bool {
+        path.ends_with("values.yaml") && self.includes_dir(path)
+    }
+
+    fn includes_dir(&self, path: &Path) -> bool {
+        self.repo_subdir.as_ref().is_none_or(|subdir| path.starts_with(subdir))
+            && !self.exclude_paths.iter().any(|pattern| pattern.matches_path(path))
+    }
+
+    fn includes_image(&self, name: &str, repo: &str) -> bool {
+        !self
+            .exclude_images
+            .iter()
+            .any(|pattern| pattern.matches(name) || pattern.matches(repo))
+    }
+
+    // The globs of the .pear-ignore file are added to the ones given with --exclude.
+    fn with_pear_ignore(&self, workspace: &Path) -> Result<Self, anyhow::Error> {
+        let path = workspace.join(PEAR_IGNORE_FILE);
+        match fs::read_to_string(&path) {
+            Ok(content) => self.with_pear_ignore_content(&content, &path.display().to_string()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(self.clone()),
+            Err(err) => Err(err).with_context(|| format!("cannot read {}", path.display())),
+        }
+    }
+
+    // Like with_pear_ignore, but reads the file from the head commit, whatever is checked out in the workspace.
+    fn with_pear_ignore_at(&self, repo: &Repository, head: &str) -> Result<Self, anyhow::Error> {
+        let tree = repo::tree_for_commit_ref(repo, head)
+            .with_context(|| format!("head ref {head:?} does not exist in the workspace"))?;
+        let entry = match tree.get_path(Path::new(PEAR_IGNORE_FILE)) {
+            Ok(entry) => entry,
+            Err(err) if err.code() == ErrorCode::NotFound => return Ok(self.clone()),
+            Err(err) => return Err(err).with_context(|| format!("cannot find {PEAR_IGNORE_FILE} in {head}")),
+        };
+        let blob = entry
+            .to_object(repo)
+            .and_then(|object| object.peel_to_blob())
+            .with_context(|| format!("cannot read {PEAR_IGNORE_FILE} of {head}"))?;
+        let content = String::from_utf8_lossy(blob.content());
+        self.with_pear_ignore_content(&content, &format!("{PEAR_IGNORE_FILE} of {head}"))
+    }
+
+    // Like with_pear_ignore_at for a diff of base and head: a .pear-ignore changed by the diff is taken as it is in
+    // head, otherwise the one in the workspace applies.
+    fn with_pear_ignore_in_diff(&self, files: &[FileDiff], workspace: &Path) -> Result<Self, anyhow::Error> {
+        match files.iter().find(|file| file.path == Path::new(PEAR_IGNORE_FILE)) {
+            Some(FileDiff { new: Some(content), .. }) => self.with_pear_ignore_content(content, PEAR_IGNORE_FILE),
+            Some(FileDiff { new: None, .. }) => Ok(self.clone()),
+            None => self.with_pear_ignore(workspace),
+        }
+    }
+
+    fn with_pear_ignore_content(&self, content: &str, path: &str) -> Result<Self, anyhow::Error> {
+        let mut path_filter = self.clone();
+        path_filter
+            .exclude_images
+            .extend(parse_pear_ignore(content).with_context(|| format!("invalid ignore file {path}"))?);
+        Ok(path_filter)
+    }
+}
+
+/// Selects the source repos to analyze by globs on their owner/repo, e.g. "sapcc/*".
+#[derive(Clone, Default)]
+pub struct RepoFilter {
+    /// only repos matching one of these are kept, unless it is empty
+    pub only: Vec<Pattern>,
+    /// takes precedence over `only`
+    pub skip: Vec<Pattern>,
+}
+
+impl RepoFilter {
+    fn includes<C: Client>(&self, remote: &Remote<C>) -> bool {
+        let repo = format!("{}/{}", remote.owner, remote.repository);
+        (self.only.is_empty() || self.only.iter().any(|pattern| pattern.matches(&repo)))
+            && !self.skip.iter().any(|pattern| pattern.matches(&repo))
+    }
+
+    /// Drops the changesets of filtered repos before they are analyzed, so that they cost no API calls.
+    pub fn retain<C: Client>(&self, changes: &mut Vec<RepoChangeset<C>>) {
+        changes.retain(|change| self.includes(&change.remote));
+    }
+}
+
+/// Parses a .pear-ignore file. Blank lines and lines starting with # are ignored.
+pub fn parse_pear_ignore(content: &str) -> Result<Vec<Pattern>, anyhow::Error> {
+    content
+        .lines()
+        .enumerate()
+        .map(|(idx, line)| (idx + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(line_number, line)| Pattern::new(line).with_context(|| format!("invalid glob on line {line_number}")))
+        .collect()
+}
+
+// Every source is treated as a change without a base, so that only its commit is analyzed.
+pub fn find_images_yaml_files<C: Client>(
+    workspace: &Path,
+    path_filter: &PathFilter,
+) -> Result<Vec<RepoChangeset<C>>, anyhow::Error> {
+    let path_filter = path_filter.with_pear_ignore(workspace)?;
+    let mut changes = Vec::new();
+    let mut dirs = vec![match &path_filter.repo_subdir {
+        Some(subdir) => workspace.join(subdir),
+        None => workspace.to_path_buf(),
+    }];
+    while let Some(dir) = dirs.pop() {
+        let mut entries = fs::read_dir(&dir)
+            .with_context(|| format!("cannot read directory {}", dir.display()))?
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("cannot read directory {}", dir.display()))?;
+        entries.sort_by_key(fs::DirEntry::file_name);
+
+        for entry in entries {
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                if entry.file_name() != ".git" {
+                    dirs.push(path);
+                }
+                continue;
+            }
+            let relative_path = path.strip_prefix(workspace).unwrap_or(&path);
+            if !path_filter.includes(relative_path) {
+                continue;
+            }
+
+            let image_refs = ImageRefs::parse_file(&path)?;
+            for (name, image) in &image_refs.container_images {
+                for source in image
+                    .sources
+                    .iter()
+                    .filter(|source| path_filter.includes_image(name, &source.repo))
+                {
+                    changes.push(RepoChangeset {
+                        name: name.clone(),
+                        remote: Remote::parse(&source.repo)?,
+                        base_commit: String::new(),
+                        head_commit: source.commit.clone(),
+                        base_ref: None,
+                        head_ref: None,
+                        min_approvals: image.required_approvals,
+                        required_approvers: image.required_approvers.clone(),
+                        compare_status: None,
+                        error: None,
+                        changes: Vec::new(),
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(dedup_changesets(changes))
+}
+
+// Images built from the same source repo would otherwise list the same changes multiple times, also when their
+// source URLs are spelled differently. The deduplicated changeset is named after all images referencing it and needs
+// the approvals of the strictest one.
+fn dedup_changesets<C: Client>(changes: Vec<RepoChangeset<C>>) -> Vec<RepoChangeset<C>> {
+    let mut deduped: Vec<(RepoChangeset<C>, Vec<String>)> = Vec::new();
+    for change in changes {
+        if let Some((existing, names)) = deduped.iter_mut().find(|(existing, _)| {
+            existing.remote.is_same_repo(&change.remote)
+                && existing.base_commit == change.base_commit
+                && existing.head_commit == change.head_commit
+        }) {
+            names.push(change.name);
+            existing.min_approvals = existing.min_approvals.max(change.min_approvals);
+            for approver in change.required_approvers {
+                if !existing.required_approvers.contains(&approver) {
+                    existing.required_approvers.push(approver);
+                }
+            }
+        } else {
+            let names = vec![change.name.clone()];
+            deduped.push((change, names));
+        }
+    }
+
+    deduped
+        .into_iter()
+        .map(|(mut change, mut names)| {
+            names.sort();
+            names.dedup();
+            change.name = names.join(", ");
+            change
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fmt::Write as _;
+
+    use git2::{Oid, Signature};
+
+    use super::*;
+    use crate::api_clients::{ClientConfig, ClientSet, MockClient};
+    use crate::changes::AnalyzeOptions;
+    use crate::github::CompareStatus;
+    use crate::output;
+
+    // commits an empty tree when content is None
+    fn commit_images_yaml(repo: &Repository, content: Option<&str>, parents: &[&git2::Commit]) -> Oid {
+        let mut tree = repo.treebuilder(None).unwrap();
+        if let Some(content) = content {
+            let blob = repo.blob(content.as_bytes()).unwrap();
+            tree.insert("images.yaml", blob, 0o100_644).unwrap();
+        }
+        let tree = repo.find_tree(tree.write().unwrap()).unwrap();
+        let signature = Signature::now("pear-reviewer", "pear-reviewer@example.com").unwrap();
+        // don't update HEAD, so that tests can create diverging branches
+        repo.commit(None, &signature, &signature, "update images", &tree, parents)
+            .unwrap()
+    }
+
+    // a workspace without a .pear-ignore file for the diffs
+    fn no_workspace() -> PathBuf {
+        env::temp_dir().join("pear-reviewer-no-workspace")
+    }
+
+    fn images_yaml(images: &[&str], commit: &str) -> String {
+        let mut yaml = "containerImages:\n".to_owned();
+        for image in images {
+            write!(
+                yaml,
+                "  {image}:
+    account: ccloud
+    repository: {image}
+    tag: latest
+    sources:
+      - repo: https://github.com/sapcc/keppel.git
+        commit: {commit}
+"
+            )
+            .unwrap();
+        }
+        yaml
+    }
+
+    #[test]
+    fn find_images_yaml_files() {
+        let workspace = env::temp_dir().join(format!("pear-reviewer-no-diff-{}", std::process::id()));
+        fs::create_dir_all(workspace.join("openstack/keppel")).unwrap();
+        fs::create_dir_all(workspace.join("openstack/limes")).unwrap();
+        fs::create_dir_all(workspace.join(".git")).unwrap();
+        fs::write(
+            workspace.join("openstack/keppel/images.yaml"),
+            images_yaml(&["keppel"], "1111111"),
+        )
+        .unwrap();
+        fs::write(
+            workspace.join("openstack/limes/images.yaml"),
+            images_yaml(&["limes"], "2222222"),
+        )
+        .unwrap();
+        fs::write(workspace.join("openstack/limes/values.yaml"), "not: relevant").unwrap();
+        fs::write(workspace.join(".git/images.yaml"), "not: relevant").unwrap();
+
+        let changes = super::find_images_yaml_files::<MockClient>(&workspace, &PathFilter::default());
+        fs::remove_dir_all(&workspace).unwrap();
+        let mut changes = changes.unwrap();
+        changes.sort_by(|a, b| a.name.cmp(&b.name));
+
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].name, "keppel");
+        assert_eq!(changes[0].base_commit, "");
+        assert_eq!(changes[0].head_commit, "1111111");
+        assert_eq!(changes[1].name, "limes");
+        assert_eq!(changes[1].head_commit, "2222222");
+    }
+
+    #[test]
+    fn find_images_yaml_files_exclude_path() {
+        let workspace = env::temp_dir().join(format!("pear-reviewer-exclude-{}", std::process::id()));
+        fs::create_dir_all(workspace.join("examples")).unwrap();
+        fs::create_dir_all(workspace.join("openstack/keppel")).unwrap();
+        fs::write(
+            workspace.join("openstack/keppel/images.yaml"),
+            images_yaml(&["keppel"], "1111111"),
+        )
+        .unwrap();
+        // the remote can't be parsed, so this fails if the file is not skipped
+        fs::write(
+            workspace.join("examples/images.yaml"),
+            images_yaml(&["example"], "2222222").replace("https://github.com/sapcc/keppel.git", "not a remote"),
+        )
+        .unwrap();
+
+        let path_filter = PathFilter {
+            exclude_paths: vec![Pattern::new("examples/**").unwrap()],
+            ..PathFilter::default()
+        };
+        let changes = super::find_images_yaml_files::<MockClient>(&workspace, &path_filter);
+        fs::remove_dir_all(&workspace).unwrap();
+        let changes = changes.unwrap();
+
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].name, "keppel");
+    }
+
+    #[test]
+    fn find_images_yaml_files_repo_subdir() {
+        let workspace = env::temp_dir().join(format!("pear-reviewer-repo-subdir-{}", std::process::id()));
+        fs::create_dir_all(workspace.join("charts/keppel")).unwrap();
+        fs::create_dir_all(workspace.join("charts/examples")).unwrap();
+        fs::create_dir_all(workspace.join("tools")).unwrap();
+        fs::write(
+            workspace.join("charts/keppel/images.yaml"),
+            images_yaml(&["keppel"], "1111111"),
+        )
+        .unwrap();
+        // the remotes can't be parsed, so this fails if the files are not skipped
+        for path in ["tools/images.yaml", "charts/examples/images.yaml"] {
+            fs::write(
+                workspace.join(path),
+                images_yaml(&["other"], "2222222").replace("https://github.com/sapcc/keppel.git", "not a remote"),
+            )
+            .unwrap();
+        }
+
+        let path_filter = PathFilter {
+            repo_subdir: Some(PathBuf::from("charts")),
+            exclude_paths: vec![Pattern::new("charts/examples/**").unwrap()],
+            ..PathFilter::default()
+        };
+        let changes = super::find_images_yaml_files::<MockClient>(&workspace, &path_filter);
+        fs::remove_dir_all(&workspace).unwrap();
+        let changes = changes.unwrap();
+
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].name, "keppel");
+    }
+
+    #[test]
+    fn path_filter() {
+        let path_filter = PathFilter {
+            repo_subdir: Some(PathBuf::from("charts")),
+            ..PathFilter::default()
+        };
+        assert!(path_filter.includes(Path::new("charts/keppel/images.yaml")));
+        assert!(!path_filter.includes(Path::new("charts/keppel/values.yaml")));
+        assert!(!path_filter.includes(Path::new("tools/images.yaml")));
+        // only whole path components are matched
+        assert!(!path_filter.includes(Path::new("charts-old/images.yaml")));
+        assert!(PathFilter::default().includes(Path::new("tools/images.yaml")));
+    }
+
+    #[test]
+    fn repo_filter() {
+        let remotes = ["sapcc/keppel", "sapcc/keppel-janitor", "sapcc/limes", "other/keppel"]
+            .map(|repo| format!("https://github.com/{repo}.git"));
+        let mut changes = crate::repo_changesets::<MockClient>(&remotes, "v1", "v2").unwrap();
+        let filter = RepoFilter {
+            only: vec![Pattern::new("sapcc/*").unwrap()],
+            skip: vec![
+                Pattern::new("*/keppel-*").unwrap(),
+                Pattern::new("sapcc/limes").unwrap(),
+            ],
+        };
+        filter.retain(&mut changes);
+        assert_eq!(
+            changes.iter().map(|change| change.name.as_str()).collect::<Vec<_>>(),
+            vec!["keppel"]
+        );
+
+        let mut changes = crate::repo_changesets::<MockClient>(&remotes, "v1", "v2").unwrap();
+        let filter = RepoFilter {
+            skip: vec![Pattern::new("sapcc/keppel*").unwrap()],
+            ..RepoFilter::default()
+        };
+        filter.retain(&mut changes);
+        assert_eq!(
+            changes
+                .iter()
+                .map(|change| change.remote.original.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "https://github.com/sapcc/limes.git",
+                "https://github.com/other/keppel.git"
+            ]
+        );
+    }
+
+    #[test]
+    fn find_values_yaml_dedup() {
+        let workspace = env::temp_dir().join(format!("pear-reviewer-dedup-{}", std::process::id()));
+        let repo = Repository::init(&workspace).unwrap();
+        let base = commit_images_yaml(&repo, Some(&images_yaml(&["keppel", "keppel-janitor"], "1111111")), &[]);
+        let base_commit = repo.find_commit(base).unwrap();
+        let head = commit_images_yaml(&repo, Some(&images_yaml(&["keppel", "keppel-janitor"], "2222222")), &[
+            &base_commit,
+        ]);
+
+        let changes = find_values_yaml(
+            workspace.to_string_lossy().to_string(),
+            &base.to_string(),
+            &head.to_string(),
+            &PathFilter::default(),
+            false,
+        )
+        .unwrap();
+        std::fs::remove_dir_all(&workspace).unwrap();
+
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].name, "keppel, keppel-janitor");
+        assert_eq!(changes[0].remote.original, "https://github.com/sapcc/keppel.git");
+    }
+
+    #[test]
+    fn dedup_changesets_spellings() {
+        let remotes = [
+            "https://github.com/sapcc/keppel.git",
+            "https://github.com/SAPCC/keppel",
+            "ssh://git@github.com/sapcc/keppel.git",
+            "https://github.com/sapcc/limes.git",
+        ]
+        .map(ToOwned::to_owned);
+        let mut changes = crate::repo_changesets::<MockClient>(&remotes, "1111111", "2222222").unwrap();
+        for (change, name) in changes
+            .iter_mut()
+            .zip(["keppel", "keppel-api", "keppel-janitor", "limes"])
+        {
+            change.name = name.to_owned();
+        }
+        // the same repo between other commits stays apart
+        changes.extend(
+            crate::repo_changesets::<MockClient>(&remotes[..1], "1111111", "3333333")
+                .unwrap()
+                .into_iter()
+                .map(|change| RepoChangeset {
+                    name: "keppel-next".to_owned(),
+                    ..change
+                }),
+        );
+
+        let deduped = super::dedup_changesets(changes);
+        let names: Vec<&str> = deduped.iter().map(|change| change.name.as_str()).collect();
+        assert_eq!(names, vec![
+            "keppel, keppel-api, keppel-janitor",
+            "limes",
+            "keppel-next"
+        ]);
+        assert_eq!(deduped[0].remote.original, "https://github.com/sapcc/keppel.git");
+    }
+
+    #[test]
+    fn find_values_yaml_pear_ignore() {
+        let workspace = env::temp_dir().join(format!("pear-reviewer-pear-ignore-{}", std::process::id()));
+        let repo = Repository::init(&workspace).unwrap();
+        let base = commit_images_yaml(
+            &repo,
+            Some(&images_yaml(&["keppel", "keppel-janitor", "limes"], "1111111")),
+            &[],
+        );
+        let base_commit = repo.find_commit(base).unwrap();
+        let head = commit_images_yaml(
+            &repo,
+            Some(&images_yaml(&["keppel", "keppel-janitor", "limes"], "2222222")),
+            &[&base_commit],
+        );
+        // the ignore file of the head commit applies, not the one checked out in the workspace
+        let head_commit = repo.find_commit(head).unwrap();
+        let mut tree = repo.treebuilder(Some(&head_commit.tree().unwrap())).unwrap();
+        let blob = repo
+            .blob(b"# built from keppel, reviewed there\n\n  *-janitor\n")
+            .unwrap();
+        tree.insert(PEAR_IGNORE_FILE, blob, 0o100_644).unwrap();
+        let tree = repo.find_tree(tree.write().unwrap()).unwrap();
+        let signature = Signature::now("pear-reviewer", "pear-reviewer@example.com").unwrap();
+        let head = repo
+            .commit(None, &signature, &signature, "ignore janitor", &tree, &[&head_commit])
+            .unwrap();
+        fs::write(workspace.join(PEAR_IGNORE_FILE), "keppel\n").unwrap();
+
+        let path_filter = PathFilter {
+            exclude_images: vec![Pattern::new("limes").unwrap()],
+            ..PathFilter::default()
+        };
+        let changes = find_values_yaml(
+            workspace.to_string_lossy().to_string(),
+            &base.to_string(),
+            &head.to_string(),
+            &path_filter,
+            false,
+        );
+        let sources_excluded = find_values_yaml(
+            workspace.to_string_lossy().to_string(),
+            &base.to_string(),
+            &head.to_string(),
+            &PathFilter {
+                exclude_images: vec![Pattern::new("https://github.com/sapcc/*").unwrap()],
+                ..PathFilter::default()
+            },
+            false,
+        );
+        std::fs::remove_dir_all(&workspace).unwrap();
+
+        let changes = changes.unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].name, "keppel");
+        assert!(sources_excluded.unwrap().is_empty());
+    }
+
+    #[test]
+    fn parse_pear_ignore() {
+        let patterns =
+            super::parse_pear_ignore("# comment\n\nkeppel-*\n  https://github.com/sapcc/limes*  \n").unwrap();
+        assert_eq!(patterns, vec![
+            Pattern::new("keppel-*").unwrap(),
+            Pattern::new("https://github.com/sapcc/limes*").unwrap(),
+        ]);
+        assert_eq!(
+            format!("{:#}", super::parse_pear_ignore("keppel\n[").unwrap_err()),
+            "invalid glob on line 2: Pattern syntax error near position 0: invalid range pattern"
+        );
+    }
+
+    #[test]
+    fn find_values_yaml_in_diff() {
+        let old = images_yaml(&["keppel"], "1111111");
+        let new = images_yaml(&["keppel"], "2222222");
+        let mut diff = "diff --git a/openstack/keppel/images.yaml b/openstack/keppel/images.yaml
+--- a/openstack/keppel/images.yaml
++++ b/openstack/keppel/images.yaml
+@@ -1,8 +1,8 @@
+"
+        .to_owned();
+        for line in old.lines() {
+            writeln!(diff, "-{line}").unwrap();
+        }
+        for line in new.lines() {
+            writeln!(diff, "+{line}").unwrap();
+        }
+        // the remote can't be parsed, so this fails if the file is not skipped
+        diff.push_str(
+            "diff --git a/examples/images.yaml b/examples/images.yaml
+--- /dev/null
++++ b/examples/images.yaml
+@@ -0,0 +1 @@
++containerImages: {example: {account: a, repository: b, tag: c, sources: [{repo: not a remote, commit: '3'}]}}
+",
+        );
+
+        let path_filter = PathFilter {
+            exclude_paths: vec![Pattern::new("examples/**").unwrap()],
+            ..PathFilter::default()
+        };
+        let changes = super::find_values_yaml_in_diff::<MockClient>(&diff, &no_workspace(), &path_filter).unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].name, "keppel");
+        assert_eq!(changes[0].base_commit, "1111111");
+        assert_eq!(changes[0].head_commit, "2222222");
+    }
+
+    #[test]
+    fn find_values_yaml_in_diff_pear_ignore() {
+        let old = images_yaml(&["keppel", "limes"], "1111111");
+        let new = images_yaml(&["keppel", "limes"], "2222222");
+        let mut diff = "diff --git a/images.yaml b/images.yaml
+--- a/images.yaml
++++ b/images.yaml
+@@ -1,15 +1,15 @@
+"
+        .to_owned();
+        for line in old.lines() {
+            writeln!(diff, "-{line}").unwrap();
+        }
+        for line in new.lines() {
+            writeln!(diff, "+{line}").unwrap();
+        }
+        let pear_ignore = "diff --git a/.pear-ignore b/.pear-ignore
+--- /dev/null
++++ b/.pear-ignore
+@@ -0,0 +1 @@
++limes
+";
+
+        // the workspace has an outdated ignore file, which is only used when the diff does not change it
+        let workspace = env::temp_dir().join(format!("pear-reviewer-diff-pear-ignore-{}", std::process::id()));
+        fs::create_dir_all(&workspace).unwrap();
+        fs::write(workspace.join(PEAR_IGNORE_FILE), "keppel\n").unwrap();
+        let with_ignore = super::find_values_yaml_in_diff::<MockClient>(
+            &(diff.clone() + pear_ignore),
+            &workspace,
+            &PathFilter::default(),
+        );
+        let without_ignore = super::find_values_yaml_in_diff::<MockClient>(&diff, &workspace, &PathFilter::default());
+        std::fs::remove_dir_all(&workspace).unwrap();
+
+        let names =
+            |changes: Vec<RepoChangeset<MockClient>>| changes.into_iter().map(|change| change.name).collect::<Vec<_>>();
+        assert_eq!(names(with_ignore.unwrap()), vec!["keppel"]);
+        assert_eq!(names(without_ignore.unwrap()), vec!["limes"]);
+    }
+
+    #[tokio::test]
+    async fn analyze_values_yaml_not_behind() {
+        let old = images_yaml(&["keppel"], "1111111");
+        let new = images_yaml(&["keppel"], "2222222");
+        let mut diff = "diff --git a/images.yaml b/images.yaml
+--- a/images.yaml
++++ b/images.yaml
+@@ -1,8 +1,8 @@
+"
+        .to_owned();
+        for line in old.lines() {
+            writeln!(diff, "-{line}").unwrap();
+        }
+        for line in new.lines() {
+            writeln!(diff, "+{line}").unwrap();
+        }
+
+        let mut changes =
+            super::find_values_yaml_in_diff::<MockClient>(&diff, &no_workspace(), &PathFilter::default()).unwrap();
+        let mut repo_changeset = changes.remove(0);
+        ClientSet::new(ClientConfig::default())
+            .fill(&mut repo_changeset.remote)
+            .unwrap();
+        // the source moved forward from 1111111 to 2222222
+        let client = repo_changeset.remote.client.as_ref().unwrap();
+        let forward = ("1111111".to_owned(), "2222222".to_owned());
+        let backward = ("2222222".to_owned(), "1111111".to_owned());
+        client.compares.lock().unwrap().insert(forward.clone(), Vec::new());
+        client.compares.lock().unwrap().insert(backward.clone(), Vec::new());
+        client
+            .compare_statuses
+            .lock()
+            .unwrap()
+            .insert(forward, CompareStatus::Ahead);
+        client
+            .compare_statuses
+            .lock()
+            .unwrap()
+            .insert(backward, CompareStatus::Behind);
+
+        let repo = repo_changeset.analyze_commits(AnalyzeOptions::default()).await.unwrap();
+        assert_eq!(repo.compare_status, Some(CompareStatus::Ahead));
+    }
+
+    #[test]
+    fn find_values_yaml_tag_bumps_in_diff() {
+        let diff = "diff --git a/openstack/keppel/values.yaml b/openstack/keppel/values.yaml
+--- a/openstack/keppel/values.yaml
++++ b/openstack/keppel/values.yaml
+@@ -1,4 +1,4 @@
+ image:
+   repository: keppel/api
+-  tag: 20240801
++  tag: 20240901
+ replicas: 2
+diff --git a/openstack/limes/values.yaml b/openstack/limes/values.yaml
+--- /dev/null
++++ b/openstack/limes/values.yaml
+@@ -0,0 +1,3 @@
++image:
++  repository: limes
++  tag: v1.2.0
+diff --git a/openstack/nova/values.yaml b/openstack/nova/values.yaml
+--- a/openstack/nova/values.yaml
++++ b/openstack/nova/values.yaml
+@@ -1,4 +1,4 @@
+ image:
+   repository: nova
+   tag: stable
+-replicas: 1
++replicas: 3
+";
+        let key_paths = vec!["image".to_owned()];
+        let tag_bumps =
+            super::find_values_yaml_tag_bumps_in_diff(diff, &key_paths, &no_workspace(), &PathFilter::default())
+                .unwrap();
+        assert_eq!(tag_bumps, vec![
+            TagBump {
+                path: PathBuf::from("openstack/keppel/values.yaml"),
+                key_path: "image".to_owned(),
+                repository: "keppel/api".to_owned(),
+                old_tag: Some("20240801".to_owned()),
+                new_tag: "20240901".to_owned(),
+            },
+            TagBump {
+                path: PathBuf::from("openstack/limes/values.yaml"),
+                key_path: "image".to_owned(),
+                repository: "limes".to_owned(),
+                old_tag: None,
+                new_tag: "v1.2.0".to_owned(),
+            },
+        ]);
+        assert_eq!(
+            output::render_tag_bumps(&tag_bumps).unwrap(),
+            "
+Image tags changed in values.yaml without sources, their changes were not analyzed:
+- `keppel/api` at `image` of openstack/keppel/values.yaml: `20240801` → `20240901`
+- `limes` at `image` of openstack/limes/values.yaml: added with `v1.2.0`
+"
+        );
+
+        // without a key path values.yaml files are not looked at
+        assert!(
+            super::find_values_yaml_tag_bumps_in_diff(diff, &[], &no_workspace(), &PathFilter::default())
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn find_values_yaml_merge_base() {
+        let workspace = env::temp_dir().join(format!("pear-reviewer-merge-base-{}", std::process::id()));
+        let repo = Repository::init(&workspace).unwrap();
+        let fork_point = commit_images_yaml(&repo, Some(&images_yaml(&["keppel"], "1111111")), &[]);
+        let fork_point_commit = repo.find_commit(fork_point).unwrap();
+        // the target branch moved on after the PR branched off
+        let base = commit_images_yaml(&repo, Some(&images_yaml(&["keppel"], "3333333")), &[&fork_point_commit]);
+        let head = commit_images_yaml(&repo, Some(&images_yaml(&["keppel"], "2222222")), &[&fork_point_commit]);
+
+        let find = |use_merge_base| {
+            find_values_yaml(
+                workspace.to_string_lossy().to_string(),
+                &base.to_string(),
+                &head.to_string(),
+                &PathFilter::default(),
+                use_merge_base,
+            )
+        };
+        let without_merge_base = find(false);
+        let with_merge_base = find(true);
+        std::fs::remove_dir_all(&workspace).unwrap();
+
+        let without_merge_base = without_merge_base.unwrap();
+        assert_eq!(without_merge_base.len(), 1);
+        assert_eq!(without_merge_base[0].base_commit, "3333333");
+
+        let with_merge_base = with_merge_base.unwrap();
+        assert_eq!(with_merge_base.len(), 1);
+        assert_eq!(with_merge_base[0].base_commit, "1111111");
+        assert_eq!(with_merge_base[0].head_commit, "2222222");
+    }
+
+    #[test]
+    fn find_values_yaml_added() {
+        let workspace = env::temp_dir().join(format!("pear-reviewer-added-{}", std::process::id()));
+        let repo = Repository::init(&workspace).unwrap();
+        let base = commit_images_yaml(&repo, None, &[]);
+        let base_commit = repo.find_commit(base).unwrap();
+        let head = commit_images_yaml(&repo, Some(&images_yaml(&["keppel"], "2222222")), &[&base_commit]);
+
+        let changes = find_values_yaml(
+            workspace.to_string_lossy().to_string(),
+            &base.to_string(),
+            &head.to_string(),
+            &PathFilter::default(),
+            false,
+        )
+        .unwrap();
+        std::fs::remove_dir_all(&workspace).unwrap();
+
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].name, "keppel");
+        assert_eq!(changes[0].base_commit, "");
+        assert_eq!(changes[0].head_commit, "2222222");
+    }
+
+    #[test]
+    fn find_values_yaml_respelled_source() {
+        let workspace = env::temp_dir().join(format!("pear-reviewer-respelled-{}", std::process::id()));
+        let repo = Repository::init(&workspace).unwrap();
+        let old = images_yaml(&["keppel", "limes"], "1111111");
+        let base = commit_images_yaml(&repo, Some(&old), &[]);
+        let base_commit = repo.find_commit(base).unwrap();
+        // keppel still comes from the same repo, but limes moved to another one
+        let new = images_yaml(&["keppel", "limes"], "2222222").replacen(
+            "https://github.com/sapcc/keppel.git",
+            "http://github.com/SAPCC/keppel/",
+            1,
+        );
+        let new = new.replacen(
+            "https://github.com/sapcc/keppel.git",
+            "https://github.com/sapcc/limes.git",
+            1,
+        );
+        let head = commit_images_yaml(&repo, Some(&new), &[&base_commit]);
+
+        let changes = find_values_yaml(
+            workspace.to_string_lossy().to_string(),
+            &base.to_string(),
+            &head.to_string(),
+            &PathFilter::default(),
+            false,
+        )
+        .unwrap();
+        std::fs::remove_dir_all(&workspace).unwrap();
+
+        let mut changes: Vec<_> = changes
+            .iter()
+            .map(|change| {
+                (
+                    change.name.as_str(),
+                    change.base_commit.as_str(),
+                    change.head_commit.as_str(),
+                )
+            })
+            .collect();
+        changes.sort_unstable();
+        assert_eq!(changes, vec![
+            ("keppel", "1111111", "2222222"),
+            ("limes", "", "2222222")
+        ]);
+    }
+
+    #[test]
+    fn find_values_yaml_deleted() {
+        let workspace = env::temp_dir().join(format!("pear-reviewer-deleted-{}", std::process::id()));
+        let repo = Repository::init(&workspace).unwrap();
+        let base = commit_images_yaml(&repo, Some(&images_yaml(&["keppel"], "1111111")), &[]);
+        let base_commit = repo.find_commit(base).unwrap();
+        let head = commit_images_yaml(&repo, None, &[&base_commit]);
+
+        let changes = find_values_yaml(
+            workspace.to_string_lossy().to_string(),
+            &base.to_string(),
+            &head.to_string(),
+            &PathFilter::default(),
+            false,
+        )
+        .unwrap();
+        std::fs::remove_dir_all(&workspace).unwrap();
+
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn find_values_yaml_invalid_refs() {
+        let workspace = env::temp_dir().join(format!("pear-reviewer-invalid-refs-{}", std::process::id()));
+        let repo = Repository::init(&workspace).unwrap();
+        let commit = commit_images_yaml(&repo, Some(&images_yaml(&["keppel"], "1111111")), &[]).to_string();
+
+        let invalid_base = find_values_yaml(
+            workspace.to_string_lossy().to_string(),
+            "v0",
+            &commit,
+            &PathFilter::default(),
+            false,
+        );
+        let invalid_head = find_values_yaml(
+            workspace.to_string_lossy().to_string(),
+            &commit,
+            "v0",
+            &PathFilter::default(),
+            false,
+        );
+        std::fs::remove_dir_all(&workspace).unwrap();
+
+        assert_eq!(
+            invalid_base.unwrap_err().to_string(),
+            "base ref \"v0\" does not exist in the workspace"
+        );
+        assert_eq!(
+            invalid_head.unwrap_err().to_string(),
+            "head ref \"v0\" does not exist in the workspace"
+        );
+    }
+
+    #[test]
+    fn dry_run_find_values_yaml() {
+        let workspace = env::temp_dir().join(format!("pear-reviewer-dry-run-{}", std::process::id()));
+        let repo = Repository::init(&workspace).unwrap();
+        let base = commit_images_yaml(&repo, Some(&images_yaml(&["keppel"], "1111111")), &[]);
+        let base_commit = repo.find_commit(base).unwrap();
+        let head = commit_images_yaml(&repo, Some(&images_yaml(&["keppel"], "2222222")), &[&base_commit]);
+
+        let changes = find_values_yaml(
+            workspace.to_string_lossy().to_string(),
+            &base.to_string(),
+            &head.to_string(),
+            &PathFilter::default(),
+            false,
+        )
+        .unwrap();
+        std::fs::remove_dir_all(&workspace).unwrap();
+
+        let output = output::render_dry_run(&changes, &AnalyzeOptions::default()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[0], "Would analyze 1 repositories:");
+        assert!(lines[1].starts_with("- keppel from https://github.com/sapcc/keppel.git moved from "));
+        assert_eq!(
+            lines.last().unwrap(),
+            &"Estimated API calls: at least 3, plus 1 per commit and 2 per associated pull request"
+        );
+    }
+}
@@ -13,13 +13,16 @@
 // limitations under the License.
 
 use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
 
-use anyhow::Context;
+use anyhow::{bail, Context};
 use git2::{DiffFile, Repository};
 use serde::{Deserialize, Serialize};
+use serde_yml::Value;
 
 #[allow(clippy::module_name_repetitions)]
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct ImageRefs {
     #[serde(rename = "containerImages")]
     pub container_images: HashMap<String, ImageRef>,
@@ -32,16 +35,120 @@ impl ImageRefs {
         let blob = repo
             .find_blob(blob_id)
             .with_context(|| format!("cannot find Git blob {blob_id}"))?;
-        serde_yml::from_slice(blob.content()).with_context(|| format!("cannot parse yaml file {:?}", diff_file.path()))
+        let path = diff_file
+            .path()
+            .map_or_else(String::new, |path| path.display().to_string());
+        Self::from_slice(blob.content(), &path)
+    }
+
+    pub fn parse_str(content: &str, path: &Path) -> Result<Self, anyhow::Error> {
+        Self::from_slice(content.as_bytes(), &path.display().to_string())
+    }
+
+    pub fn parse_file(path: &Path) -> Result<Self, anyhow::Error> {
+        let content = fs::read(path).with_context(|| format!("cannot read {}", path.display()))?;
+        Self::from_slice(&content, &path.display().to_string())
+    }
+
+    // Validates the structure before deserializing because the errors of serde_yml do not name the offending image.
+    fn from_slice(content: &[u8], path: &str) -> Result<Self, anyhow::Error> {
+        let value: Value = serde_yml::from_slice(content).with_context(|| format!("cannot parse yaml file {path}"))?;
+        validate(&value, &String::from_utf8_lossy(content)).with_context(|| format!("invalid images file {path}"))?;
+        serde_yml::from_slice(content).with_context(|| format!("cannot parse yaml file {path}"))
     }
 }
 
+fn validate(value: &Value, content: &str) -> Result<(), anyhow::Error> {
+    let Some(container_images) = value.get("containerImages") else {
+        bail!("missing `containerImages`");
+    };
+    let Some(container_images) = container_images.as_mapping() else {
+        bail!("`containerImages` is not a map{}", location(content, "containerImages"));
+    };
+
+    for (name, image) in container_images {
+        let name = name.as_str().map_or_else(|| format!("{name:?}"), str::to_owned);
+        let location = location(content, &name);
+        if !image.is_mapping() {
+            bail!("image `{name}` is not a map{location}");
+        }
+        for field in ["account", "repository", "tag"] {
+            match image.get(field) {
+                None => bail!("image `{name}` missing `{field}`{location}"),
+                Some(value) if !is_string(value) => {
+                    bail!("image `{name}` has invalid `{field}`, expected a string{location}")
+                },
+                Some(_) => {},
+            }
+        }
+
+        if image
+            .get("requiredApprovals")
+            .is_some_and(|value| value.as_u64().is_none())
+        {
+            bail!("image `{name}` has invalid `requiredApprovals`, expected a non-negative number{location}");
+        }
+        if let Some(approvers) = image.get("requiredApprovers") {
+            if !approvers
+                .as_sequence()
+                .is_some_and(|approvers| approvers.iter().all(Value::is_string))
+            {
+                bail!("image `{name}` has invalid `requiredApprovers`, expected a list of users{location}");
+            }
+        }
+
+        let Some(sources) = image.get("sources") else {
+            bail!("image `{name}` missing `sources`{location}");
+        };
+        let Some(sources) = sources.as_sequence() else {
+            bail!("image `{name}` has invalid `sources`, expected a list{location}");
+        };
+        for (idx, source) in sources.iter().enumerate() {
+            for field in ["repo", "commit"] {
+                match source.get(field) {
+                    None => bail!("image `{name}` source {idx} missing `{field}`{location}"),
+                    Some(value) if !is_string(value) => {
+                        bail!("image `{name}` source {idx} has invalid `{field}`, expected a string{location}")
+                    },
+                    Some(_) => {},
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// serde_yml::from_slice() also accepts unquoted numbers for strings, e.g. a commit hash consisting only of digits.
+fn is_string(value: &Value) -> bool {
+    value.is_string() || value.is_number()
+}
+
+// Finds the line on which a key is defined, serde_yml::Value does not retain the positions of the parsed nodes.
+fn location(content: &str, key: &str) -> String {
+    content
+        .lines()
+        .position(|line| {
+            let line = line.trim_start();
+            [format!("{key}:"), format!("\"{key}\":"), format!("'{key}':")]
+                .iter()
+                .any(|prefix| line.starts_with(prefix.as_str()))
+        })
+        .map_or_else(String::new, |idx| format!(" (near line {})", idx + 1))
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ImageRef {
     pub account: String,
     pub repository: String,
     pub tag: String,
     pub sources: Vec<SourceRepoRef>,
+    /// approvals every change of the sources needs, overrides --min-approvals when it is stricter
+    #[serde(rename = "requiredApprovals", default, skip_serializing_if = "Option::is_none")]
+    pub required_approvals: Option<usize>,
+    /// users who all have to approve every change of the sources
+    #[serde(rename = "requiredApprovers", default, skip_serializing_if = "Vec::is_empty")]
+    pub required_approvers: Vec<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -49,3 +156,226 @@ pub struct SourceRepoRef {
     pub repo: String,
     pub commit: String,
 }
+
+/// An image that values.yaml references by repository and tag only, without sources to review.
+#[derive(Debug, PartialEq)]
+pub struct ValuesImage {
+    pub repository: String,
+    pub tag: String,
+}
+
+impl ValuesImage {
+    /// Parses the image at a dotted key path like `image` or `global.images.api`, None when the path does not exist.
+    /// The image is either a map with `repository` and `tag` or a string like `keppel/api:20240801`.
+    pub fn from_slice(content: &[u8], key_path: &str, path: &str) -> Result<Option<Self>, anyhow::Error> {
+        let value: Value = serde_yml::from_slice(content).with_context(|| format!("cannot parse yaml file {path}"))?;
+        let Some(image) = key_path.split('.').try_fold(&value, |value, key| value.get(key)) else {
+            return Ok(None);
+        };
+
+        if let Value::String(image) = image {
+            // the tag follows the last colon, unless that one separates the port of the registry
+            let Some((repository, tag)) = image.rsplit_once(':').filter(|(_, tag)| !tag.contains('/')) else {
+                bail!("`{key_path}` in {path} has no tag in `{image}`");
+            };
+            return Ok(Some(Self {
+                repository: repository.to_owned(),
+                tag: tag.to_owned(),
+            }));
+        }
+
+        let field = |field: &str| match image.get(field) {
+            Some(Value::String(value)) => Ok(value.clone()),
+            // unquoted tags like 20240801 are parsed as numbers
+            Some(Value::Number(value)) => Ok(value.to_string()),
+            Some(_) => bail!(
+                "`{key_path}.{field}` in {path} is not a string{}",
+                location(&String::from_utf8_lossy(content), field)
+            ),
+            None => bail!("`{key_path}` in {path} is missing `{field}`"),
+        };
+        Ok(Some(Self {
+            repository: field("repository")?,
+            tag: field("tag")?,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(content: &str) -> String {
+        let err = ImageRefs::from_slice(content.as_bytes(), "images.yaml").unwrap_err();
+        format!("{err:#}")
+    }
+
+    #[test]
+    fn from_slice() {
+        let image_refs = ImageRefs::from_slice(
+            b"containerImages:
+  keppel:
+    account: sapcc
+    repository: keppel
+    tag: \"20240801\"
+    sources:
+      - repo: https://github.com/sapcc/keppel.git
+        commit: 1111111
+",
+            "images.yaml",
+        )
+        .unwrap();
+        let image = &image_refs.container_images["keppel"];
+        assert_eq!(image.tag, "20240801");
+        assert_eq!(image.sources[0].commit, "1111111");
+        assert_eq!(image.required_approvals, None);
+        assert!(image.required_approvers.is_empty());
+    }
+
+    #[test]
+    fn from_slice_required_approvals() {
+        let image_refs = ImageRefs::from_slice(
+            b"containerImages:
+  keppel:
+    account: sapcc
+    repository: keppel
+    tag: latest
+    requiredApprovals: 2
+    requiredApprovers: [security-lead]
+    sources:
+      - repo: https://github.com/sapcc/keppel.git
+        commit: 1111111
+",
+            "images.yaml",
+        )
+        .unwrap();
+        let image = &image_refs.container_images["keppel"];
+        assert_eq!(image.required_approvals, Some(2));
+        assert_eq!(image.required_approvers, vec!["security-lead".to_owned()]);
+
+        let image = "containerImages:\n  keppel:\n    account: sapcc\n    repository: keppel\n    tag: latest\n";
+        assert_eq!(
+            parse_error(&format!("{image}    requiredApprovals: two\n    sources: []\n")),
+            "invalid images file images.yaml: image `keppel` has invalid `requiredApprovals`, expected a non-negative \
+             number (near line 2)"
+        );
+        assert_eq!(
+            parse_error(&format!("{image}    requiredApprovers: security-lead\n    sources: []\n")),
+            "invalid images file images.yaml: image `keppel` has invalid `requiredApprovers`, expected a list of users \
+             (near line 2)"
+        );
+    }
+
+    #[test]
+    fn from_slice_missing_container_images() {
+        assert_eq!(
+            parse_error("images:\n  keppel: {}\n"),
+            "invalid images file images.yaml: missing `containerImages`"
+        );
+        assert_eq!(
+            parse_error("containerImages:\n  - keppel\n"),
+            "invalid images file images.yaml: `containerImages` is not a map (near line 1)"
+        );
+    }
+
+    #[test]
+    fn from_slice_missing_field() {
+        assert_eq!(
+            parse_error(
+                "containerImages:
+  keppel:
+    account: sapcc
+    repository: keppel
+    tag: latest
+    sources: []
+  limes:
+    account: sapcc
+    repository: limes
+    tag: latest
+"
+            ),
+            "invalid images file images.yaml: image `limes` missing `sources` (near line 7)"
+        );
+    }
+
+    #[test]
+    fn from_slice_invalid_field() {
+        assert_eq!(
+            parse_error(
+                "containerImages:
+  keppel:
+    account: sapcc
+    repository: [keppel]
+    tag: latest
+    sources: []
+"
+            ),
+            "invalid images file images.yaml: image `keppel` has invalid `repository`, expected a string (near line 2)"
+        );
+        assert_eq!(
+            parse_error(
+                "containerImages:
+  keppel:
+    account: sapcc
+    repository: keppel
+    tag: latest
+    sources: https://github.com/sapcc/keppel.git
+"
+            ),
+            "invalid images file images.yaml: image `keppel` has invalid `sources`, expected a list (near line 2)"
+        );
+    }
+
+    #[test]
+    fn from_slice_invalid_source() {
+        assert_eq!(
+            parse_error(
+                "containerImages:
+  \"keppel\":
+    account: sapcc
+    repository: keppel
+    tag: latest
+    sources:
+      - repo: https://github.com/sapcc/keppel.git
+"
+            ),
+            "invalid images file images.yaml: image `keppel` source 0 missing `commit` (near line 2)"
+        );
+    }
+
+    #[test]
+    fn values_image_from_slice() {
+        let values = b"global:\n  images:\n    api:\n      repository: keppel/api\n      tag: 20240801\n";
+        assert_eq!(
+            ValuesImage::from_slice(values, "global.images.api", "values.yaml").unwrap(),
+            Some(ValuesImage {
+                repository: "keppel/api".to_owned(),
+                tag: "20240801".to_owned(),
+            })
+        );
+        assert_eq!(ValuesImage::from_slice(values, "image", "values.yaml").unwrap(), None);
+
+        let err = ValuesImage::from_slice(b"image:\n  repository: keppel\n", "image", "values.yaml").unwrap_err();
+        assert_eq!(err.to_string(), "`image` in values.yaml is missing `tag`");
+
+        let values = b"image: registry.example.com:5000/keppel/api:20240801\n";
+        assert_eq!(
+            ValuesImage::from_slice(values, "image", "values.yaml").unwrap(),
+            Some(ValuesImage {
+                repository: "registry.example.com:5000/keppel/api".to_owned(),
+                tag: "20240801".to_owned(),
+            })
+        );
+        let err =
+            ValuesImage::from_slice(b"image: registry.example.com:5000/keppel\n", "image", "values.yaml").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`image` in values.yaml has no tag in `registry.example.com:5000/keppel`"
+        );
+    }
+
+    #[test]
+    fn from_slice_invalid_yaml() {
+        assert!(parse_error("containerImages: [").starts_with("cannot parse yaml file images.yaml: "));
+    }
+}
@@ -0,0 +1,503 @@
+// Copyright 2024 SAP SE
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll};
+use std::time::Duration;
+use std::{env, fs};
+
+use anyhow::{bail, Context};
+use chrono::{DateTime, Utc};
+use http::header::{AUTHORIZATION, RETRY_AFTER, USER_AGENT};
+use http::{HeaderMap, HeaderName, HeaderValue, Response, StatusCode, Uri};
+use http_body_util::BodyExt;
+use hyper_rustls::HttpsConnectorBuilder;
+use hyper_timeout::TimeoutConnector;
+use hyper_util::client::legacy::connect::proxy::Tunnel;
+use hyper_util::client::legacy::connect::{Connection, HttpConnector};
+use hyper_util::client::legacy::Client;
+use hyper_util::client::proxy::matcher::Matcher;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use octocrab::service::middleware::base_uri::BaseUriLayer;
+use octocrab::service::middleware::extra_headers::ExtraHeadersLayer;
+use octocrab::{AuthState, Octocrab, OctocrabBuilder};
+use rustls::pki_types::CertificateDer;
+use rustls::RootCertStore;
+use tokio::net::TcpStream;
+use tower_http::follow_redirect::FollowRedirectLayer;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::api_clients::ClientConfig;
+
+const GITHUB_API_VERSION: HeaderName = HeaderName::from_static("x-github-api-version");
+const RATE_LIMIT_REMAINING: HeaderName = HeaderName::from_static("x-ratelimit-remaining");
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Builds the octocrab client on top of our own connector stack, so that proxies and timeouts
+/// are applied the same way for every GitHub instance.
+pub fn build_octocrab(token: &str, api_endpoint: &str, config: &ClientConfig) -> anyhow::Result<Octocrab> {
+    build_with_token(Some(token), api_endpoint, None, config)
+}
+
+/// Like [`build_octocrab`], but also asks GitHub for this version of the REST API, e.g. "2022-11-28".
+pub fn build_github_octocrab(
+    token: &str,
+    api_endpoint: &str,
+    api_version: &str,
+    config: &ClientConfig,
+) -> anyhow::Result<Octocrab> {
+    build_with_token(Some(token), api_endpoint, Some(api_version), config)
+}
+
+/// Like [`build_octocrab`], but without sending a token, for endpoints like webhooks that are not a GitHub instance.
+pub fn build_unauthenticated(endpoint: &str, config: &ClientConfig) -> anyhow::Result<Octocrab> {
+    build_with_token(None, endpoint, None, config)
+}
+
+fn build_with_token(
+    token: Option<&str>,
+    api_endpoint: &str,
+    api_version: Option<&str>,
+    config: &ClientConfig,
+) -> anyhow::Result<Octocrab> {
+    let base_uri: Uri = api_endpoint
+        .parse()
+        .with_context(|| format!("failed to parse api endpoint {api_endpoint}"))?;
+
+    let mut http_connector = HttpConnector::new();
+    http_connector.enforce_http(false);
+
+    let no_proxy = env::var("NO_PROXY").or_else(|_| env::var("no_proxy")).ok();
+    match proxy_matcher(config.proxy.as_deref(), no_proxy).intercept(&base_uri) {
+        Some(intercept) => {
+            let mut tunnel = Tunnel::new(intercept.uri().clone(), http_connector);
+            if let Some(auth) = intercept.basic_auth() {
+                tunnel = tunnel.with_auth(auth.clone());
+            }
+            build_octocrab_with_connector(tunnel, base_uri, token, api_version, config)
+        },
+        None => build_octocrab_with_connector(http_connector, base_uri, token, api_version, config),
+    }
+}
+
+// An explicitly configured proxy takes precedence over HTTPS_PROXY, HTTP_PROXY and ALL_PROXY.
+// Hosts listed in NO_PROXY always bypass the proxy.
+fn proxy_matcher(proxy: Option<&str>, no_proxy: Option<String>) -> Matcher {
+    match proxy {
+        Some(proxy) => Matcher::builder()
+            .all(proxy.to_string())
+            .no(no_proxy.unwrap_or_default())
+            .build(),
+        None => Matcher::from_env(),
+    }
+}
+
+fn build_octocrab_with_connector<C>(
+    connector: C,
+    base_uri: Uri,
+    token: Option<&str>,
+    api_version: Option<&str>,
+    config: &ClientConfig,
+) -> anyhow::Result<Octocrab>
+where
+    C: Service<Uri, Response = TokioIo<TcpStream>> + Clone + Send + Sync + 'static,
+    C::Future: Send + Unpin + 'static,
+    C::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    TokioIo<TcpStream>: Connection,
+{
+    let tls_config = rustls::ClientConfig::builder()
+        .with_root_certificates(root_cert_store(config.ca_cert.as_deref())?)
+        .with_no_client_auth();
+    let https_connector = HttpsConnectorBuilder::new()
+        .with_tls_config(tls_config)
+        .https_or_http()
+        .enable_http1()
+        .wrap_connector(connector);
+
+    let mut timeout_connector = TimeoutConnector::new(https_connector);
+    timeout_connector.set_connect_timeout(Some(config.request_timeout));
+    timeout_connector.set_read_timeout(Some(config.request_timeout));
+    timeout_connector.set_write_timeout(Some(config.request_timeout));
+
+    // connections are pooled per client and every host gets its own client, see ClientSet
+    let mut client_builder = Client::builder(TokioExecutor::new());
+    if let Some(pool_size) = config.pool_size {
+        client_builder.pool_max_idle_per_host(pool_size);
+    }
+    let client = client_builder.build(timeout_connector);
+
+    let mut headers = vec![(USER_AGENT, HeaderValue::from_static("pear-reviewer"))];
+    if let Some(token) = token {
+        headers.push((
+            AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {token}")).context("token contains invalid characters")?,
+        ));
+    }
+    if let Some(api_version) = api_version {
+        headers.push((
+            GITHUB_API_VERSION,
+            HeaderValue::from_str(api_version)
+                .with_context(|| format!("invalid GitHub API version {api_version:?}"))?,
+        ));
+    }
+
+    Ok(OctocrabBuilder::new_empty()
+        .with_service(client)
+        .with_layer(&FollowRedirectLayer::new())
+        .with_layer(&BaseUriLayer::new(base_uri))
+        .with_layer(&ExtraHeadersLayer::new(Arc::new(headers)))
+        .with_layer(&RetryAfterLayer)
+        .with_auth(AuthState::None)
+        .build()?)
+}
+
+/// A rate limited response with a Retry-After header. octocrab drops the headers of error responses, so
+/// [`RetryAfterLayer`] turns these responses into this error, which octocrab passes on as `Error::Service`.
+#[derive(Debug, thiserror::Error)]
+#[error("{message}")]
+pub struct RetryAfter {
+    pub status: StatusCode,
+    pub wait: Duration,
+    /// the message of the GitHub style error body, or the body itself when it is not JSON
+    pub message: String,
+}
+
+#[derive(Clone)]
+struct RetryAfterLayer;
+
+impl<S> Layer<S> for RetryAfterLayer {
+    type Service = RetryAfterService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RetryAfterService(inner)
+    }
+}
+
+#[derive(Clone)]
+struct RetryAfterService<S>(S);
+
+impl<S, Req, B> Service<Req> for RetryAfterService<S>
+where
+    S: Service<Req, Response = Response<B>>,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+    B: http_body::Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Response<B>, BoxError>> + Send>>;
+    type Response = Response<B>;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), BoxError>> {
+        self.0.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, request: Req) -> Self::Future {
+        let response = self.0.call(request);
+        Box::pin(async move {
+            let response = response.await.map_err(Into::into)?;
+            let status = response.status();
+            let Some(wait) = retry_after(status, response.headers(), Utc::now()) else {
+                return Ok(response);
+            };
+            let body = response.into_body().collect().await.map_err(Into::into)?.to_bytes();
+            let message = serde_json::from_slice::<serde_json::Value>(&body)
+                .ok()
+                .and_then(|body| body.get("message")?.as_str().map(str::to_owned))
+                .unwrap_or_else(|| String::from_utf8_lossy(&body).into_owned());
+            Err(RetryAfter { status, wait, message }.into())
+        })
+    }
+}
+
+// The Retry-After header is either a number of seconds or an HTTP date. An exhausted primary rate limit is left to
+// --wait-on-rate-limit, which waits for its reset.
+fn retry_after(status: StatusCode, headers: &HeaderMap, now: DateTime<Utc>) -> Option<Duration> {
+    if status != StatusCode::FORBIDDEN && status != StatusCode::TOO_MANY_REQUESTS {
+        return None;
+    }
+    if headers
+        .get(RATE_LIMIT_REMAINING)
+        .is_some_and(|remaining| remaining == "0")
+    {
+        return None;
+    }
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some((date.with_timezone(&Utc) - now).to_std().unwrap_or_default())
+}
+
+// The system roots are always trusted, certificates from ca_cert are trusted in addition to them.
+fn root_cert_store(ca_cert: Option<&Path>) -> anyhow::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    roots.add_parsable_certificates(rustls_native_certs::load_native_certs().certs);
+
+    if let Some(path) = ca_cert {
+        let pem = fs::read(path).with_context(|| format!("failed to read CA certificates from {}", path.display()))?;
+        for cert in parse_ca_certs(&pem).with_context(|| format!("failed to parse {}", path.display()))? {
+            roots
+                .add(cert)
+                .with_context(|| format!("invalid CA certificate in {}", path.display()))?;
+        }
+    }
+
+    if roots.is_empty() {
+        bail!("no root certificates found");
+    }
+
+    Ok(roots)
+}
+
+fn parse_ca_certs(pem: &[u8]) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    let certs = rustls_pemfile::certs(&mut &pem[..])
+        .collect::<Result<Vec<_>, _>>()
+        .context("malformed PEM")?;
+    if certs.is_empty() {
+        bail!("no PEM encoded certificates found");
+    }
+
+    Ok(certs)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Instant;
+
+    use super::*;
+    use crate::error::PearError;
+    use crate::retry::TransientError;
+
+    #[tokio::test]
+    async fn build_octocrab_request_timeout() {
+        // a server which accepts connections but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut streams = Vec::new();
+            for stream in listener.incoming() {
+                streams.push(stream);
+            }
+        });
+
+        let config = ClientConfig {
+            request_timeout: Duration::from_millis(200),
+            ..ClientConfig::default()
+        };
+        let octocrab = build_octocrab("token", &format!("http://{addr}"), &config).unwrap();
+
+        let start = Instant::now();
+        let result = octocrab._get("/rate_limit").await;
+        assert!(result.is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn build_octocrab_proxy() {
+        // a proxy which records the first request it receives
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0; 1024];
+            let len = stream.read(&mut buf).unwrap();
+            sender.send(String::from_utf8_lossy(&buf[..len]).to_string()).unwrap();
+        });
+
+        let config = ClientConfig {
+            request_timeout: Duration::from_secs(2),
+            proxy: Some(format!("http://{addr}")),
+            ..ClientConfig::default()
+        };
+        let octocrab = build_octocrab("token", "https://github.example.com/api/v3", &config).unwrap();
+        let _ = octocrab._get("/rate_limit").await;
+
+        let request = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(request.starts_with("CONNECT github.example.com:443 HTTP/1.1"));
+    }
+
+    #[tokio::test]
+    async fn build_github_octocrab_api_version() {
+        // a server which records the first request it receives
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0; 1024];
+            let len = stream.read(&mut buf).unwrap();
+            sender
+                .send(String::from_utf8_lossy(&buf[..len]).to_lowercase())
+                .unwrap();
+        });
+
+        let config = ClientConfig {
+            request_timeout: Duration::from_secs(2),
+            ..ClientConfig::default()
+        };
+        let octocrab = build_github_octocrab("token", &format!("http://{addr}"), "2021-06-01", &config).unwrap();
+        let _ = octocrab._get("/rate_limit").await;
+
+        let request = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(
+            request.contains("\r\nx-github-api-version: 2021-06-01\r\n"),
+            "{request}"
+        );
+    }
+
+    #[tokio::test]
+    async fn build_octocrab_retry_after() {
+        // a server which asks to slow down
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0; 1024];
+            let _ = stream.read(&mut buf);
+            let body = r#"{"message":"You have exceeded a secondary rate limit."}"#;
+            let _ = write!(
+                stream,
+                "HTTP/1.1 403 Forbidden\r\nRetry-After: 7\r\nContent-Type: application/json\r\nContent-Length: \
+                 {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+        });
+
+        let config = ClientConfig {
+            request_timeout: Duration::from_secs(2),
+            ..ClientConfig::default()
+        };
+        let octocrab = build_octocrab("token", &format!("http://{addr}"), &config).unwrap();
+        let err = octocrab
+            .get::<serde_json::Value, _, ()>("/rate_limit", None)
+            .await
+            .unwrap_err();
+
+        assert_eq!(err.suggested_wait(), Some(Duration::from_secs(7)));
+        assert!(matches!(
+            PearError::from(err),
+            PearError::RateLimited(message) if message == "You have exceeded a secondary rate limit."
+        ));
+    }
+
+    #[test]
+    fn retry_after() {
+        let now = DateTime::parse_from_rfc3339("2024-08-01T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let headers = |pairs: &[(&'static str, &'static str)]| {
+            pairs
+                .iter()
+                .map(|(name, value)| (HeaderName::from_static(name), HeaderValue::from_static(value)))
+                .collect::<HeaderMap>()
+        };
+
+        let seconds = headers(&[("retry-after", "30")]);
+        assert_eq!(
+            super::retry_after(StatusCode::TOO_MANY_REQUESTS, &seconds, now),
+            Some(Duration::from_secs(30))
+        );
+        let date = headers(&[("retry-after", "Thu, 01 Aug 2024 12:01:30 GMT")]);
+        assert_eq!(
+            super::retry_after(StatusCode::FORBIDDEN, &date, now),
+            Some(Duration::from_secs(90))
+        );
+        let past = headers(&[("retry-after", "Thu, 01 Aug 2024 11:00:00 GMT")]);
+        assert_eq!(
+            super::retry_after(StatusCode::FORBIDDEN, &past, now),
+            Some(Duration::ZERO)
+        );
+
+        assert_eq!(super::retry_after(StatusCode::FORBIDDEN, &headers(&[]), now), None);
+        assert_eq!(super::retry_after(StatusCode::SERVICE_UNAVAILABLE, &seconds, now), None);
+        let exhausted = headers(&[("retry-after", "30"), ("x-ratelimit-remaining", "0")]);
+        assert_eq!(super::retry_after(StatusCode::FORBIDDEN, &exhausted, now), None);
+    }
+
+    #[test]
+    fn proxy_matcher() {
+        let uri: Uri = "https://api.github.com".parse().unwrap();
+        let matcher = super::proxy_matcher(Some("http://proxy.example.com:3128"), None);
+        assert_eq!(matcher.intercept(&uri).unwrap().uri(), "http://proxy.example.com:3128");
+
+        let matcher = super::proxy_matcher(
+            Some("http://proxy.example.com:3128"),
+            Some("github.example.com,.internal".to_owned()),
+        );
+        assert!(matcher
+            .intercept(&"https://github.example.com/api/v3".parse().unwrap())
+            .is_none());
+        assert!(matcher
+            .intercept(&"https://git.internal/api/v3".parse().unwrap())
+            .is_none());
+        assert!(matcher.intercept(&uri).is_some());
+    }
+
+    const CA_BUNDLE: &str = "-----BEGIN CERTIFICATE-----
+MIIBmzCCAUGgAwIBAgIUBgeu+QyDl9ODJW5UVieWzu2fLvIwCgYIKoZIzj0EAwIw
+IjEgMB4GA1UEAwwXcGVhci1yZXZpZXdlciB0ZXN0IENBIDEwIBcNMjYxMDE2MTY0
+ODQ4WhgPMjEyNjA5MjIxNjQ4NDhaMCIxIDAeBgNVBAMMF3BlYXItcmV2aWV3ZXIg
+dGVzdCBDQSAxMFkwEwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAEiLRBApEJpm26/7+g
+Z06ANDLcdUsX4kp9Wuboyjwxn5KYmu7pRiyaMscY7S91YA8v4rOu9EzTWNBhPIPq
+/GUfwaNTMFEwHQYDVR0OBBYEFHw3f8NDd7/fJxKZRRYCW/ITAYKXMB8GA1UdIwQY
+MBaAFHw3f8NDd7/fJxKZRRYCW/ITAYKXMA8GA1UdEwEB/wQFMAMBAf8wCgYIKoZI
+zj0EAwIDSAAwRQIgMzfHa8K76u9kQAJqAHdf77yxlQ+pQRoLHkOFuZTM4TACIQCO
+d8tBnxSN22lx4YHKsRhmjB6sWnMaDak1/qxcxyfQqQ==
+-----END CERTIFICATE-----
+-----BEGIN CERTIFICATE-----
+MIIBmjCCAUGgAwIBAgIUKZYrs/RdVxQc0XX93f2sXItYqtEwCgYIKoZIzj0EAwIw
+IjEgMB4GA1UEAwwXcGVhci1yZXZpZXdlciB0ZXN0IENBIDIwIBcNMjYxMDE2MTY0
+ODQ4WhgPMjEyNjA5MjIxNjQ4NDhaMCIxIDAeBgNVBAMMF3BlYXItcmV2aWV3ZXIg
+dGVzdCBDQSAyMFkwEwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAEiYh0Huy5+XHBBG9n
+OoS3VwvqrR0nKMveg3g1chMp65la3xHw35PuT8Kdbkgaft70eAKZsGF5pAMmNLOX
+iXycNqNTMFEwHQYDVR0OBBYEFEEr+xybeJtmwI/rOAYnGYBJfFwAMB8GA1UdIwQY
+MBaAFEEr+xybeJtmwI/rOAYnGYBJfFwAMA8GA1UdEwEB/wQFMAMBAf8wCgYIKoZI
+zj0EAwIDRwAwRAIgSAK1LWOQ6AaX7MTiC6Ls1P02mPVOUZdyazoJxPMjY4UCIFm8
+5bL3UW8adNSmjCabEobJOZadr5ZJq7MZ8tLBFD4s
+-----END CERTIFICATE-----
+";
+
+    #[test]
+    fn parse_ca_certs() {
+        assert_eq!(super::parse_ca_certs(CA_BUNDLE.as_bytes()).unwrap().len(), 2);
+        assert!(super::parse_ca_certs(b"not a certificate").is_err());
+        assert!(super::parse_ca_certs(b"-----BEGIN CERTIFICATE-----\n!!!\n-----END CERTIFICATE-----\n").is_err());
+    }
+
+    #[test]
+    fn root_cert_store_ca_cert() {
+        let path = std::env::temp_dir().join(format!("pear-reviewer-ca-{}.pem", std::process::id()));
+        std::fs::write(&path, CA_BUNDLE).unwrap();
+        let with_ca = root_cert_store(Some(&path)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(root_cert_store(Some(Path::new("/nonexistent/ca.pem"))).is_err());
+        // the custom certificates are added on top of the system ones
+        let system_len = root_cert_store(None).map(|roots| roots.len()).unwrap_or_default();
+        assert_eq!(with_ca.len(), system_len + 2);
+    }
+}
@@ -0,0 +1,441 @@
+// Copyright 2024 SAP SE
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Analyzes whether the changes between two commits of GitHub or Gitea repos went through reviewed PRs.
+//!
+//! The stable surface is [`repo_changesets`] and [`analyze`] together with the types re-exported here. A
+//! [`ClientSet`] creates one client per host. Everything else is public for the pear-reviewer CLI and may change
+//! between releases, like the `MockClient` of the `test-util` feature.
+
+#![warn(clippy::pedantic)]
+#![allow(clippy::missing_errors_doc, clippy::missing_panics_doc, clippy::must_use_candidate)]
+
+pub mod api_clients;
+pub mod approvers;
+pub mod changes;
+mod diff;
+pub mod error;
+mod gitea;
+pub mod github;
+mod graphql;
+pub mod helm_chart;
+mod helm_config;
+mod http_client;
+pub mod notify;
+pub mod output;
+pub mod policy;
+pub mod remote;
+mod repo;
+mod retry;
+pub mod serve;
+pub mod submodule;
+pub mod template;
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+
+use anyhow::Context;
+#[cfg(any(test, feature = "test-util"))]
+pub use api_clients::MockClient;
+use api_clients::{check_rate_limit, RateLimitStatus};
+pub use api_clients::{AnyClient, Client, ClientConfig, ClientSet};
+use changes::AutoBase;
+pub use changes::{AnalyzeOptions, Changeset, RepoChangeset};
+pub use remote::Remote;
+use serde::Deserialize;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+/// One changeset per remote, all of them between the same base and head.
+pub fn repo_changesets<C: Client>(
+    remotes: &[String],
+    base: &str,
+    head: &str,
+) -> Result<Vec<RepoChangeset<C>>, anyhow::Error> {
+    let ranges: Vec<Range> = remotes
+        .iter()
+        .map(|remote| Range {
+            remote: remote.clone(),
+            base: base.to_owned(),
+            head: head.to_owned(),
+        })
+        .collect();
+    range_changesets(&ranges)
+}
+
+/// A remote with its own base and head, as listed in --ranges-file.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Range {
+    pub remote: String,
+    pub base: String,
+    pub head: String,
+}
+
+/// One changeset per range, in the same order.
+pub fn range_changesets<C: Client>(ranges: &[Range]) -> Result<Vec<RepoChangeset<C>>, anyhow::Error> {
+    let mut changes = Vec::new();
+    for range in ranges {
+        let remote = Remote::parse(&range.remote).with_context(|| format!("while parsing remote {}", range.remote))?;
+        changes.push(RepoChangeset {
+            name: remote.repository.clone(),
+            remote,
+            base_commit: range.base.clone(),
+            head_commit: range.head.clone(),
+            base_ref: None,
+            head_ref: None,
+            min_approvals: None,
+            required_approvers: Vec::new(),
+            compare_status: None,
+            error: None,
+            changes: Vec::new(),
+        });
+    }
+
+    Ok(changes)
+}
+
+/// Analyzes all repos concurrently, remotes on the same host share one client.
+/// The refs of all repos are validated upfront. The result keeps the order of the input.
+///
+/// ```
+/// use pear_reviewer::github::Commit;
+/// use pear_reviewer::{
+///     analyze, repo_changesets, AnalyzeOptions, ClientConfig, ClientSet, MockClient,
+/// };
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() -> anyhow::Result<()> {
+/// let (base, head) = ("1111111", "2222222");
+/// let remotes = vec!["https://github.com/example/project.git".to_owned()];
+/// let mut repos = repo_changesets::<MockClient>(&remotes, base, head)?;
+///
+/// let mut api_clients = ClientSet::new(ClientConfig::default());
+/// api_clients.fill(&mut repos[0].remote)?;
+/// let client = repos[0].remote.client.clone().unwrap();
+/// for sha in [base, head] {
+///     let commit = Commit {
+///         sha: sha.to_owned(),
+///         ..Commit::default()
+///     };
+///     client
+///         .commits
+///         .lock()
+///         .unwrap()
+///         .insert(sha.to_owned(), commit);
+/// }
+/// let head_commit = client.commits.lock().unwrap()[head].clone();
+/// client
+///     .compares
+///     .lock()
+///     .unwrap()
+///     .insert((base.to_owned(), head.to_owned()), vec![head_commit]);
+/// client
+///     .associated_prs
+///     .lock()
+///     .unwrap()
+///     .insert(head.to_owned(), Vec::new());
+///
+/// let repos = analyze(
+///     &mut api_clients,
+///     repos,
+///     &AnalyzeOptions::default(),
+///     4,
+///     false,
+/// )
+/// .await?;
+/// assert_eq!(repos[0].changes.len(), 1);
+/// assert!(repos[0].changes[0].approvals.is_empty());
+/// # Ok(())
+/// # }
+/// ```
+pub async fn analyze<C: Client + Send + 'static>(
+    api_clients: &mut ClientSet<C>,
+    mut repos: Vec<RepoChangeset<C>>,
+    options: &AnalyzeOptions,
+    max_in_flight: usize,
+    wait_on_rate_limit: bool,
+) -> Result<Vec<RepoChangeset<C>>, anyhow::Error> {
+    for repo in &mut repos {
+        if let Err(err) = prepare(api_clients, repo, options).await {
+            if options.strict {
+                return Err(err);
+            }
+            report_error(repo, &err);
+        }
+    }
+
+    let mut changes = Vec::new();
+    let mut placeholders = HashMap::new();
+    let mut join_set = JoinSet::new();
+    let semaphore = Arc::new(Semaphore::new(max_in_flight));
+    for (idx, repo) in repos.into_iter().enumerate() {
+        if repo.error.is_some() {
+            changes.push((idx, repo));
+            continue;
+        }
+        placeholders.insert(idx, repo.without_changes());
+        let options = options.clone();
+        spawn_limited(&mut join_set, &semaphore, async move {
+            (idx, repo.analyze_commits(options).await)
+        });
+    }
+
+    while let Some(res) = join_set.join_next().await {
+        let (idx, repo_changeset) = res?;
+        let repo_changeset = match repo_changeset.context("while collecting repo changes") {
+            Ok(repo_changeset) => repo_changeset,
+            Err(err) if !options.strict => {
+                let mut repo = placeholders.remove(&idx).expect("every spawned repo has a placeholder");
+                report_error(&mut repo, &err);
+                repo
+            },
+            Err(err) => return Err(err),
+        };
+        changes.push((idx, repo_changeset));
+
+        if !join_set.is_empty() {
+            check_rate_limits(api_clients, wait_on_rate_limit).await;
+        }
+    }
+    changes.sort_by_key(|(idx, _)| *idx);
+
+    Ok(changes.into_iter().map(|(_, repo_changeset)| repo_changeset).collect())
+}
+
+async fn prepare<C: Client + Send + 'static>(
+    api_clients: &mut ClientSet<C>,
+    repo: &mut RepoChangeset<C>,
+    options: &AnalyzeOptions,
+) -> Result<(), anyhow::Error> {
+    api_clients.fill(&mut repo.remote)?;
+    repo.resolve_head().await?;
+    if options.auto_base == Some(AutoBase::PreviousTag) {
+        repo.resolve_previous_tag().await?;
+    }
+    repo.validate_refs().await?;
+    repo.resolve_refs().await?;
+    if let Some(base_ref) = &repo.base_ref {
+        eprintln!("resolved base {base_ref} of {} to {}", repo.name, repo.base_commit);
+    }
+    Ok(())
+}
+
+// Unreachable repos, e.g. on a host without a token, are reported in the output instead of aborting the whole run.
+fn report_error<C: Client>(repo: &mut RepoChangeset<C>, err: &anyhow::Error) {
+    eprintln!("warning: could not analyze {}: {err:#}", repo.name);
+    repo.error = Some(format!("{err:#}"));
+}
+
+// Spawns the task, which only starts running once it got a permit from the semaphore.
+fn spawn_limited<T, F>(join_set: &mut JoinSet<T>, semaphore: &Arc<Semaphore>, task: F)
+where
+    T: Send + 'static,
+    F: Future<Output = T> + Send + 'static,
+{
+    let semaphore = semaphore.clone();
+    join_set.spawn(async move {
+        let _permit = semaphore.acquire_owned().await.expect("semaphore is never closed");
+        task.await
+    });
+}
+
+async fn check_rate_limits<C: Client>(api_clients: &ClientSet<C>, wait_on_rate_limit: bool) {
+    for (host, client) in api_clients.clients() {
+        match check_rate_limit(client.as_ref()).await {
+            Ok(RateLimitStatus::Ok) => {},
+            Ok(RateLimitStatus::NearlyExhausted { remaining, reset_in }) => {
+                eprintln!("warning: only {remaining} API requests left for {host}, the limit resets in {reset_in:?}");
+                if wait_on_rate_limit {
+                    eprintln!("waiting for the rate limit of {host} to reset");
+                    if let Err(err) = client.pause(reset_in).await {
+                        eprintln!("warning: failed to wait for the rate limit of {host}: {err:#}");
+                    }
+                }
+            },
+            Err(err) => eprintln!("warning: failed to check the rate limit of {host}: {err:#}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use super::*;
+    use crate::github::{Commit, RateLimit};
+
+    #[tokio::test]
+    async fn spawn_limited_respects_bound() {
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        let semaphore = Arc::new(Semaphore::new(3));
+
+        let mut join_set = JoinSet::new();
+        for idx in 0..20 {
+            let in_flight = in_flight.clone();
+            let max_in_flight = max_in_flight.clone();
+            spawn_limited(&mut join_set, &semaphore, async move {
+                let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                max_in_flight.fetch_max(current, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(5)).await;
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                idx
+            });
+        }
+
+        let mut finished = 0;
+        while let Some(res) = join_set.join_next().await {
+            res.unwrap();
+            finished += 1;
+        }
+        assert_eq!(finished, 20);
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn repo_changesets_multiple_remotes() {
+        let remotes = vec![
+            "https://github.com/sapcc/keppel.git".to_owned(),
+            "https://github.com/sapcc/limes.git".to_owned(),
+        ];
+        let mut changes = repo_changesets::<MockClient>(&remotes, "v1", "v2").unwrap();
+        let names: Vec<&str> = changes.iter().map(|change| change.name.as_str()).collect();
+        assert_eq!(names, vec!["keppel", "limes"]);
+        assert!(changes
+            .iter()
+            .all(|change| change.base_commit == "v1" && change.head_commit == "v2"));
+
+        // both remotes are on github.com and have to share the client
+        let mut api_clients = ClientSet::<MockClient>::new(ClientConfig::default());
+        for change in &mut changes {
+            api_clients.fill(&mut change.remote).unwrap();
+        }
+        assert!(Arc::ptr_eq(
+            changes[0].remote.client.as_ref().unwrap(),
+            changes[1].remote.client.as_ref().unwrap()
+        ));
+
+        assert!(repo_changesets::<MockClient>(&["https://github.com/sapcc".to_owned()], "v1", "v2").is_err());
+    }
+
+    #[tokio::test]
+    async fn analyze_ranges_shared_host() {
+        let ranges: Vec<Range> = serde_json::from_str(
+            r#"[
+                {"remote": "https://github.com/sapcc/keppel.git", "base": "1111111", "head": "2222222"},
+                {"remote": "https://github.com/sapcc/limes.git", "base": "3333333", "head": "4444444"}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(ranges[1], Range {
+            remote: "https://github.com/sapcc/limes.git".to_owned(),
+            base: "3333333".to_owned(),
+            head: "4444444".to_owned(),
+        });
+
+        let mut repos = range_changesets::<MockClient>(&ranges).unwrap();
+        let mut api_clients = ClientSet::<MockClient>::new(ClientConfig::default());
+        api_clients.fill(&mut repos[0].remote).unwrap();
+        let client = repos[0].remote.client.clone().unwrap();
+        for (base, head) in [("1111111", "2222222"), ("3333333", "4444444")] {
+            for sha in [base, head] {
+                client.commits.lock().unwrap().insert(sha.to_owned(), Commit::test(sha));
+            }
+            client
+                .compares
+                .lock()
+                .unwrap()
+                .insert((base.to_owned(), head.to_owned()), vec![Commit::test(head)]);
+            client
+                .associated_prs
+                .lock()
+                .unwrap()
+                .insert(head.to_owned(), Vec::new());
+        }
+        *client.rate_limit.lock().unwrap() = Some(RateLimit {
+            limit: 5000,
+            remaining: 5000,
+            reset: 0,
+        });
+
+        let repos = analyze(&mut api_clients, repos, &AnalyzeOptions::default(), 2, false)
+            .await
+            .unwrap();
+        assert_eq!(api_clients.clients().count(), 1);
+        let shas: Vec<(&str, &str)> = repos
+            .iter()
+            .map(|repo| (repo.name.as_str(), repo.changes[0].commits[0].sha.as_str()))
+            .collect();
+        assert_eq!(shas, vec![("keppel", "2222222"), ("limes", "4444444")]);
+    }
+
+    #[tokio::test]
+    async fn analyze_reports_unreachable_repos() {
+        let ranges: Vec<Range> = serde_json::from_str(
+            r#"[
+                {"remote": "https://github.com/sapcc/keppel.git", "base": "1111111", "head": "2222222"},
+                {"remote": "https://github.com/sapcc/limes.git", "base": "3333333", "head": "4444444"},
+                {"remote": "https://github.com/sapcc/nova.git", "base": "5555555", "head": "6666666"}
+            ]"#,
+        )
+        .unwrap();
+        // keppel can be analyzed, limes has no compare and nova has no head commit
+        let setup = || {
+            let mut repos = range_changesets::<MockClient>(&ranges).unwrap();
+            let mut api_clients = ClientSet::<MockClient>::new(ClientConfig::default());
+            api_clients.fill(&mut repos[0].remote).unwrap();
+            let client = repos[0].remote.client.clone().unwrap();
+            for sha in ["1111111", "2222222", "3333333", "4444444", "5555555"] {
+                client.commits.lock().unwrap().insert(sha.to_owned(), Commit::test(sha));
+            }
+            client
+                .compares
+                .lock()
+                .unwrap()
+                .insert(("1111111".to_owned(), "2222222".to_owned()), vec![Commit::test(
+                    "2222222",
+                )]);
+            client
+                .associated_prs
+                .lock()
+                .unwrap()
+                .insert("2222222".to_owned(), Vec::new());
+            (api_clients, repos)
+        };
+
+        let (mut api_clients, repos) = setup();
+        let repos = analyze(&mut api_clients, repos, &AnalyzeOptions::default(), 2, false)
+            .await
+            .unwrap();
+        let results: Vec<(&str, usize, bool)> = repos
+            .iter()
+            .map(|repo| (repo.name.as_str(), repo.changes.len(), repo.error.is_some()))
+            .collect();
+        assert_eq!(results, vec![
+            ("keppel", 1, false),
+            ("limes", 0, true),
+            ("nova", 0, true)
+        ]);
+        assert!(repos[2].error.as_ref().unwrap().contains("6666666"));
+
+        let (mut api_clients, repos) = setup();
+        let options = AnalyzeOptions {
+            strict: true,
+            ..AnalyzeOptions::default()
+        };
+        assert!(analyze(&mut api_clients, repos, &options, 2, false).await.is_err());
+    }
+}
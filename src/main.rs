@@ -14,56 +14,96 @@
 
 #![warn(clippy::pedantic)]
 
-mod api_clients;
-mod changes;
-mod github;
-mod helm_config;
-mod remote;
-mod repo;
-
-use std::fs::File;
-use std::io::Write;
-use std::sync::LazyLock;
-use std::{env, str};
-
-use anyhow::{anyhow, Context};
-use api_clients::{ClientSet, RealClient};
-use changes::RepoChangeset;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+use std::{env, slice, str};
+
+use anyhow::{anyhow, bail, Context};
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
 use clap::builder::styling::Style;
 use clap::builder::NonEmptyStringValueParser;
 use clap::{Parser, Subcommand};
-use git2::Repository;
-use helm_config::ImageRefs;
-use remote::Remote;
-use tokio::task::JoinSet;
-use url::{Host, Url};
+use glob::Pattern;
+use pear_reviewer::api_clients::{AnyClient, Client, ClientConfig, ClientSet, Tokens, DEFAULT_GITHUB_API_VERSION};
+use pear_reviewer::changes::{
+    is_commit_hash, AnalyzeOptions, AutoBase, DateRange, GroupBy, RepoChangeset, BRANCH_REF_PREFIX,
+};
+use pear_reviewer::github::Team;
+use pear_reviewer::helm_chart::{
+    find_images_yaml_files, find_values_yaml, find_values_yaml_in_diff, find_values_yaml_tag_bumps,
+    find_values_yaml_tag_bumps_in_diff, PathFilter, RepoFilter, TagBump,
+};
+use pear_reviewer::output::{self, Colors, Decisions, JsonRepo, OutputFormat, RedirectRule, RenderOptions};
+use pear_reviewer::policy::{self, FailPolicy};
+use pear_reviewer::remote::Remote;
+use pear_reviewer::submodule::find_submodule_changes;
+use pear_reviewer::template::Template;
+use pear_reviewer::{analyze, approvers, notify, range_changesets, repo_changesets, serve, Range};
+use tokio::net::TcpListener;
+use url::Url;
+
+const EXIT_VIOLATIONS: u8 = 1;
+const EXIT_ERROR: u8 = 2;
 
 const BOLD_UNDERLINE: Style = Style::new().bold().underline();
 static GITHUB_TOKEN_HELP: LazyLock<String> = LazyLock::new(|| {
     format!(
         "{BOLD_UNDERLINE}Environment variables:{BOLD_UNDERLINE:#}
-  GITHUB_TOKEN                 GitHub token to use for API requests
+  GITHUB_TOKEN                 GitHub token to use for API requests, unless overridden with --token or a token file
+  HTTPS_PROXY, HTTP_PROXY      Proxy to use for API requests, unless overridden with --proxy
+  NO_PROXY                     Comma separated list of hosts which are reached without proxy
+  GITHUB_SERVER_URL            Host for which GITHUB_API_URL is used, set by GitHub Actions
+  GITHUB_API_URL               API endpoint to use instead of deriving it from the host, set by GitHub Actions
+  GITHUB_<HOST>_API_PATH       API path of a GitHub Enterprise host, unless overridden with --api-path
+  GITEA_<HOST>_TOKEN           Token for a host given with --gitea-host
+  GITEA_<HOST>_API_PATH        API path of a Gitea host instead of /api/v1, unless overridden with --api-path
+
+{BOLD_UNDERLINE}Exit codes:{BOLD_UNDERLINE:#}
+  0                            All changes pass the gate of --fail-on, --require-pr, --require-signed or --check
+  1                            The result was printed, but changes violate the gate
+  2                            The analysis failed, e.g. because of invalid arguments or a failed API request
 "
     )
 });
 
 /// Program to simplify PCI double approval process across repositories
 #[derive(Parser)]
+#[allow(clippy::struct_excessive_bools)]
 #[command(version, about, long_about = None, after_help = GITHUB_TOKEN_HELP.to_string(), propagate_version = true)]
 // see https://docs.github.com/en/actions/writing-workflows/choosing-what-your-workflow-does/variables for environment variablesuse
 struct Cli {
-    /// The git base ref to compare against
+    /// The git base ref to compare against, not needed with --from or --no-diff
     #[arg(
         long,
         env = "GITHUB_BASE_REF",
         hide_env_values = true,
-        required = false,
         value_parser = NonEmptyStringValueParser::new(),
         global = true
     )]
-    base: String,
+    base: Option<String>,
 
-    /// The git head ref or source branch of the PR to compare against
+    /// Branch to compare against, resolved to its current tip even when the name looks like a commit hash. Takes
+    /// precedence over --base.
+    #[arg(long, value_parser = NonEmptyStringValueParser::new(), global = true)]
+    base_branch: Option<String>,
+
+    /// Commit to compare against, used as it is. Takes precedence over --base.
+    #[arg(long, value_parser = parse_sha, conflicts_with = "base_branch", global = true)]
+    base_sha: Option<String>,
+
+    /// Derive the base of each remote from the head instead, e.g. previous-tag compares a release tag given as
+    /// --head with the tag of the newest earlier release that it contains. Takes precedence over --base and only
+    /// applies to the repo subcommand.
+    #[arg(long, value_enum, conflicts_with_all = ["base_branch", "base_sha"], global = true)]
+    auto_base: Option<AutoBase>,
+
+    /// The git head ref or source branch of the PR to compare against. HEAD is the default branch of each remote
+    /// for the repo subcommand and the checked out commit of the workspace for the helm-chart subcommand.
     #[arg(
         long,
         default_value = "HEAD",
@@ -75,6 +115,234 @@ struct Cli {
     )]
     head: String,
 
+    /// Timeout in seconds for every request sent to GitHub
+    #[arg(long, env = "PEAR_REQUEST_TIMEOUT", default_value_t = 30, global = true)]
+    request_timeout: u64,
+
+    /// Maximum number of idle connections kept open to each host, unlimited by default
+    #[arg(long, env = "PEAR_POOL_SIZE", global = true)]
+    pool_size: Option<usize>,
+
+    /// Fail when a paginated API response, e.g. the reviews of a PR, has more pages than this, instead of using up the
+    /// rate limit on a range that is too large
+    #[arg(
+        long,
+        env = "PEAR_MAX_PAGES",
+        default_value_t = 50,
+        value_parser = clap::value_parser!(u32).range(1..),
+        global = true
+    )]
+    max_pages: u32,
+
+    /// Proxy to use for all requests, takes precedence over `HTTPS_PROXY` and `HTTP_PROXY`. Hosts in `NO_PROXY`
+    /// are still reached directly.
+    #[arg(long, global = true)]
+    proxy: Option<Url>,
+
+    /// PEM file with additional CA certificates to trust, may contain multiple certificates
+    #[arg(long, env = "PEAR_CA_CERT", global = true)]
+    ca_cert: Option<PathBuf>,
+
+    /// API path of a GitHub Enterprise host in the format of host=path, e.g. github.example.com=/ when the API is
+    /// served at the root instead of /api/v3. Hosts on a port other than 443 are given as host:port. May be given
+    /// multiple times.
+    #[arg(long, value_parser = parse_api_path, global = true)]
+    api_path: Vec<(String, String)>,
+
+    /// Version of the GitHub REST API to request, for GitHub Enterprise instances that reject the default. Give it as
+    /// host=date to only apply it to one host, e.g. "github.example.com=2022-11-28". Can be given multiple times.
+    #[arg(long, value_name = "[HOST=]DATE", value_parser = parse_github_api_version, global = true)]
+    github_api_version: Vec<(Option<String>, String)>,
+
+    /// Token for all hosts instead of the token env variables, see below
+    #[arg(long, conflicts_with = "token_file", global = true)]
+    token: Option<String>,
+
+    /// File containing the token for all hosts instead of the token env variables
+    #[arg(long, global = true)]
+    token_file: Option<PathBuf>,
+
+    /// File containing the token of one host in the format of host=path, takes precedence over --token and
+    /// --token-file. May be given multiple times.
+    #[arg(long, value_parser = parse_host_token_file, global = true)]
+    host_token_file: Vec<(String, PathBuf)>,
+
+    /// Host running Gitea or Forgejo instead of GitHub. May be given multiple times.
+    #[arg(long, global = true)]
+    gitea_host: Vec<String>,
+
+    /// Only analyze commits authored within this duration, e.g. 30d or 12h. Supported units are s, m, h, d and w.
+    #[arg(long, value_parser = parse_duration, global = true)]
+    since: Option<Duration>,
+
+    /// Take the PR number from the commit message, e.g. "Fix things (#187)" or "Merge pull request #187", when GitHub
+    /// does not associate a commit with any PR
+    #[arg(long, global = true)]
+    parse_pr_from_message: bool,
+
+    /// Compare head with the merge base of base and head instead of base itself, so that only the commits of head
+    /// are analyzed when base has moved on, e.g. when base is the target branch of a PR
+    #[arg(long, global = true)]
+    use_merge_base: bool,
+
+    /// Take the number of approvals a change needs from the protection of the default branch of its repo, repos
+    /// whose default branch requires no approvals fall back to --min-approvals
+    #[arg(long, global = true)]
+    use_branch_protection: bool,
+
+    /// Only analyze commits that touched a file matching this glob, e.g. "src/auth/**". Can be given multiple times.
+    /// Costs one additional API call per commit.
+    #[arg(long, global = true)]
+    path: Vec<Pattern>,
+
+    /// Fetch the head and reviews of a PR with one GraphQL query instead of two REST requests, and the associated PRs
+    /// of up to 50 commits with one query instead of one REST request per commit
+    #[arg(long, global = true)]
+    use_graphql: bool,
+
+    /// Wait for the rate limit to reset when it is nearly exhausted instead of only warning about it
+    #[arg(long, global = true)]
+    wait_on_rate_limit: bool,
+
+    /// Maximum number of repositories that are analyzed at the same time
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..), global = true)]
+    max_repos_in_flight: u32,
+
+    /// Only count approvals from members of this team, given as org/team
+    #[arg(long, global = true)]
+    required_team: Option<Team>,
+
+    /// Format of the rendered report
+    #[arg(long, value_enum, default_value_t = OutputFormat::Markdown, global = true)]
+    format: OutputFormat,
+
+    /// Render the report with this Handlebars template instead of --format, see src/templates/markdown.hbs for the
+    /// built-in one. Values are not HTML-escaped, the `escape_table_cell` helper escapes them for markdown tables and
+    /// `escape_markdown` also escapes formatting.
+    #[arg(long, global = true)]
+    output_template: Option<PathBuf>,
+
+    /// Whether the report has one row per commit or per PR
+    #[arg(long, value_enum, default_value_t = GroupBy::Commit, global = true)]
+    group_by: GroupBy,
+
+    /// Number of approvals a change needs, changes with fewer approvals fail in the junit format
+    #[arg(long, default_value_t = 1, global = true)]
+    min_approvals: usize,
+
+    /// Fill the verdict with OK when a change has --min-approvals and no outstanding change requests, otherwise
+    /// with NEEDS REVIEW
+    #[arg(long, global = true)]
+    auto_verdict: bool,
+
+    /// Text to show in the verdict column when the verdict is left to the reviewer, may be empty
+    #[arg(long, default_value = output::VERDICT_PLACEHOLDER, global = true)]
+    verdict_placeholder: String,
+
+    /// Exit with an error after printing the result when any commit has no associated PR, e.g. after a direct push.
+    /// Same as --fail-on no-pr.
+    #[arg(long, global = true)]
+    require_pr: bool,
+
+    /// Exit with an error after printing the result when any commit is unsigned or its signature could not be
+    /// verified. Same as --fail-on unsigned.
+    #[arg(long, global = true)]
+    require_signed: bool,
+
+    /// Exit with an error after printing the result when any change violates one of these policies
+    #[arg(long, value_enum, value_delimiter = ',', global = true)]
+    fail_on: Vec<FailPolicy>,
+
+    /// Fail on the default gate of changes without PR or with fewer than --min-approvals approvals. Same as
+    /// --fail-on below-threshold,no-pr.
+    #[arg(long, global = true)]
+    check: bool,
+
+    /// Only report the changes which violate --fail-on, --require-pr or --require-signed, followed by a one-line
+    /// summary on stderr
+    #[arg(long, global = true)]
+    quiet: bool,
+
+    /// Also count the approvals of the original PR when the PR of a change is a backport, i.e. its title or
+    /// description says "Backport of #123", the original PR is merged and every commit of the backport is a
+    /// cherry-pick of it. Backports of backports are followed up to three PRs back.
+    #[arg(long, global = true)]
+    follow_backports: bool,
+
+    /// Mark a commit and its revert as self-cancelling when both are part of the analyzed range, based on the
+    /// "This reverts commit <sha>" line of git revert and a diff that undoes the reverted commit. Such changes are
+    /// shown struck through and do not fail --fail-on below-threshold.
+    #[arg(long, global = true)]
+    pair_reverts: bool,
+
+    /// Ignore draft PRs associated with a commit, so that their approvals do not count. A commit that is only part of
+    /// draft PRs is reported like one without a PR.
+    #[arg(long, global = true)]
+    ignore_drafts: bool,
+
+    /// Abort when a repo cannot be analyzed, e.g. because it is unreachable, instead of reporting it and continuing
+    /// with the others
+    #[arg(long, global = true)]
+    strict: bool,
+
+    /// POST a JSON summary of the repos with policy violations to this URL when the --fail-on gate fails, e.g. a
+    /// Slack incoming webhook. Failing to notify is only logged.
+    #[arg(long, global = true)]
+    notify_webhook: Option<Url>,
+
+    /// JSON object mapping commit shas or PRs to verdicts, e.g. decided by a policy engine. PRs are given by their
+    /// URL or as owner/repo#number. The verdicts are shown instead of the placeholder, rows without a decision keep
+    /// it.
+    #[arg(long, global = true)]
+    decisions_file: Option<PathBuf>,
+
+    /// Add columns with the author and authored date of every commit
+    #[arg(long, global = true)]
+    show_authors: bool,
+
+    /// Add a column telling whether every commit has a verified signature
+    #[arg(long, global = true)]
+    show_signatures: bool,
+
+    /// List every approver with the commit they approved, e.g. user@1a2b3c4
+    #[arg(long, global = true)]
+    detailed_approvals: bool,
+
+    /// List every approver with the time of their approval in UTC, e.g. user at 2024-07-01T12:00:00Z
+    #[arg(long, global = true)]
+    show_approval_time: bool,
+
+    /// Rewrite links to host to replacement, given as host=replacement. Links to github.com are always
+    /// rewritten to redirect.github.com unless overridden.
+    #[arg(long, global = true)]
+    redirect_map: Vec<RedirectRule>,
+
+    /// Emit links to github.com and other hosts as they are instead of rewriting them
+    #[arg(long, conflicts_with = "redirect_map", global = true)]
+    no_redirect: bool,
+
+    /// JSON output of a previous run, changes with the same PR, commits and approvals as in there are omitted
+    #[arg(long, global = true)]
+    baseline: Option<PathBuf>,
+
+    /// Write the report to this file instead of stdout or `$GITHUB_OUTPUT`
+    #[arg(long, global = true)]
+    output: Option<PathBuf>,
+
+    /// Also write a JSON summary of the analyzed repos and ranges with commit and violation counts to this file,
+    /// independently of --format
+    #[arg(long, global = true)]
+    metadata_file: Option<PathBuf>,
+
+    /// Also write gauges of the changes per repo in the Prometheus text format to this file, e.g. a .prom file in
+    /// the directory of the textfile collector of `node_exporter`
+    #[arg(long, global = true)]
+    metrics_file: Option<PathBuf>,
+
+    /// Only list the changesets which would be analyzed and estimate the number of API calls
+    #[arg(long, global = true)]
+    dry_run: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -84,8 +352,29 @@ enum Commands {
     /// Analyzes commits in a repo and finds relevant reviews
     #[command(after_help = GITHUB_TOKEN_HELP.to_string())]
     Repo {
-        /// GitHub git remote to use
-        remote: String,
+        /// GitHub git remotes to use, all of them are compared between the same base and head
+        #[arg(required_unless_present_any = ["remotes_from", "ranges_file"])]
+        remotes: Vec<String>,
+
+        /// File with one remote per line, use - to read from stdin. Blank lines and lines starting with # are
+        /// ignored.
+        #[arg(long)]
+        remotes_from: Option<String>,
+
+        /// JSON file with an array of objects with remote, base and head, so that every remote has its own range.
+        /// They are analyzed together with the remotes given as arguments.
+        #[arg(long, conflicts_with = "from")]
+        ranges_file: Option<PathBuf>,
+
+        /// Analyze the commits of head committed at or after this date instead of comparing base and head, given as
+        /// 2024-07-01 or in RFC 3339. Dates without a time are midnight UTC.
+        #[arg(long, value_parser = parse_date)]
+        from: Option<DateTime<Utc>>,
+
+        /// End of the date range started with --from, defaults to now. Dates without a time include the whole day
+        /// up to 23:59:59 UTC.
+        #[arg(long, value_parser = parse_end_date, requires = "from")]
+        to: Option<DateTime<Utc>>,
     },
 
     /// Analyzes a helm-charts repo, finds sources from values.yaml files and runs repo subcommand on them
@@ -94,167 +383,953 @@ enum Commands {
         /// Git repository where to discover images.yaml files
         #[arg(env = "GITHUB_WORKSPACE", hide_env_values = true, required = false, global = true)]
         workspace: String,
+
+        /// Analyze the source commit of every image in the workspace instead of the changes between base and
+        /// head. The workspace does not need to be a git repository.
+        #[arg(long)]
+        no_diff: bool,
+
+        /// Skip images.yaml files whose path relative to the workspace matches this glob, e.g. "examples/**". Can be
+        /// given multiple times.
+        #[arg(long)]
+        exclude_path: Vec<Pattern>,
+
+        /// Skip images whose name or source repo matches this glob, e.g. "*-janitor" or "*/sapcc/*".
+        /// Can be given multiple times and adds to the globs of a .pear-ignore file. That file is read from the head
+        /// commit, or with --diff-from from the diff when it changes the file and from the workspace otherwise.
+        #[arg(long)]
+        exclude: Vec<Pattern>,
+
+        /// Only analyze source repos whose owner/repo matches this glob, e.g. "sapcc/*". Can be given multiple times.
+        #[arg(long, value_name = "OWNER/REPO")]
+        only_repos: Vec<Pattern>,
+
+        /// Skip source repos whose owner/repo matches this glob, even when they match --only-repos. Can be given
+        /// multiple times.
+        #[arg(long, value_name = "OWNER/REPO")]
+        skip_repos: Vec<Pattern>,
+
+        /// Only look for images.yaml files below this directory of the workspace, e.g. "charts". --exclude-path
+        /// globs are still relative to the workspace.
+        #[arg(long)]
+        repo_subdir: Option<PathBuf>,
+
+        /// Take the changed images.yaml files from a unified diff with whole files, e.g. of `git diff
+        /// --unified=1000000 base head`, instead of diffing base and head in the workspace. Use - to read from stdin.
+        #[arg(long, conflicts_with = "no_diff")]
+        diff_from: Option<String>,
+
+        /// Also list the tag changes of the image at this dotted key path of values.yaml files, e.g. "image" for
+        /// image.repository and image.tag. Such images have no sources, so their changes are only listed below the
+        /// report and not analyzed. Can be given multiple times.
+        #[arg(long, value_name = "KEY_PATH", conflicts_with = "no_diff")]
+        values_image_key: Vec<String>,
+    },
+
+    /// Analyzes a repo that pins its sources as git submodules, finds the submodules whose commit changed between
+    /// base and head and runs repo subcommand on them
+    #[command(after_help = GITHUB_TOKEN_HELP.to_string())]
+    Submodules {
+        /// Git repository with the submodules
+        #[arg(env = "GITHUB_WORKSPACE", hide_env_values = true)]
+        workspace: String,
     },
+
+    /// Analyzes the reviews of all open PRs in a repo, e.g. for a periodic report
+    #[command(after_help = GITHUB_TOKEN_HELP.to_string())]
+    OpenPrs {
+        /// GitHub git remote of the repo
+        remote: String,
+    },
+
+    /// Runs a webhook server for GitHub `push` and `pull_request_review` events. Every event analyzes the pushed range
+    /// or the base and head of the reviewed PR and prints the markdown table, which is also posted to
+    /// --notify-webhook when given.
+    #[command(after_help = GITHUB_TOKEN_HELP.to_string())]
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        addr: SocketAddr,
+
+        /// Secret of the GitHub webhook, deliveries without a valid signature are rejected
+        #[arg(
+            long,
+            env = "PEAR_WEBHOOK_SECRET",
+            hide_env_values = true,
+            value_parser = NonEmptyStringValueParser::new()
+        )]
+        webhook_secret: String,
+    },
+
+    /// Lists the users who may approve PRs in a repo according to branch protection and CODEOWNERS
+    #[command(after_help = GITHUB_TOKEN_HELP.to_string())]
+    Approvers {
+        /// GitHub git remote of the repo
+        remote: String,
+
+        /// Branch whose protection and CODEOWNERS file are used, the default branch of the repo if not given
+        #[arg(long)]
+        branch: Option<String>,
+    },
+}
+
+/// Analyzes the range of every push and pull request review that GitHub delivers to the webhook server.
+struct WebhookReporter {
+    config: ClientConfig,
+    analyze_options: AnalyzeOptions,
+    render_options: RenderOptions,
+    max_in_flight: usize,
+    wait_on_rate_limit: bool,
+    notify_webhook: Option<Url>,
+}
+
+impl WebhookReporter {
+    async fn report(&self, range: Range) {
+        if let Err(err) = self.try_report(&range).await {
+            eprintln!(
+                "warning: failed to analyze {} {}..{}: {err:#}",
+                range.remote, range.base, range.head
+            );
+        }
+    }
+
+    async fn try_report(&self, range: &Range) -> Result<(), anyhow::Error> {
+        // fresh clients per delivery, so that cached team members and CODEOWNERS don't go stale
+        let mut api_clients: ClientSet<AnyClient> = ClientSet::new(self.config.clone());
+        let changes = analyze(
+            &mut api_clients,
+            range_changesets(slice::from_ref(range))?,
+            &self.analyze_options,
+            self.max_in_flight,
+            self.wait_on_rate_limit,
+        )
+        .await?;
+        let table = output::render(OutputFormat::Markdown, &changes, &self.render_options)?;
+        println!("{table}");
+
+        if let Some(webhook) = &self.notify_webhook {
+            notify::send(webhook, &notify::TableNotification { text: table }, &self.config)
+                .await
+                .with_context(|| format!("failed to notify webhook at {}", webhook.host_str().unwrap_or_default()))?;
+        }
+        Ok(())
+    }
 }
 
+/// Returned after printing the result when changes violate --fail-on, which exits with its own code.
+#[derive(Debug, thiserror::Error)]
+#[error("{0} policy violations")]
+struct PolicyViolations(usize);
+
 #[tokio::main]
-async fn main() -> Result<(), anyhow::Error> {
+async fn main() -> ExitCode {
     let cli = Cli::parse();
+    let result = run(&cli).await;
+    if let Err(err) = &result {
+        eprintln!("Error: {err:?}");
+    }
+    ExitCode::from(exit_code(&result))
+}
 
-    let mut api_clients = ClientSet::new();
-
-    match &cli.command {
-        Commands::Repo { remote } => {
-            let mut remote = Remote::parse(remote)?;
-            api_clients.fill(&mut remote)?;
-            let repo = RepoChangeset {
-                name: remote.repository.clone(),
-                remote,
-                base_commit: cli.base,
-                head_commit: cli.head,
-                changes: Vec::new(),
-            };
-            let repo = repo.analyze_commits().await.context("while finding reviews")?;
-            print_changes(&[repo])?;
-        },
-        Commands::HelmChart { workspace } => {
-            let changes =
-                find_values_yaml(workspace.clone(), &cli.base, &cli.head).context("while finding values.yaml files")?;
-
-            let mut join_set = JoinSet::new();
-            for mut repo in changes {
-                api_clients.fill(&mut repo.remote)?;
-                join_set.spawn(repo.analyze_commits());
-            }
+fn exit_code(result: &Result<(), anyhow::Error>) -> u8 {
+    match result {
+        Ok(()) => 0,
+        Err(err) if err.downcast_ref::<PolicyViolations>().is_some() => EXIT_VIOLATIONS,
+        Err(_) => EXIT_ERROR,
+    }
+}
 
-            let mut changes = Vec::new();
-            while let Some(res) = join_set.join_next().await {
-                let repo_changeset = res?.context("while collecting repo changes")?;
-                changes.push(repo_changeset);
-            }
+async fn run(cli: &Cli) -> Result<(), anyhow::Error> {
+    let mut config = client_config(cli);
+    config.tokens = tokens(cli)?;
+    if let Commands::Serve { addr, webhook_secret } = &cli.command {
+        return serve(cli, config, *addr, webhook_secret).await;
+    }
+    let mut api_clients = ClientSet::new(config);
+    let analyze_options = analyze_options(cli)?;
 
-            print_changes(&changes)?;
-        },
+    if let Commands::Approvers { remote, branch } = &cli.command {
+        return print_approvers(&mut api_clients, remote, branch.as_deref(), cli.output.as_deref()).await;
+    }
+    let changes = collect_changesets(cli)?;
+    let tag_bumps = collect_tag_bumps(cli)?;
+    let fail_on = fail_on(cli)?;
+    let render_options = render_options(cli)?;
+
+    if cli.dry_run {
+        print_or_redirect(
+            &output::render_dry_run(&changes, &analyze_options)?,
+            cli.output.as_deref(),
+        )?;
+        return Ok(());
+    }
+
+    let mut changes = analyze(
+        &mut api_clients,
+        changes,
+        &analyze_options,
+        cli.max_repos_in_flight as usize,
+        cli.wait_on_rate_limit,
+    )
+    .await?;
+    if let Some(path) = &cli.baseline {
+        omit_baseline(&mut changes, &read_baseline(path)?);
+    }
+    // written before --quiet drops the changes without violations
+    if let Some(path) = &cli.metadata_file {
+        let metadata = output::render_metadata(&changes, &fail_on, cli.min_approvals, Utc::now())?;
+        fs::write(path, metadata).with_context(|| format!("cannot write to {}", path.display()))?;
+    }
+    if let Some(path) = &cli.metrics_file {
+        write_metrics(path, &output::render_metrics(&changes, cli.min_approvals)?)?;
+    }
+    let colors = Colors::detect();
+    if cli.quiet {
+        let omitted = policy::retain_violating(&mut changes, &fail_on, cli.min_approvals);
+        let violating: usize = changes.iter().map(|repo| repo.changes.len()).sum();
+        let summary = format!("{violating} changes violate --fail-on, {omitted} changes omitted");
+        if violating == 0 {
+            eprintln!("{}", colors.success(&summary));
+        } else {
+            eprintln!("{}", colors.failure(&summary));
+        }
+    }
+
+    let format = if render_options.template.is_some() {
+        OutputFormat::Template
+    } else {
+        cli.format
+    };
+    let mut report = output::render(format, &changes, &render_options)?;
+    // the other formats are read by tools, which do not expect the list in there
+    if format == OutputFormat::Markdown {
+        report.push_str(&output::render_tag_bumps(&tag_bumps)?);
+    } else {
+        eprint!("{}", output::render_tag_bumps(&tag_bumps)?);
+    }
+    print_or_redirect(&report, cli.output.as_deref())?;
+
+    let violations = policy::violations(&changes, &fail_on, cli.min_approvals);
+    if violations.is_empty() && !fail_on.is_empty() && !cli.quiet {
+        eprintln!("{}", colors.success("no changes violate --fail-on"));
+    }
+    if !violations.is_empty() {
+        eprint!(
+            "{}\n{}",
+            colors.failure("changes violating --fail-on:"),
+            policy::summary(&violations)
+        );
+        if let Some(webhook) = &cli.notify_webhook {
+            notify_webhook(webhook, &changes, &fail_on, cli).await;
+        }
+        return Err(PolicyViolations(violations.len()).into());
     }
 
     Ok(())
 }
 
-fn find_values_yaml(
-    workspace: String,
-    base: &str,
-    head: &str,
-) -> Result<Vec<RepoChangeset<RealClient>>, anyhow::Error> {
-    let repo = Repository::open(workspace).context("failed to open repository")?;
+fn fail_on(cli: &Cli) -> Result<Vec<FailPolicy>, anyhow::Error> {
+    let mut fail_on = cli.fail_on.clone();
+    let mut implied = Vec::new();
+    if cli.check {
+        implied.extend([FailPolicy::BelowThreshold, FailPolicy::NoPr]);
+    }
+    if cli.require_pr {
+        implied.push(FailPolicy::NoPr);
+    }
+    if cli.require_signed {
+        implied.push(FailPolicy::Unsigned);
+    }
+    // every violation is only reported once, even when its policy was enabled by multiple flags
+    for policy in implied {
+        if !fail_on.contains(&policy) {
+            fail_on.push(policy);
+        }
+    }
+    if cli.quiet && fail_on.is_empty() {
+        bail!("--quiet needs --fail-on, --require-pr or --check to decide which changes to report");
+    }
+    Ok(fail_on)
+}
 
-    let base_tree = repo::tree_for_commit_ref(&repo, base).context("while parsing base")?;
-    let head_tree = repo::tree_for_commit_ref(&repo, head).context("while parsing head")?;
-    let diff_tree = repo
-        .diff_tree_to_tree(Some(&base_tree), Some(&head_tree), None)
-        .with_context(|| format!("cannot diff trees {} and {}", base_tree.id(), head_tree.id()))?;
+fn parse_github_api_version(api_version: &str) -> Result<(Option<String>, String), anyhow::Error> {
+    let (host, date) = match api_version.split_once('=') {
+        Some((host, date)) if !host.is_empty() => (Some(host.to_owned()), date),
+        Some(_) => bail!("GitHub API versions are expected to be in the format of [host=]date"),
+        None => (None, api_version),
+    };
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .with_context(|| format!("GitHub API version {date:?} is not a date like 2022-11-28"))?;
+    Ok((host, date.to_owned()))
+}
 
-    let mut changes = Vec::<RepoChangeset<RealClient>>::new();
+fn parse_api_path(api_path: &str) -> Result<(String, String), anyhow::Error> {
+    match api_path.split_once('=') {
+        Some((host, path)) if !host.is_empty() => Ok((host.to_owned(), path.to_owned())),
+        _ => bail!("API paths are expected to be in the format of host=path"),
+    }
+}
 
-    for diff_delta in diff_tree.deltas() {
-        let new_file = diff_delta.new_file();
-        if !new_file.exists() {
-            continue;
-        }
+fn parse_host_token_file(host_token_file: &str) -> Result<(String, PathBuf), anyhow::Error> {
+    match host_token_file.split_once('=') {
+        Some((host, path)) if !host.is_empty() && !path.is_empty() => Ok((host.to_owned(), PathBuf::from(path))),
+        _ => bail!("host token files are expected to be in the format of host=path"),
+    }
+}
 
-        let path = new_file.path().ok_or_else(|| anyhow!("failed to get file path"))?;
-        if !path.ends_with("images.yaml") {
-            continue;
-        }
+fn parse_duration(duration: &str) -> Result<Duration, anyhow::Error> {
+    let (number, unit) = duration.split_at(duration.find(|c: char| !c.is_ascii_digit()).unwrap_or(duration.len()));
+    let number: u64 = number
+        .parse()
+        .with_context(|| format!("invalid duration {duration:?}, expected e.g. 30d or 12h"))?;
+    let unit_secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => bail!("invalid duration unit {unit:?} in {duration:?}, supported are s, m, h, d and w"),
+    };
+
+    number
+        .checked_mul(unit_secs)
+        .map(Duration::from_secs)
+        .ok_or_else(|| anyhow!("duration {duration:?} is too large"))
+}
+
+fn parse_sha(sha: &str) -> Result<String, anyhow::Error> {
+    if !is_commit_hash(sha) {
+        bail!("{sha:?} is not a commit hash");
+    }
+    Ok(sha.to_owned())
+}
 
-        let old_file = diff_delta.old_file();
-        if !old_file.exists() {
+fn parse_date(date: &str) -> Result<DateTime<Utc>, anyhow::Error> {
+    parse_date_at(date, NaiveTime::MIN)
+}
+
+// The end of a range includes the whole day when only the date is given.
+fn parse_end_date(date: &str) -> Result<DateTime<Utc>, anyhow::Error> {
+    parse_date_at(date, NaiveTime::from_hms_opt(23, 59, 59).unwrap_or_default())
+}
+
+fn parse_date_at(date: &str, time_of_day: NaiveTime) -> Result<DateTime<Utc>, anyhow::Error> {
+    if let Ok(day) = NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+        return Ok(day.and_time(time_of_day).and_utc());
+    }
+    DateTime::parse_from_rfc3339(date)
+        .map(|date| date.with_timezone(&Utc))
+        .with_context(|| format!("invalid date {date:?}, expected e.g. 2024-07-01 or 2024-07-01T12:00:00Z"))
+}
+
+fn read_remotes_from(path: &str) -> Result<Vec<String>, anyhow::Error> {
+    Ok(parse_remote_list(&read_file_or_stdin(path, "remotes")?))
+}
+
+// - stands for stdin
+fn read_file_or_stdin(path: &str, what: &str) -> Result<String, anyhow::Error> {
+    if path == "-" {
+        let mut content = String::new();
+        io::stdin()
+            .read_to_string(&mut content)
+            .with_context(|| format!("cannot read {what} from stdin"))?;
+        return Ok(content);
+    }
+    fs::read_to_string(path).with_context(|| format!("cannot read {what} from {path}"))
+}
+
+fn parse_remote_list(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
+fn read_baseline(path: &Path) -> Result<Vec<JsonRepo>, anyhow::Error> {
+    let content = fs::read(path).with_context(|| format!("cannot read baseline {}", path.display()))?;
+    serde_json::from_slice(&content).with_context(|| format!("cannot parse baseline {}", path.display()))
+}
+
+fn read_template(path: &Path) -> Result<Template, anyhow::Error> {
+    let source = fs::read_to_string(path).with_context(|| format!("cannot read template {}", path.display()))?;
+    Template::parse(&source).with_context(|| format!("invalid template {}", path.display()))
+}
+
+fn read_ranges(path: &Path) -> Result<Vec<Range>, anyhow::Error> {
+    let content = fs::read(path).with_context(|| format!("cannot read ranges {}", path.display()))?;
+    serde_json::from_slice(&content).with_context(|| format!("cannot parse ranges {}", path.display()))
+}
+
+fn read_decisions(path: &Path) -> Result<Decisions, anyhow::Error> {
+    let content = fs::read(path).with_context(|| format!("cannot read decisions {}", path.display()))?;
+    serde_json::from_slice(&content).with_context(|| format!("cannot parse decisions {}", path.display()))
+}
+
+// Drops the changes that were already reported in the baseline with the same PR, commits and approvals,
+// so that only what changed since then is shown.
+fn omit_baseline<C: Client>(repos: &mut [RepoChangeset<C>], baseline: &[JsonRepo]) {
+    for repo in repos {
+        let Some(baseline_repo) = baseline
+            .iter()
+            .find(|baseline_repo| baseline_repo.name == repo.name && baseline_repo.remote == repo.remote.original)
+        else {
             continue;
-        }
+        };
 
-        let new_image_refs = ImageRefs::parse(&repo, &new_file).context("while parsing new file")?;
-        let old_image_refs = ImageRefs::parse(&repo, &old_file).context("while parsing old file")?;
-        for (name, image) in &new_image_refs.container_images {
-            for source in &image.sources {
-                for container_image_source in &old_image_refs.container_images[name].sources {
-                    changes.push(RepoChangeset {
-                        name: name.clone(),
-                        remote: remote::Remote::parse(&source.repo)?,
-                        base_commit: source.commit.clone(),
-                        head_commit: container_image_source.commit.clone(),
-                        changes: Vec::new(),
-                    });
-                }
-            }
-        }
+        repo.changes.retain(|changeset| {
+            !baseline_repo.changes.iter().any(|baseline_changeset| {
+                baseline_changeset.pr_link == changeset.pr_link
+                    && baseline_changeset.commits == changeset.commits
+                    && baseline_changeset.approvals == changeset.approvals
+            })
+        });
+    }
+}
+
+fn base(cli: &Cli) -> Option<&str> {
+    cli.base_sha.as_deref().or(cli.base.as_deref())
+}
+
+fn path_filter(repo_subdir: Option<&PathBuf>, exclude_path: &[Pattern], exclude: &[Pattern]) -> PathFilter {
+    PathFilter {
+        repo_subdir: repo_subdir.cloned(),
+        exclude_paths: exclude_path.to_vec(),
+        exclude_images: exclude.to_vec(),
     }
+}
+
+// Only diffs of helm charts can have images in values.yaml that changed.
+fn collect_tag_bumps(cli: &Cli) -> Result<Vec<TagBump>, anyhow::Error> {
+    let Commands::HelmChart {
+        workspace,
+        exclude_path,
+        exclude,
+        repo_subdir,
+        diff_from,
+        values_image_key,
+        ..
+    } = &cli.command
+    else {
+        return Ok(Vec::new());
+    };
+    if values_image_key.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let path_filter = path_filter(repo_subdir.as_ref(), exclude_path, exclude);
+    let tag_bumps = if let Some(path) = diff_from {
+        find_values_yaml_tag_bumps_in_diff(
+            &read_file_or_stdin(path, "diff")?,
+            values_image_key,
+            Path::new(workspace),
+            &path_filter,
+        )
+    } else {
+        find_values_yaml_tag_bumps(
+            workspace,
+            cli.base_branch
+                .as_deref()
+                .or(base(cli))
+                .context("--base is required unless --no-diff is given")?,
+            &cli.head,
+            values_image_key,
+            &path_filter,
+            cli.use_merge_base,
+        )
+    };
+    tag_bumps.context("while finding image tags in values.yaml files")
+}
+
+fn collect_changesets(cli: &Cli) -> Result<Vec<RepoChangeset<AnyClient>>, anyhow::Error> {
+    let changes = match &cli.command {
+        Commands::Repo {
+            remotes,
+            remotes_from,
+            ranges_file,
+            from,
+            ..
+        } => {
+            let mut remotes = remotes.clone();
+            if let Some(path) = remotes_from {
+                remotes.extend(read_remotes_from(path)?);
+            }
+            let mut changes = Vec::new();
+            if !remotes.is_empty() {
+                // the date range replaces the base, and --auto-base finds it when analyzing
+                let base = if from.is_some() || cli.auto_base.is_some() {
+                    String::new()
+                } else {
+                    match &cli.base_branch {
+                        Some(branch) => format!("{BRANCH_REF_PREFIX}{branch}"),
+                        None => base(cli)
+                            .context("--base is required unless --from, --auto-base or only --ranges-file is given")?
+                            .to_owned(),
+                    }
+                };
+                changes = repo_changesets(&remotes, &base, &cli.head)?;
+            }
+            if let Some(path) = ranges_file {
+                changes.extend(range_changesets(&read_ranges(path)?)?);
+            }
+            changes
+        },
+        Commands::HelmChart {
+            workspace,
+            no_diff,
+            exclude_path,
+            exclude,
+            only_repos,
+            skip_repos,
+            repo_subdir,
+            diff_from,
+            ..
+        } => {
+            let path_filter = path_filter(repo_subdir.as_ref(), exclude_path, exclude);
+            let mut changes = if let Some(path) = diff_from {
+                find_values_yaml_in_diff(&read_file_or_stdin(path, "diff")?, Path::new(workspace), &path_filter)
+                    .context("while finding values.yaml files in the diff")?
+            } else if *no_diff {
+                find_images_yaml_files(Path::new(workspace), &path_filter).context("while finding images.yaml files")?
+            } else {
+                find_values_yaml(
+                    workspace.clone(),
+                    cli.base_branch
+                        .as_deref()
+                        .or(base(cli))
+                        .context("--base is required unless --no-diff is given")?,
+                    &cli.head,
+                    &path_filter,
+                    cli.use_merge_base,
+                )
+                .context("while finding values.yaml files")?
+            };
+            let repo_filter = RepoFilter {
+                only: only_repos.clone(),
+                skip: skip_repos.clone(),
+            };
+            repo_filter.retain(&mut changes);
+            changes
+        },
+        Commands::Submodules { workspace } => find_submodule_changes(
+            workspace.clone(),
+            cli.base_branch
+                .as_deref()
+                .or(base(cli))
+                .context("--base is required for submodules")?,
+            &cli.head,
+            cli.use_merge_base,
+        )
+        .context("while finding submodule changes")?,
+        // the head is only validated, the open PRs are listed when analyzing
+        Commands::OpenPrs { remote } => repo_changesets(slice::from_ref(remote), "", &cli.head)?,
+        Commands::Approvers { .. } => unreachable!("approvers are listed without collecting changesets"),
+        Commands::Serve { .. } => unreachable!("the server collects a changeset per webhook"),
+    };
 
     Ok(changes)
 }
 
-fn println_or_redirect(line: String) -> Result<(), anyhow::Error> {
-    if env::var("GITHUB_ACTIONS").is_ok() {
+fn analyze_options(cli: &Cli) -> Result<AnalyzeOptions, anyhow::Error> {
+    let date_range = match &cli.command {
+        Commands::Repo {
+            from: Some(from), to, ..
+        } => Some(DateRange {
+            from: *from,
+            to: to.unwrap_or_else(Utc::now),
+        }),
+        _ => None,
+    };
+    let since = cli
+        .since
+        .map(|since| {
+            chrono::Duration::from_std(since)
+                .ok()
+                .and_then(|since| Utc::now().checked_sub_signed(since))
+                .context("--since reaches back too far")
+        })
+        .transpose()?;
+
+    Ok(AnalyzeOptions {
+        required_team: cli.required_team.clone(),
+        since,
+        parse_pr_from_message: cli.parse_pr_from_message,
+        // helm charts and submodules resolve the merge base in the local workspace instead
+        use_merge_base: cli.use_merge_base && matches!(cli.command, Commands::Repo { .. }),
+        use_branch_protection: cli.use_branch_protection,
+        date_range,
+        paths: cli.path.clone(),
+        follow_backports: cli.follow_backports,
+        strict: cli.strict,
+        open_prs: matches!(cli.command, Commands::OpenPrs { .. }),
+        auto_base: cli.auto_base.filter(|_| matches!(cli.command, Commands::Repo { .. })),
+        pair_reverts: cli.pair_reverts,
+        ignore_drafts: cli.ignore_drafts,
+        group_by: cli.group_by,
+    })
+}
+
+fn render_options(cli: &Cli) -> Result<RenderOptions, anyhow::Error> {
+    let mut render_options = RenderOptions {
+        min_approvals: cli.min_approvals,
+        auto_verdict: cli.auto_verdict,
+        verdict_placeholder: cli.verdict_placeholder.clone(),
+        show_authors: cli.show_authors,
+        show_signatures: cli.show_signatures,
+        detailed_approvals: cli.detailed_approvals,
+        show_approval_time: cli.show_approval_time,
+        decisions: cli
+            .decisions_file
+            .as_deref()
+            .map(read_decisions)
+            .transpose()?
+            .unwrap_or_default(),
+        template: cli.output_template.as_deref().map(read_template).transpose()?,
+        ..RenderOptions::default()
+    };
+    if cli.no_redirect {
+        render_options.redirect_map.clear();
+    } else {
+        render_options.redirect_map.extend(cli.redirect_map.clone());
+    }
+    Ok(render_options)
+}
+
+fn client_config(cli: &Cli) -> ClientConfig {
+    ClientConfig {
+        request_timeout: Duration::from_secs(cli.request_timeout),
+        proxy: cli.proxy.as_ref().map(Url::to_string),
+        ca_cert: cli.ca_cert.clone(),
+        use_graphql: cli.use_graphql,
+        api_paths: cli.api_path.iter().cloned().collect(),
+        gitea_hosts: cli.gitea_host.clone(),
+        pool_size: cli.pool_size,
+        github_api_version: cli
+            .github_api_version
+            .iter()
+            .rev()
+            .find_map(|(host, date)| host.is_none().then(|| date.clone()))
+            .unwrap_or_else(|| DEFAULT_GITHUB_API_VERSION.to_owned()),
+        github_api_versions: cli
+            .github_api_version
+            .iter()
+            .filter_map(|(host, date)| Some((host.clone()?, date.clone())))
+            .collect(),
+        max_pages: cli.max_pages as usize,
+        verify_signatures: cli.show_signatures || cli.require_signed || cli.fail_on.contains(&FailPolicy::Unsigned),
+        // reading the token files can fail, see tokens()
+        tokens: Tokens::default(),
+    }
+}
+
+fn tokens(cli: &Cli) -> Result<Tokens, anyhow::Error> {
+    let mut tokens = Tokens {
+        default: cli.token.clone(),
+        ..Tokens::default()
+    };
+    if let Some(path) = &cli.token_file {
+        tokens.default = Some(read_token(path)?);
+    }
+    for (host, path) in &cli.host_token_file {
+        tokens.hosts.insert(host.clone(), read_token(path)?);
+    }
+    Ok(tokens)
+}
+
+// Token files usually end with a newline.
+fn read_token(path: &Path) -> Result<String, anyhow::Error> {
+    let token = fs::read_to_string(path).with_context(|| format!("cannot read token file {}", path.display()))?;
+    let token = token.trim();
+    if token.is_empty() {
+        bail!("token file {} is empty", path.display());
+    }
+    Ok(token.to_owned())
+}
+
+// Best-effort, a failing webhook must not hide the actual result.
+async fn notify_webhook(webhook: &Url, changes: &[RepoChangeset<AnyClient>], fail_on: &[FailPolicy], cli: &Cli) {
+    let Some(notification) = notify::notification(changes, fail_on, cli.min_approvals) else {
+        return;
+    };
+    if let Err(err) = notify::send(webhook, &notification, &client_config(cli)).await {
+        // webhook URLs usually contain a secret, so only the host is logged
+        let host = webhook.host_str().unwrap_or_default();
+        eprintln!("warning: failed to notify webhook at {host}: {err:#}");
+    }
+}
+
+async fn serve(cli: &Cli, config: ClientConfig, addr: SocketAddr, secret: &str) -> Result<(), anyhow::Error> {
+    let reporter = Arc::new(WebhookReporter {
+        config,
+        analyze_options: analyze_options(cli)?,
+        render_options: render_options(cli)?,
+        max_in_flight: cli.max_repos_in_flight as usize,
+        wait_on_rate_limit: cli.wait_on_rate_limit,
+        notify_webhook: cli.notify_webhook.clone(),
+    });
+    let router = serve::router(secret.as_bytes().to_vec(), serve::MAX_RANGES_IN_FLIGHT, move |range| {
+        let reporter = Arc::clone(&reporter);
+        async move { reporter.report(range).await }
+    });
+
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("cannot listen on {addr}"))?;
+    eprintln!("listening for webhooks on {addr}");
+    axum::serve(listener, router).await.context("webhook server failed")
+}
+
+async fn print_approvers(
+    api_clients: &mut ClientSet<AnyClient>,
+    remote: &str,
+    branch: Option<&str>,
+    output_path: Option<&Path>,
+) -> Result<(), anyhow::Error> {
+    let mut remote = Remote::parse(remote).with_context(|| format!("while parsing remote {remote}"))?;
+    api_clients.fill(&mut remote)?;
+
+    let branch = match branch {
+        Some(branch) => branch.to_owned(),
+        None => remote
+            .default_branch()
+            .await
+            .context("while getting the default branch")?,
+    };
+    let approvers = approvers::eligible_approvers(&remote, &branch).await?;
+    if approvers.is_empty() {
+        eprintln!("no approvers found, branch {branch} neither restricts reviewers nor has a CODEOWNERS file");
+    }
+    let mut output = String::new();
+    for approver in approvers {
+        output.push_str(&approver);
+        output.push('\n');
+    }
+    print_or_redirect(&output, output_path)
+}
+
+// The textfile collector may read the file at any time, so it is replaced at once instead of written in place.
+fn write_metrics(path: &Path, metrics: &str) -> Result<(), anyhow::Error> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    fs::write(&tmp_path, metrics).with_context(|| format!("cannot write to {}", Path::new(&tmp_path).display()))?;
+    fs::rename(&tmp_path, path).with_context(|| format!("cannot write to {}", path.display()))
+}
+
+// An explicit output path takes precedence over $GITHUB_OUTPUT.
+fn print_or_redirect(output: &str, output_path: Option<&Path>) -> Result<(), anyhow::Error> {
+    if let Some(path) = output_path {
+        let mut file = File::create(path).with_context(|| format!("cannot write to {}", path.display()))?;
+        file.write_all(output.as_bytes())?;
+    } else if env::var("GITHUB_ACTIONS").is_ok() {
         let path = env::var("GITHUB_OUTPUT").context("cannot find GITHUB_OUTPUT")?;
         let mut file = File::create(path.clone()).with_context(|| format!("cannot write to $GITHUB_OUTPUT {path}"))?;
-        file.write_all((line + "\n").as_bytes())?;
+        file.write_all(output.as_bytes())?;
     } else {
-        println!("{line}");
+        print!("{output}");
     }
 
     Ok(())
 }
 
-fn print_changes(repo_changeset: &[RepoChangeset<RealClient>]) -> Result<(), anyhow::Error> {
-    for change in repo_changeset {
-        println_or_redirect(format!(
-            "Name {} from {} moved from {} to {}",
-            change.name, change.remote.original, change.base_commit, change.head_commit,
-        ))?;
-        println_or_redirect("| Commit link | Pull Request link | Approvals | Reviewer's verdict |".to_string())?;
-        println_or_redirect("|-------------|-------------------|-----------|--------------------|".to_string())?;
-        for commit_change in &change.changes {
-            let mut commit_links: Vec<String> = vec![];
-            for commit in &commit_change.commits {
-                commit_links.push(format!(
-                    "[{}]({})",
-                    match commit.headline.char_indices().nth(45) {
-                        None => commit.headline.clone(),
-                        Some((idx, _)) => commit.headline[..idx].to_string() + "…",
-                    },
-                    prepend_redirect_to_domain(&commit.link)?
-                ));
-            }
+#[cfg(test)]
+mod tests {
+    use pear_reviewer::changes::{Changeset, CommitMetadata};
+    use pear_reviewer::MockClient;
 
-            let pr_link = commit_change.pr_link.clone();
-            println_or_redirect(format!(
-                "| {} | {} | {} | <enter your decision> |",
-                commit_links.join(" ,<br>"),
-                match pr_link {
-                    Some(link) => {
-                        // PRs prefix number with pound
-                        // https://github.com/sapcc/tenso/pull/187
-                        // [tenso #187](https://github.com/sapcc/tenso/pull/187)
-                        let split: Vec<&str> = link.split('/').collect();
-                        if split[5] == "pull" {
-                            format!("[{} #{}]({})", split[4], split[6], prepend_redirect_to_domain(&link)?)
-                        } else {
-                            link
-                        }
-                    },
-                    None => String::new(),
-                },
-                commit_change.approvals.join(", "),
-            ))?;
-        }
+    use super::*;
+
+    #[test]
+    fn omit_baseline() {
+        let changeset = |headline: &str, approvals: &[&str]| Changeset {
+            commits: vec![CommitMetadata {
+                headline: Some(headline.to_owned()),
+                link: format!("https://github.com/sapcc/keppel/commit/{headline:0>40}"),
+                sha: format!("{headline:0>40}"),
+                ..CommitMetadata::default()
+            }],
+            pr_link: Some(format!("https://github.com/sapcc/keppel/pull/{headline}")),
+            approvals: approvals.iter().map(|approval| (*approval).to_owned()).collect(),
+            ..Changeset::default()
+        };
+        let mut previous =
+            repo_changesets::<MockClient>(&["https://github.com/sapcc/keppel.git".to_owned()], "v1", "v2").unwrap();
+        previous[0].changes = vec![changeset("1", &["user1"]), changeset("2", &[])];
+        let baseline: Vec<JsonRepo> =
+            serde_json::from_str(&output::render(OutputFormat::Json, &previous, &RenderOptions::default()).unwrap())
+                .unwrap();
+
+        let mut repos =
+            repo_changesets::<MockClient>(&["https://github.com/sapcc/keppel.git".to_owned()], "v1", "v3").unwrap();
+        repos[0].changes = vec![
+            changeset("1", &["user1"]),
+            changeset("2", &["user2"]),
+            changeset("3", &[]),
+        ];
+        super::omit_baseline(&mut repos, &baseline);
+
+        // the unchanged PR 1 is omitted, PR 2 got approved since the baseline and PR 3 is new
+        assert_eq!(repos[0].changes, vec![changeset("2", &["user2"]), changeset("3", &[])]);
     }
 
-    Ok(())
-}
+    #[test]
+    fn exit_code() {
+        assert_eq!(super::exit_code(&Ok(())), 0);
+        assert_eq!(
+            super::exit_code(&Err(anyhow::Error::from(PolicyViolations(2)))),
+            EXIT_VIOLATIONS
+        );
+        assert_eq!(super::exit_code(&Err(anyhow!("rate limit exceeded"))), EXIT_ERROR);
+        assert_eq!(
+            super::exit_code(&Err(anyhow!("not found").context("while collecting repo changes"))),
+            EXIT_ERROR
+        );
+    }
+
+    #[test]
+    fn fail_on_check() {
+        let cli = Cli::parse_from([
+            "pear-reviewer",
+            "--check",
+            "--require-pr",
+            "repo",
+            "https://github.com/sapcc/keppel.git",
+        ]);
+        assert_eq!(super::fail_on(&cli).unwrap(), vec![
+            FailPolicy::BelowThreshold,
+            FailPolicy::NoPr
+        ]);
+
+        let cli = Cli::parse_from([
+            "pear-reviewer",
+            "--quiet",
+            "repo",
+            "https://github.com/sapcc/keppel.git",
+        ]);
+        assert!(super::fail_on(&cli).is_err());
+    }
 
-fn prepend_redirect_to_domain(link: &str) -> Result<String, anyhow::Error> {
-    let mut parsed_link = Url::parse(link).with_context(|| "failed to parse link {link}")?;
-    if parsed_link.host() == Some(Host::Domain("github.com")) {
-        parsed_link.set_host(Some("redirect.github.com"))?;
+    #[test]
+    fn parse_duration() {
+        assert_eq!(super::parse_duration("30d").unwrap().as_secs(), 30 * 24 * 60 * 60);
+        assert_eq!(super::parse_duration("12h").unwrap().as_secs(), 12 * 60 * 60);
+        assert_eq!(super::parse_duration("2w").unwrap().as_secs(), 14 * 24 * 60 * 60);
+        assert_eq!(super::parse_duration("90s").unwrap().as_secs(), 90);
+        assert!(super::parse_duration("30").is_err());
+        assert!(super::parse_duration("d").is_err());
+        assert!(super::parse_duration("1.5h").is_err());
+        assert!(super::parse_duration("30y").is_err());
+        assert!(super::parse_duration("99999999999999999999w").is_err());
     }
 
-    Ok(parsed_link.to_string())
+    #[test]
+    fn approvers_branch() {
+        let branch = |args: &[&str]| match Cli::parse_from(args).command {
+            Commands::Approvers { branch, .. } => branch,
+            _ => unreachable!(),
+        };
+        // the default branch of the repo is looked up when no branch is given
+        assert_eq!(
+            branch(&["pear-reviewer", "approvers", "https://github.com/sapcc/keppel"]),
+            None
+        );
+        assert_eq!(
+            branch(&[
+                "pear-reviewer",
+                "approvers",
+                "https://github.com/sapcc/keppel",
+                "--branch",
+                "master"
+            ]),
+            Some("master".to_owned())
+        );
+    }
+
+    #[test]
+    fn analyze_options_since() {
+        let cli = Cli::parse_from([
+            "pear-reviewer",
+            "--since",
+            "12h",
+            "repo",
+            "https://github.com/sapcc/keppel",
+        ]);
+        let since = super::analyze_options(&cli).unwrap().since.unwrap();
+        assert!((Utc::now() - since - chrono::Duration::hours(12)).num_seconds().abs() < 60);
+
+        let cli = Cli::parse_from([
+            "pear-reviewer",
+            "--since",
+            "99999999999w",
+            "repo",
+            "https://github.com/sapcc/keppel",
+        ]);
+        assert_eq!(
+            super::analyze_options(&cli).unwrap_err().to_string(),
+            "--since reaches back too far"
+        );
+    }
+
+    #[test]
+    fn parse_date() {
+        assert_eq!(
+            super::parse_date("2024-07-01").unwrap().to_rfc3339(),
+            "2024-07-01T00:00:00+00:00"
+        );
+        assert_eq!(
+            super::parse_date("2024-07-01T12:30:00+02:00").unwrap().to_rfc3339(),
+            "2024-07-01T10:30:00+00:00"
+        );
+        assert!(super::parse_date("2024-13-01").is_err());
+        assert!(super::parse_date("last quarter").is_err());
+
+        assert_eq!(
+            super::parse_end_date("2024-07-01").unwrap().to_rfc3339(),
+            "2024-07-01T23:59:59+00:00"
+        );
+        assert_eq!(
+            super::parse_end_date("2024-07-01T12:30:00Z").unwrap().to_rfc3339(),
+            "2024-07-01T12:30:00+00:00"
+        );
+    }
+
+    #[test]
+    fn parse_github_api_version() {
+        assert_eq!(
+            super::parse_github_api_version("2022-11-28").unwrap(),
+            (None, "2022-11-28".to_owned())
+        );
+        assert_eq!(
+            super::parse_github_api_version("github.example.com=2021-06-01").unwrap(),
+            (Some("github.example.com".to_owned()), "2021-06-01".to_owned())
+        );
+        assert!(super::parse_github_api_version("=2022-11-28").is_err());
+        assert!(super::parse_github_api_version("latest").is_err());
+    }
+
+    #[test]
+    fn parse_remote_list() {
+        let content = "# audit 2024-Q4
+https://github.com/sapcc/keppel.git
+
+  https://github.com/sapcc/limes.git  
+#https://github.com/sapcc/castellum.git
+";
+        assert_eq!(super::parse_remote_list(content), vec![
+            "https://github.com/sapcc/keppel.git",
+            "https://github.com/sapcc/limes.git",
+        ]);
+        assert!(super::parse_remote_list("\n# nothing here\n").is_empty());
+    }
+
+    #[test]
+    fn print_or_redirect_output_path() {
+        let path = env::temp_dir().join(format!("pear-reviewer-output-{}.md", std::process::id()));
+        fs::write(&path, "previous content which is longer than the new one").unwrap();
+
+        print_or_redirect("| Commit link |\n", Some(&path)).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(content, "| Commit link |\n");
+    }
 }
@@ -0,0 +1,168 @@
+// Copyright 2024 SAP SE
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::{bail, Context};
+use serde::Serialize;
+use url::Url;
+
+use crate::api_clients::{Client, ClientConfig};
+use crate::changes::RepoChangeset;
+use crate::http_client::build_unauthenticated;
+use crate::policy::{self, FailPolicy};
+
+/// Summary of a failed gate as posted to --notify-webhook. `text` is what Slack shows for incoming webhooks.
+#[derive(Debug, Serialize)]
+pub struct Notification {
+    pub text: String,
+    pub repos: Vec<RepoNotification>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RepoNotification {
+    pub repo: String,
+    pub violations: usize,
+    pub link: String,
+}
+
+/// Rendered output of a range analyzed by the serve subcommand, posted to --notify-webhook.
+#[derive(Debug, Serialize)]
+pub struct TableNotification {
+    pub text: String,
+}
+
+/// The notification for the repos with policy violations, None if there are none.
+pub fn notification<C: Client>(
+    repo_changesets: &[RepoChangeset<C>],
+    policies: &[FailPolicy],
+    min_approvals: usize,
+) -> Option<Notification> {
+    let repos: Vec<_> = repo_changesets
+        .iter()
+        .filter_map(|repo| {
+            let violations = policy::violations(std::slice::from_ref(repo), policies, min_approvals).len();
+            (violations > 0).then(|| RepoNotification {
+                repo: repo.name.clone(),
+                violations,
+                link: repo.remote.compare_url(&repo.base_commit, &repo.head_commit),
+            })
+        })
+        .collect();
+    if repos.is_empty() {
+        return None;
+    }
+
+    let total: usize = repos.iter().map(|repo| repo.violations).sum();
+    Some(Notification {
+        text: format!(
+            "pear-reviewer found {total} policy violations in {}",
+            repos
+                .iter()
+                .map(|repo| repo.repo.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        repos,
+    })
+}
+
+/// Posts the notification as JSON to the webhook. The GitHub token is never sent along.
+pub async fn send(webhook: &Url, notification: &impl Serialize, config: &ClientConfig) -> anyhow::Result<()> {
+    let octocrab = build_unauthenticated(&webhook[..url::Position::BeforePath], config)?;
+    let response = octocrab
+        ._post(&webhook[url::Position::BeforePath..], Some(notification))
+        .await
+        .context("failed to post to webhook")?;
+    if !response.status().is_success() {
+        bail!("webhook answered with {}", response.status());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+
+    use super::*;
+    use crate::api_clients::MockClient;
+    use crate::changes::{Changeset, CommitMetadata};
+    use crate::repo_changesets;
+
+    #[tokio::test]
+    async fn send_notification() {
+        // a webhook which records the first request it receives
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut head = Vec::new();
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+                head.push(line);
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            let _ = reader
+                .get_mut()
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok");
+            sender.send((head, body)).unwrap();
+        });
+
+        let mut repos =
+            repo_changesets::<MockClient>(&["https://github.com/sapcc/keppel.git".to_owned()], "v1", "v2").unwrap();
+        repos[0].changes = vec![Changeset {
+            commits: vec![CommitMetadata {
+                headline: Some("direct push".to_owned()),
+                link: "https://github.com/sapcc/keppel/commit/4e1a3b7c9d2f6e8a0b5c7d9e1f3a5b7c9d0e2f4a".to_owned(),
+                sha: "4e1a3b7c9d2f6e8a0b5c7d9e1f3a5b7c9d0e2f4a".to_owned(),
+                ..CommitMetadata::default()
+            }],
+            ..Changeset::default()
+        }];
+        assert!(notification(&repos, &[FailPolicy::ChangesRequested], 1).is_none());
+        let notification = notification(&repos, &[FailPolicy::NoPr], 1).unwrap();
+        let webhook = Url::parse(&format!("http://{addr}/services/T000/B000?token=secret")).unwrap();
+        send(&webhook, &notification, &ClientConfig::default()).await.unwrap();
+
+        let (head, body) = receiver.recv().unwrap();
+        assert_eq!(head[0], "POST /services/T000/B000?token=secret HTTP/1.1\r\n");
+        assert!(!head.iter().any(|line| line.to_lowercase().starts_with("authorization")));
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "text": "pear-reviewer found 1 policy violations in keppel",
+                "repos": [{
+                    "repo": "keppel",
+                    "violations": 1,
+                    "link": "https://github.com/sapcc/keppel/compare/v1...v2",
+                }],
+            })
+        );
+    }
+}
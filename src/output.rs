@@ -0,0 +1,1892 @@
+// Copyright 2024 SAP SE
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsString;
+use std::fmt::Write;
+use std::io::{self, IsTerminal};
+use std::str::FromStr;
+
+use anstyle::{AnsiColor, Style};
+use anyhow::{bail, Context};
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use url::{Host, Url};
+
+use crate::api_clients::Client;
+use crate::changes::{is_commit_hash, AnalyzeOptions, Changeset, CommitMetadata, RepoChangeset};
+use crate::github::CompareStatus;
+use crate::helm_chart::TagBump;
+use crate::policy::{self, FailPolicy};
+use crate::template::Template;
+
+/// Renders the same table as the markdown format, a starting point for --output-template.
+pub const DEFAULT_TEMPLATE: &str = include_str!("templates/markdown.hbs");
+
+pub const VERDICT_PLACEHOLDER: &str = "<enter your decision>";
+const VERDICT_OK: &str = "OK";
+const VERDICT_NEEDS_REVIEW: &str = "NEEDS REVIEW";
+// commits pushed without a PR are exactly the compliance risk we look for, so they are called out
+const VERDICT_NO_PR: &str = "⚠ no PR";
+const VERDICT_ERROR: &str = "⚠ could not analyze";
+const VERDICT_SELF_CANCELLING: &str = "self-cancelling";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Markdown,
+    /// standalone HTML report, e.g. for archiving
+    Html,
+    /// `JUnit` XML with one test case per change, failing when it has too few approvals
+    Junit,
+    /// all results as JSON, which can be passed to --baseline of a later run
+    Json,
+    /// rendered with --output-template, or with the built-in template of the markdown table
+    Template,
+}
+
+#[derive(Clone, Debug)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct RenderOptions {
+    /// number of approvals a change needs to pass
+    pub min_approvals: usize,
+    /// fills the verdict from the approvals instead of leaving a placeholder for the reviewer
+    pub auto_verdict: bool,
+    /// shown in the verdict column when the verdict is left to the reviewer, may be empty
+    pub verdict_placeholder: String,
+    /// adds the author and authored date of every commit as extra columns
+    pub show_authors: bool,
+    /// adds whether every commit has a verified signature as an extra column
+    pub show_signatures: bool,
+    /// lists every approver with the commit they approved as user@sha7
+    pub detailed_approvals: bool,
+    /// lists every approver with the time of their approval in UTC
+    pub show_approval_time: bool,
+    /// links to these hosts are rewritten so that they don't create backlinks in the linked issues and PRs,
+    /// links are emitted unmodified when empty
+    pub redirect_map: Vec<RedirectRule>,
+    /// verdicts decided outside of pear-reviewer, they take precedence over the placeholder and --auto-verdict
+    pub decisions: Decisions,
+    /// replaces the built-in template of the template format
+    pub template: Option<Template>,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            min_approvals: 1,
+            auto_verdict: false,
+            verdict_placeholder: VERDICT_PLACEHOLDER.to_owned(),
+            show_authors: false,
+            show_signatures: false,
+            detailed_approvals: false,
+            show_approval_time: false,
+            redirect_map: vec![RedirectRule::default()],
+            decisions: Decisions::default(),
+            template: None,
+        }
+    }
+}
+
+/// Verdicts of an external policy engine as read from --decisions-file, keyed by commit sha or by PR, either as its
+/// URL or as `owner/repo#number`. Commit shas may be abbreviated.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(transparent)]
+pub struct Decisions(BTreeMap<String, String>);
+
+impl Decisions {
+    // The decision for a commit is more specific and wins over the one for its PR.
+    fn verdict_for(&self, changeset: &Changeset) -> Option<&str> {
+        let commit_decision = changeset.commits.iter().find_map(|commit| {
+            let sha = commit.link.rsplit('/').next()?;
+            self.0.get(sha).or_else(|| {
+                self.0
+                    .iter()
+                    .find(|(key, _)| is_commit_hash(key) && sha.starts_with(key.as_str()))
+                    .map(|(_, decision)| decision)
+            })
+        });
+        commit_decision
+            .or_else(|| {
+                let pr_link = changeset.pr_link.as_deref()?;
+                self.0.get(pr_link).or_else(|| {
+                    // PR links end in owner/repo/pull/number on GitHub and owner/repo/pulls/number on Gitea
+                    let mut segments = pr_link.rsplit('/');
+                    let (number, _, repo, owner) =
+                        (segments.next()?, segments.next()?, segments.next()?, segments.next()?);
+                    self.0.get(&format!("{owner}/{repo}#{number}"))
+                })
+            })
+            .map(String::as_str)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RedirectRule {
+    pub host: String,
+    pub replacement: String,
+}
+
+impl Default for RedirectRule {
+    fn default() -> Self {
+        Self {
+            host: "github.com".to_owned(),
+            replacement: "redirect.github.com".to_owned(),
+        }
+    }
+}
+
+impl FromStr for RedirectRule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((host, replacement)) if !host.is_empty() && !replacement.is_empty() => Ok(Self {
+                host: host.to_string(),
+                replacement: replacement.to_string(),
+            }),
+            _ => bail!("redirect rules are expected to be in the format of host=replacement"),
+        }
+    }
+}
+
+pub fn render<C: Client>(
+    format: OutputFormat,
+    repo_changesets: &[RepoChangeset<C>],
+    options: &RenderOptions,
+) -> Result<String, anyhow::Error> {
+    match format {
+        OutputFormat::Markdown => render_markdown(repo_changesets, options),
+        OutputFormat::Html => render_html(repo_changesets, options),
+        OutputFormat::Junit => render_junit(repo_changesets, options),
+        OutputFormat::Json => render_json(repo_changesets),
+        OutputFormat::Template => render_template(repo_changesets, options),
+    }
+}
+
+fn render_template<C: Client>(
+    repo_changesets: &[RepoChangeset<C>],
+    options: &RenderOptions,
+) -> Result<String, anyhow::Error> {
+    let template = match &options.template {
+        Some(template) => template.clone(),
+        None => Template::parse(DEFAULT_TEMPLATE).context("invalid built-in template")?,
+    };
+    template.render(&template_context(repo_changesets, options)?)
+}
+
+// The same data as the JSON output with links rewritten like in the other formats, plus what the markdown table
+// shows in addition.
+fn template_context<C: Client>(
+    repo_changesets: &[RepoChangeset<C>],
+    options: &RenderOptions,
+) -> Result<serde_json::Value, anyhow::Error> {
+    let mut repos = Vec::new();
+    for repo in repo_changesets {
+        let mut changes = Vec::new();
+        for changeset in &repo.changes {
+            let mut commits = Vec::new();
+            for commit in &changeset.commits {
+                let mut value = serde_json::to_value(commit).context("cannot serialize commit")?;
+                value["link"] = prepend_redirect_to_domain(&commit.link, &options.redirect_map)?.into();
+                value["short_headline"] = commit_label(commit).into();
+                commits.push(value);
+            }
+
+            let mut value = serde_json::to_value(changeset).context("cannot serialize change")?;
+            value["commits"] = commits.into();
+            if let Some(link) = &changeset.pr_link {
+                value["pr_link"] = prepend_redirect_to_domain(link, &options.redirect_map)?.into();
+                value["pr_label"] = pr_label(link).unwrap_or_else(|| link.clone()).into();
+            }
+            value["approvals_text"] = format_approvals(changeset, options).into();
+            value["verdict"] = verdict(changeset, repo, options).into();
+            changes.push(value);
+        }
+
+        repos.push(serde_json::json!({
+            "name": repo.name,
+            "remote": repo.remote.original,
+            "base_commit": repo.base_commit,
+            "head_commit": repo.head_commit,
+            "base_ref": repo.base_ref,
+            "head_ref": repo.head_ref,
+            "header": repo_header(repo),
+            "error": repo.error,
+            "changes": changes,
+        }));
+    }
+
+    Ok(serde_json::json!({
+        "repos": repos,
+        "summary": summary(repo_changesets),
+    }))
+}
+
+/// A repository in the JSON output.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct JsonRepo {
+    pub name: String,
+    pub remote: String,
+    pub base_commit: String,
+    pub head_commit: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_ref: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub head_ref: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compare_status: Option<CompareStatus>,
+    /// why the repo could not be analyzed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub changes: Vec<Changeset>,
+}
+
+fn render_json<C: Client>(repo_changesets: &[RepoChangeset<C>]) -> Result<String, anyhow::Error> {
+    let repos: Vec<JsonRepo> = repo_changesets
+        .iter()
+        .map(|repo| JsonRepo {
+            name: repo.name.clone(),
+            remote: repo.remote.original.clone(),
+            base_commit: repo.base_commit.clone(),
+            head_commit: repo.head_commit.clone(),
+            base_ref: repo.base_ref.clone(),
+            head_ref: repo.head_ref.clone(),
+            compare_status: repo.compare_status,
+            error: repo.error.clone(),
+            changes: repo.changes.clone(),
+        })
+        .collect();
+    let mut json = serde_json::to_string_pretty(&repos).context("cannot serialize results")?;
+    json.push('\n');
+    Ok(json)
+}
+
+/// What a run analyzed, written to --metadata-file independently of --format.
+#[derive(Debug, Serialize)]
+pub struct Metadata {
+    pub tool_version: &'static str,
+    pub generated_at: DateTime<Utc>,
+    pub repos: Vec<MetadataRepo>,
+}
+
+/// A repository in the metadata file, with counts instead of the changes themselves.
+#[derive(Debug, Serialize)]
+pub struct MetadataRepo {
+    pub name: String,
+    pub remote: String,
+    pub base_commit: String,
+    pub head_commit: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_ref: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub head_ref: Option<String>,
+    pub commits: usize,
+    pub changes: usize,
+    /// violations of the --fail-on policies, a change violating multiple policies is counted for each
+    pub violations: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+pub fn render_metadata<C: Client>(
+    repo_changesets: &[RepoChangeset<C>],
+    fail_on: &[FailPolicy],
+    min_approvals: usize,
+    generated_at: DateTime<Utc>,
+) -> Result<String, anyhow::Error> {
+    let metadata = Metadata {
+        tool_version: env!("CARGO_PKG_VERSION"),
+        generated_at,
+        repos: repo_changesets
+            .iter()
+            .map(|repo| MetadataRepo {
+                name: repo.name.clone(),
+                remote: repo.remote.original.clone(),
+                base_commit: repo.base_commit.clone(),
+                head_commit: repo.head_commit.clone(),
+                base_ref: repo.base_ref.clone(),
+                head_ref: repo.head_ref.clone(),
+                commits: repo.changes.iter().map(|change| change.commits.len()).sum(),
+                changes: repo.changes.len(),
+                violations: policy::violations(std::slice::from_ref(repo), fail_on, min_approvals).len(),
+                error: repo.error.clone(),
+            })
+            .collect(),
+    };
+    let mut json = serde_json::to_string_pretty(&metadata).context("cannot serialize metadata")?;
+    json.push('\n');
+    Ok(json)
+}
+
+/// Gauges per repo in the Prometheus text format, e.g. for the textfile collector of `node_exporter`.
+pub fn render_metrics<C: Client>(
+    repo_changesets: &[RepoChangeset<C>],
+    min_approvals: usize,
+) -> Result<String, anyhow::Error> {
+    type Gauge<C> = fn(&RepoChangeset<C>, usize) -> usize;
+    let gauges: [(&str, &str, Gauge<C>); 4] = [
+        (
+            "pear_changes_total",
+            "Changes found between base and head.",
+            |repo, _| repo.changes.len(),
+        ),
+        (
+            "pear_changes_unapproved",
+            "Changes with fewer approvals than required.",
+            |repo, min_approvals| {
+                policy::violations(std::slice::from_ref(repo), &[FailPolicy::BelowThreshold], min_approvals).len()
+            },
+        ),
+        (
+            "pear_commits_without_pr",
+            "Commits which were not merged through a PR.",
+            |repo, _| {
+                repo.changes
+                    .iter()
+                    .filter(|change| change.pr_link.is_none())
+                    .map(|change| change.commits.len())
+                    .sum()
+            },
+        ),
+        // the other gauges are 0 for repos that could not be analyzed
+        (
+            "pear_analysis_failed",
+            "1 if the repo could not be analyzed.",
+            |repo, _| usize::from(repo.error.is_some()),
+        ),
+    ];
+
+    let mut out = String::new();
+    for (name, help, value) in gauges {
+        writeln!(out, "# HELP {name} {help}")?;
+        writeln!(out, "# TYPE {name} gauge")?;
+        // the remote tells apart repos with the same name from different owners or hosts
+        for repo in repo_changesets {
+            writeln!(
+                out,
+                "{name}{{repo=\"{}\",remote=\"{}\"}} {}",
+                escape_label_value(&repo.name),
+                escape_label_value(&repo.remote.original),
+                value(repo, min_approvals)
+            )?;
+        }
+    }
+    Ok(out)
+}
+
+fn escape_label_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+pub fn render_markdown<C: Client>(
+    repo_changesets: &[RepoChangeset<C>],
+    options: &RenderOptions,
+) -> Result<String, anyhow::Error> {
+    let mut out = String::new();
+
+    for change in repo_changesets {
+        writeln!(out, "{}", repo_header(change))?;
+        let columns = columns(options);
+        writeln!(out, "| {} |", columns.join(" | "))?;
+        let separators: Vec<String> = columns.iter().map(|column| "-".repeat(column.len() + 2)).collect();
+        writeln!(out, "|{}|", separators.join("|"))?;
+        if let Some(error) = &change.error {
+            let cells = error_cells(escape_table_cell(error), options, escape_markdown);
+            writeln!(out, "| {} |", cells.join(" | "))?;
+        }
+        for commit_change in &change.changes {
+            let mut commit_links: Vec<String> = vec![];
+            for commit in &commit_change.commits {
+                let link = format!(
+                    "[{}]({})",
+                    escape_table_cell(&commit_label(commit)),
+                    prepend_redirect_to_domain(&commit.link, &options.redirect_map)?
+                );
+                commit_links.push(if commit.self_cancelling {
+                    format!("~~{link}~~")
+                } else {
+                    link
+                });
+            }
+
+            let mut cells = vec![commit_links.join(" ,<br>")];
+            if options.show_authors {
+                cells.push(
+                    commit_change
+                        .commits
+                        .iter()
+                        .map(|commit| escape_table_cell(&commit_author(commit)))
+                        .collect::<Vec<_>>()
+                        .join(" ,<br>"),
+                );
+                cells.push(
+                    commit_change
+                        .commits
+                        .iter()
+                        .map(commit_authored_at)
+                        .collect::<Vec<_>>()
+                        .join(" ,<br>"),
+                );
+            }
+            if options.show_signatures {
+                cells.push(
+                    commit_change
+                        .commits
+                        .iter()
+                        .map(commit_signature)
+                        .collect::<Vec<_>>()
+                        .join(" ,<br>"),
+                );
+            }
+            let mut pr_cell = match &commit_change.pr_link {
+                Some(link) => match pr_label(link) {
+                    Some(label) => format!(
+                        "[{}]({})",
+                        escape_table_cell(&label),
+                        prepend_redirect_to_domain(link, &options.redirect_map)?
+                    ),
+                    None => escape_table_cell(link),
+                },
+                None => String::new(),
+            };
+            if commit_change.pr_without_commits {
+                pr_cell.push_str(" (no commits)");
+            }
+            cells.push(pr_cell);
+            cells.push(format_approvals(commit_change, options));
+            cells.push(escape_markdown(verdict(commit_change, change, options)));
+
+            writeln!(out, "| {} |", cells.join(" | "))?;
+        }
+    }
+
+    writeln!(out, "\n{}", summary(repo_changesets))?;
+
+    Ok(out)
+}
+
+pub fn render_html<C: Client>(
+    repo_changesets: &[RepoChangeset<C>],
+    options: &RenderOptions,
+) -> Result<String, anyhow::Error> {
+    let mut out = String::new();
+
+    writeln!(
+        out,
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>pear-reviewer report</title>
+<style>
+body {{ font-family: sans-serif; }}
+table {{ border-collapse: collapse; margin-bottom: 2em; }}
+th, td {{ border: 1px solid #ccc; padding: 4px 8px; text-align: left; vertical-align: top; }}
+th {{ background: #f0f0f0; }}
+</style>
+</head>
+<body>"#
+    )?;
+
+    for change in repo_changesets {
+        writeln!(out, "<h2>{}</h2>", escape_html(&repo_header(change)))?;
+        writeln!(out, "<table>")?;
+        writeln!(out, "<tr><th>{}</th></tr>", columns(options).join("</th><th>"))?;
+        if let Some(error) = &change.error {
+            let cells = error_cells(escape_html(error), options, escape_html);
+            writeln!(out, "<tr><td>{}</td></tr>", cells.join("</td><td>"))?;
+        }
+        for commit_change in &change.changes {
+            let mut commit_links: Vec<String> = vec![];
+            for commit in &commit_change.commits {
+                let link = format!(
+                    r#"<a href="{}">{}</a>"#,
+                    escape_html(&prepend_redirect_to_domain(&commit.link, &options.redirect_map)?),
+                    escape_html(&commit_label(commit)),
+                );
+                commit_links.push(if commit.self_cancelling {
+                    format!("<s>{link}</s>")
+                } else {
+                    link
+                });
+            }
+
+            let mut cells = vec![commit_links.join(",<br>")];
+            let mut commit_columns: Vec<fn(&CommitMetadata) -> String> = Vec::new();
+            if options.show_authors {
+                commit_columns.push(commit_author);
+                commit_columns.push(commit_authored_at);
+            }
+            if options.show_signatures {
+                commit_columns.push(commit_signature);
+            }
+            for column in commit_columns {
+                cells.push(
+                    commit_change
+                        .commits
+                        .iter()
+                        .map(|commit| escape_html(&column(commit)))
+                        .collect::<Vec<_>>()
+                        .join(",<br>"),
+                );
+            }
+            let mut pr_cell = match &commit_change.pr_link {
+                Some(link) => format!(
+                    r#"<a href="{}">{}</a>"#,
+                    escape_html(&prepend_redirect_to_domain(link, &options.redirect_map)?),
+                    escape_html(&pr_label(link).unwrap_or_else(|| link.clone())),
+                ),
+                None => String::new(),
+            };
+            if commit_change.pr_without_commits {
+                pr_cell.push_str(" (no commits)");
+            }
+            cells.push(pr_cell);
+            cells.push(
+                approval_lines(commit_change, options)
+                    .iter()
+                    .map(|line| escape_html(line))
+                    .collect::<Vec<_>>()
+                    .join("<br>"),
+            );
+            cells.push(escape_html(verdict(commit_change, change, options)));
+
+            writeln!(out, "<tr><td>{}</td></tr>", cells.join("</td><td>"))?;
+        }
+        writeln!(out, "</table>")?;
+    }
+
+    writeln!(out, "<p>{}</p>", escape_html(&summary(repo_changesets)))?;
+    writeln!(out, "</body>\n</html>")?;
+
+    Ok(out)
+}
+
+pub fn render_junit<C: Client>(
+    repo_changesets: &[RepoChangeset<C>],
+    options: &RenderOptions,
+) -> Result<String, anyhow::Error> {
+    let mut out = String::new();
+    let is_failure =
+        |repo: &RepoChangeset<C>, change: &Changeset| policy::is_below_threshold(repo, change, options.min_approvals);
+    // a repo that could not be analyzed is reported as one failed test case
+    let errors = |repo: &RepoChangeset<C>| usize::from(repo.error.is_some());
+
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<testsuites name="pear-reviewer" tests="{}" failures="{}">"#,
+        repo_changesets
+            .iter()
+            .map(|repo| repo.changes.len() + errors(repo))
+            .sum::<usize>(),
+        repo_changesets
+            .iter()
+            .flat_map(|repo| repo.changes.iter().filter(|change| is_failure(repo, change)))
+            .count()
+            + repo_changesets.iter().map(errors).sum::<usize>(),
+    )?;
+
+    for repo in repo_changesets {
+        let min_approvals = policy::min_approvals_for(repo, options.min_approvals);
+        writeln!(
+            out,
+            r#"  <testsuite name="{}" tests="{}" failures="{}">"#,
+            escape_html(&repo.name),
+            repo.changes.len() + errors(repo),
+            repo.changes.iter().filter(|change| is_failure(repo, change)).count() + errors(repo),
+        )?;
+
+        if let Some(error) = &repo.error {
+            writeln!(
+                out,
+                r#"    <testcase classname="{}" name="analyze {}">"#,
+                escape_html(&repo.name),
+                escape_html(&repo.name),
+            )?;
+            writeln!(
+                out,
+                r#"      <failure message="could not analyze">{}</failure>"#,
+                escape_html(error),
+            )?;
+            writeln!(out, "    </testcase>")?;
+        }
+
+        for change in &repo.changes {
+            let headlines: Vec<&str> = change.commits.iter().map(CommitMetadata::title).collect();
+            let name = format!(
+                "{} ({})",
+                headlines.join(", "),
+                change.pr_link.as_ref().map_or_else(
+                    || "no PR".to_owned(),
+                    |link| pr_label(link).unwrap_or_else(|| link.clone())
+                ),
+            );
+
+            if !is_failure(repo, change) {
+                writeln!(
+                    out,
+                    r#"    <testcase classname="{}" name="{}"/>"#,
+                    escape_html(&repo.name),
+                    escape_html(&name),
+                )?;
+                continue;
+            }
+
+            let missing = min_approvals.saturating_sub(change.approvals.len());
+            let mut details = Vec::new();
+            if missing > 0 {
+                details.push(format!(
+                    "missing {missing} approval{}",
+                    if missing == 1 { "" } else { "s" }
+                ));
+            }
+            for approver in &repo.required_approvers {
+                if !change.approvals.contains(approver) {
+                    details.push(format!("missing approval of {approver}"));
+                }
+            }
+            details.extend(approval_lines(change, options));
+            if change.pr_link.is_none() {
+                details.push("no pull request".to_owned());
+            }
+
+            writeln!(
+                out,
+                r#"    <testcase classname="{}" name="{}">"#,
+                escape_html(&repo.name),
+                escape_html(&name),
+            )?;
+            writeln!(
+                out,
+                r#"      <failure message="{} of {} required approvals">{}</failure>"#,
+                change.approvals.len(),
+                min_approvals,
+                escape_html(&details.join("\n")),
+            )?;
+            writeln!(out, "    </testcase>")?;
+        }
+
+        writeln!(out, "  </testsuite>")?;
+    }
+    writeln!(out, "</testsuites>")?;
+
+    Ok(out)
+}
+
+fn columns(options: &RenderOptions) -> Vec<&'static str> {
+    let mut columns = vec!["Commit link"];
+    if options.show_authors {
+        columns.extend(["Author", "Authored at"]);
+    }
+    if options.show_signatures {
+        columns.push("Signature");
+    }
+    columns.extend(["Pull Request link", "Approvals", "Reviewer's verdict"]);
+    columns
+}
+
+// The error takes the first column and the verdict column is flagged, the columns in between stay empty.
+fn error_cells(error: String, options: &RenderOptions, escape: fn(&str) -> String) -> Vec<String> {
+    let mut cells = vec![String::new(); columns(options).len()];
+    cells[0] = error;
+    if let Some(verdict) = cells.last_mut() {
+        *verdict = escape(VERDICT_ERROR);
+    }
+    cells
+}
+
+fn repo_header<C: Client>(change: &RepoChangeset<C>) -> String {
+    // the usual case of head being ahead of base is not worth mentioning
+    let status = match change.compare_status {
+        None | Some(CompareStatus::Ahead) => "",
+        Some(CompareStatus::Behind) => " (head is behind base)",
+        Some(CompareStatus::Diverged) => " (head has diverged from base)",
+        Some(CompareStatus::Identical) => " (head is identical to base)",
+    };
+    format!(
+        "Name {} from {} moved from {} to {}{status}",
+        change.name,
+        change.remote.original,
+        format_ref(&change.base_commit, change.base_ref.as_deref()),
+        format_ref(&change.head_commit, change.head_ref.as_deref()),
+    )
+}
+
+// Shows the tag or branch next to the commit it was resolved to.
+fn format_ref(commit: &str, reference: Option<&str>) -> String {
+    match reference {
+        Some(reference) => format!("{reference} ({commit})"),
+        None => commit.to_owned(),
+    }
+}
+
+fn summary<C: Client>(repo_changesets: &[RepoChangeset<C>]) -> String {
+    let changes = repo_changesets.iter().flat_map(|repo| &repo.changes);
+    let total_changes = changes.clone().count();
+    let changes_without_pr = changes.filter(|change| change.pr_link.is_none()).count();
+    let mut summary = format!(
+        "Summary: {total_changes} changes in {} repositories, {changes_without_pr} without a pull request",
+        repo_changesets.len()
+    );
+    let errors = repo_changesets.iter().filter(|repo| repo.error.is_some()).count();
+    if errors > 0 {
+        let _ = write!(summary, ", {errors} could not be analyzed");
+    }
+    summary
+}
+
+fn commit_label(commit: &CommitMetadata) -> String {
+    shorten_headline(commit.title())
+}
+
+fn shorten_headline(headline: &str) -> String {
+    match headline.char_indices().nth(45) {
+        None => headline.to_string(),
+        Some((idx, _)) => headline[..idx].to_string() + "…",
+    }
+}
+
+// PRs prefix number with pound, Gitea has them below pulls instead of pull
+// https://github.com/sapcc/tenso/pull/187
+// tenso #187
+fn pr_label(link: &str) -> Option<String> {
+    let split: Vec<&str> = link.split('/').collect();
+    match (split.get(4), split.get(5), split.get(6)) {
+        (Some(repo), Some(&("pull" | "pulls")), Some(number)) => Some(format!("{repo} #{number}")),
+        _ => None,
+    }
+}
+
+fn commit_author(commit: &CommitMetadata) -> String {
+    commit.author.clone().unwrap_or_else(|| "unknown".to_owned())
+}
+
+fn commit_signature(commit: &CommitMetadata) -> String {
+    match &commit.signature {
+        Some(signature) if signature.verified => "verified".to_owned(),
+        Some(signature) => format!("✘ {}", signature.reason),
+        None => "unknown".to_owned(),
+    }
+}
+
+fn commit_authored_at(commit: &CommitMetadata) -> String {
+    commit.authored_at.map_or_else(
+        || "unknown".to_owned(),
+        |authored_at| authored_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+    )
+}
+
+fn verdict<'a, C: Client>(changeset: &Changeset, repo: &RepoChangeset<C>, options: &'a RenderOptions) -> &'a str {
+    if let Some(decision) = options.decisions.verdict_for(changeset) {
+        decision
+    } else if changeset.is_self_cancelling() {
+        VERDICT_SELF_CANCELLING
+    } else if changeset.pr_link.is_none() {
+        VERDICT_NO_PR
+    } else if !options.auto_verdict {
+        &options.verdict_placeholder
+    } else if !policy::is_below_threshold(repo, changeset, options.min_approvals)
+        && changeset.changes_requested_by.is_empty()
+    {
+        VERDICT_OK
+    } else {
+        VERDICT_NEEDS_REVIEW
+    }
+}
+
+// Keeps user provided text like the verdict placeholder from breaking the table or being rendered as formatting.
+// Angle brackets are left alone, so that the default placeholder shows up as it always did.
+pub(crate) fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '|' | '*' | '_' | '`' | '[' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// Headlines and links keep their formatting, only the characters that break the table layout are escaped.
+pub(crate) fn escape_table_cell(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '|') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// also used for XML, which needs the same characters escaped
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Images of values.yaml files have no sources, so they are listed apart from the analyzed changes. Empty without any.
+pub fn render_tag_bumps(tag_bumps: &[TagBump]) -> Result<String, anyhow::Error> {
+    let mut out = String::new();
+    if tag_bumps.is_empty() {
+        return Ok(out);
+    }
+
+    writeln!(
+        out,
+        "\nImage tags changed in values.yaml without sources, their changes were not analyzed:"
+    )?;
+    for tag_bump in tag_bumps {
+        let change = match &tag_bump.old_tag {
+            Some(old_tag) => format!("`{old_tag}` → `{}`", tag_bump.new_tag),
+            None => format!("added with `{}`", tag_bump.new_tag),
+        };
+        writeln!(
+            out,
+            "- `{}` at `{}` of {}: {change}",
+            tag_bump.repository,
+            tag_bump.key_path,
+            tag_bump.path.display(),
+        )?;
+    }
+    Ok(out)
+}
+
+// Lists the changesets which would be analyzed without sending any request to GitHub.
+pub fn render_dry_run<C: Client>(
+    repo_changesets: &[RepoChangeset<C>],
+    options: &AnalyzeOptions,
+) -> Result<String, anyhow::Error> {
+    let mut out = String::new();
+
+    writeln!(out, "Would analyze {} repositories:", repo_changesets.len())?;
+    for change in repo_changesets {
+        writeln!(
+            out,
+            "- {} from {} moved from {} to {}",
+            change.name, change.remote.original, change.base_commit, change.head_commit,
+        )?;
+    }
+
+    // --path needs the files of every commit on top
+    let calls_per_commit = if options.paths.is_empty() { 1 } else { 2 };
+    writeln!(
+        out,
+        "\nEstimated API calls: at least {}, plus {calls_per_commit} per commit and 2 per associated pull request",
+        estimate_api_calls(repo_changesets, options)
+    )?;
+
+    Ok(out)
+}
+
+// The number of commits is only known after the compare call, so only the fixed calls per repository are counted.
+fn estimate_api_calls<C: Client>(repo_changesets: &[RepoChangeset<C>], options: &AnalyzeOptions) -> usize {
+    let mut calls_per_repo = 2; // validate head, compare
+    if options.required_team.is_some() {
+        calls_per_repo += 1; // team members
+    }
+    if options.use_merge_base {
+        calls_per_repo += 1; // merge base
+    }
+    if options.use_branch_protection {
+        calls_per_repo += 2; // default branch, branch protection
+    }
+    let validate_base_calls = repo_changesets
+        .iter()
+        .filter(|repo_changeset| !repo_changeset.base_commit.is_empty())
+        .count();
+    let default_branch_calls = repo_changesets
+        .iter()
+        .filter(|repo_changeset| repo_changeset.head_commit == "HEAD")
+        .count();
+    let resolve_ref_calls = repo_changesets
+        .iter()
+        .flat_map(|repo_changeset| [&repo_changeset.base_commit, &repo_changeset.head_commit])
+        .filter(|commit| !commit.is_empty() && !is_commit_hash(commit))
+        .count();
+    repo_changesets.len() * calls_per_repo + validate_base_calls + default_branch_calls + resolve_ref_calls
+}
+
+fn format_approvals(changeset: &Changeset, options: &RenderOptions) -> String {
+    approval_lines(changeset, options).join("<br>")
+}
+
+fn approval_lines(changeset: &Changeset, options: &RenderOptions) -> Vec<String> {
+    let approver = |user: &String| {
+        let approver = match changeset.approved_commits.get(user) {
+            Some(commit_id) if options.detailed_approvals => {
+                format!("{user}@{}", commit_id.get(..7).unwrap_or(commit_id))
+            },
+            _ => user.clone(),
+        };
+        let approver = match changeset.approved_at.get(user) {
+            Some(approved_at) if options.show_approval_time => {
+                format!(
+                    "{approver} at {}",
+                    approved_at.to_rfc3339_opts(SecondsFormat::Secs, true)
+                )
+            },
+            _ => approver,
+        };
+        let approver = if changeset.approved_before_head.contains(user) {
+            format!("{approver} (before head commit)")
+        } else {
+            approver
+        };
+        match changeset.backport_approvals.get(user) {
+            Some(link) => format!(
+                "{approver} (via original PR {})",
+                pr_label(link).unwrap_or_else(|| link.clone())
+            ),
+            None => approver,
+        }
+    };
+    let approvers = |users: &[String]| users.iter().map(approver).collect::<Vec<_>>().join(", ");
+
+    let mut lines = Vec::new();
+    if !changeset.approvals.is_empty() {
+        lines.push(approvers(&changeset.approvals));
+    }
+    if !changeset.partial_approvals.is_empty() {
+        let partial: Vec<String> = changeset
+            .partial_approvals
+            .iter()
+            .map(|user| format!("{} (partial)", approver(user)))
+            .collect();
+        lines.push(partial.join(", "));
+    }
+    if !changeset.non_qualifying_approvals.is_empty() {
+        lines.push(format!(
+            "non-qualifying: {}",
+            approvers(&changeset.non_qualifying_approvals)
+        ));
+    }
+    if !changeset.changes_requested_by.is_empty() {
+        lines.push(format!(
+            "⛔ changes requested by: {}",
+            changeset.changes_requested_by.join(", ")
+        ));
+    }
+    lines
+}
+
+fn prepend_redirect_to_domain(link: &str, redirect_map: &[RedirectRule]) -> Result<String, anyhow::Error> {
+    if redirect_map.is_empty() {
+        return Ok(link.to_owned());
+    }
+
+    let mut parsed_link = Url::parse(link).with_context(|| "failed to parse link {link}")?;
+    // later rules win, so that the default for github.com can be overridden
+    if let Some(rule) = redirect_map
+        .iter()
+        .rev()
+        .find(|rule| parsed_link.host() == Some(Host::Domain(rule.host.as_str())))
+    {
+        parsed_link.set_host(Some(&rule.replacement))?;
+    }
+
+    Ok(parsed_link.to_string())
+}
+
+/// Colors of the summary printed to the terminal. Rendered reports are never colored, so that they stay valid
+/// markdown, HTML, XML or JSON.
+#[derive(Clone, Copy, Debug)]
+pub struct Colors {
+    enabled: bool,
+}
+
+impl Colors {
+    /// Colors stderr when it is a terminal, unless `NO_COLOR` is set or running in GitHub Actions.
+    pub fn detect() -> Self {
+        Self::new(io::stderr().is_terminal(), |name| env::var_os(name))
+    }
+
+    fn new(is_terminal: bool, var: impl Fn(&str) -> Option<OsString>) -> Self {
+        // an empty NO_COLOR does not disable color, see https://no-color.org
+        let no_color = var("NO_COLOR").is_some_and(|value| !value.is_empty());
+        Self {
+            enabled: is_terminal && !no_color && var("GITHUB_ACTIONS").is_none(),
+        }
+    }
+
+    pub fn success(self, text: &str) -> String {
+        self.paint(text, AnsiColor::Green)
+    }
+
+    pub fn failure(self, text: &str) -> String {
+        self.paint(text, AnsiColor::Red)
+    }
+
+    fn paint(self, text: &str, color: AnsiColor) -> String {
+        if !self.enabled {
+            return text.to_owned();
+        }
+        let style = Style::new().fg_color(Some(color.into())).bold();
+        format!("{style}{text}{style:#}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::api_clients::MockClient;
+    use crate::github::{Commit, Review, ReviewState, Signature};
+    use crate::remote::Remote;
+
+    fn gen_repo_changeset(changes: Vec<Changeset>) -> RepoChangeset<MockClient> {
+        RepoChangeset {
+            name: "project".to_owned(),
+            remote: Remote::parse("https://github.com/example/project.git").unwrap(),
+            base_commit: "00000000000000000000000000000001".to_owned(),
+            head_commit: "00000000000000000000000000000003".to_owned(),
+            base_ref: None,
+            head_ref: None,
+            min_approvals: None,
+            required_approvers: Vec::new(),
+            compare_status: None,
+            error: None,
+            changes,
+        }
+    }
+
+    #[test]
+    fn format_approvals_changes_requested() {
+        let changeset = Changeset {
+            pr_link: Some("https://github.com/example/project/pull/1".to_owned()),
+            approvals: vec!["user1".to_owned()],
+            changes_requested_by: vec!["user2".to_owned()],
+            ..Changeset::default()
+        };
+        assert_eq!(
+            format_approvals(&changeset, &RenderOptions::default()),
+            "user1<br>⛔ changes requested by: user2"
+        );
+    }
+
+    #[test]
+    fn format_approvals_partial() {
+        let changeset = Changeset {
+            pr_link: Some("https://github.com/example/project/pull/1".to_owned()),
+            approvals: vec!["user1".to_owned()],
+            partial_approvals: vec!["user2".to_owned(), "user3".to_owned()],
+            ..Changeset::default()
+        };
+        assert_eq!(
+            format_approvals(&changeset, &RenderOptions::default()),
+            "user1<br>user2 (partial), user3 (partial)"
+        );
+    }
+
+    #[test]
+    fn format_approvals_detailed() {
+        let changeset = Changeset {
+            pr_link: Some("https://github.com/example/project/pull/1".to_owned()),
+            approvals: vec!["user1".to_owned()],
+            non_qualifying_approvals: vec!["user2".to_owned()],
+            approved_commits: BTreeMap::from([
+                ("user1".to_owned(), "1234567890abcdef".to_owned()),
+                ("user2".to_owned(), "fedcba0987654321".to_owned()),
+            ]),
+            ..Changeset::default()
+        };
+        let options = RenderOptions {
+            detailed_approvals: true,
+            ..RenderOptions::default()
+        };
+        assert_eq!(
+            format_approvals(&changeset, &RenderOptions::default()),
+            "user1<br>non-qualifying: user2"
+        );
+        assert_eq!(
+            format_approvals(&changeset, &options),
+            "user1@1234567<br>non-qualifying: user2@fedcba0"
+        );
+    }
+
+    #[test]
+    fn format_approvals_time() {
+        let mut changeset = Changeset {
+            pr_link: Some("https://github.com/example/project/pull/1".to_owned()),
+            ..Changeset::default()
+        };
+        changeset.collect_approved_reviews(
+            &[Review {
+                id: 1,
+                state: ReviewState::Approved,
+                commit_id: "1234567890abcdef".to_owned(),
+                submitted_at: 1_719_835_200_123_456,
+                user: "user1".to_owned(),
+            }],
+            &"1234567890abcdef".to_owned(),
+            None,
+        );
+        let options = RenderOptions {
+            detailed_approvals: true,
+            show_approval_time: true,
+            ..RenderOptions::default()
+        };
+        assert_eq!(
+            format_approvals(&changeset, &options),
+            "user1@1234567 at 2024-07-01T12:00:00Z"
+        );
+        assert_eq!(
+            serde_json::to_value(&changeset).unwrap()["approved_at"],
+            serde_json::json!({"user1": "2024-07-01T12:00:00.123456Z"})
+        );
+    }
+
+    #[test]
+    fn render_markdown_no_pr() {
+        let repo = gen_repo_changeset(vec![
+            Changeset {
+                commits: vec![CommitMetadata {
+                    headline: Some("Reviewed commit".to_owned()),
+                    link: "https://github.com/example/project/commit/00000000000000000000000000000002".to_owned(),
+                    sha: "00000000000000000000000000000002".to_owned(),
+                    ..CommitMetadata::default()
+                }],
+                pr_link: Some("https://github.com/example/project/pull/1".to_owned()),
+                approvals: vec!["user1".to_owned()],
+                ..Changeset::default()
+            },
+            Changeset {
+                commits: vec![CommitMetadata {
+                    headline: Some("Direct push".to_owned()),
+                    link: "https://github.com/example/project/commit/00000000000000000000000000000003".to_owned(),
+                    sha: "00000000000000000000000000000003".to_owned(),
+                    ..CommitMetadata::default()
+                }],
+                ..Changeset::default()
+            },
+        ]);
+
+        let output = render_markdown(&[repo], &RenderOptions::default()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(
+            lines[3],
+            "| [Reviewed commit](https://redirect.github.com/example/project/commit/00000000000000000000000000000002) \
+             | [project #1](https://redirect.github.com/example/project/pull/1) | user1 | <enter your decision> |"
+        );
+        assert_eq!(
+            lines[4],
+            "| [Direct push](https://redirect.github.com/example/project/commit/00000000000000000000000000000003) |  \
+             |  | ⚠ no PR |"
+        );
+        assert_eq!(
+            lines.last().unwrap(),
+            &"Summary: 2 changes in 1 repositories, 1 without a pull request"
+        );
+    }
+
+    #[test]
+    fn render_markdown_compare_status() {
+        let mut repo = gen_repo_changeset(Vec::new());
+        repo.compare_status = Some(CompareStatus::Behind);
+
+        let output = render_markdown(&[repo], &RenderOptions::default()).unwrap();
+        assert_eq!(
+            output.lines().next().unwrap(),
+            "Name project from https://github.com/example/project.git moved from 00000000000000000000000000000001 to \
+             00000000000000000000000000000003 (head is behind base)"
+        );
+    }
+
+    #[test]
+    fn render_markdown_error() {
+        let mut repo = gen_repo_changeset(Vec::new());
+        repo.error = Some("not found | 404".to_owned());
+
+        let output = render_markdown(&[repo], &RenderOptions::default()).unwrap();
+        assert_eq!(output.lines().skip(1).collect::<Vec<_>>(), vec![
+            "| Commit link | Pull Request link | Approvals | Reviewer's verdict |",
+            "|-------------|-------------------|-----------|--------------------|",
+            "| not found \\| 404 |  |  | ⚠ could not analyze |",
+            "",
+            "Summary: 0 changes in 1 repositories, 0 without a pull request, 1 could not be analyzed",
+        ]);
+    }
+
+    #[test]
+    fn render_metrics() {
+        let changeset = |sha: &str, pr_link: Option<&str>, approvals: &[&str]| Changeset {
+            commits: vec![CommitMetadata {
+                headline: Some(format!("Commit {sha}")),
+                link: format!("https://github.com/example/project/commit/{sha}"),
+                sha: sha.to_owned(),
+                ..CommitMetadata::default()
+            }],
+            pr_link: pr_link.map(ToOwned::to_owned),
+            approvals: approvals.iter().map(|approval| (*approval).to_owned()).collect(),
+            ..Changeset::default()
+        };
+        let approved = gen_repo_changeset(vec![
+            changeset("2", Some("https://github.com/example/project/pull/1"), &["user1"]),
+            changeset("3", Some("https://github.com/example/project/pull/2"), &[]),
+            changeset("4", None, &[]),
+        ]);
+        let mut failed = gen_repo_changeset(Vec::new());
+        failed.name = "say \"hi\"\\n".to_owned();
+        failed.remote = Remote::parse("https://github.com/example/other.git").unwrap();
+        failed.error = Some("not found".to_owned());
+
+        assert_eq!(
+            super::render_metrics(&[approved, failed], 1).unwrap(),
+            r#"# HELP pear_changes_total Changes found between base and head.
+# TYPE pear_changes_total gauge
+pear_changes_total{repo="project",remote="https://github.com/example/project.git"} 3
+pear_changes_total{repo="say \"hi\"\\n",remote="https://github.com/example/other.git"} 0
+# HELP pear_changes_unapproved Changes with fewer approvals than required.
+# TYPE pear_changes_unapproved gauge
+pear_changes_unapproved{repo="project",remote="https://github.com/example/project.git"} 2
+pear_changes_unapproved{repo="say \"hi\"\\n",remote="https://github.com/example/other.git"} 0
+# HELP pear_commits_without_pr Commits which were not merged through a PR.
+# TYPE pear_commits_without_pr gauge
+pear_commits_without_pr{repo="project",remote="https://github.com/example/project.git"} 1
+pear_commits_without_pr{repo="say \"hi\"\\n",remote="https://github.com/example/other.git"} 0
+# HELP pear_analysis_failed 1 if the repo could not be analyzed.
+# TYPE pear_analysis_failed gauge
+pear_analysis_failed{repo="project",remote="https://github.com/example/project.git"} 0
+pear_analysis_failed{repo="say \"hi\"\\n",remote="https://github.com/example/other.git"} 1
+"#
+        );
+    }
+
+    #[test]
+    fn render_metadata() {
+        let changeset = |sha: &str, pr_link: Option<&str>| Changeset {
+            commits: vec![CommitMetadata {
+                headline: Some(format!("Commit {sha}")),
+                link: format!("https://github.com/example/project/commit/{sha}"),
+                sha: sha.to_owned(),
+                ..CommitMetadata::default()
+            }],
+            pr_link: pr_link.map(ToOwned::to_owned),
+            ..Changeset::default()
+        };
+        let mut repo = gen_repo_changeset(vec![
+            changeset(
+                "00000000000000000000000000000002",
+                Some("https://github.com/example/project/pull/1"),
+            ),
+            changeset("00000000000000000000000000000003", None),
+        ]);
+        repo.head_ref = Some("v2".to_owned());
+        let generated_at = DateTime::parse_from_rfc3339("2024-07-01T12:00:00Z").unwrap().into();
+
+        let output = super::render_metadata(
+            &[repo],
+            &[FailPolicy::NoPr, FailPolicy::BelowThreshold],
+            1,
+            generated_at,
+        )
+        .unwrap();
+        assert_eq!(
+            output,
+            format!(
+                r#"{{
+  "tool_version": "{}",
+  "generated_at": "2024-07-01T12:00:00Z",
+  "repos": [
+    {{
+      "name": "project",
+      "remote": "https://github.com/example/project.git",
+      "base_commit": "00000000000000000000000000000001",
+      "head_commit": "00000000000000000000000000000003",
+      "head_ref": "v2",
+      "commits": 2,
+      "changes": 2,
+      "violations": 3
+    }}
+  ]
+}}
+"#,
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+    }
+
+    #[test]
+    fn render_markdown_escapes_headline() {
+        let repo = gen_repo_changeset(vec![Changeset {
+            commits: vec![CommitMetadata {
+                headline: Some(r"fix: handle a|b parsing in C:\path".to_owned()),
+                link: "https://github.com/example/project/commit/00000000000000000000000000000002".to_owned(),
+                sha: "00000000000000000000000000000002".to_owned(),
+                ..CommitMetadata::default()
+            }],
+            pr_link: Some("https://github.com/example/project/pull/1".to_owned()),
+            approvals: vec!["user1".to_owned()],
+            ..Changeset::default()
+        }]);
+
+        let output = render_markdown(&[repo], &RenderOptions::default()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(
+            lines[3],
+            "| [fix: handle a\\|b parsing in C:\\\\path](https://redirect.github.com/example/project/commit/\
+             00000000000000000000000000000002) | [project #1](https://redirect.github.com/example/project/pull/1) | user1 | <enter your decision> |"
+        );
+    }
+
+    #[test]
+    fn render_markdown_empty_headline() {
+        let commit = Commit {
+            message: String::new(),
+            ..Commit::test("4e1a3b7c9d2f6e8a0b5c7d9e1f3a5b7c9d0e2f4a")
+        };
+        let repo = gen_repo_changeset(vec![Changeset {
+            commits: vec![
+                CommitMetadata::new(&commit),
+                CommitMetadata::new(&Commit {
+                    message: "<empty commit message>".to_owned(),
+                    ..commit
+                }),
+            ],
+            pr_link: Some("https://github.com/example/project/pull/1".to_owned()),
+            approvals: vec!["user1".to_owned()],
+            ..Changeset::default()
+        }]);
+
+        let output = render_markdown(&[repo], &RenderOptions::default()).unwrap();
+        // a message that merely looks like a placeholder is shown as it is
+        assert_eq!(
+            output.lines().nth(3).unwrap(),
+            "| [4e1a3b7](https://redirect.github.com/example/project/commit/4e1a3b7c9d2f6e8a0b5c7d9e1f3a5b7c9d0e2f4a) \
+             ,<br>[<empty commit message>](https://redirect.github.com/example/project/commit/\
+             4e1a3b7c9d2f6e8a0b5c7d9e1f3a5b7c9d0e2f4a) | \
+             [project #1](https://redirect.github.com/example/project/pull/1) | user1 | <enter your decision> |"
+        );
+    }
+
+    #[test]
+    fn render_html_escapes_headline() {
+        let repo = gen_repo_changeset(vec![Changeset {
+            commits: vec![CommitMetadata {
+                headline: Some("<script>alert(1)</script>".to_owned()),
+                link: "https://github.com/example/project/commit/00000000000000000000000000000002".to_owned(),
+                sha: "00000000000000000000000000000002".to_owned(),
+                ..CommitMetadata::default()
+            }],
+            pr_link: Some("https://github.com/example/project/pull/1".to_owned()),
+            approvals: vec!["user1".to_owned()],
+            ..Changeset::default()
+        }]);
+
+        let output = render_html(&[repo], &RenderOptions::default()).unwrap();
+        assert!(!output.contains("<script>"));
+        assert!(output.contains(
+            "<tr><td><a href=\"https://redirect.github.com/example/project/commit/00000000000000000000000000000002\">\
+             &lt;script&gt;alert(1)&lt;/script&gt;</a></td>\
+             <td><a href=\"https://redirect.github.com/example/project/pull/1\">project #1</a></td>\
+             <td>user1</td><td>&lt;enter your decision&gt;</td></tr>"
+        ));
+        assert!(output.contains(
+            "<h2>Name project from https://github.com/example/project.git moved from \
+             00000000000000000000000000000001 to 00000000000000000000000000000003</h2>"
+        ));
+        assert!(output.contains("<p>Summary: 1 changes in 1 repositories, 0 without a pull request</p>"));
+    }
+
+    #[test]
+    fn render_junit_unapproved() {
+        let repo = gen_repo_changeset(vec![
+            Changeset {
+                commits: vec![CommitMetadata {
+                    headline: Some("Reviewed commit".to_owned()),
+                    link: "https://github.com/example/project/commit/00000000000000000000000000000002".to_owned(),
+                    sha: "00000000000000000000000000000002".to_owned(),
+                    ..CommitMetadata::default()
+                }],
+                pr_link: Some("https://github.com/example/project/pull/1".to_owned()),
+                approvals: vec!["user1".to_owned()],
+                ..Changeset::default()
+            },
+            Changeset {
+                commits: vec![CommitMetadata {
+                    headline: Some("Unreviewed commit".to_owned()),
+                    link: "https://github.com/example/project/commit/00000000000000000000000000000003".to_owned(),
+                    sha: "00000000000000000000000000000003".to_owned(),
+                    ..CommitMetadata::default()
+                }],
+                pr_link: Some("https://github.com/example/project/pull/2".to_owned()),
+                non_qualifying_approvals: vec!["user2".to_owned()],
+                ..Changeset::default()
+            },
+        ]);
+
+        let output = render_junit(&[repo], &RenderOptions::default()).unwrap();
+        assert_eq!(
+            output,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites name="pear-reviewer" tests="2" failures="1">
+  <testsuite name="project" tests="2" failures="1">
+    <testcase classname="project" name="Reviewed commit (project #1)"/>
+    <testcase classname="project" name="Unreviewed commit (project #2)">
+      <failure message="0 of 1 required approvals">missing 1 approval
+non-qualifying: user2</failure>
+    </testcase>
+  </testsuite>
+</testsuites>
+"#
+        );
+    }
+
+    #[test]
+    fn pr_label() {
+        assert_eq!(
+            super::pr_label("https://github.com/sapcc/tenso/pull/187").as_deref(),
+            Some("tenso #187")
+        );
+        assert_eq!(
+            super::pr_label("https://gitea.example.com/sapcc/tenso/pulls/187").as_deref(),
+            Some("tenso #187")
+        );
+        assert_eq!(super::pr_label("https://github.com/sapcc/tenso/issues/187"), None);
+    }
+
+    #[test]
+    fn prepend_redirect_to_domain_default() {
+        let redirect_map = RenderOptions::default().redirect_map;
+        assert_eq!(
+            prepend_redirect_to_domain("https://github.com/sapcc/tenso/pull/187", &redirect_map).unwrap(),
+            "https://redirect.github.com/sapcc/tenso/pull/187"
+        );
+        assert_eq!(
+            prepend_redirect_to_domain("https://github.example.com/sapcc/tenso/pull/187", &redirect_map).unwrap(),
+            "https://github.example.com/sapcc/tenso/pull/187"
+        );
+    }
+
+    #[test]
+    fn prepend_redirect_to_domain_enterprise() {
+        let mut redirect_map = RenderOptions::default().redirect_map;
+        redirect_map.push("github.example.com=redirect.github.example.com".parse().unwrap());
+        assert_eq!(
+            prepend_redirect_to_domain("https://github.example.com/sapcc/tenso/pull/187", &redirect_map).unwrap(),
+            "https://redirect.github.example.com/sapcc/tenso/pull/187"
+        );
+        assert_eq!(
+            prepend_redirect_to_domain("https://github.com/sapcc/tenso/pull/187", &redirect_map).unwrap(),
+            "https://redirect.github.com/sapcc/tenso/pull/187"
+        );
+
+        assert!("github.example.com".parse::<RedirectRule>().is_err());
+        assert!("=redirect.github.example.com".parse::<RedirectRule>().is_err());
+    }
+
+    #[test]
+    fn render_markdown_no_redirect() {
+        let repo = gen_repo_changeset(vec![Changeset {
+            commits: vec![CommitMetadata {
+                headline: Some("Reviewed commit".to_owned()),
+                link: "https://github.com/example/project/commit/00000000000000000000000000000002".to_owned(),
+                sha: "00000000000000000000000000000002".to_owned(),
+                ..CommitMetadata::default()
+            }],
+            pr_link: Some("https://github.com/example/project/pull/1".to_owned()),
+            approvals: vec!["user1".to_owned()],
+            ..Changeset::default()
+        }]);
+
+        let options = RenderOptions {
+            redirect_map: Vec::new(),
+            ..RenderOptions::default()
+        };
+        let output = render_markdown(&[repo], &options).unwrap();
+        assert_eq!(
+            output.lines().nth(3).unwrap(),
+            "| [Reviewed commit](https://github.com/example/project/commit/00000000000000000000000000000002) \
+             | [project #1](https://github.com/example/project/pull/1) | user1 | <enter your decision> |"
+        );
+    }
+
+    #[test]
+    fn render_markdown_show_authors() {
+        let repo = gen_repo_changeset(vec![Changeset {
+            commits: vec![
+                CommitMetadata {
+                    headline: Some("Reviewed commit".to_owned()),
+                    link: "https://github.com/example/project/commit/00000000000000000000000000000002".to_owned(),
+                    sha: "00000000000000000000000000000002".to_owned(),
+                    author: Some("user1".to_owned()),
+                    authored_at: DateTime::from_timestamp(1_727_784_000, 0),
+                    ..CommitMetadata::default()
+                },
+                CommitMetadata {
+                    headline: Some("Imported commit".to_owned()),
+                    link: "https://github.com/example/project/commit/00000000000000000000000000000003".to_owned(),
+                    sha: "00000000000000000000000000000003".to_owned(),
+                    ..CommitMetadata::default()
+                },
+            ],
+            pr_link: Some("https://github.com/example/project/pull/1".to_owned()),
+            approvals: vec!["user2".to_owned()],
+            ..Changeset::default()
+        }]);
+
+        let options = RenderOptions {
+            show_authors: true,
+            ..RenderOptions::default()
+        };
+        let output = render_markdown(&[repo], &options).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(
+            lines[1],
+            "| Commit link | Author | Authored at | Pull Request link | Approvals | Reviewer's verdict |"
+        );
+        assert_eq!(
+            lines[3],
+            "| [Reviewed commit](https://redirect.github.com/example/project/commit/00000000000000000000000000000002) \
+             ,<br>[Imported commit](https://redirect.github.com/example/project/commit/00000000000000000000000000000003) \
+             | user1 ,<br>unknown | 2024-10-01T12:00:00Z ,<br>unknown \
+             | [project #1](https://redirect.github.com/example/project/pull/1) | user2 | <enter your decision> |"
+        );
+    }
+
+    #[test]
+    fn verdict_auto() {
+        let mut changeset = Changeset {
+            pr_link: Some("https://github.com/example/project/pull/1".to_owned()),
+            approvals: vec!["user1".to_owned()],
+            ..Changeset::default()
+        };
+        let mut repo = gen_repo_changeset(Vec::new());
+        let mut options = RenderOptions::default();
+        assert_eq!(verdict(&changeset, &repo, &options), "<enter your decision>");
+
+        options.auto_verdict = true;
+        assert_eq!(verdict(&changeset, &repo, &options), "OK");
+        options.min_approvals = 2;
+        assert_eq!(verdict(&changeset, &repo, &options), "NEEDS REVIEW");
+
+        changeset.approvals.push("user2".to_owned());
+        assert_eq!(verdict(&changeset, &repo, &options), "OK");
+
+        // the same as --fail-on below-threshold
+        repo.required_approvers = vec!["lead".to_owned()];
+        assert_eq!(verdict(&changeset, &repo, &options), "NEEDS REVIEW");
+        repo.required_approvers.clear();
+        repo.min_approvals = Some(3);
+        assert_eq!(verdict(&changeset, &repo, &options), "NEEDS REVIEW");
+        repo.min_approvals = Some(1);
+        assert_eq!(verdict(&changeset, &repo, &options), "OK");
+
+        changeset.changes_requested_by.push("user3".to_owned());
+        assert_eq!(verdict(&changeset, &repo, &options), "NEEDS REVIEW");
+
+        changeset.pr_link = None;
+        assert_eq!(verdict(&changeset, &repo, &options), "⚠ no PR");
+    }
+
+    #[test]
+    fn render_markdown_verdict_placeholder() {
+        let repo_changesets = vec![gen_repo_changeset(vec![Changeset {
+            commits: vec![CommitMetadata {
+                headline: Some("Testing test".to_owned()),
+                link: "https://github.com/example/project/commit/00000000000000000000000000000002".to_owned(),
+                sha: "00000000000000000000000000000002".to_owned(),
+                ..CommitMetadata::default()
+            }],
+            pr_link: Some("https://github.com/example/project/pull/1".to_owned()),
+            approvals: vec!["user1".to_owned()],
+            ..Changeset::default()
+        }])];
+
+        let options = RenderOptions {
+            verdict_placeholder: "TODO | *decide*".to_owned(),
+            redirect_map: Vec::new(),
+            ..RenderOptions::default()
+        };
+        let output = render_markdown(&repo_changesets, &options).unwrap();
+        assert!(output.contains("| user1 | TODO \\| \\*decide\\* |\n"), "{output}");
+
+        let options = RenderOptions {
+            verdict_placeholder: String::new(),
+            redirect_map: Vec::new(),
+            ..RenderOptions::default()
+        };
+        let output = render_markdown(&repo_changesets, &options).unwrap();
+        assert!(output.contains("| user1 |  |\n"), "{output}");
+    }
+
+    #[test]
+    fn render_pr_without_commits() {
+        let repo_changesets = vec![gen_repo_changeset(vec![Changeset {
+            commits: vec![CommitMetadata {
+                headline: Some("Commit 2".to_owned()),
+                link: "https://github.com/example/project/commit/00000000000000000000000000000002".to_owned(),
+                sha: "00000000000000000000000000000002".to_owned(),
+                ..CommitMetadata::default()
+            }],
+            pr_link: Some("https://github.com/example/project/pull/1".to_owned()),
+            pr_without_commits: true,
+            ..Changeset::default()
+        }])];
+        let options = RenderOptions {
+            redirect_map: Vec::new(),
+            ..RenderOptions::default()
+        };
+        let output = render_markdown(&repo_changesets, &options).unwrap();
+        assert!(
+            output.contains("| [project #1](https://github.com/example/project/pull/1) (no commits) |"),
+            "{output}"
+        );
+        assert_eq!(super::render_template(&repo_changesets, &options).unwrap(), output);
+
+        let output = render_html(&repo_changesets, &options).unwrap();
+        assert!(
+            output.contains(
+                r#"<td><a href="https://github.com/example/project/pull/1">project #1</a> (no commits)</td>"#
+            ),
+            "{output}"
+        );
+    }
+
+    #[test]
+    fn render_markdown_decisions() {
+        let changeset = |sha: &str, pr: Option<u64>| Changeset {
+            commits: vec![CommitMetadata {
+                headline: Some(format!("Commit {sha}")),
+                link: format!("https://github.com/example/project/commit/{sha}"),
+                sha: sha.to_owned(),
+                ..CommitMetadata::default()
+            }],
+            pr_link: pr.map(|number| format!("https://github.com/example/project/pull/{number}")),
+            ..Changeset::default()
+        };
+        let repo_changesets = vec![gen_repo_changeset(vec![
+            changeset("1111111111111111111111111111111111111111", Some(1)),
+            changeset("2222222222222222222222222222222222222222", Some(2)),
+            changeset("3333333333333333333333333333333333333333", Some(3)),
+            changeset("4444444444444444444444444444444444444444", None),
+            changeset("5555555555555555555555555555555555555555", Some(5)),
+            changeset("6666666666666666666666666666666666666666", Some(6)),
+        ])];
+
+        // a bare PR number is ambiguous when multiple repos are analyzed
+        let decisions: Decisions = serde_json::from_str(
+            r#"{
+                "1111111": "allowed by policy",
+                "example/project#2": "exempt",
+                "3333333333333333333333333333333333333333": "denied",
+                "https://github.com/example/project/pull/5": "waived",
+                "6": "ignored",
+                "other/project#6": "ignored"
+            }"#,
+        )
+        .unwrap();
+        let options = RenderOptions {
+            redirect_map: Vec::new(),
+            decisions,
+            ..RenderOptions::default()
+        };
+        let output = render_markdown(&repo_changesets, &options).unwrap();
+        let verdicts: Vec<&str> = output
+            .lines()
+            .filter(|line| line.starts_with("| [Commit"))
+            .map(|line| line.trim_end_matches(" |").rsplit(" | ").next().unwrap())
+            .collect();
+        assert_eq!(verdicts, vec![
+            "allowed by policy",
+            "exempt",
+            "denied",
+            "⚠ no PR",
+            "waived",
+            VERDICT_PLACEHOLDER
+        ]);
+    }
+
+    #[test]
+    fn render_markdown_show_signatures() {
+        let commit = |headline: &str, signature: Option<(bool, &str)>| CommitMetadata {
+            headline: Some(headline.to_owned()),
+            link: "https://github.com/example/project/commit/00000000000000000000000000000002".to_owned(),
+            sha: "00000000000000000000000000000002".to_owned(),
+            signature: signature.map(|(verified, reason)| Signature {
+                verified,
+                reason: reason.to_owned(),
+            }),
+            ..CommitMetadata::default()
+        };
+        let repo = gen_repo_changeset(vec![Changeset {
+            commits: vec![
+                commit("Signed", Some((true, "valid"))),
+                commit("Unsigned", Some((false, "unsigned"))),
+                commit("Unknown", None),
+            ],
+            ..Changeset::default()
+        }]);
+
+        let options = RenderOptions {
+            show_signatures: true,
+            redirect_map: Vec::new(),
+            ..RenderOptions::default()
+        };
+        let output = render_markdown(&[repo], &options).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(
+            lines[1],
+            "| Commit link | Signature | Pull Request link | Approvals | Reviewer's verdict |"
+        );
+        assert_eq!(
+            lines[2],
+            "|-------------|-----------|-------------------|-----------|--------------------|"
+        );
+        assert!(
+            lines[3].contains("| verified ,<br>✘ unsigned ,<br>unknown |"),
+            "{}",
+            lines[3]
+        );
+    }
+
+    #[test]
+    fn colors() {
+        let var = |vars: &'static [(&str, &str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| OsString::from(value))
+            }
+        };
+        assert_eq!(
+            Colors::new(true, var(&[])).success("ok"),
+            "\u{1b}[1m\u{1b}[32mok\u{1b}[0m"
+        );
+        assert_eq!(
+            Colors::new(true, var(&[("NO_COLOR", "")])).failure("failed"),
+            "\u{1b}[1m\u{1b}[31mfailed\u{1b}[0m"
+        );
+
+        // suppressed when not on a terminal, with NO_COLOR and in GitHub Actions
+        assert_eq!(Colors::new(false, var(&[])).success("ok"), "ok");
+        assert_eq!(Colors::new(true, var(&[("NO_COLOR", "1")])).success("ok"), "ok");
+        assert_eq!(
+            Colors::new(true, var(&[("GITHUB_ACTIONS", "true")])).failure("failed"),
+            "failed"
+        );
+    }
+
+    #[test]
+    fn render_template() {
+        let mut repo_changesets = vec![gen_repo_changeset(vec![
+            Changeset {
+                commits: vec![CommitMetadata {
+                    headline: Some("Reviewed | commit".to_owned()),
+                    link: "https://github.com/example/project/commit/00000000000000000000000000000002".to_owned(),
+                    sha: "00000000000000000000000000000002".to_owned(),
+                    ..CommitMetadata::default()
+                }],
+                pr_link: Some("https://github.com/example/project/pull/1".to_owned()),
+                approvals: vec!["user1".to_owned()],
+                ..Changeset::default()
+            },
+            Changeset {
+                commits: vec![CommitMetadata {
+                    headline: Some("Direct push".to_owned()),
+                    link: "https://github.com/example/project/commit/00000000000000000000000000000003".to_owned(),
+                    sha: "00000000000000000000000000000003".to_owned(),
+                    ..CommitMetadata::default()
+                }],
+                ..Changeset::default()
+            },
+            Changeset {
+                commits: vec![
+                    CommitMetadata {
+                        headline: Some("Add feature".to_owned()),
+                        link: "https://github.com/example/project/commit/00000000000000000000000000000004".to_owned(),
+                        sha: "00000000000000000000000000000004".to_owned(),
+                        self_cancelling: true,
+                        ..CommitMetadata::default()
+                    },
+                    CommitMetadata {
+                        headline: Some("Revert \"Add feature\"".to_owned()),
+                        link: "https://github.com/example/project/commit/00000000000000000000000000000005".to_owned(),
+                        sha: "00000000000000000000000000000005".to_owned(),
+                        self_cancelling: true,
+                        ..CommitMetadata::default()
+                    },
+                ],
+                pr_link: Some("https://github.com/example/project/pull/2".to_owned()),
+                ..Changeset::default()
+            },
+            Changeset {
+                commits: vec![CommitMetadata {
+                    headline: Some("Reset branch".to_owned()),
+                    link: "https://github.com/example/project/commit/00000000000000000000000000000006".to_owned(),
+                    sha: "00000000000000000000000000000006".to_owned(),
+                    ..CommitMetadata::default()
+                }],
+                pr_link: Some("https://github.com/example/project/pull/3".to_owned()),
+                pr_without_commits: true,
+                ..Changeset::default()
+            },
+        ])];
+
+        // the built-in template renders the markdown table, with the same escaping
+        repo_changesets.push(RepoChangeset {
+            error: Some("failed | badly".to_owned()),
+            ..gen_repo_changeset(Vec::new())
+        });
+        let options = RenderOptions::default();
+        assert_eq!(
+            render(OutputFormat::Template, &repo_changesets, &options).unwrap(),
+            render_markdown(&repo_changesets, &options).unwrap()
+        );
+        let options = RenderOptions {
+            decisions: serde_json::from_str(r#"{"example/project#3": "exempt | see *TICKET-1*"}"#).unwrap(),
+            ..RenderOptions::default()
+        };
+        let output = render_markdown(&repo_changesets, &options).unwrap();
+        assert!(output.contains("~~[Add feature]"), "{output}");
+        assert!(
+            output.contains(r"(no commits) |  | exempt \| see \*TICKET-1\* |"),
+            "{output}"
+        );
+        assert_eq!(
+            render(OutputFormat::Template, &repo_changesets, &options).unwrap(),
+            output
+        );
+
+        let options = RenderOptions {
+            template: Some(
+                Template::parse(
+                    "{{#each repos}}{{#each changes}}{{../name}};{{pr_label}};{{verdict}}\n{{/each}}{{/each}}",
+                )
+                .unwrap(),
+            ),
+            ..RenderOptions::default()
+        };
+        assert_eq!(
+            render(OutputFormat::Template, &repo_changesets, &options).unwrap(),
+            "project;project #1;<enter your decision>\nproject;;⚠ no PR\nproject;project #2;self-cancelling\n\
+             project;project #3;<enter your decision>\n"
+        );
+    }
+}
@@ -0,0 +1,424 @@
+// Copyright 2024 SAP SE
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::Write;
+
+use clap::ValueEnum;
+
+use crate::api_clients::Client;
+use crate::changes::{Changeset, CommitMetadata, RepoChangeset};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum FailPolicy {
+    /// a change has fewer than --min-approvals approvals, or than its branch protection requires with
+    /// --use-branch-protection, or lacks the approval of a requiredApprovers user of its image
+    BelowThreshold,
+    /// a change was not merged through a PR, e.g. after a direct push
+    NoPr,
+    /// a reviewer requested changes on the head of the PR
+    ChangesRequested,
+    /// an author or co-author of a change approved it
+    SelfApproved,
+    /// a commit of a change is unsigned or its signature could not be verified
+    Unsigned,
+    /// a change lacks two distinct approvers who neither authored nor co-authored any of its commits, as required
+    /// for the PCI double approval
+    NotIndependent,
+    /// an approval was submitted before the head commit it approved was authored
+    ApprovedBeforeHead,
+}
+
+const MIN_INDEPENDENT_APPROVERS: usize = 2;
+
+impl FailPolicy {
+    fn description(self) -> &'static str {
+        match self {
+            FailPolicy::BelowThreshold => "too few approvals",
+            FailPolicy::NoPr => "no PR",
+            FailPolicy::ChangesRequested => "changes requested",
+            FailPolicy::SelfApproved => "approved by an author",
+            FailPolicy::Unsigned => "unsigned commits",
+            FailPolicy::NotIndependent => "fewer than two independent approvers",
+            FailPolicy::ApprovedBeforeHead => "approved before the head commit",
+        }
+    }
+
+    fn is_violated_by(self, changeset: &Changeset, min_approvals: usize, required_approvers: &[String]) -> bool {
+        match self {
+            // a change that is reverted within the range needs no review, but it still needs to be signed and so on
+            FailPolicy::BelowThreshold => {
+                !changeset.is_self_cancelling()
+                    && (changeset.approvals.len() < min_approvals
+                        || required_approvers
+                            .iter()
+                            .any(|approver| !changeset.approvals.contains(approver)))
+            },
+            FailPolicy::NoPr => changeset.pr_link.is_none(),
+            FailPolicy::ChangesRequested => !changeset.changes_requested_by.is_empty(),
+            FailPolicy::SelfApproved => changeset
+                .commits
+                .iter()
+                .flat_map(|commit| commit.author.iter().chain(&commit.co_authors))
+                .any(|author| {
+                    changeset.approvals.contains(author) || changeset.non_qualifying_approvals.contains(author)
+                }),
+            FailPolicy::Unsigned => changeset
+                .commits
+                .iter()
+                .any(|commit| !commit.signature.as_ref().is_some_and(|signature| signature.verified)),
+            FailPolicy::NotIndependent => independent_approvers(changeset).len() < MIN_INDEPENDENT_APPROVERS,
+            FailPolicy::ApprovedBeforeHead => !changeset.approved_before_head.is_empty(),
+        }
+    }
+}
+
+// Approvals are usually cleaned of authors already, but not in changesets read back from JSON.
+fn independent_approvers(changeset: &Changeset) -> Vec<&String> {
+    let mut approvers: Vec<&String> = changeset
+        .approvals
+        .iter()
+        .filter(|approver| {
+            !changeset
+                .commits
+                .iter()
+                .any(|commit| commit.author.as_ref() == Some(*approver) || commit.co_authors.contains(approver))
+        })
+        .collect();
+    approvers.sort();
+    approvers.dedup();
+    approvers
+}
+
+/// The approvals a change of the repo needs. The requiredApprovals of an image or the branch protection of a repo
+/// can only raise --min-approvals, never lower it.
+pub fn min_approvals_for<C: Client>(repo: &RepoChangeset<C>, min_approvals: usize) -> usize {
+    repo.min_approvals.map_or(min_approvals, |repo_min_approvals| {
+        repo_min_approvals.max(min_approvals)
+    })
+}
+
+/// Whether the change lacks approvals like --fail-on below-threshold decides it, so that the rendered verdicts agree
+/// with the exit code.
+pub fn is_below_threshold<C: Client>(repo: &RepoChangeset<C>, changeset: &Changeset, min_approvals: usize) -> bool {
+    FailPolicy::BelowThreshold.is_violated_by(
+        changeset,
+        min_approvals_for(repo, min_approvals),
+        &repo.required_approvers,
+    )
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Violation {
+    pub policy: FailPolicy,
+    /// the repo name followed by the PR link, or the commit headlines for changes without a PR
+    pub change: String,
+}
+
+pub fn violations<C: Client>(
+    repo_changesets: &[RepoChangeset<C>],
+    policies: &[FailPolicy],
+    min_approvals: usize,
+) -> Vec<Violation> {
+    let mut policies = policies.to_vec();
+    policies.sort();
+    policies.dedup();
+
+    let mut violations = Vec::new();
+    for policy in policies {
+        for repo in repo_changesets {
+            for changeset in &repo.changes {
+                if policy.is_violated_by(
+                    changeset,
+                    min_approvals_for(repo, min_approvals),
+                    &repo.required_approvers,
+                ) {
+                    violations.push(Violation {
+                        policy,
+                        change: describe_change(&repo.name, changeset),
+                    });
+                }
+            }
+        }
+    }
+    violations
+}
+
+/// Drops the changes which violate none of the policies and then the repos without changes, for --quiet.
+/// Returns the number of dropped changes.
+pub fn retain_violating<C: Client>(
+    repo_changesets: &mut Vec<RepoChangeset<C>>,
+    policies: &[FailPolicy],
+    min_approvals: usize,
+) -> usize {
+    let mut omitted = 0;
+    for repo in repo_changesets.iter_mut() {
+        let min_approvals = min_approvals_for(repo, min_approvals);
+        let before = repo.changes.len();
+        repo.changes.retain(|changeset| {
+            policies
+                .iter()
+                .any(|policy| policy.is_violated_by(changeset, min_approvals, &repo.required_approvers))
+        });
+        omitted += before - repo.changes.len();
+    }
+    repo_changesets.retain(|repo| !repo.changes.is_empty());
+    omitted
+}
+
+fn describe_change(repo_name: &str, changeset: &Changeset) -> String {
+    if let Some(pr_link) = &changeset.pr_link {
+        return format!("{repo_name}: {pr_link}");
+    }
+    let headlines: Vec<&str> = changeset.commits.iter().map(CommitMetadata::title).collect();
+    format!("{repo_name}: {}", headlines.join("; "))
+}
+
+// Groups the violations by policy, the violations are expected to be sorted by policy like violations() returns them.
+pub fn summary(violations: &[Violation]) -> String {
+    let mut summary = String::new();
+    let mut current_policy = None;
+    for violation in violations {
+        if current_policy != Some(violation.policy) {
+            current_policy = Some(violation.policy);
+            let _ = writeln!(summary, "{}:", violation.policy.description());
+        }
+        let _ = writeln!(summary, "  {}", violation.change);
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_clients::MockClient;
+    use crate::output::{render_markdown, RenderOptions};
+    use crate::repo_changesets;
+
+    fn changeset(headline: &str, pr_link: Option<&str>, approvals: &[&str]) -> Changeset {
+        Changeset {
+            commits: vec![CommitMetadata {
+                headline: Some(headline.to_owned()),
+                link: "https://github.com/sapcc/keppel/commit/4e1a3b7c9d2f6e8a0b5c7d9e1f3a5b7c9d0e2f4a".to_owned(),
+                sha: "4e1a3b7c9d2f6e8a0b5c7d9e1f3a5b7c9d0e2f4a".to_owned(),
+                author: Some("author".to_owned()),
+                ..CommitMetadata::default()
+            }],
+            pr_link: pr_link.map(str::to_owned),
+            approvals: approvals.iter().map(|approval| (*approval).to_owned()).collect(),
+            ..Changeset::default()
+        }
+    }
+
+    fn repos(changes: Vec<Changeset>) -> Vec<RepoChangeset<MockClient>> {
+        let mut repos =
+            repo_changesets::<MockClient>(&["https://github.com/sapcc/keppel.git".to_owned()], "v1", "v2").unwrap();
+        repos[0].changes = changes;
+        repos
+    }
+
+    #[test]
+    fn violations_below_threshold() {
+        let repos = repos(vec![
+            changeset("Approved", Some("https://github.com/sapcc/keppel/pull/1"), &["user1"]),
+            changeset("Unapproved", Some("https://github.com/sapcc/keppel/pull/2"), &[]),
+        ]);
+        assert_eq!(violations(&repos, &[FailPolicy::BelowThreshold], 1), vec![Violation {
+            policy: FailPolicy::BelowThreshold,
+            change: "keppel: https://github.com/sapcc/keppel/pull/2".to_owned(),
+        }]);
+        assert_eq!(violations(&repos, &[FailPolicy::BelowThreshold], 0), Vec::new());
+    }
+
+    #[test]
+    fn violations_required_approvers() {
+        let mut repos = repos(vec![
+            changeset("Approved by both", Some("https://github.com/sapcc/keppel/pull/1"), &[
+                "user1", "lead",
+            ]),
+            changeset("Approved by user1", Some("https://github.com/sapcc/keppel/pull/2"), &[
+                "user1",
+            ]),
+        ]);
+        repos[0].required_approvers = vec!["lead".to_owned()];
+        assert_eq!(violations(&repos, &[FailPolicy::BelowThreshold], 1), vec![Violation {
+            policy: FailPolicy::BelowThreshold,
+            change: "keppel: https://github.com/sapcc/keppel/pull/2".to_owned(),
+        }]);
+
+        // requiredApprovals of images.yaml raises --min-approvals, but does not lower it
+        repos[0].required_approvers.clear();
+        repos[0].min_approvals = Some(0);
+        assert_eq!(violations(&repos, &[FailPolicy::BelowThreshold], 3).len(), 2);
+        repos[0].min_approvals = Some(2);
+        assert_eq!(violations(&repos, &[FailPolicy::BelowThreshold], 1).len(), 1);
+        assert_eq!(retain_violating(&mut repos, &[FailPolicy::BelowThreshold], 1), 1);
+    }
+
+    #[test]
+    fn violations_self_cancelling() {
+        let mut reverted = changeset("Reverted", None, &[]);
+        reverted.commits[0].self_cancelling = true;
+        let mut repos = repos(vec![reverted]);
+        let policies = [FailPolicy::BelowThreshold, FailPolicy::Unsigned];
+        // a reverted change needs no approval, but its unsigned commit still counts
+        assert_eq!(violations(&repos, &policies, 1), vec![Violation {
+            policy: FailPolicy::Unsigned,
+            change: "keppel: Reverted".to_owned(),
+        }]);
+        assert_eq!(retain_violating(&mut repos, &[FailPolicy::BelowThreshold], 1), 1);
+        assert!(repos.is_empty());
+    }
+
+    #[test]
+    fn violations_no_pr() {
+        let mut repos = repos(vec![changeset(
+            "Merged through a PR",
+            Some("https://github.com/sapcc/keppel/pull/1"),
+            &[],
+        )]);
+        assert_eq!(violations(&repos, &[FailPolicy::NoPr], 1), Vec::new());
+
+        repos[0].changes.push(changeset("Pushed directly", None, &[]));
+        assert_eq!(violations(&repos, &[FailPolicy::NoPr], 1), vec![Violation {
+            policy: FailPolicy::NoPr,
+            change: "keppel: Pushed directly".to_owned(),
+        }]);
+    }
+
+    #[test]
+    fn violations_changes_requested() {
+        let mut blocked = changeset("Blocked", Some("https://github.com/sapcc/keppel/pull/1"), &["user1"]);
+        blocked.changes_requested_by = vec!["user2".to_owned()];
+        let repos = repos(vec![
+            blocked,
+            changeset("Approved", Some("https://github.com/sapcc/keppel/pull/2"), &["user1"]),
+        ]);
+        assert_eq!(violations(&repos, &[FailPolicy::ChangesRequested], 1), vec![
+            Violation {
+                policy: FailPolicy::ChangesRequested,
+                change: "keppel: https://github.com/sapcc/keppel/pull/1".to_owned(),
+            }
+        ]);
+    }
+
+    #[test]
+    fn violations_self_approved() {
+        let mut co_authored = changeset("Co-authored", Some("https://github.com/sapcc/keppel/pull/2"), &[]);
+        co_authored.commits[0].co_authors = vec!["user2".to_owned()];
+        co_authored.non_qualifying_approvals = vec!["user2".to_owned()];
+        let repos = repos(vec![
+            changeset("Self-approved", Some("https://github.com/sapcc/keppel/pull/1"), &[
+                "author",
+            ]),
+            co_authored,
+            changeset("Approved", Some("https://github.com/sapcc/keppel/pull/3"), &["user1"]),
+        ]);
+        assert_eq!(violations(&repos, &[FailPolicy::SelfApproved], 1), vec![
+            Violation {
+                policy: FailPolicy::SelfApproved,
+                change: "keppel: https://github.com/sapcc/keppel/pull/1".to_owned(),
+            },
+            Violation {
+                policy: FailPolicy::SelfApproved,
+                change: "keppel: https://github.com/sapcc/keppel/pull/2".to_owned(),
+            },
+        ]);
+    }
+
+    #[test]
+    fn violations_not_independent() {
+        let mut co_authored = changeset("Co-authored", Some("https://github.com/sapcc/keppel/pull/3"), &[
+            "user1", "user2",
+        ]);
+        co_authored.commits[0].co_authors = vec!["user2".to_owned()];
+        let repos = repos(vec![
+            changeset("Single approver", Some("https://github.com/sapcc/keppel/pull/1"), &[
+                "user1",
+            ]),
+            changeset("Author approved", Some("https://github.com/sapcc/keppel/pull/2"), &[
+                "author", "user1",
+            ]),
+            co_authored,
+            changeset(
+                "Same approver twice",
+                Some("https://github.com/sapcc/keppel/pull/4"),
+                &["user1", "user1"],
+            ),
+            changeset("Independent", Some("https://github.com/sapcc/keppel/pull/5"), &[
+                "user1", "user2",
+            ]),
+        ]);
+
+        let changes: Vec<String> = violations(&repos, &[FailPolicy::NotIndependent], 1)
+            .into_iter()
+            .map(|violation| violation.change)
+            .collect();
+        assert_eq!(changes, vec![
+            "keppel: https://github.com/sapcc/keppel/pull/1",
+            "keppel: https://github.com/sapcc/keppel/pull/2",
+            "keppel: https://github.com/sapcc/keppel/pull/3",
+            "keppel: https://github.com/sapcc/keppel/pull/4",
+        ]);
+    }
+
+    #[test]
+    fn violations_combined() {
+        let repos = repos(vec![
+            changeset("Pushed directly", None, &[]),
+            changeset("Approved", Some("https://github.com/sapcc/keppel/pull/1"), &["user1"]),
+        ]);
+        // a change violating multiple policies is listed under each of them, duplicate policies are ignored
+        let violations = violations(
+            &repos,
+            &[
+                FailPolicy::NoPr,
+                FailPolicy::BelowThreshold,
+                FailPolicy::ChangesRequested,
+                FailPolicy::NoPr,
+            ],
+            1,
+        );
+        assert_eq!(violations, vec![
+            Violation {
+                policy: FailPolicy::BelowThreshold,
+                change: "keppel: Pushed directly".to_owned(),
+            },
+            Violation {
+                policy: FailPolicy::NoPr,
+                change: "keppel: Pushed directly".to_owned(),
+            },
+        ]);
+        assert_eq!(
+            summary(&violations),
+            "too few approvals:\n  keppel: Pushed directly\nno PR:\n  keppel: Pushed directly\n"
+        );
+    }
+
+    #[test]
+    fn retain_violating_renders_only_failing_row() {
+        let mut repos = repos(vec![
+            changeset("Approved", Some("https://github.com/sapcc/keppel/pull/1"), &["user1"]),
+            changeset("Unapproved", Some("https://github.com/sapcc/keppel/pull/2"), &[]),
+        ]);
+        assert_eq!(retain_violating(&mut repos, &[FailPolicy::BelowThreshold], 1), 1);
+
+        let rendered = render_markdown(&repos, &RenderOptions::default()).unwrap();
+        assert!(rendered.contains("[Unapproved]"));
+        assert!(!rendered.contains("[Approved]"));
+
+        // repos without violations are dropped completely
+        assert_eq!(retain_violating(&mut repos, &[FailPolicy::NoPr], 1), 1);
+        assert!(repos.is_empty());
+    }
+}
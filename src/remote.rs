@@ -12,29 +12,50 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
 use std::sync::Arc;
 
-use anyhow::{anyhow, bail, Context};
+use anyhow::{bail, Context};
+use chrono::{DateTime, Utc};
 use url::Url;
 
-use crate::api_clients::Client;
-use crate::github::{Commit, PullRequest, Review};
+use crate::api_clients::{Client, Forge};
+use crate::error::PearError;
+use crate::github::{
+    dedup_prs, BranchProtection, Commit, Comparison, FilePatch, OpenPullRequest, PullRequest, PullRequestDescription,
+    PullRequestHead, Review, Team,
+};
 
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 #[allow(dead_code)]
 pub struct Remote<C: Client> {
     pub host: url::Host,
-    pub port: u16,
+    /// explicit port of an HTTP(S) remote, unless it is the default one of its scheme
+    pub port: Option<u16>,
     pub owner: String,
     pub repository: String,
     pub original: String,
     pub client: Option<Arc<C>>,
 }
 
+// not derived, because the client is shared and does not need to be Clone itself
+impl<C: Client> Clone for Remote<C> {
+    fn clone(&self) -> Self {
+        Self {
+            host: self.host.clone(),
+            port: self.port,
+            owner: self.owner.clone(),
+            repository: self.repository.clone(),
+            original: self.original.clone(),
+            client: self.client.clone(),
+        }
+    }
+}
+
 impl<C: Client> Remote<C> {
     pub fn parse(url: &str) -> Result<Self, anyhow::Error> {
         let remote_url = Url::parse(url).context("can't parse remote")?;
-        let path_elements: Vec<&str> = remote_url.path().trim_start_matches('/').split('/').collect();
+        let path_elements: Vec<&str> = remote_url.path().trim_matches('/').split('/').collect();
 
         if path_elements.len() != 2 {
             bail!("remote URLs are expected to be in the format of https://domain.com/owner/repo.git");
@@ -42,7 +63,10 @@ impl<C: Client> Remote<C> {
 
         Ok(Self {
             host: remote_url.host().context("remote has no host")?.to_owned(),
-            port: remote_url.port_or_known_default().context("remote has no port")?,
+            // the port of other schemes like ssh does not tell where the web UI and the API are
+            port: remote_url
+                .port()
+                .filter(|_| matches!(remote_url.scheme(), "http" | "https")),
             owner: path_elements[0].to_string(),
             repository: path_elements[1].trim_end_matches(".git").to_string(),
             original: url.into(),
@@ -50,35 +74,200 @@ impl<C: Client> Remote<C> {
         })
     }
 
+    /// Whether both remotes point to the same repository, regardless of how their URLs are spelled, e.g. with or
+    /// without .git or with http instead of https.
+    pub fn is_same_repo<D: Client>(&self, other: &Remote<D>) -> bool {
+        self.host == other.host
+            && self.owner.eq_ignore_ascii_case(&other.owner)
+            && self.repository.eq_ignore_ascii_case(&other.repository)
+    }
+
+    /// Link to the PR, whose path depends on the forge of the client. Remotes without a client are taken for GitHub.
+    pub fn pr_url(&self, pr_number: u64) -> String {
+        match self.client.as_ref().map(|client| client.forge()) {
+            Some(Forge::Gitea) => self.web_url(&format!("pulls/{pr_number}")),
+            Some(Forge::GitHub) | None => self.web_url(&format!("pull/{pr_number}")),
+        }
+    }
+
+    pub fn commit_url(&self, sha: &str) -> String {
+        self.web_url(&format!("commit/{sha}"))
+    }
+
+    /// Link to the diff between the commits, or to the head commit when there is no base.
+    pub fn compare_url(&self, base_commit: &str, head_commit: &str) -> String {
+        if base_commit.is_empty() {
+            self.commit_url(head_commit)
+        } else {
+            self.web_url(&format!("compare/{base_commit}...{head_commit}"))
+        }
+    }
+
+    /// Host of the remote with its explicit port, if any. Picks the API client of the remote.
+    pub fn authority(&self) -> String {
+        match self.port {
+            Some(port) => format!("{}:{port}", self.host),
+            None => self.host.to_string(),
+        }
+    }
+
+    fn web_url(&self, path: &str) -> String {
+        format!("https://{}/{}/{}/{path}", self.authority(), self.owner, self.repository)
+    }
+
     pub async fn associated_prs(&self, sha: String) -> anyhow::Result<Vec<PullRequest>> {
         self.client
             .as_ref()
-            .ok_or_else(|| anyhow!("no client attached to remote"))?
+            .ok_or(PearError::NoClient)?
             .associated_prs(&self.owner, &self.repository, sha)
             .await
+            .map(dedup_prs)
     }
 
-    pub async fn compare(&self, base_commit: &str, head_commit: &str) -> anyhow::Result<Vec<Commit>> {
+    pub async fn associated_prs_batch(&self, shas: &[String]) -> anyhow::Result<HashMap<String, Vec<PullRequest>>> {
         self.client
             .as_ref()
-            .ok_or_else(|| anyhow!("no client attached to remote"))?
+            .ok_or(PearError::NoClient)?
+            .associated_prs_batch(&self.owner, &self.repository, shas)
+            .await
+            .map(|associated_prs| {
+                associated_prs
+                    .into_iter()
+                    .map(|(sha, prs)| (sha, dedup_prs(prs)))
+                    .collect()
+            })
+    }
+
+    pub async fn list_open_prs(&self) -> anyhow::Result<Vec<OpenPullRequest>> {
+        self.client
+            .as_ref()
+            .ok_or(PearError::NoClient)?
+            .list_open_prs(&self.owner, &self.repository)
+            .await
+    }
+
+    pub async fn compare(&self, base_commit: &str, head_commit: &str) -> anyhow::Result<Comparison> {
+        self.client
+            .as_ref()
+            .ok_or(PearError::NoClient)?
             .compare(&self.owner, &self.repository, &self.original, base_commit, head_commit)
             .await
     }
 
-    pub async fn pr_head_hash(&self, pr_number: u64) -> Result<String, anyhow::Error> {
+    pub async fn commit(&self, sha: &str) -> anyhow::Result<Commit> {
+        self.client
+            .as_ref()
+            .ok_or(PearError::NoClient)?
+            .commit(&self.owner, &self.repository, sha)
+            .await
+    }
+
+    pub async fn commit_files(&self, sha: &str) -> anyhow::Result<Vec<String>> {
+        self.client
+            .as_ref()
+            .ok_or(PearError::NoClient)?
+            .commit_files(&self.owner, &self.repository, sha)
+            .await
+    }
+
+    pub async fn commit_patches(&self, sha: &str) -> anyhow::Result<Vec<FilePatch>> {
+        self.client
+            .as_ref()
+            .ok_or(PearError::NoClient)?
+            .commit_patches(&self.owner, &self.repository, sha)
+            .await
+    }
+
+    pub async fn commits_in_range(
+        &self,
+        head: &str,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<Commit>> {
+        self.client
+            .as_ref()
+            .ok_or(PearError::NoClient)?
+            .commits_in_range(&self.owner, &self.repository, head, since, until)
+            .await
+    }
+
+    pub async fn resolve_ref(&self, reference: &str) -> anyhow::Result<String> {
+        self.client
+            .as_ref()
+            .ok_or(PearError::NoClient)?
+            .resolve_ref(&self.owner, &self.repository, reference)
+            .await
+    }
+
+    pub async fn merge_base(&self, base_commit: &str, head_commit: &str) -> anyhow::Result<String> {
+        self.client
+            .as_ref()
+            .ok_or(PearError::NoClient)?
+            .merge_base(&self.owner, &self.repository, base_commit, head_commit)
+            .await
+    }
+
+    pub async fn pr_commits(&self, pr_number: u64) -> anyhow::Result<Vec<String>> {
+        self.client
+            .as_ref()
+            .ok_or(PearError::NoClient)?
+            .pr_commits(&self.owner, &self.repository, pr_number)
+            .await
+    }
+
+    pub async fn pr_head_and_reviews(&self, pr_number: u64) -> anyhow::Result<(PullRequestHead, Vec<Review>)> {
         self.client
             .as_ref()
-            .ok_or_else(|| anyhow!("no client attached to remote"))?
-            .pr_head_hash(&self.owner, &self.repository, pr_number)
+            .ok_or(PearError::NoClient)?
+            .pr_head_and_reviews(&self.owner, &self.repository, pr_number)
             .await
     }
 
-    pub async fn pr_reviews(&self, pr_number: u64) -> Result<Vec<Review>, anyhow::Error> {
+    pub async fn pr_description(&self, pr_number: u64) -> anyhow::Result<PullRequestDescription> {
         self.client
             .as_ref()
-            .ok_or_else(|| anyhow!("no client attached to remote"))?
-            .pr_reviews(&self.owner, &self.repository, pr_number)
+            .ok_or(PearError::NoClient)?
+            .pr_description(&self.owner, &self.repository, pr_number)
+            .await
+    }
+
+    pub async fn team_members(&self, team: &Team) -> anyhow::Result<Vec<String>> {
+        self.client
+            .as_ref()
+            .ok_or(PearError::NoClient)?
+            .team_members(&team.org, &team.slug)
+            .await
+    }
+
+    pub async fn default_branch(&self) -> anyhow::Result<String> {
+        self.client
+            .as_ref()
+            .ok_or(PearError::NoClient)?
+            .default_branch(&self.owner, &self.repository)
+            .await
+    }
+
+    pub async fn branch_protection(&self, branch: &str) -> anyhow::Result<Option<BranchProtection>> {
+        self.client
+            .as_ref()
+            .ok_or(PearError::NoClient)?
+            .branch_protection(&self.owner, &self.repository, branch)
+            .await
+    }
+
+    pub async fn previous_tags(&self, tag: &str) -> anyhow::Result<Vec<String>> {
+        self.client
+            .as_ref()
+            .ok_or(PearError::NoClient)?
+            .previous_tags(&self.owner, &self.repository, tag)
+            .await
+    }
+
+    pub async fn codeowners(&self, branch: &str) -> anyhow::Result<Option<String>> {
+        self.client
+            .as_ref()
+            .ok_or(PearError::NoClient)?
+            .codeowners(&self.owner, &self.repository, branch)
             .await
     }
 }
@@ -86,7 +275,7 @@ impl<C: Client> Remote<C> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::api_clients::RealClient;
+    use crate::api_clients::{ClientConfig, ClientSet, MockClient, RealClient};
 
     #[test]
     fn parse_remote() -> Result<(), anyhow::Error> {
@@ -99,6 +288,74 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn parse_remote_port() -> Result<(), anyhow::Error> {
+        let result = Remote::<RealClient>::parse("https://git.example.com:8443/sapcc/pear-reviewer.git")?;
+        assert_eq!(result.port, Some(8443));
+        assert_eq!(result.authority(), "git.example.com:8443");
+        assert_eq!(
+            result.pr_url(1),
+            "https://git.example.com:8443/sapcc/pear-reviewer/pull/1"
+        );
+
+        // default ports and ports of other schemes are left out
+        for remote in [
+            "https://git.example.com:443/sapcc/pear-reviewer.git",
+            "http://git.example.com/sapcc/pear-reviewer.git",
+            "http://git.example.com:80/sapcc/pear-reviewer.git",
+            "ssh://git@git.example.com/sapcc/pear-reviewer.git",
+            "ssh://git@git.example.com:2222/sapcc/pear-reviewer.git",
+        ] {
+            let result = Remote::<RealClient>::parse(remote)?;
+            assert_eq!(result.authority(), "git.example.com", "{remote}");
+            assert_eq!(
+                result.pr_url(1),
+                "https://git.example.com/sapcc/pear-reviewer/pull/1",
+                "{remote}"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn pr_url_gitea() -> Result<(), anyhow::Error> {
+        let mut api_clients = ClientSet::<MockClient>::new(ClientConfig {
+            gitea_hosts: vec!["gitea.example.com".to_owned()],
+            ..ClientConfig::default()
+        });
+        let mut remote = Remote::<MockClient>::parse("https://gitea.example.com/sapcc/pear-reviewer.git")?;
+        api_clients.fill(&mut remote)?;
+        assert_eq!(
+            remote.pr_url(1),
+            "https://gitea.example.com/sapcc/pear-reviewer/pulls/1"
+        );
+
+        let mut remote = Remote::<MockClient>::parse("https://github.com/sapcc/pear-reviewer.git")?;
+        api_clients.fill(&mut remote)?;
+        assert_eq!(remote.pr_url(1), "https://github.com/sapcc/pear-reviewer/pull/1");
+        Ok(())
+    }
+
+    #[test]
+    fn is_same_repo() -> Result<(), anyhow::Error> {
+        let remote = Remote::<RealClient>::parse("https://github.com/sapcc/pear-reviewer.git")?;
+        for other in [
+            "https://github.com/sapcc/pear-reviewer",
+            "https://github.com/sapcc/pear-reviewer/",
+            "http://github.com/SAPCC/pear-reviewer.git",
+        ] {
+            assert!(remote.is_same_repo(&Remote::<RealClient>::parse(other)?), "{other}");
+        }
+        for other in [
+            "https://github.com/sapcc/keppel.git",
+            "https://github.com/other/pear-reviewer.git",
+            "https://gitlab.com/sapcc/pear-reviewer.git",
+        ] {
+            assert!(!remote.is_same_repo(&Remote::<RealClient>::parse(other)?), "{other}");
+        }
+        Ok(())
+    }
+
     #[test]
     fn parse_remote_invalid() {
         let result = Remote::<RealClient>::parse("https://sapcc/pear-reviewer.git");
@@ -13,7 +13,7 @@
 // limitations under the License.
 
 use anyhow::Context;
-use git2::{Repository, Tree};
+use git2::{Diff, Repository, Tree};
 
 pub fn tree_for_commit_ref<'r>(repo: &'r Repository, commit_ref: &'_ str) -> Result<Tree<'r>, anyhow::Error> {
     let commit_id = repo
@@ -28,3 +28,45 @@ pub fn tree_for_commit_ref<'r>(repo: &'r Repository, commit_ref: &'_ str) -> Res
         .with_context(|| format!("cannot find tree for Git commit {commit_id}"))?;
     Ok(tree)
 }
+
+pub fn merge_base_for_refs(repo: &Repository, base_ref: &str, head_ref: &str) -> Result<String, anyhow::Error> {
+    let base_id = repo
+        .revparse_single(base_ref)
+        .with_context(|| format!("cannot revparse {base_ref:?}"))?
+        .peel_to_commit()
+        .with_context(|| format!("{base_ref:?} is not a commit"))?
+        .id();
+    let head_id = repo
+        .revparse_single(head_ref)
+        .with_context(|| format!("cannot revparse {head_ref:?}"))?
+        .peel_to_commit()
+        .with_context(|| format!("{head_ref:?} is not a commit"))?
+        .id();
+    let merge_base = repo
+        .merge_base(base_id, head_id)
+        .with_context(|| format!("cannot find merge base of {base_ref:?} and {head_ref:?}"))?;
+    Ok(merge_base.to_string())
+}
+
+/// The diff between the trees of base and head, or of the merge base of both and head.
+pub fn diff_refs<'r>(
+    repo: &'r Repository,
+    base: &str,
+    head: &str,
+    use_merge_base: bool,
+) -> Result<Diff<'r>, anyhow::Error> {
+    let merge_base;
+    let base = if use_merge_base {
+        merge_base = merge_base_for_refs(repo, base, head)?;
+        merge_base.as_str()
+    } else {
+        base
+    };
+
+    let base_tree = tree_for_commit_ref(repo, base)
+        .with_context(|| format!("base ref {base:?} does not exist in the workspace"))?;
+    let head_tree = tree_for_commit_ref(repo, head)
+        .with_context(|| format!("head ref {head:?} does not exist in the workspace"))?;
+    repo.diff_tree_to_tree(Some(&base_tree), Some(&head_tree), None)
+        .with_context(|| format!("cannot diff trees {} and {}", base_tree.id(), head_tree.id()))
+}
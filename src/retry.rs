@@ -0,0 +1,250 @@
+// Copyright 2024 SAP SE
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::Display;
+use std::future::Future;
+use std::time::Duration;
+
+use crate::http_client::RetryAfter;
+
+const TRANSIENT_RETRIES: u32 = 3;
+const TRANSIENT_RETRY_DELAY: Duration = Duration::from_secs(2);
+// GitHub documents to wait at least a minute when it does not say how long
+const SECONDARY_RATE_LIMIT_WAIT: Duration = Duration::from_mins(1);
+const MAX_SUGGESTED_WAIT: Duration = Duration::from_mins(5);
+
+pub trait TransientError: Display {
+    /// Whether the error is caused by the transport (connection reset, DNS, timeout) or a server hiccup
+    /// and the request is likely to succeed when sent again.
+    fn is_transient(&self) -> bool;
+
+    /// How long the server asked to wait before sending the request again, instead of the usual retry delay.
+    fn suggested_wait(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl TransientError for octocrab::Error {
+    fn is_transient(&self) -> bool {
+        match self {
+            // connection level errors and timeouts
+            octocrab::Error::Hyper { .. } | octocrab::Error::Service { .. } => true,
+            // everything 4xx is our fault and won't get better by retrying, except for being rate limited
+            octocrab::Error::GitHub { source, .. } => {
+                source.status_code.is_server_error()
+                    || source.status_code.as_u16() == 429
+                    || self.suggested_wait().is_some()
+            },
+            _ => false,
+        }
+    }
+
+    fn suggested_wait(&self) -> Option<Duration> {
+        match self {
+            octocrab::Error::Service { source, .. } => {
+                source.downcast_ref::<RetryAfter>().map(|retry_after| retry_after.wait)
+            },
+            octocrab::Error::GitHub { source, .. } => {
+                secondary_rate_limit_wait(source.status_code.as_u16(), &source.message)
+            },
+            _ => None,
+        }
+    }
+}
+
+// The secondary rate limit, formerly called abuse detection, is reported as 403 or 429 with a message like "You have
+// exceeded a secondary rate limit. Please wait a few minutes before you try again". The primary rate limit is not
+// matched, waiting for its reset is up to --wait-on-rate-limit. Responses with a Retry-After header never get here,
+// they become a RetryAfter error instead.
+fn secondary_rate_limit_wait(status: u16, message: &str) -> Option<Duration> {
+    if status != 403 && status != 429 {
+        return None;
+    }
+    let message = message.to_lowercase();
+    if !message.contains("secondary rate limit") && !message.contains("abuse detection") {
+        return None;
+    }
+
+    let words: Vec<&str> = message
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect();
+    let wait = words.windows(2).find_map(|pair| {
+        let number: u64 = pair[0].parse().ok()?;
+        match pair[1] {
+            "second" | "seconds" => Some(Duration::from_secs(number)),
+            "minute" | "minutes" => Some(Duration::from_mins(number)),
+            _ => None,
+        }
+    });
+    Some(wait.unwrap_or(SECONDARY_RATE_LIMIT_WAIT))
+}
+
+/// Runs the request produced by `request` and sends it again a few times when it failed with a transient error.
+pub async fn retry_transient<T, E, F, Fut>(request: F) -> Result<T, E>
+where
+    E: TransientError,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    retry_transient_with(TRANSIENT_RETRIES, TRANSIENT_RETRY_DELAY, request).await
+}
+
+async fn retry_transient_with<T, E, F, Fut>(retries: u32, delay: Duration, mut request: F) -> Result<T, E>
+where
+    E: TransientError,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut attempt = 0;
+    loop {
+        match request().await {
+            Err(err) if attempt < retries && err.is_transient() => {
+                attempt += 1;
+                if let Some(wait) = err.suggested_wait() {
+                    let wait = wait.min(MAX_SUGGESTED_WAIT);
+                    eprintln!("asked to slow down, pausing for {wait:?} before retrying ({attempt}/{retries}): {err}");
+                    tokio::time::sleep(wait).await;
+                } else {
+                    eprintln!("transient error, retrying ({attempt}/{retries}) in {delay:?}: {err}");
+                    tokio::time::sleep(delay).await;
+                }
+            },
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    enum TestError {
+        ConnectionReset,
+        NotFound,
+        SlowDown,
+    }
+
+    impl fmt::Display for TestError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{self:?}")
+        }
+    }
+
+    impl TransientError for TestError {
+        fn is_transient(&self) -> bool {
+            *self != TestError::NotFound
+        }
+
+        fn suggested_wait(&self) -> Option<Duration> {
+            (*self == TestError::SlowDown).then_some(Duration::from_millis(50))
+        }
+    }
+
+    #[tokio::test]
+    async fn retry_transient_recovers() {
+        let calls = AtomicU32::new(0);
+        let result = retry_transient_with(3, Duration::ZERO, || async {
+            if calls.fetch_add(1, Ordering::SeqCst) < 2 {
+                Err(TestError::ConnectionReset)
+            } else {
+                Ok("done")
+            }
+        })
+        .await;
+
+        assert_eq!(result, Ok("done"));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn retry_transient_gives_up() {
+        let calls = AtomicU32::new(0);
+        let result: Result<(), _> = retry_transient_with(2, Duration::ZERO, || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err(TestError::ConnectionReset)
+        })
+        .await;
+
+        assert_eq!(result, Err(TestError::ConnectionReset));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn retry_transient_permanent_error() {
+        let calls = AtomicU32::new(0);
+        let result: Result<(), _> = retry_transient_with(3, Duration::ZERO, || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err(TestError::NotFound)
+        })
+        .await;
+
+        assert_eq!(result, Err(TestError::NotFound));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn retry_transient_suggested_wait() {
+        let calls = AtomicU32::new(0);
+        let start = std::time::Instant::now();
+        let result = retry_transient_with(3, Duration::ZERO, || async {
+            if calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                Err(TestError::SlowDown)
+            } else {
+                Ok("done")
+            }
+        })
+        .await;
+
+        assert_eq!(result, Ok("done"));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn secondary_rate_limit_wait() {
+        let body: serde_json::Value = serde_json::from_str(
+            r#"{
+                "documentation_url": "https://docs.github.com/free-pro-team@latest/rest/overview/rate-limits-for-the-rest-api#about-secondary-rate-limits",
+                "message": "You have exceeded a secondary rate limit. Please wait a few minutes before you try again. If you reach out to GitHub Support for help, please include the request ID 1234:5678."
+            }"#,
+        )
+        .unwrap();
+        let message = body["message"].as_str().unwrap();
+        assert_eq!(
+            super::secondary_rate_limit_wait(403, message),
+            Some(Duration::from_mins(1))
+        );
+        assert_eq!(
+            super::secondary_rate_limit_wait(429, message),
+            Some(Duration::from_mins(1))
+        );
+        assert_eq!(super::secondary_rate_limit_wait(404, message), None);
+
+        assert_eq!(
+            super::secondary_rate_limit_wait(
+                403,
+                "You have triggered an abuse detection mechanism. Please wait 30 seconds before you try again."
+            ),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            super::secondary_rate_limit_wait(403, "API rate limit exceeded for user ID 1."),
+            None
+        );
+    }
+}
@@ -0,0 +1,308 @@
+// Copyright 2024 SAP SE
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::future::Future;
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::routing::post;
+use axum::Router;
+use ring::hmac;
+use serde::Deserialize;
+use tokio::sync::Semaphore;
+
+use crate::Range;
+
+/// Header with the HMAC-SHA256 of the payload, keyed with the secret of the webhook.
+const SIGNATURE_HEADER: &str = "x-hub-signature-256";
+const EVENT_HEADER: &str = "x-github-event";
+/// What git sends as the commit before a branch was created or after it was deleted.
+const NULL_SHA: &str = "0000000000000000000000000000000000000000";
+/// Ranges analyzed at the same time, further deliveries are rejected until one of them is done.
+pub const MAX_RANGES_IN_FLIGHT: usize = 16;
+
+#[derive(Debug, Deserialize)]
+struct Repository {
+    clone_url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct PushEvent {
+    before: String,
+    after: String,
+    repository: Repository,
+}
+
+#[derive(Debug, Deserialize)]
+struct PullRequestReviewEvent {
+    pull_request: PullRequest,
+    repository: Repository,
+}
+
+#[derive(Debug, Deserialize)]
+struct PullRequest {
+    base: Branch,
+    head: Branch,
+}
+
+#[derive(Debug, Deserialize)]
+struct Branch {
+    sha: String,
+}
+
+/// Checks the signature header of a webhook delivery, e.g. "sha256=1a2b...", against the payload.
+pub fn verify_signature(secret: &[u8], payload: &[u8], signature: Option<&str>) -> bool {
+    let Some(signature) = signature.and_then(|signature| signature.strip_prefix("sha256=")) else {
+        return false;
+    };
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+    // compares in constant time, so that the signature cannot be guessed byte by byte
+    hmac::verify(&hmac::Key::new(hmac::HMAC_SHA256, secret), payload, &signature).is_ok()
+}
+
+/// The range to analyze for a webhook event, None for events that change no reviews or commits, e.g. ping.
+pub fn range_for_event(event: &str, payload: &[u8]) -> Result<Option<Range>, anyhow::Error> {
+    match event {
+        "push" => {
+            let push: PushEvent = serde_json::from_slice(payload).context("invalid push event")?;
+            // a new branch has nothing to compare against and a deleted one nothing left to review
+            if push.before == NULL_SHA || push.after == NULL_SHA {
+                return Ok(None);
+            }
+            Ok(Some(Range {
+                remote: push.repository.clone_url,
+                base: push.before,
+                head: push.after,
+            }))
+        },
+        "pull_request_review" => {
+            let review: PullRequestReviewEvent =
+                serde_json::from_slice(payload).context("invalid pull_request_review event")?;
+            Ok(Some(Range {
+                remote: review.repository.clone_url,
+                base: review.pull_request.base.sha,
+                head: review.pull_request.head.sha,
+            }))
+        },
+        _ => Ok(None),
+    }
+}
+
+struct ServerState<F> {
+    secret: Vec<u8>,
+    in_flight: Arc<Semaphore>,
+    on_range: F,
+}
+
+/// Receives GitHub webhooks on any path and passes the range of every push and review to `on_range`, which runs in
+/// the background. GitHub gives up on deliveries that are not answered within ten seconds. While `max_in_flight`
+/// ranges are running, deliveries are answered with 503, so that they can be redelivered later.
+pub fn router<F, Fut>(secret: Vec<u8>, max_in_flight: usize, on_range: F) -> Router
+where
+    F: Fn(Range) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    Router::new()
+        .route("/", post(receive::<F, Fut>))
+        .route("/{*path}", post(receive::<F, Fut>))
+        .with_state(Arc::new(ServerState {
+            secret,
+            in_flight: Arc::new(Semaphore::new(max_in_flight)),
+            on_range,
+        }))
+}
+
+async fn receive<F, Fut>(State(state): State<Arc<ServerState<F>>>, headers: HeaderMap, payload: Bytes) -> StatusCode
+where
+    F: Fn(Range) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    if !verify_signature(&state.secret, &payload, header(SIGNATURE_HEADER)) {
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    match range_for_event(header(EVENT_HEADER).unwrap_or_default(), &payload) {
+        Ok(Some(range)) => {
+            let Ok(permit) = Arc::clone(&state.in_flight).try_acquire_owned() else {
+                eprintln!(
+                    "warning: rejecting webhook for {}, too many ranges in flight",
+                    range.remote
+                );
+                return StatusCode::SERVICE_UNAVAILABLE;
+            };
+            let analysis = (state.on_range)(range);
+            tokio::spawn(async move {
+                analysis.await;
+                drop(permit);
+            });
+            StatusCode::ACCEPTED
+        },
+        Ok(None) => StatusCode::NO_CONTENT,
+        Err(err) => {
+            eprintln!("warning: ignoring webhook: {err:#}");
+            StatusCode::BAD_REQUEST
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::{mpsc, Notify};
+
+    use super::*;
+
+    const SECRET: &[u8] = b"It's a Secret to Everybody";
+
+    fn sign(payload: &[u8]) -> String {
+        let tag = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, SECRET), payload);
+        format!("sha256={}", hex::encode(tag.as_ref()))
+    }
+
+    #[test]
+    fn verify_signature() {
+        // the example of the GitHub documentation on validating webhook deliveries
+        let signature = "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+        assert!(super::verify_signature(SECRET, b"Hello, World!", Some(signature)));
+        assert!(!super::verify_signature(SECRET, b"Hello, World?", Some(signature)));
+        assert!(!super::verify_signature(
+            b"other secret",
+            b"Hello, World!",
+            Some(signature)
+        ));
+        assert!(!super::verify_signature(
+            SECRET,
+            b"Hello, World!",
+            Some(&signature[7..])
+        ));
+        assert!(!super::verify_signature(
+            SECRET,
+            b"Hello, World!",
+            Some("sha256=not hex")
+        ));
+        assert!(!super::verify_signature(SECRET, b"Hello, World!", None));
+    }
+
+    #[test]
+    fn range_for_event() {
+        let push = |before: &str, after: &str| {
+            format!(
+                r#"{{"ref": "refs/heads/main", "before": "{before}", "after": "{after}",
+                    "repository": {{"clone_url": "https://github.com/sapcc/keppel.git"}}}}"#
+            )
+        };
+        let sha = |digit: &str| digit.repeat(40);
+
+        assert_eq!(
+            super::range_for_event("push", push(&sha("1"), &sha("2")).as_bytes()).unwrap(),
+            Some(Range {
+                remote: "https://github.com/sapcc/keppel.git".to_owned(),
+                base: sha("1"),
+                head: sha("2"),
+            })
+        );
+        assert_eq!(
+            super::range_for_event("push", push(NULL_SHA, &sha("2")).as_bytes()).unwrap(),
+            None
+        );
+        assert_eq!(
+            super::range_for_event("push", push(&sha("1"), NULL_SHA).as_bytes()).unwrap(),
+            None
+        );
+        assert_eq!(super::range_for_event("ping", b"{}").unwrap(), None);
+        assert!(super::range_for_event("push", b"{}").is_err());
+    }
+
+    async fn serve_router(router: Router) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        addr
+    }
+
+    // Delivers a review event and returns the status line of the response. The payload is signed correctly unless a
+    // signature is given.
+    async fn deliver(addr: SocketAddr, signature: Option<&str>) -> String {
+        let payload = r#"{"action": "submitted",
+            "pull_request": {"base": {"sha": "1111111"}, "head": {"sha": "2222222"}},
+            "repository": {"clone_url": "https://github.com/sapcc/keppel.git"}}"#;
+        let signature = signature.map_or_else(|| sign(payload.as_bytes()), ToOwned::to_owned);
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!(
+            "POST /webhook HTTP/1.1\r\nHost: {addr}\r\nX-GitHub-Event: pull_request_review\r\n\
+             X-Hub-Signature-256: {signature}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{payload}",
+            payload.len()
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response.lines().next().unwrap_or_default().to_owned()
+    }
+
+    #[tokio::test]
+    async fn router_dispatches_review() {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let addr = serve_router(router(SECRET.to_vec(), MAX_RANGES_IN_FLIGHT, move |range| {
+            let sender = sender.clone();
+            async move {
+                sender.send(range).unwrap();
+            }
+        }))
+        .await;
+
+        assert_eq!(deliver(addr, Some("sha256=00")).await, "HTTP/1.1 401 Unauthorized");
+        assert_eq!(deliver(addr, None).await, "HTTP/1.1 202 Accepted");
+        assert_eq!(receiver.recv().await.unwrap(), Range {
+            remote: "https://github.com/sapcc/keppel.git".to_owned(),
+            base: "1111111".to_owned(),
+            head: "2222222".to_owned(),
+        });
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn router_rejects_when_busy() {
+        let release = Arc::new(Notify::new());
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let addr = serve_router(router(SECRET.to_vec(), 1, {
+            let release = Arc::clone(&release);
+            move |_| {
+                let release = Arc::clone(&release);
+                let sender = sender.clone();
+                async move {
+                    release.notified().await;
+                    sender.send(()).unwrap();
+                }
+            }
+        }))
+        .await;
+
+        assert_eq!(deliver(addr, None).await, "HTTP/1.1 202 Accepted");
+        assert_eq!(deliver(addr, None).await, "HTTP/1.1 503 Service Unavailable");
+
+        // the permit is given back once the first range is done
+        release.notify_one();
+        receiver.recv().await.unwrap();
+        assert_eq!(deliver(addr, None).await, "HTTP/1.1 202 Accepted");
+    }
+}
@@ -0,0 +1,243 @@
+// Copyright 2024 SAP SE
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context};
+use git2::{Delta, FileMode, Repository, Tree};
+
+use crate::api_clients::Client;
+use crate::changes::RepoChangeset;
+use crate::remote::Remote;
+use crate::repo;
+
+const GITMODULES: &str = ".gitmodules";
+
+/// The submodules whose pinned commit changed between the base and head commit of the workspace, one changeset per
+/// submodule. The pinned commits are the source commits, like the ones of an images.yaml file.
+pub fn find_submodule_changes<C: Client>(
+    workspace: String,
+    base: &str,
+    head: &str,
+    use_merge_base: bool,
+) -> Result<Vec<RepoChangeset<C>>, anyhow::Error> {
+    let repo = Repository::open(workspace).context("failed to open repository")?;
+    let diff_tree = repo::diff_refs(&repo, base, head, use_merge_base)?;
+    let urls = submodule_urls(&repo, &repo::tree_for_commit_ref(&repo, head)?)?;
+
+    let mut changes = Vec::new();
+    for diff_delta in diff_tree.deltas() {
+        let new_file = diff_delta.new_file();
+        // removed submodules don't need a review
+        if diff_delta.status() == Delta::Deleted || new_file.mode() != FileMode::Commit {
+            continue;
+        }
+
+        let path = new_file.path().ok_or_else(|| anyhow!("failed to get file path"))?;
+        let path = path.to_string_lossy();
+        let url = urls
+            .get(path.as_ref())
+            .with_context(|| format!("submodule {path} has no URL in {GITMODULES}"))?;
+        let old_file = diff_delta.old_file();
+        // a file or directory that was replaced by the submodule has no commit to compare against
+        let base_commit = if diff_delta.status() == Delta::Added || old_file.mode() != FileMode::Commit {
+            String::new()
+        } else {
+            old_file.id().to_string()
+        };
+
+        changes.push(RepoChangeset {
+            name: path.to_string(),
+            remote: Remote::parse(&remote_url(url)?).with_context(|| format!("invalid URL of submodule {path}"))?,
+            base_commit,
+            head_commit: new_file.id().to_string(),
+            base_ref: None,
+            head_ref: None,
+            min_approvals: None,
+            required_approvers: Vec::new(),
+            compare_status: None,
+            error: None,
+            changes: Vec::new(),
+        });
+    }
+
+    Ok(changes)
+}
+
+// Maps the path of every submodule to its URL, as configured in the .gitmodules file of the tree.
+fn submodule_urls(repo: &Repository, tree: &Tree) -> Result<HashMap<String, String>, anyhow::Error> {
+    let Ok(entry) = tree.get_path(Path::new(GITMODULES)) else {
+        return Ok(HashMap::new());
+    };
+    let blob = entry
+        .to_object(repo)
+        .and_then(|object| object.peel_to_blob())
+        .with_context(|| format!("cannot read {GITMODULES}"))?;
+    let content = std::str::from_utf8(blob.content()).with_context(|| format!("{GITMODULES} is not UTF-8"))?;
+    Ok(parse_gitmodules(content))
+}
+
+// Only the subset of the git config format that .gitmodules files use in practice: sections with one key per line.
+fn parse_gitmodules(content: &str) -> HashMap<String, String> {
+    let mut sections: Vec<(Option<String>, Option<String>)> = Vec::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            sections.push((None, None));
+            continue;
+        }
+        let (Some(section), Some((key, value))) = (sections.last_mut(), line.split_once('=')) else {
+            continue;
+        };
+        let value = value.trim().trim_matches('"').to_owned();
+        match key.trim().to_lowercase().as_str() {
+            "path" => section.0 = Some(value),
+            "url" => section.1 = Some(value),
+            _ => {},
+        }
+    }
+
+    sections
+        .into_iter()
+        .filter_map(|(path, url)| Some((path?, url?)))
+        .collect()
+}
+
+// Submodules are often cloned over SSH, but remotes are web URLs.
+fn remote_url(url: &str) -> Result<String, anyhow::Error> {
+    if let Some(rest) = url.strip_prefix("ssh://") {
+        let authority_end = rest.find('/').unwrap_or(rest.len());
+        let (authority, path) = rest.split_at(authority_end);
+        let host = authority.rsplit('@').next().unwrap_or(authority);
+        // the SSH port says nothing about the port of the web interface
+        let host = host.split(':').next().unwrap_or(host);
+        return Ok(format!("https://{host}{path}"));
+    }
+    if url.contains("://") {
+        return Ok(url.to_owned());
+    }
+    match url.split_once(':') {
+        // scp-like syntax, e.g. git@github.com:sapcc/keppel.git
+        Some((user_host, path)) if !user_host.contains('/') => {
+            let host = user_host.rsplit('@').next().unwrap_or(user_host);
+            Ok(format!("https://{host}/{path}"))
+        },
+        _ => bail!("relative submodule URL {url:?} is not supported"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use git2::{Oid, Signature};
+
+    use super::*;
+    use crate::api_clients::MockClient;
+
+    fn commit_tree(repo: &Repository, gitmodules: &str, submodules: &[(&str, Oid)], parents: &[&git2::Commit]) -> Oid {
+        let mut tree = repo.treebuilder(None).unwrap();
+        let blob = repo.blob(gitmodules.as_bytes()).unwrap();
+        tree.insert(GITMODULES, blob, 0o100_644).unwrap();
+        for (path, commit) in submodules {
+            tree.insert(path, *commit, 0o160_000).unwrap();
+        }
+        let tree = repo.find_tree(tree.write().unwrap()).unwrap();
+        let signature = Signature::now("pear-reviewer", "pear-reviewer@example.com").unwrap();
+        repo.commit(None, &signature, &signature, "bump submodules", &tree, parents)
+            .unwrap()
+    }
+
+    #[test]
+    fn find_submodule_changes() {
+        let workspace = env::temp_dir().join(format!("pear-reviewer-submodules-{}", std::process::id()));
+        let repo = Repository::init(&workspace).unwrap();
+        let gitmodules = r#"[submodule "keppel"]
+	path = keppel
+	url = git@github.com:sapcc/keppel.git
+[submodule "limes"]
+	path = limes
+	url = https://github.com/sapcc/limes.git
+"#;
+        // libgit2 only accepts submodule commits that exist, so they are created in the same repo
+        let signature = Signature::now("pear-reviewer", "pear-reviewer@example.com").unwrap();
+        let empty_tree = repo
+            .find_tree(repo.treebuilder(None).unwrap().write().unwrap())
+            .unwrap();
+        let sha = |message: char| {
+            repo.commit(None, &signature, &signature, &message.to_string(), &empty_tree, &[])
+                .unwrap()
+        };
+        let (keppel_old, keppel_new, limes) = (sha('1'), sha('2'), sha('3'));
+        let base = commit_tree(&repo, gitmodules, &[("keppel", keppel_old)], &[]);
+        let base_commit = repo.find_commit(base).unwrap();
+        let head = commit_tree(&repo, gitmodules, &[("keppel", keppel_new), ("limes", limes)], &[
+            &base_commit,
+        ]);
+
+        let changes = super::find_submodule_changes::<MockClient>(
+            workspace.to_string_lossy().to_string(),
+            &base.to_string(),
+            &head.to_string(),
+            false,
+        )
+        .unwrap();
+        std::fs::remove_dir_all(&workspace).unwrap();
+
+        let changes: Vec<(&str, &str, String, String)> = changes
+            .iter()
+            .map(|change| {
+                (
+                    change.name.as_str(),
+                    change.remote.original.as_str(),
+                    change.base_commit.clone(),
+                    change.head_commit.clone(),
+                )
+            })
+            .collect();
+        assert_eq!(changes, vec![
+            (
+                "keppel",
+                "https://github.com/sapcc/keppel.git",
+                keppel_old.to_string(),
+                keppel_new.to_string()
+            ),
+            (
+                "limes",
+                "https://github.com/sapcc/limes.git",
+                String::new(),
+                limes.to_string()
+            ),
+        ]);
+    }
+
+    #[test]
+    fn remote_url() {
+        for (url, expected) in [
+            (
+                "https://github.com/sapcc/keppel.git",
+                "https://github.com/sapcc/keppel.git",
+            ),
+            ("git@github.com:sapcc/keppel.git", "https://github.com/sapcc/keppel.git"),
+            (
+                "ssh://git@github.com:22/sapcc/keppel.git",
+                "https://github.com/sapcc/keppel.git",
+            ),
+        ] {
+            assert_eq!(super::remote_url(url).unwrap(), expected);
+        }
+        assert!(super::remote_url("../keppel.git").is_err());
+    }
+}
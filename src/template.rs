@@ -0,0 +1,100 @@
+// Copyright 2024 SAP SE
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Handlebars templates for --output-template.
+//!
+//! Values are inserted as they are, without HTML escaping, as most templates produce markdown or plain text. The
+//! `escape_table_cell` helper escapes a value for a cell of a markdown table, e.g. `{{escape_table_cell error}}`, and
+//! `escape_markdown` also escapes formatting for plain text like the verdict.
+
+use anyhow::Context;
+use handlebars::{handlebars_helper, no_escape, Handlebars};
+use serde_json::Value;
+
+use crate::output::{escape_markdown, escape_table_cell};
+
+const TEMPLATE_NAME: &str = "template";
+
+handlebars_helper!(escape_table_cell_helper: |text: str| escape_table_cell(text));
+handlebars_helper!(escape_markdown_helper: |text: str| escape_markdown(text));
+
+#[derive(Clone, Debug)]
+pub struct Template {
+    registry: Handlebars<'static>,
+}
+
+impl Template {
+    /// Parses the template, so that syntax errors show up before anything is analyzed.
+    pub fn parse(source: &str) -> anyhow::Result<Self> {
+        let mut registry = Handlebars::new();
+        registry.register_escape_fn(no_escape);
+        registry.register_helper("escape_table_cell", Box::new(escape_table_cell_helper));
+        registry.register_helper("escape_markdown", Box::new(escape_markdown_helper));
+        registry.register_template_string(TEMPLATE_NAME, source)?;
+        Ok(Self { registry })
+    }
+
+    pub fn render(&self, context: &Value) -> anyhow::Result<String> {
+        self.registry
+            .render(TEMPLATE_NAME, context)
+            .context("cannot render template")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn render_csv() {
+        let template = Template::parse(
+            "repo,pr,approvals\n\
+             {{#each repos}}\n\
+             {{#each changes}}\n\
+             {{../name}},{{#if pr_link}}{{pr_link}}{{else}}none{{/if}},\
+             {{#each approvals}}{{this}}{{#unless @last}} {{/unless}}{{/each}}\n\
+             {{/each}}\n\
+             {{/each}}\n",
+        )
+        .unwrap();
+        let context = json!({"repos": [
+            {"name": "keppel", "changes": [
+                {"pr_link": "https://github.com/sapcc/keppel/pull/1", "approvals": ["user1", "user2"]},
+                {"pr_link": null, "approvals": []},
+            ]},
+        ]});
+        assert_eq!(
+            template.render(&context).unwrap(),
+            "repo,pr,approvals\nkeppel,https://github.com/sapcc/keppel/pull/1,user1 user2\nkeppel,none,\n"
+        );
+    }
+
+    #[test]
+    fn render_unescaped() {
+        let template = Template::parse("{{text}} | {{escape_table_cell text}} | {{escape_markdown text}}").unwrap();
+        assert_eq!(
+            template.render(&json!({"text": "<a|*b*>"})).unwrap(),
+            "<a|*b*> | <a\\|*b*> | <a\\|\\*b\\*>"
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        for source in ["{{#each repos}}", "{{#each repos}}\n{{/if}}", "{{/each}}", "\n{{name"] {
+            assert!(Template::parse(source).is_err(), "{source} should not parse");
+        }
+    }
+}